@@ -0,0 +1,333 @@
+//! The automation of the volume and the pan of timeline tracks.
+//!
+//! The program layer converts the automation lanes of a channel from musical
+//! time into frames on the timeline, and sends them to the timeline player
+//! with `TimelineMsg::SetAutomation`. Every track then evaluates its lanes once
+//! per frame with an `AutomationEvaluator`, in the same parts of the block
+//! that it renders its clips in. Since the player splits the block where the
+//! playhead loops, the lanes are evaluated at the positions after the jump.
+
+/// The length of the ramp in seconds that hides a jump in the value of an
+/// automation lane (i.e. after a seek or a loop, or where the lane jumps).
+pub const AUTOMATION_DECLICK_SECS: f64 = 0.002;
+
+/// Returns the linear gain of the normalized volume `normalized` in the range
+/// [0.0, 1.0], where 0.0 is silence and 1.0 is unity. This is used for the
+/// fader of a channel and for its gain lane alike.
+pub fn fader_gain(normalized: f64) -> f32 {
+    normalized.clamp(0.0, 1.0) as f32
+}
+
+/// The shape of the segment from a point of a lane to the next one (see
+/// `AutomationCurve` in the program layer).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LaneCurve {
+    /// A straight line.
+    Linear,
+    /// Keep the value of this point until the next one, then jump.
+    Hold,
+    /// Ease out of this point and into the next one.
+    SCurve,
+    /// Bend the line so that most of the change happens near the next point
+    /// (for positive tension) or near this point (for negative tension).
+    Tension(f32),
+}
+
+impl LaneCurve {
+    /// Maps the linear position `t` in the range [0.0, 1.0] between two points
+    /// to the position along this curve.
+    pub fn shape(&self, t: f64) -> f64 {
+        match self {
+            LaneCurve::Linear => t,
+            LaneCurve::Hold => 0.0,
+            LaneCurve::SCurve => t * t * (3.0 - 2.0 * t),
+            LaneCurve::Tension(tension) => t.powf(f64::from(*tension).exp2()),
+        }
+    }
+}
+
+/// A point of an `AutomationLane`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LanePoint {
+    /// The position of the point on the timeline in frames. This is
+    /// fractional, since the musical position of the point rarely falls on a
+    /// frame.
+    pub frame: f64,
+    /// The normalized value of the parameter in the range [0.0, 1.0].
+    pub value: f64,
+    /// The shape of the segment from this point to the next one.
+    pub curve: LaneCurve,
+}
+
+/// An automation lane of a timeline track, ready to be evaluated on the audio
+/// thread.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AutomationLane {
+    /// Sorted by frame. Two points may share the same frame to make a jump.
+    points: Vec<LanePoint>,
+}
+
+impl AutomationLane {
+    /// Creates a lane from `points`, which must be sorted by frame.
+    pub fn new(points: Vec<LanePoint>) -> Self {
+        Self { points }
+    }
+
+    /// Returns `true` if the lane has no points, in which case the parameter
+    /// is not automated.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Returns the value of this lane at `frame`, or `None` if the lane has no
+    /// points.
+    pub fn value_at(&self, frame: f64) -> Option<f64> {
+        // Of several points at the same frame, the last one wins.
+        self.value_around(frame, |p| p.frame <= frame)
+    }
+
+    /// Like `value_at()`, but if there are points at `frame`, this returns the
+    /// value just before them.
+    pub fn value_before(&self, frame: f64) -> Option<f64> {
+        self.value_around(frame, |p| p.frame < frame)
+    }
+
+    /// Interpolates between the last point for which `is_before` is true and
+    /// the point after it.
+    fn value_around(&self, frame: f64, is_before: impl FnMut(&LanePoint) -> bool) -> Option<f64> {
+        let first = self.points.first()?;
+
+        let next = self.points.partition_point(is_before);
+        if next == 0 {
+            return Some(first.value);
+        }
+        if next == self.points.len() {
+            return self.points.last().map(|p| p.value);
+        }
+
+        let a = &self.points[next - 1];
+        let b = &self.points[next];
+        let span = b.frame - a.frame;
+        if span <= 0.0 {
+            return Some(b.value);
+        }
+        let t = a.curve.shape((frame - a.frame) / span);
+        Some(a.value + (b.value - a.value) * t)
+    }
+}
+
+/// The automation of a timeline track.
+#[derive(Debug, Clone)]
+pub struct TrackAutomation {
+    /// The normalized volume (see `fader_gain()`). While this is empty, the
+    /// track plays at the volume of its fader.
+    pub gain: AutomationLane,
+    /// The normalized pan. While this is empty, the track plays at the pan of
+    /// its pan control.
+    pub pan: AutomationLane,
+    /// The pan law of the track (see `backend::pan`), which turns the pan lane
+    /// into the gains of the left and the right channel.
+    pub pan_law: fn(f64) -> (f64, f64),
+    /// The time constant in seconds of the lowpass that rounds off sharp
+    /// corners of the lanes (see `AutomationEvaluator::new()`), or `None` to
+    /// follow the lanes exactly.
+    pub corner_smoothing: Option<f64>,
+}
+
+impl Default for TrackAutomation {
+    fn default() -> Self {
+        Self {
+            gain: AutomationLane::default(),
+            pan: AutomationLane::default(),
+            pan_law: super::pan::constant_power,
+            corner_smoothing: None,
+        }
+    }
+}
+
+/// Evaluates an automation lane once per frame, block by block.
+///
+/// Every block starts from the value the previous block ended on, so the value
+/// never jumps at the edge of a block. Where the value would jump (because the
+/// playhead looped or seeked, or because the lane itself jumps), the output
+/// ramps from where it was to the lane over `AUTOMATION_DECLICK_SECS`. The
+/// ramps are tracked per frame and carry over into the next block, so the
+/// output doesn't depend on the block size.
+///
+/// With corner smoothing, the output follows the lane through a one-pole
+/// lowpass instead, which also rounds off sharp changes of direction (e.g. of a
+/// sawtooth drawn into a gain lane) that can otherwise be heard as clicks.
+#[derive(Debug, Clone)]
+pub struct AutomationEvaluator {
+    last_value: Option<f64>,
+    /// The frame the next block starts at if the playhead keeps moving
+    /// forward.
+    next_frame: Option<u64>,
+    sample_rate: f64,
+    declick_frames: usize,
+    /// The distance from the lane that the current declick ramp started at,
+    /// and the number of frames that are left of it.
+    ramp: Option<(f64, usize)>,
+    /// The coefficient of the one-pole lowpass, or `None` if corners are not
+    /// smoothed.
+    smoothing_coeff: Option<f64>,
+}
+
+impl AutomationEvaluator {
+    /// * `sample_rate` - The sample rate of the engine.
+    /// * `corner_smoothing` - The time constant in seconds of the lowpass that
+    ///   rounds off sharp corners, or `None` to follow the lane exactly.
+    pub fn new(sample_rate: f64, corner_smoothing: Option<f64>) -> Self {
+        let declick_frames = (AUTOMATION_DECLICK_SECS * sample_rate).round().max(1.0) as usize;
+        let mut evaluator = Self {
+            last_value: None,
+            next_frame: None,
+            sample_rate,
+            declick_frames,
+            ramp: None,
+            smoothing_coeff: None,
+        };
+        evaluator.set_corner_smoothing(corner_smoothing);
+        evaluator
+    }
+
+    /// Sets the time constant of the lowpass that rounds off sharp corners (see
+    /// `new()`).
+    pub fn set_corner_smoothing(&mut self, corner_smoothing: Option<f64>) {
+        self.smoothing_coeff = corner_smoothing
+            .filter(|secs| *secs > 0.0)
+            .map(|secs| 1.0 - (-1.0 / (secs * self.sample_rate)).exp());
+    }
+
+    /// Forgets the last value, so that the next block starts right on the lane.
+    /// Call this when the transport starts or stops.
+    pub fn reset(&mut self) {
+        self.last_value = None;
+        self.next_frame = None;
+        self.ramp = None;
+    }
+
+    /// Makes the next block ramp from the last value to the lane, like after a
+    /// jump of the playhead. Call this when the lane was replaced.
+    pub fn lane_changed(&mut self) {
+        self.next_frame = None;
+    }
+
+    /// Fills `out` with the value of `lane` for each frame of a block that
+    /// starts at the timeline frame `start`.
+    ///
+    /// When the playhead loops or seeks inside a block, call this once for each
+    /// contiguous part of the block with `out` sliced to the part.
+    ///
+    /// If the lane has no points, `out` is left as it is.
+    pub fn process_block(&mut self, lane: &AutomationLane, start: u64, out: &mut [f64]) {
+        if lane.is_empty() || out.is_empty() {
+            return;
+        }
+
+        // If the playhead moved on from the last block, the jumps of the lane
+        // between the two blocks are looked for from the last frame on.
+        // Otherwise the block jumps as a whole.
+        let continues = self.next_frame == Some(start);
+        let prev_frame = if continues { start as f64 - 1.0 } else { start as f64 };
+        let mut next_point = lane.points.partition_point(|p| p.frame <= prev_frame);
+        let mut jumped = !continues;
+
+        for (i, out) in out.iter_mut().enumerate() {
+            let frame = (start + i as u64) as f64;
+            while let Some(point) = lane.points.get(next_point) {
+                if point.frame > frame {
+                    break;
+                }
+                jumped |= lane.value_before(point.frame) != lane.value_at(point.frame);
+                next_point += 1;
+            }
+            let value = lane.value_at(frame).unwrap_or(*out);
+
+            *out = match (self.smoothing_coeff, self.last_value) {
+                (Some(coeff), Some(last_value)) => last_value + (value - last_value) * coeff,
+                (None, Some(last_value)) => {
+                    if jumped {
+                        self.ramp = Some((last_value - value, self.declick_frames));
+                    }
+                    match &mut self.ramp {
+                        Some((offset, frames_left)) => {
+                            *frames_left -= 1;
+                            let out =
+                                value + *offset * *frames_left as f64 / self.declick_frames as f64;
+                            if *frames_left == 0 {
+                                self.ramp = None;
+                            }
+                            out
+                        }
+                        None => value,
+                    }
+                }
+                (_, None) => value,
+            };
+            jumped = false;
+            self.last_value = Some(*out);
+        }
+
+        self.next_frame = Some(start + out.len() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lane(points: &[(f64, f64)]) -> AutomationLane {
+        AutomationLane::new(
+            points
+                .iter()
+                .map(|(frame, value)| LanePoint {
+                    frame: *frame,
+                    value: *value,
+                    curve: LaneCurve::Linear,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn jumps_between_blocks_are_ramped() {
+        let lane = lane(&[(0.0, 0.0), (24_000.0, 0.0), (24_000.0, 1.0)]);
+        let mut evaluator = AutomationEvaluator::new(48_000.0, None);
+
+        let mut before = vec![0.0; 256];
+        evaluator.process_block(&lane, 0, &mut before);
+        // Seek to frame 48000, where the lane is at 1.0.
+        let mut after = vec![0.0; 256];
+        evaluator.process_block(&lane, 48_000, &mut after);
+
+        // The jump is spread over the 96 frames of `AUTOMATION_DECLICK_SECS`.
+        assert!((after[0] - 1.0 / 96.0).abs() < 1e-12);
+        assert!(after.windows(2).all(|w| (w[1] - w[0]).abs() <= 1.0 / 96.0 + 1e-12));
+        assert_eq!(after[95], 1.0);
+    }
+
+    #[test]
+    fn replaced_lanes_are_ramped_to() {
+        let mut evaluator = AutomationEvaluator::new(48_000.0, None);
+        let mut out = vec![0.0; 256];
+        evaluator.process_block(&lane(&[(0.0, 0.0)]), 0, &mut out);
+
+        evaluator.lane_changed();
+        evaluator.process_block(&lane(&[(0.0, 1.0)]), 256, &mut out);
+        assert!((out[0] - 1.0 / 96.0).abs() < 1e-12);
+        assert_eq!(out[95], 1.0);
+    }
+
+    #[test]
+    fn the_first_block_after_a_reset_starts_on_the_lane() {
+        let lane = lane(&[(0.0, 0.0), (1000.0, 1.0)]);
+        let mut evaluator = AutomationEvaluator::new(48_000.0, None);
+        let mut out = vec![0.0; 100];
+        evaluator.process_block(&lane, 0, &mut out);
+
+        evaluator.reset();
+        evaluator.process_block(&lane, 500, &mut out);
+        assert_eq!(out[0], 0.5);
+    }
+}
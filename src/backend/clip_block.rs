@@ -0,0 +1,193 @@
+//! The block math for playing audio clips on the timeline.
+//!
+//! The timeline track in the engine processes audio in blocks, and the start and
+//! end of a clip rarely line up with the edges of a block. These helpers work
+//! out which frames of a block a clip writes to, so that clip onsets and tails
+//! are sample accurate no matter how the blocks are aligned.
+//!
+//! When the playhead loops or seeks inside a block, the block is split into
+//! contiguous segments, and each segment is handled on its own with `playhead`
+//! set to the timeline position of the segment's first frame and `out` sliced to
+//! the segment.
+
+/// The part of a block that an audio clip writes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClipBlockSpan {
+    /// The frame in the block where the clip starts writing.
+    pub out_offset: usize,
+    /// The frame of the clip's source audio that is written at `out_offset`.
+    pub source_frame: u64,
+    /// The number of frames to write.
+    pub len: usize,
+}
+
+/// Returns the part of a block that a clip writes to, or `None` if the clip is
+/// silent during the block.
+///
+/// * `playhead` - The frame on the timeline of the first frame in the block.
+/// * `block_frames` - The number of frames in the block.
+/// * `clip_start` - The frame on the timeline where the clip starts.
+/// * `clip_end` - The frame on the timeline where the clip ends (exclusive).
+/// * `source_offset` - The frame of the source audio that plays at `clip_start`
+/// (the clip start offset).
+/// * `source_len` - The number of frames in the source audio.
+pub fn clip_block_span(
+    playhead: u64,
+    block_frames: usize,
+    clip_start: u64,
+    clip_end: u64,
+    source_offset: u64,
+    source_len: u64,
+) -> Option<ClipBlockSpan> {
+    let block_end = playhead + block_frames as u64;
+    if clip_start >= block_end || clip_end <= playhead || clip_end <= clip_start {
+        return None;
+    }
+
+    // If the clip starts inside the block, the frames before its start are left
+    // alone instead of being filled from the start of the source.
+    let out_offset = clip_start.saturating_sub(playhead) as usize;
+    let first_frame = playhead.max(clip_start);
+
+    let source_frame = source_offset + (first_frame - clip_start);
+    if source_frame >= source_len {
+        return None;
+    }
+
+    // If the clip ends inside the block, it stops writing at its exact end
+    // instead of playing the source past it until the end of the block.
+    let len = (block_frames - out_offset)
+        .min((clip_end - first_frame) as usize)
+        .min((source_len - source_frame) as usize);
+
+    Some(ClipBlockSpan { out_offset, source_frame, len })
+}
+
+/// Adds the frames of `source` (one channel of the clip's source audio) covered
+/// by `span` to `out` (the same channel of the block), multiplied by `gain`.
+pub fn mix_clip_block(out: &mut [f32], source: &[f32], span: &ClipBlockSpan, gain: f32) {
+    let source_start = span.source_frame as usize;
+    let len = span
+        .len
+        .min(out.len().saturating_sub(span.out_offset))
+        .min(source.len().saturating_sub(source_start));
+
+    let out = &mut out[span.out_offset..span.out_offset + len];
+    let source = &source[source_start..source_start + len];
+    for (out, source) in out.iter_mut().zip(source.iter()) {
+        *out += *source * gain;
+    }
+}
+
+/// The regions of a track's timeline (in frames) that have clips on them,
+/// sorted and with overlapping regions merged.
+///
+/// This is built whenever the clips of a track change, so that the track can
+/// skip a block that no clip intersects with a single binary search.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClipIntervalIndex {
+    /// Non-overlapping `(start, end)` ranges sorted by start, where `end` is
+    /// exclusive.
+    intervals: Vec<(u64, u64)>,
+}
+
+impl ClipIntervalIndex {
+    /// Builds the index from the `(start, end)` frames of each clip, in any
+    /// order. Empty ranges are ignored.
+    pub fn new(mut regions: Vec<(u64, u64)>) -> Self {
+        regions.retain(|(start, end)| end > start);
+        regions.sort_unstable();
+
+        let mut intervals: Vec<(u64, u64)> = Vec::with_capacity(regions.len());
+        for (start, end) in regions {
+            match intervals.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => intervals.push((start, end)),
+            }
+        }
+
+        Self { intervals }
+    }
+
+    /// Returns `true` if any clip intersects the block of `block_frames` frames
+    /// that starts at frame `playhead`.
+    pub fn intersects(&self, playhead: u64, block_frames: usize) -> bool {
+        let block_end = playhead + block_frames as u64;
+
+        // The first interval that ends after the start of the block.
+        let i = self.intervals.partition_point(|(_, end)| *end <= playhead);
+        match self.intervals.get(i) {
+            Some((start, _)) => *start < block_end,
+            None => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the clip through consecutive blocks of `block_frames` frames, and
+    /// returns the first `num_frames` frames of the output.
+    fn render(
+        source: &[f32],
+        clip_start: u64,
+        clip_end: u64,
+        block_frames: usize,
+        num_frames: usize,
+    ) -> Vec<f32> {
+        let mut out = vec![0.0; num_frames];
+        for (i, block) in out.chunks_mut(block_frames).enumerate() {
+            let playhead = (i * block_frames) as u64;
+            let span = clip_block_span(
+                playhead,
+                block.len(),
+                clip_start,
+                clip_end,
+                0,
+                source.len() as u64,
+            );
+            if let Some(span) = span {
+                mix_clip_block(block, source, &span, 1.0);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn clips_start_at_the_exact_frame_inside_a_block() {
+        let span = clip_block_span(1024, 512, 1027, 5000, 0, 4000).unwrap();
+        assert_eq!(span, ClipBlockSpan { out_offset: 3, source_frame: 0, len: 509 });
+
+        let source = vec![1.0; 4000];
+        let out = render(&source, 1027, 5027, 512, 2048);
+        let onset = out.iter().position(|s| *s != 0.0);
+        assert_eq!(onset, Some(1027));
+        assert!(out[1027..].iter().all(|s| *s == 1.0));
+    }
+
+    #[test]
+    fn clips_stop_at_the_exact_frame_inside_a_block() {
+        let span = clip_block_span(768, 256, 0, 1001, 0, 4000).unwrap();
+        assert_eq!(span, ClipBlockSpan { out_offset: 0, source_frame: 768, len: 233 });
+
+        // The source is longer than the clip, so it would play on to the end
+        // of the block if the clip didn't stop at its end.
+        let source = vec![1.0; 4000];
+        let out = render(&source, 0, 1001, 256, 2048);
+        assert!(out[..1001].iter().all(|s| *s == 1.0));
+        assert!(out[1001..].iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn clips_stop_where_their_source_ends() {
+        let source = vec![1.0; 700];
+        let out = render(&source, 99, 1001, 256, 2048);
+        assert!(out[99..799].iter().all(|s| *s == 1.0));
+        assert!(out[799..].iter().all(|s| *s == 0.0));
+    }
+}
@@ -0,0 +1,184 @@
+//! A phase correlation meter for checking the mono compatibility of a stereo
+//! bus.
+//!
+//! The meter reads +1.0 when both channels carry the same signal (which sums to
+//! mono without loss), 0.0 when they are unrelated (i.e. a wide stereo reverb),
+//! and -1.0 when one channel is the inverse of the other (which cancels out when
+//! summed to mono). Readings that stay below 0.0 point to phase problems.
+//!
+//! The meter runs on the audio thread in the `TimelinePlayer`, after every block
+//! of the metered bus (the master output, or the output of one timeline
+//! track), and publishes its value through a `SharedCorrelation` that the UI
+//! reads.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// The time constant in seconds of the averaging of the meter. This is slow
+/// enough to read but still follows the changes between sections of a song.
+pub const CORRELATION_INTEGRATION_SECS: f64 = 0.3;
+
+/// Below this power (about -100 dBFS per channel), the bus is treated as silent.
+const SILENCE_POWER: f64 = 1e-10;
+
+/// Measures the phase correlation between the two channels of a stereo bus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorrelationMeter {
+    /// The averaged product of the two channels.
+    lr: f64,
+    /// The averaged power of the left channel.
+    ll: f64,
+    /// The averaged power of the right channel.
+    rr: f64,
+    /// The coefficient of the one-pole averaging filter.
+    coeff: f64,
+}
+
+impl CorrelationMeter {
+    pub fn new(sample_rate: f64) -> Self {
+        let coeff = (-1.0 / (CORRELATION_INTEGRATION_SECS * sample_rate)).exp();
+        Self { lr: 0.0, ll: 0.0, rr: 0.0, coeff }
+    }
+
+    /// Clears the history of the meter, i.e. when the metered bus changes.
+    pub fn reset(&mut self) {
+        self.lr = 0.0;
+        self.ll = 0.0;
+        self.rr = 0.0;
+    }
+
+    /// Adds a block of the bus to the measurement.
+    pub fn process(&mut self, left: &[f32], right: &[f32]) {
+        for (l, r) in left.iter().zip(right.iter()) {
+            self.add_frame(*l, *r);
+        }
+    }
+
+    /// Adds a block of an interleaved buffer with `num_channels` channels to
+    /// the measurement, where the first two channels are the bus. A mono
+    /// buffer is measured as both channels.
+    pub fn process_interleaved(&mut self, out: &[f32], num_channels: usize) {
+        if num_channels == 0 {
+            return;
+        }
+        for frame in out.chunks_exact(num_channels) {
+            self.add_frame(frame[0], frame[num_channels.min(2) - 1]);
+        }
+    }
+
+    /// Adds `num_frames` frames of silence to the measurement, i.e. while the
+    /// metered bus is skipped.
+    pub fn process_silence(&mut self, num_frames: usize) {
+        let decay = self.coeff.powi(num_frames.min(i32::MAX as usize) as i32);
+        self.lr *= decay;
+        self.ll *= decay;
+        self.rr *= decay;
+    }
+
+    fn add_frame(&mut self, l: f32, r: f32) {
+        // A NaN would stay in the averages forever. The output is cleaned up
+        // after the meter, so it is measured as silence here too.
+        let (l, r) =
+            if l.is_finite() && r.is_finite() { (f64::from(l), f64::from(r)) } else { (0.0, 0.0) };
+        let Self { lr, ll, rr, coeff } = self;
+        *lr = l * r + (*lr - l * r) * *coeff;
+        *ll = l * l + (*ll - l * l) * *coeff;
+        *rr = r * r + (*rr - r * r) * *coeff;
+    }
+
+    /// Returns the correlation in the range [-1.0, 1.0]. A silent bus reads
+    /// 0.0.
+    pub fn value(&self) -> f32 {
+        let power = (self.ll * self.rr).sqrt();
+        if power < SILENCE_POWER {
+            return 0.0;
+        }
+        (self.lr / power).clamp(-1.0, 1.0) as f32
+    }
+}
+
+/// The last value of a `CorrelationMeter`, shared between the audio thread and
+/// the rest of the program. Cloning this shares the same value.
+#[derive(Debug, Clone, Default)]
+pub struct SharedCorrelation {
+    /// The bits of the `f32` value.
+    value: Arc<AtomicU32>,
+}
+
+impl SharedCorrelation {
+    /// Called by the meter on the audio thread after every block.
+    pub fn set(&self, value: f32) {
+        self.value.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.value.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_nodes::sine;
+
+    fn noise(num_frames: usize, seed: u32) -> Vec<f32> {
+        let mut x = seed;
+        (0..num_frames)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as f32 / u32::MAX as f32 - 0.5
+            })
+            .collect()
+    }
+
+    fn measure(left: &[f32], right: &[f32]) -> f32 {
+        let mut meter = CorrelationMeter::new(48_000.0);
+        meter.process(left, right);
+        meter.value()
+    }
+
+    #[test]
+    fn identical_channels_read_plus_one() {
+        let sine = sine(48_000);
+        assert!((measure(&sine, &sine) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn inverted_channels_read_minus_one() {
+        let sine = sine(48_000);
+        let inverted: Vec<f32> = sine.iter().map(|s| -s).collect();
+        assert!((measure(&sine, &inverted) + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn independent_channels_read_about_zero() {
+        let value = measure(&noise(48_000, 1), &noise(48_000, 2));
+        assert!(value.abs() < 0.1, "{}", value);
+    }
+
+    #[test]
+    fn silence_reads_zero() {
+        let mut meter = CorrelationMeter::new(48_000.0);
+        assert_eq!(meter.value(), 0.0);
+
+        // Once a signal has faded out, the meter falls back to zero.
+        let sine = sine(4800);
+        meter.process(&sine, &sine);
+        meter.process_silence(48_000 * 10);
+        assert_eq!(meter.value(), 0.0);
+    }
+
+    #[test]
+    fn interleaved_buffers_are_measured_on_their_first_two_channels() {
+        let sine = sine(4800);
+        let mut out = Vec::new();
+        for s in sine.iter() {
+            out.extend_from_slice(&[*s, -*s, 0.0, f32::NAN]);
+        }
+        let mut meter = CorrelationMeter::new(48_000.0);
+        meter.process_interleaved(&out, 4);
+        assert!((meter.value() + 1.0).abs() < 1e-6);
+    }
+}
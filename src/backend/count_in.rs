@@ -0,0 +1,214 @@
+//! The count-in before recording.
+//!
+//! When recording starts with a count-in, the `TimelinePlayer` holds the
+//! playhead at the record start position and only plays the metronome click of
+//! the `CountIn`, and then starts playing exactly on the downbeat. The count-in
+//! and the recording share the same start of the transport, and the recorder
+//! only keeps the input that lines up with frames where the transport moved
+//! (see `backend::transport_clock`). So the input captured during the count-in
+//! is thrown away, and the first recorded frame lands on the record start
+//! position as well. The count-in is counted in frames rather than in blocks,
+//! so this lines up no matter how the blocks are aligned.
+
+/// The length of a metronome click in seconds.
+pub const CLICK_SECS: f64 = 0.02;
+
+/// The peak level of a metronome click.
+const CLICK_GAIN: f32 = 0.5;
+
+/// The pitch of the click on the first beat of a bar.
+const ACCENT_HZ: f64 = 1500.0;
+
+/// The pitch of the click on the other beats.
+const BEAT_HZ: f64 = 1000.0;
+
+/// Returns the length in frames of a count-in of `bars` bars of `bar_beats`
+/// beats at `bpm`.
+pub fn count_in_frames(bars: u8, bar_beats: f64, bpm: f64, sample_rate: f64) -> u64 {
+    let secs = f64::from(bars) * bar_beats * 60.0 / bpm.max(f64::EPSILON);
+    (secs * sample_rate).round().max(0.0) as u64
+}
+
+/// Counts down the frames of a count-in on the audio thread, and plays the
+/// metronome during it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CountIn {
+    total_frames: u64,
+    frames_left: u64,
+    /// The length of a beat in frames.
+    beat_frames: f64,
+    bar_beats: f64,
+    sample_rate: f64,
+}
+
+impl CountIn {
+    /// Creates a count-in of `bars` bars of `bar_beats` beats at `bpm`.
+    pub fn new(bars: u8, bar_beats: f64, bpm: f64, sample_rate: f64) -> Self {
+        let total_frames = count_in_frames(bars, bar_beats, bpm, sample_rate);
+        Self {
+            total_frames,
+            frames_left: total_frames,
+            beat_frames: (60.0 / bpm.max(f64::EPSILON) * sample_rate).max(1.0),
+            bar_beats,
+            sample_rate,
+        }
+    }
+
+    /// Returns `true` while the count-in is still running.
+    pub fn is_counting(&self) -> bool {
+        self.frames_left > 0
+    }
+
+    /// The length of the count-in in frames.
+    pub fn total_frames(&self) -> u64 {
+        self.total_frames
+    }
+
+    /// The number of frames of the count-in that have played.
+    pub fn elapsed_frames(&self) -> u64 {
+        self.total_frames - self.frames_left
+    }
+
+    /// Advances the count-in by a block of `block_frames` frames. Returns the
+    /// number of frames at the start of the block that still belong to the
+    /// count-in.
+    ///
+    /// The transport starts moving at that offset in the block, or in a later
+    /// block if the whole block belongs to the count-in.
+    pub fn process_block(&mut self, block_frames: usize) -> usize {
+        let count_in = self.frames_left.min(block_frames as u64);
+        self.frames_left -= count_in;
+        count_in as usize
+    }
+
+    /// Like `process_block()`, but also adds the metronome clicks of the
+    /// count-in to the first two channels of the interleaved `out` buffer with
+    /// `num_channels` channels.
+    pub fn process_interleaved(&mut self, out: &mut [f32], num_channels: usize) -> usize {
+        if num_channels == 0 {
+            return 0;
+        }
+        let start = self.elapsed_frames();
+        let count_in = self.process_block(out.len() / num_channels);
+        for (i, frame) in out.chunks_exact_mut(num_channels).take(count_in).enumerate() {
+            let click = self.click_at(start + i as u64);
+            for out in frame.iter_mut().take(2) {
+                *out += click;
+            }
+        }
+        count_in
+    }
+
+    /// Stops the count-in, i.e. when the transport is stopped during it. The
+    /// transport then doesn't start moving, and nothing is recorded.
+    pub fn cancel(&mut self) {
+        self.total_frames = 0;
+        self.frames_left = 0;
+    }
+
+    /// The sample of the metronome at the frame `frame` of the count-in. Every
+    /// beat starts with a short decaying sine, which is higher on the first
+    /// beat of a bar.
+    fn click_at(&self, frame: u64) -> f32 {
+        let beat = ((frame as f64 + 0.5) / self.beat_frames).floor();
+        let offset = frame as f64 - (beat * self.beat_frames).round();
+        let click_frames = CLICK_SECS * self.sample_rate;
+        if offset < 0.0 || offset >= click_frames {
+            return 0.0;
+        }
+
+        let hz = if beat % self.bar_beats.max(1.0) < 1.0 { ACCENT_HZ } else { BEAT_HZ };
+        let envelope = 1.0 - offset / click_frames;
+        let phase = offset / self.sample_rate * hz * std::f64::consts::TAU;
+        (phase.sin() * envelope) as f32 * CLICK_GAIN
+    }
+}
+
+impl Default for CountIn {
+    /// A count-in that is not running.
+    fn default() -> Self {
+        Self::new(0, 4.0, 120.0, 48_000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a count-in in blocks of `block_frames` frames, and returns the
+    /// frame where the transport starts moving.
+    fn first_played_frame(mut count_in: CountIn, block_frames: usize) -> u64 {
+        let mut frame = 0;
+        loop {
+            let counted = count_in.process_block(block_frames);
+            if counted < block_frames {
+                return frame + counted as u64;
+            }
+            frame += block_frames as u64;
+        }
+    }
+
+    #[test]
+    fn playback_starts_right_after_the_count_in_at_any_block_size() {
+        for sample_rate in [44_100.0, 48_000.0, 96_000.0] {
+            for bpm in [60.0, 97.3, 120.0, 174.0] {
+                for (bars, bar_beats) in [(1, 4.0), (2, 3.0), (4, 3.5)] {
+                    let count_in = CountIn::new(bars, bar_beats, bpm, sample_rate);
+                    let expected = count_in_frames(bars, bar_beats, bpm, sample_rate);
+                    assert_eq!(count_in.total_frames(), expected);
+
+                    for block_frames in (1..=4096).step_by(97).chain([1, 2, 64, 511, 4096]) {
+                        assert_eq!(
+                            first_played_frame(count_in, block_frames),
+                            expected,
+                            "{} Hz, {} bpm, {} bars, blocks of {}",
+                            sample_rate,
+                            bpm,
+                            bars,
+                            block_frames
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn count_in_length() {
+        // Two bars of 4/4 at 120 bpm are four seconds.
+        assert_eq!(count_in_frames(2, 4.0, 120.0, 48_000.0), 192_000);
+        assert_eq!(count_in_frames(0, 4.0, 120.0, 48_000.0), 0);
+        assert!(!CountIn::new(0, 4.0, 120.0, 48_000.0).is_counting());
+    }
+
+    #[test]
+    fn clicks_on_every_beat_and_nowhere_else() {
+        // One bar of 4/4 at 120 bpm, so a beat is 24000 frames.
+        let mut count_in = CountIn::new(1, 4.0, 120.0, 48_000.0);
+        let mut out = vec![0.0; 100_000 * 2];
+        for block in out.chunks_mut(300 * 2) {
+            count_in.process_interleaved(block, 2);
+        }
+
+        let left: Vec<f32> = out.iter().step_by(2).copied().collect();
+        let click_frames = (CLICK_SECS * 48_000.0) as usize;
+        for beat in 0..4 {
+            let start = beat * 24_000;
+            assert!(left[start..start + click_frames].iter().any(|s| s.abs() > 0.1));
+            assert!(left[start + click_frames + 1..start + 24_000].iter().all(|s| *s == 0.0));
+        }
+        // Nothing is added after the count-in.
+        assert!(left[96_000..].iter().all(|s| *s == 0.0));
+        assert_eq!(out.chunks_exact(2).filter(|f| f[0] != f[1]).count(), 0);
+    }
+
+    #[test]
+    fn cancelling_stops_the_count_in() {
+        let mut count_in = CountIn::new(1, 4.0, 120.0, 48_000.0);
+        assert_eq!(count_in.process_block(1000), 1000);
+        assert_eq!(count_in.elapsed_frames(), 1000);
+        count_in.cancel();
+        assert!(!count_in.is_counting());
+        assert_eq!(count_in.process_block(1000), 0);
+    }
+}
@@ -0,0 +1,379 @@
+//! Decoding of audio files (WAV, AIFF, FLAC, OGG/Vorbis, and MP3) using
+//! symphonia.
+//!
+//! The engine's resource loader only understands a few formats, and its errors
+//! don't say what went wrong. This is used to analyze files offline and to tell
+//! the user why a file could not be loaded.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{
+    CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_MP3, CODEC_TYPE_NULL, CODEC_TYPE_VORBIS,
+};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Files with more channels than this are rejected.
+pub const MAX_DECODE_CHANNELS: usize = 32;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    /// The container format was not recognized.
+    UnsupportedFormat,
+    /// The file has no audio track.
+    NoAudioTrack,
+    UnsupportedCodec {
+        codec: String,
+    },
+    UnsupportedChannelCount {
+        channels: usize,
+    },
+    /// The file is damaged or truncated. `at_frame` is the number of frames that
+    /// could be decoded before the damaged part.
+    CorruptFile {
+        at_frame: u64,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "{}", e),
+            DecodeError::UnsupportedFormat => write!(
+                f,
+                "The file format is not supported. Supported formats are WAV, AIFF, FLAC, OGG, and MP3."
+            ),
+            DecodeError::NoAudioTrack => write!(f, "The file does not contain any audio."),
+            DecodeError::UnsupportedCodec { codec } => write!(
+                f,
+                "The audio is encoded with {}, which is not supported. Try converting the file to WAV or FLAC.",
+                codec
+            ),
+            DecodeError::UnsupportedChannelCount { channels } => write!(
+                f,
+                "The file has {} channels, but only 1 to {} channels are supported.",
+                channels, MAX_DECODE_CHANNELS
+            ),
+            DecodeError::CorruptFile { at_frame } => write!(
+                f,
+                "The file is damaged or incomplete after frame {}. Try exporting it again.",
+                at_frame
+            ),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> Self {
+        DecodeError::Io(e)
+    }
+}
+
+/// Information about an audio file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioFileInfo {
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// This is `None` for lossy codecs.
+    pub bit_depth: Option<u32>,
+    /// The length of the file in frames. This is counted from the packets in the
+    /// file, so it is exact even for VBR MP3s.
+    pub frames: u64,
+    /// A short name of the codec (i.e. "flac").
+    pub codec: String,
+}
+
+/// A decoded audio file.
+#[derive(Debug, Clone)]
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    /// One buffer per channel.
+    pub buffers: Vec<Vec<f32>>,
+}
+
+/// Reads the information of the audio file at `path` without decoding it.
+pub fn scan_file(path: &Path) -> Result<AudioFileInfo, DecodeError> {
+    let (mut format, track_id, params) = open(path)?;
+
+    // Make sure the codec is supported, even though nothing is decoded.
+    make_decoder(&params)?;
+
+    let channels = params.channels.map(|c| c.count()).unwrap_or(0);
+    check_channel_count(channels)?;
+
+    // The frame count in the header of VBR MP3s is only an estimate, so count
+    // the frames of every packet instead.
+    let mut frames = 0u64;
+    loop {
+        match format.next_packet() {
+            Ok(packet) => {
+                if packet.track_id() == track_id {
+                    let trim = u64::from(packet.trim_start) + u64::from(packet.trim_end);
+                    frames += packet.dur.saturating_sub(trim);
+                }
+            }
+            Err(e) if is_end_of_stream(&e) => break,
+            Err(e) => return Err(map_error(e, frames)),
+        }
+    }
+    check_truncated(&params, frames)?;
+
+    Ok(AudioFileInfo {
+        sample_rate: params.sample_rate.unwrap_or(0),
+        channels,
+        bit_depth: params.bits_per_sample,
+        frames,
+        codec: codec_name(&params),
+    })
+}
+
+/// Decodes the whole audio file at `path`.
+pub fn decode_file(path: &Path) -> Result<DecodedAudio, DecodeError> {
+    let (mut format, track_id, params) = open(path)?;
+    let mut decoder = make_decoder(&params)?;
+
+    let mut buffers: Vec<Vec<f32>> = Vec::new();
+    let mut frames = 0u64;
+    let mut sample_rate = params.sample_rate.unwrap_or(0);
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(e) if is_end_of_stream(&e) => break,
+            Err(e) => return Err(map_error(e, frames)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(e) => return Err(map_error(e, frames)),
+        };
+
+        let spec = *decoded.spec();
+        let num_channels = spec.channels.count();
+        if buffers.is_empty() {
+            check_channel_count(num_channels)?;
+            buffers = vec![Vec::new(); num_channels];
+            sample_rate = spec.rate;
+        } else if buffers.len() != num_channels {
+            return Err(DecodeError::CorruptFile { at_frame: frames });
+        }
+
+        let num_frames = decoded.frames();
+        let mut sample_buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        sample_buffer.copy_planar_ref(decoded);
+        let samples = sample_buffer.samples();
+        for (channel, buffer) in buffers.iter_mut().enumerate() {
+            buffer.extend_from_slice(&samples[channel * num_frames..(channel + 1) * num_frames]);
+        }
+
+        frames += num_frames as u64;
+    }
+
+    if buffers.is_empty() {
+        return Err(DecodeError::NoAudioTrack);
+    }
+    check_truncated(&params, frames)?;
+
+    Ok(DecodedAudio { sample_rate, buffers })
+}
+
+/// Mixes `buffers` with more than two channels down to stereo.
+///
+/// Six channels are treated as 5.1 (L, R, C, LFE, Ls, Rs), where the center and
+/// surround channels are mixed in at -3 dB and the LFE channel is dropped. For
+/// any other layout, the extra channels are alternately mixed into the left and
+/// right channel at -3 dB.
+pub fn downmix_to_stereo(buffers: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    if buffers.len() <= 2 {
+        return buffers.to_vec();
+    }
+
+    const MINUS_3_DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    let len = buffers.iter().map(|b| b.len()).min().unwrap_or(0);
+    let mut left = buffers[0][0..len].to_vec();
+    let mut right = buffers[1][0..len].to_vec();
+
+    let mut mix = |buffer: &[f32], gain_l: f32, gain_r: f32| {
+        for i in 0..len {
+            left[i] += buffer[i] * gain_l;
+            right[i] += buffer[i] * gain_r;
+        }
+    };
+
+    if buffers.len() == 6 {
+        mix(&buffers[2], MINUS_3_DB, MINUS_3_DB);
+        mix(&buffers[4], MINUS_3_DB, 0.0);
+        mix(&buffers[5], 0.0, MINUS_3_DB);
+    } else {
+        for (i, buffer) in buffers.iter().enumerate().skip(2) {
+            if i % 2 == 0 {
+                mix(buffer, MINUS_3_DB, 0.0);
+            } else {
+                mix(buffer, 0.0, MINUS_3_DB);
+            }
+        }
+    }
+
+    vec![left, right]
+}
+
+/// Sums the two channels of a stereo signal into one, at -3 dB so that the
+/// power of uncorrelated channels stays the same.
+pub fn sum_stereo_to_mono(left: &[f32], right: &[f32]) -> Vec<f32> {
+    const MINUS_3_DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    left.iter().zip(right.iter()).map(|(l, r)| (l + r) * MINUS_3_DB).collect()
+}
+
+/// Copies a mono signal into both channels of a stereo signal at -3 dB, so that
+/// summing it back with `sum_stereo_to_mono()` gives the original signal.
+pub fn mono_to_stereo(buffer: &[f32]) -> Vec<Vec<f32>> {
+    const MINUS_3_DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    let channel: Vec<f32> = buffer.iter().map(|s| s * MINUS_3_DB).collect();
+    vec![channel.clone(), channel]
+}
+
+/// Resamples `buffer` from `from_rate` to `to_rate` with linear interpolation.
+///
+/// This is only meant for offline analysis.
+pub fn resample_linear(buffer: &[f32], from_rate: f64, to_rate: f64) -> Vec<f32> {
+    if buffer.is_empty() || from_rate <= 0.0 || (from_rate - to_rate).abs() < f64::EPSILON {
+        return buffer.to_vec();
+    }
+
+    let len = (buffer.len() as f64 * to_rate / from_rate).round() as usize;
+    let step = from_rate / to_rate;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let index = pos as usize;
+            let t = (pos - index as f64) as f32;
+            let a = buffer[index.min(buffer.len() - 1)];
+            let b = buffer[(index + 1).min(buffer.len() - 1)];
+            a + (b - a) * t
+        })
+        .collect()
+}
+
+fn open(path: &Path) -> Result<(Box<dyn FormatReader>, u32, CodecParameters), DecodeError> {
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| match e {
+            SymphoniaError::IoError(e) if e.kind() != io::ErrorKind::UnexpectedEof => {
+                DecodeError::Io(e)
+            }
+            _ => DecodeError::UnsupportedFormat,
+        })?;
+    let format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(DecodeError::NoAudioTrack)?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    Ok((format, track_id, params))
+}
+
+fn make_decoder(params: &CodecParameters) -> Result<Box<dyn Decoder>, DecodeError> {
+    symphonia::default::get_codecs()
+        .make(params, &DecoderOptions::default())
+        .map_err(|_| DecodeError::UnsupportedCodec { codec: codec_name(params) })
+}
+
+fn codec_name(params: &CodecParameters) -> String {
+    match symphonia::default::get_codecs().get_codec(params.codec) {
+        Some(descriptor) => descriptor.short_name.to_string(),
+        None => format!("an unknown codec ({:?})", params.codec),
+    }
+}
+
+fn check_channel_count(channels: usize) -> Result<(), DecodeError> {
+    if channels == 0 || channels > MAX_DECODE_CHANNELS {
+        Err(DecodeError::UnsupportedChannelCount { channels })
+    } else {
+        Ok(())
+    }
+}
+
+/// The end of the stream and a truncated file look the same to symphonia, so
+/// compare the number of frames with the header, but only for the codecs where
+/// the header is exact.
+fn check_truncated(params: &CodecParameters, frames: u64) -> Result<(), DecodeError> {
+    if params.codec == CODEC_TYPE_MP3 || params.codec == CODEC_TYPE_VORBIS {
+        return Ok(());
+    }
+    match params.n_frames {
+        Some(expected) if frames < expected => Err(DecodeError::CorruptFile { at_frame: frames }),
+        _ => Ok(()),
+    }
+}
+
+fn is_end_of_stream(e: &SymphoniaError) -> bool {
+    match e {
+        SymphoniaError::IoError(e) => e.kind() == io::ErrorKind::UnexpectedEof,
+        // A chained OGG stream starts a new track, which is not supported.
+        SymphoniaError::ResetRequired => true,
+        _ => false,
+    }
+}
+
+fn map_error(e: SymphoniaError, at_frame: u64) -> DecodeError {
+    match e {
+        SymphoniaError::IoError(e) if e.kind() != io::ErrorKind::UnexpectedEof => {
+            DecodeError::Io(e)
+        }
+        SymphoniaError::Unsupported(what) => DecodeError::UnsupportedCodec { codec: what.into() },
+        _ => DecodeError::CorruptFile { at_frame },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summing_a_forced_stereo_clip_to_mono_gives_the_source() {
+        let source: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.01).sin() * 0.9).collect();
+
+        let stereo = mono_to_stereo(&source);
+        assert_eq!(stereo.len(), 2);
+        assert_eq!(stereo[0], stereo[1]);
+        // Each channel is at -3 dB.
+        for (s, source) in stereo[0].iter().zip(source.iter()) {
+            assert!((s - source * std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        }
+
+        let mono = sum_stereo_to_mono(&stereo[0], &stereo[1]);
+        assert_eq!(mono.len(), source.len());
+        for (mono, source) in mono.iter().zip(source.iter()) {
+            assert!((mono - source).abs() < 1e-6, "{} != {}", mono, source);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+/// The number of bits of precision in the engine's internal `f32` samples.
+///
+/// Reducing to a bit depth at or above this doesn't lose any precision, so no
+/// dither is applied.
+pub const FLOAT_PRECISION_BITS: u32 = 24;
+
+/// The seed that is used when none is given.
+pub const DEFAULT_DITHER_SEED: u32 = 0x1234_5678;
+
+/// Settings for reducing the bit depth of rendered audio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DitherConfig {
+    /// Whether to apply TPDF dither at all. If this is false, samples are only
+    /// rounded.
+    pub enabled: bool,
+
+    /// Whether to apply first-order noise shaping, which pushes the dither
+    /// noise towards higher frequencies.
+    pub noise_shaping: bool,
+
+    /// The seed of the noise generator. Two renders with the same seed and
+    /// the same input produce identical output.
+    pub seed: u32,
+}
+
+impl Default for DitherConfig {
+    fn default() -> Self {
+        Self { enabled: true, noise_shaping: false, seed: DEFAULT_DITHER_SEED }
+    }
+}
+
+/// Converts `f32` samples to integer samples of a lower bit depth.
+///
+/// The noise is generated with a seeded xorshift generator instead of a system
+/// RNG so that renders are reproducible.
+pub struct Ditherer {
+    config: DitherConfig,
+    bits: u32,
+    scale: f32,
+    max: i32,
+    min: i32,
+    rng_state: u32,
+    /// The quantization error of the previous sample in each channel (used for
+    /// noise shaping).
+    prev_error: Vec<f32>,
+}
+
+impl Ditherer {
+    /// Creates a new ditherer for `num_channels` channels of `bits`-bit
+    /// integer output (i.e. 16 or 24).
+    pub fn new(config: DitherConfig, bits: u32, num_channels: usize) -> Self {
+        let bits = bits.clamp(2, 32);
+        let scale = (1u64 << (bits - 1)) as f32;
+        let max = ((1u64 << (bits - 1)) - 1) as i32;
+        let min = -max - 1;
+
+        Self {
+            config,
+            bits,
+            scale,
+            max,
+            min,
+            rng_state: initial_rng_state(config.seed),
+            prev_error: vec![0.0; num_channels],
+        }
+    }
+
+    /// Returns `true` if dither is applied for this bit depth.
+    pub fn is_dithering(&self) -> bool {
+        self.config.enabled && self.bits < FLOAT_PRECISION_BITS
+    }
+
+    /// Converts `sample` in the range `[-1.0, 1.0]` on the given `channel` to
+    /// an integer sample, clamping anything outside of that range.
+    pub fn process(&mut self, sample: f32, channel: usize) -> i32 {
+        let mut value = sample * self.scale;
+
+        if !self.is_dithering() {
+            return (value.round() as i32).clamp(self.min, self.max);
+        }
+
+        if self.config.noise_shaping {
+            value -= self.prev_error[channel];
+        }
+
+        // Two uniform values in the range `[-0.5, 0.5)` LSB added together
+        // give a triangular distribution in the range `[-1.0, 1.0)` LSB.
+        let noise = self.next_uniform() + self.next_uniform();
+
+        let quantized = (value + noise).round().clamp(self.min as f32, self.max as f32);
+
+        if self.config.noise_shaping {
+            self.prev_error[channel] = quantized - value;
+        }
+
+        quantized as i32
+    }
+
+    /// Resets the generator and the noise shaping state so that the next
+    /// render produces the same output as the first one.
+    pub fn reset(&mut self) {
+        self.rng_state = initial_rng_state(self.config.seed);
+        for e in self.prev_error.iter_mut() {
+            *e = 0.0;
+        }
+    }
+
+    /// Returns a uniformly distributed value in the range `[-0.5, 0.5)`.
+    fn next_uniform(&mut self) -> f32 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+
+        (x as f64 / 4_294_967_296.0 - 0.5) as f32
+    }
+}
+
+fn initial_rng_state(seed: u32) -> u32 {
+    // Xorshift gets stuck on a state of 0.
+    if seed == 0 {
+        DEFAULT_DITHER_SEED
+    } else {
+        seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A slow sine of 10 LSB at 16 bits, sampled `num_frames` times.
+    fn quiet_sine(num_frames: usize) -> Vec<f32> {
+        (0..num_frames).map(|i| (i as f32 * 0.001).sin() * 10.0 / 32_768.0).collect()
+    }
+
+    /// The difference in LSB between the dithered output and the input.
+    fn errors(config: DitherConfig, input: &[f32]) -> Vec<f32> {
+        let mut ditherer = Ditherer::new(config, 16, 1);
+        input.iter().map(|s| ditherer.process(*s, 0) as f32 - s * 32_768.0).collect()
+    }
+
+    /// The average energy of `errors` in blocks of `len` samples summed up,
+    /// which is a crude lowpass whose output is small for noise that
+    /// alternates quickly.
+    fn low_band_energy(errors: &[f32], len: usize) -> f32 {
+        let sums: Vec<f32> = errors.chunks_exact(len).map(|c| c.iter().sum::<f32>()).collect();
+        sums.iter().map(|s| s * s / len as f32).sum::<f32>() / sums.len() as f32
+    }
+
+    fn energy(errors: &[f32]) -> f32 {
+        errors.iter().map(|e| e * e).sum::<f32>() / errors.len() as f32
+    }
+
+    #[test]
+    fn tpdf_noise_stays_within_one_lsb() {
+        let mut ditherer = Ditherer::new(DitherConfig::default(), 16, 1);
+        let noise: Vec<f32> =
+            (0..100_000).map(|_| ditherer.next_uniform() + ditherer.next_uniform()).collect();
+        assert!(noise.iter().all(|n| (-1.0..1.0).contains(n)));
+        // A triangular distribution over [-1, 1) has a variance of 1/6.
+        assert!((energy(&noise) - 1.0 / 6.0).abs() < 0.01, "{}", energy(&noise));
+
+        // Samples that fall on a step are never moved by more than one step.
+        ditherer.reset();
+        for step in -100..100 {
+            let out = ditherer.process(step as f32 / 32_768.0, 0);
+            assert!((out - step).abs() <= 1, "{} -> {}", step, out);
+        }
+    }
+
+    #[test]
+    fn bit_depths_with_float_precision_are_not_dithered() {
+        let input = quiet_sine(10_000);
+        for bits in [FLOAT_PRECISION_BITS, 32] {
+            let config = DitherConfig { noise_shaping: true, ..DitherConfig::default() };
+            let mut ditherer = Ditherer::new(config, bits, 1);
+            assert!(!ditherer.is_dithering());
+
+            let scale = (1u64 << (bits - 1)) as f32;
+            for s in input.iter() {
+                assert_eq!(ditherer.process(*s, 0), (s * scale).round() as i32);
+            }
+        }
+        assert!(Ditherer::new(DitherConfig::default(), 16, 1).is_dithering());
+    }
+
+    #[test]
+    fn noise_shaping_moves_the_noise_up() {
+        let input = quiet_sine(100_000);
+        let flat = errors(DitherConfig::default(), &input);
+        let shaped =
+            errors(DitherConfig { noise_shaping: true, ..DitherConfig::default() }, &input);
+
+        // First-order shaping cancels most of the noise below the cutoff of
+        // the lowpass, and makes up for it above.
+        let (flat_low, shaped_low) = (low_band_energy(&flat, 16), low_band_energy(&shaped, 16));
+        assert!(shaped_low < flat_low / 4.0, "{} vs {}", shaped_low, flat_low);
+        assert!(energy(&shaped) > energy(&flat));
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_output() {
+        let input = quiet_sine(1000);
+        let config = DitherConfig { noise_shaping: true, ..DitherConfig::default() };
+        assert_eq!(errors(config, &input), errors(config, &input));
+        let other_seed = DitherConfig { seed: 7, ..config };
+        assert_ne!(errors(config, &input), errors(other_seed, &input));
+    }
+}
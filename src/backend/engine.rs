@@ -0,0 +1,39 @@
+use crossbeam::channel::Receiver;
+use dropseed::plugin::HostInfo;
+use dropseed::{ActivateEngineSettings, DSEngineEvent, DSEngineHandle};
+use dropseed_sample_browser_plug::SampleBrowserPlugFactory;
+use meadowlark_core_types::time::SampleRate;
+
+// TODO: Have these be configurable.
+pub const MIN_FRAMES: u32 = 1;
+pub const MAX_FRAMES: u32 = 512;
+pub const GRAPH_IN_CHANNELS: u16 = 2;
+pub const GRAPH_OUT_CHANNELS: u16 = 2;
+
+/// Creates a new engine along with its internal plugins.
+///
+/// This does not need a system audio stream. The engine's audio thread is sent
+/// back in `DSEngineEvent::EngineActivated` once it is activated with
+/// `activate_settings()`, and it is up to the caller to drive it.
+pub fn spawn_engine() -> (DSEngineHandle, Receiver<DSEngineEvent>) {
+    let (engine_handle, engine_rx) = DSEngineHandle::new(
+        HostInfo::new(String::from("RustyDAW integration test"), String::from("0.1.0"), None, None),
+        vec![Box::new(SampleBrowserPlugFactory)],
+    );
+
+    log::debug!("{:?}", &engine_handle.internal_plugins_res);
+
+    (engine_handle, engine_rx)
+}
+
+/// The settings to activate the engine with at the given sample rate.
+pub fn activate_settings(sample_rate: SampleRate) -> ActivateEngineSettings {
+    ActivateEngineSettings {
+        sample_rate,
+        min_frames: MIN_FRAMES,
+        max_frames: MAX_FRAMES,
+        num_audio_in_channels: GRAPH_IN_CHANNELS,
+        num_audio_out_channels: GRAPH_OUT_CHANNELS,
+        ..ActivateEngineSettings::default()
+    }
+}
@@ -0,0 +1,319 @@
+use std::error::Error;
+use std::time::Duration;
+
+use crossbeam::channel::Receiver;
+use dropseed::{DSEngineAudioThread, DSEngineEvent, DSEngineHandle, DSEngineRequest};
+use meadowlark_core_types::time::SampleRate;
+
+use super::engine::{self, GRAPH_OUT_CHANNELS, MAX_FRAMES};
+#[cfg(test)]
+use super::test_nodes::TestNode;
+use super::timeline::{self, TimelineHandle, TimelinePlayer};
+
+/// An engine that runs without a system audio stream or a UI.
+///
+/// Blocks are processed by calling `process()` directly, which is useful for
+/// automated tests and for rendering on a server. The full audio graph is run,
+/// just like when it is driven by a system audio stream, and the timeline is
+/// played after it. In tests, a chain of `TestNode`s runs last.
+pub struct HeadlessEngine {
+    pub ds_handle: DSEngineHandle,
+    engine_rx: Receiver<DSEngineEvent>,
+    audio_thread: Option<DSEngineAudioThread>,
+    sample_rate: SampleRate,
+    timeline: TimelineHandle,
+    timeline_player: TimelinePlayer,
+    #[cfg(test)]
+    test_nodes: Vec<Box<dyn TestNode>>,
+}
+
+impl HeadlessEngine {
+    /// Creates and activates a new engine at the given sample rate.
+    ///
+    /// This blocks until the engine is activated or `timeout` has passed.
+    pub fn new(sample_rate: SampleRate, timeout: Duration) -> Result<Self, Box<dyn Error>> {
+        let (mut ds_handle, engine_rx) = engine::spawn_engine();
+
+        ds_handle.send(DSEngineRequest::ActivateEngine(Box::new(engine::activate_settings(
+            sample_rate,
+        ))));
+
+        let (timeline, timeline_player) = timeline::timeline(sample_rate.0);
+        let mut new_self = Self {
+            ds_handle,
+            engine_rx,
+            audio_thread: None,
+            sample_rate,
+            timeline,
+            timeline_player,
+            #[cfg(test)]
+            test_nodes: Vec::new(),
+        };
+
+        while new_self.audio_thread.is_none() {
+            match new_self.engine_rx.recv_timeout(timeout)? {
+                DSEngineEvent::EngineActivated(event) => {
+                    new_self.audio_thread = Some(event.audio_thread);
+                }
+                event => log::debug!("{:?}", event),
+            }
+        }
+
+        Ok(new_self)
+    }
+
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
+    /// The handle of the timeline player. Messages sent to it are applied at
+    /// the start of the next block.
+    pub fn timeline(&mut self) -> &mut TimelineHandle {
+        &mut self.timeline
+    }
+
+    pub fn num_out_channels(&self) -> usize {
+        usize::from(GRAPH_OUT_CHANNELS)
+    }
+
+    /// Returns all of the events the engine has sent since the last call.
+    pub fn poll_events(&mut self) -> Vec<DSEngineEvent> {
+        self.engine_rx.try_iter().collect()
+    }
+
+    /// Processes the next block of audio into the interleaved `out` buffer.
+    ///
+    /// The length of `out` must be a multiple of `num_out_channels()`.
+    pub fn process(&mut self, out: &mut [f32]) {
+        let num_out_channels = self.num_out_channels();
+        if let Some(audio_thread) = &mut self.audio_thread {
+            audio_thread.process_cpal_interleaved_output_only(num_out_channels, out);
+        } else {
+            out.fill(0.0);
+        }
+
+        self.timeline_player.process_interleaved(out, num_out_channels);
+
+        #[cfg(test)]
+        self.process_test_nodes(out);
+    }
+
+    /// Adds `node` to the end of the chain of test nodes.
+    #[cfg(test)]
+    pub fn push_test_node(&mut self, node: impl TestNode + 'static) {
+        self.test_nodes.push(Box::new(node));
+    }
+
+    #[cfg(test)]
+    fn process_test_nodes(&mut self, out: &mut [f32]) {
+        if self.test_nodes.is_empty() {
+            return;
+        }
+        let num_out_channels = self.num_out_channels();
+        let mut buffers: Vec<Vec<f32>> = (0..num_out_channels)
+            .map(|channel| out.iter().skip(channel).step_by(num_out_channels).copied().collect())
+            .collect();
+        for node in self.test_nodes.iter_mut() {
+            node.process(&mut buffers);
+        }
+        for (i, frame) in out.chunks_exact_mut(num_out_channels).enumerate() {
+            for (out, buffer) in frame.iter_mut().zip(buffers.iter()) {
+                *out = buffer[i];
+            }
+        }
+    }
+
+    /// Renders the next `num_frames` frames into one buffer per channel (the
+    /// layout `wav_export::write_wav()` expects).
+    ///
+    /// The frames are always processed in blocks of `MAX_FRAMES`, and engine
+    /// events are not polled during the render, so rendering the same graph
+    /// twice gives identical output. Use this to compare a render against a
+    /// golden file.
+    pub fn render(&mut self, num_frames: usize) -> Vec<Vec<f32>> {
+        let num_out_channels = self.num_out_channels();
+        let mut out = vec![Vec::with_capacity(num_frames); num_out_channels];
+        let mut block = vec![0.0; MAX_FRAMES as usize * num_out_channels];
+
+        let mut frames_rendered = 0;
+        while frames_rendered < num_frames {
+            let frames = (num_frames - frames_rendered).min(MAX_FRAMES as usize);
+            let block = &mut block[0..frames * num_out_channels];
+
+            self.process(block);
+
+            for frame in block.chunks_exact(num_out_channels) {
+                for (channel, sample) in out.iter_mut().zip(frame.iter()) {
+                    channel.push(*sample);
+                }
+            }
+            frames_rendered += frames;
+        }
+
+        out
+    }
+}
+
+/// A minimal example that runs the engine headlessly for `seconds` and logs
+/// the peak of the output.
+pub fn run_headless_example(seconds: f64) -> Result<(), Box<dyn Error>> {
+    let mut engine = HeadlessEngine::new(SampleRate(48_000.0), Duration::from_secs(5))?;
+
+    let total_frames = (seconds * engine.sample_rate().0).round() as usize;
+
+    let out = engine.render(total_frames);
+    let peak = out.iter().flatten().fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+    log::info!("Processed {} frames headlessly, peak output: {}", total_frames, peak);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::test_nodes::{CaptureNode, GainNode, ToneNode, Unwarped};
+    use crate::backend::timeline::{TimelineClip, TimelineMsg, TimelineTrack, TrackClips};
+
+    const SAMPLE_RATE: SampleRate = SampleRate(48_000.0);
+    /// A tone with exactly 64 frames per period.
+    const TONE_HZ: f64 = 750.0;
+    const TONE_AMPLITUDE: f32 = 0.5;
+
+    fn engine() -> HeadlessEngine {
+        HeadlessEngine::new(SAMPLE_RATE, Duration::from_secs(5)).unwrap()
+    }
+
+    /// A clip with a sine tone of `TONE_HZ` that lasts `num_frames` frames.
+    fn tone(num_frames: usize) -> TimelineClip {
+        let audio: Vec<f32> = (0..num_frames)
+            .map(|i| {
+                let phase = i as f64 * TONE_HZ * std::f64::consts::TAU / SAMPLE_RATE.0;
+                phase.sin() as f32 * TONE_AMPLITUDE
+            })
+            .collect();
+        TimelineClip {
+            id: 1,
+            start: 0,
+            end: num_frames as u64,
+            audio: Arc::new(vec![audio]),
+            source: Arc::new(Unwarped),
+            rate: 1.0,
+            polarity: 1.0,
+            trims: [1.0, 1.0],
+        }
+    }
+
+    /// Plays the tone through a track with an input trim of `gain_db`, and
+    /// returns the captured output.
+    fn render_tone(engine: &mut HeadlessEngine, gain_db: f32, num_frames: usize) -> Vec<Vec<f32>> {
+        let timeline = engine.timeline();
+        timeline.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE.0)));
+        timeline.send(TimelineMsg::SetInput { track: 1, trim_db: gain_db, phase_invert: false });
+        let clips = TrackClips::new(vec![tone(num_frames)], SAMPLE_RATE.0);
+        timeline.send(TimelineMsg::SetClips { track: 1, clips });
+        timeline.send(TimelineMsg::Play { from: 0 });
+
+        engine.render(num_frames)
+    }
+
+    /// The RMS level of `buffer` over whole periods of the tone, after the
+    /// input trim finished ramping to its value.
+    fn tone_rms(buffer: &[f32]) -> f32 {
+        let settled = &buffer[64 * 100..64 * 700];
+        (settled.iter().map(|s| s * s).sum::<f32>() / settled.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn captured_amplitude_follows_the_gain() {
+        for gain_db in [0.0, -6.0, -12.0, 6.0] {
+            let mut engine = engine();
+            let out = render_tone(&mut engine, gain_db, 48_000);
+
+            let expected =
+                TONE_AMPLITUDE * std::f32::consts::FRAC_1_SQRT_2 * 10.0f32.powf(gain_db / 20.0);
+            for channel in out.iter() {
+                assert_eq!(channel.len(), 48_000);
+                let rms = tone_rms(channel);
+                assert!((rms - expected).abs() < 1e-3, "{} dB: {} != {}", gain_db, rms, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn nothing_is_captured_while_stopped() {
+        let mut engine = engine();
+        render_tone(&mut engine, 0.0, 1000);
+
+        engine.timeline().send(TimelineMsg::Stop);
+        let out = engine.render(4096);
+        assert!(out.iter().flatten().all(|s| *s == 0.0));
+    }
+
+    fn rms(buffer: &[f32]) -> f32 {
+        (buffer.iter().map(|s| s * s).sum::<f32>() / buffer.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn the_tone_node_plays_its_frequency_and_amplitude() {
+        let mut engine = engine();
+        let tone = ToneNode::new(TONE_HZ as f32, TONE_AMPLITUDE, SAMPLE_RATE.0);
+        let amplitude = Arc::clone(&tone.amplitude);
+        engine.push_test_node(tone);
+
+        let out = engine.render(48_000);
+        let expected = TONE_AMPLITUDE * std::f32::consts::FRAC_1_SQRT_2;
+        assert!((rms(&out[0]) - expected).abs() < 1e-3);
+        // A period is 64 frames, so the tone crosses zero upwards every 64
+        // frames after the first one.
+        let upward = out[0].windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert_eq!(upward, 48_000 / 64 - 1);
+        assert_eq!(out[0], out[1]);
+
+        amplitude.set(0.0);
+        assert!(engine.render(4096).iter().flatten().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn the_gain_node_follows_its_setting_while_running() {
+        let mut engine = engine();
+        engine.push_test_node(ToneNode::new(TONE_HZ as f32, TONE_AMPLITUDE, SAMPLE_RATE.0));
+        let gain = GainNode::new(0.0, SAMPLE_RATE.0);
+        let gain_db = Arc::clone(&gain.gain_db);
+        engine.push_test_node(gain);
+
+        let unity = TONE_AMPLITUDE * std::f32::consts::FRAC_1_SQRT_2;
+        for db in [0.0, -6.0, -20.0, 3.0] {
+            gain_db.set(db);
+            let out = engine.render(48_000);
+            let expected = unity * 10.0f32.powf(db / 20.0);
+            let rms = tone_rms(&out[0]);
+            assert!((rms - expected).abs() < 1e-3, "{} dB: {} != {}", db, rms, expected);
+        }
+    }
+
+    #[test]
+    fn the_capture_node_records_what_the_engine_plays() {
+        let mut engine = engine();
+        let capture = CaptureNode::default();
+        let captured = Arc::clone(&capture.captured);
+        engine.push_test_node(GainNode::new(-6.0, SAMPLE_RATE.0));
+        engine.push_test_node(capture);
+
+        // The tone comes from the timeline, so the capture node sees the
+        // graph, the timeline and the gain node before it.
+        let out = render_tone(&mut engine, 0.0, 48_000);
+        assert_eq!(*captured.lock().unwrap(), out);
+        let expected = TONE_AMPLITUDE * std::f32::consts::FRAC_1_SQRT_2 * 10.0f32.powf(-0.3);
+        assert!((tone_rms(&out[0]) - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn renders_are_identical() {
+        let first = render_tone(&mut engine(), -3.0, 10_000);
+        let second = render_tone(&mut engine(), -3.0, 10_000);
+        assert_eq!(first, second);
+    }
+}
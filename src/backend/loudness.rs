@@ -0,0 +1,305 @@
+//! Loudness measurement according to ITU-R BS.1770 (the "LUFS" used by
+//! streaming services and EBU R 128).
+//!
+//! These functions work on any buffers (one buffer per channel), so they can be
+//! used for exports as well as for analyzing clips.
+
+use meadowlark_core_types::time::SampleRate;
+
+/// The length of one gating block in seconds.
+const BLOCK_SECS: f64 = 0.4;
+
+/// The blocks overlap by 75%.
+const BLOCK_OVERLAP: f64 = 0.75;
+
+/// Blocks quieter than this are never counted.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+
+/// Blocks more than this much quieter than the ungated loudness are not counted.
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// The factor the signal is oversampled by to find the true peak.
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+
+/// The number of input samples on each side of the interpolated point that are
+/// used to find the true peak.
+const TRUE_PEAK_HALF_TAPS: usize = 6;
+
+/// The result of measuring the loudness of some audio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessMeasurement {
+    /// The integrated (gated) loudness in LUFS, or `None` if the audio is
+    /// silent or shorter than one gating block.
+    pub integrated_lufs: Option<f64>,
+
+    /// The highest (4x oversampled) peak of all channels in dBTP.
+    pub true_peak_dbtp: f64,
+}
+
+/// Measures the integrated loudness and the true peak of `buffers` (one buffer
+/// per channel, all of the same length).
+///
+/// All channels are weighted equally, which is correct for mono and stereo.
+pub fn measure_loudness<T: AsRef<[f32]>>(
+    buffers: &[T],
+    sample_rate: SampleRate,
+) -> LoudnessMeasurement {
+    LoudnessMeasurement {
+        integrated_lufs: integrated_loudness(buffers, sample_rate),
+        true_peak_dbtp: gain_to_db(true_peak(buffers)),
+    }
+}
+
+/// Returns the integrated (gated) loudness of `buffers` in LUFS, or `None` if
+/// the audio is silent or shorter than one gating block.
+pub fn integrated_loudness<T: AsRef<[f32]>>(buffers: &[T], sample_rate: SampleRate) -> Option<f64> {
+    let num_frames = buffers.iter().map(|b| b.as_ref().len()).min()?;
+
+    // The block length is derived from the rounded hop length, so that every
+    // block is made of exactly the same number of whole hops at any sample rate.
+    let hops_per_block = (1.0 / (1.0 - BLOCK_OVERLAP)).round() as usize;
+    let hop_len = ((1.0 - BLOCK_OVERLAP) * BLOCK_SECS * sample_rate.0).round() as usize;
+    let block_len = hops_per_block * hop_len;
+    if block_len == 0 || num_frames < block_len {
+        return None;
+    }
+    let num_blocks = (num_frames - block_len) / hop_len + 1;
+
+    // The mean square of each block, summed over all channels.
+    let mut block_powers = vec![0.0f64; num_blocks];
+    for buffer in buffers.iter() {
+        let mut filter = KWeighting::new(sample_rate);
+        let weighted: Vec<f64> =
+            buffer.as_ref()[0..num_frames].iter().map(|s| filter.process(f64::from(*s))).collect();
+
+        // The sum of squares of each hop, so that blocks can be summed from
+        // their hops.
+        let num_hops = num_frames / hop_len;
+        let hop_sums: Vec<f64> = (0..num_hops)
+            .map(|hop| weighted[hop * hop_len..(hop + 1) * hop_len].iter().map(|s| s * s).sum())
+            .collect();
+
+        for (block, power) in block_powers.iter_mut().enumerate() {
+            let sum: f64 = hop_sums[block..block + hops_per_block].iter().sum();
+            *power += sum / block_len as f64;
+        }
+    }
+
+    let above_absolute: Vec<f64> =
+        block_powers.into_iter().filter(|p| power_to_lufs(*p) > ABSOLUTE_GATE_LUFS).collect();
+    if above_absolute.is_empty() {
+        return None;
+    }
+
+    let relative_gate = power_to_lufs(mean(&above_absolute)) + RELATIVE_GATE_LU;
+    let above_relative: Vec<f64> =
+        above_absolute.into_iter().filter(|p| power_to_lufs(*p) > relative_gate).collect();
+    if above_relative.is_empty() {
+        return None;
+    }
+
+    Some(power_to_lufs(mean(&above_relative)))
+}
+
+/// Returns the highest absolute value of the (4x oversampled) signal in any
+/// channel of `buffers`, as a linear gain.
+pub fn true_peak<T: AsRef<[f32]>>(buffers: &[T]) -> f32 {
+    let taps = true_peak_taps();
+    let half = TRUE_PEAK_HALF_TAPS as isize;
+
+    let mut peak = 0.0f32;
+    for buffer in buffers.iter() {
+        let buffer = buffer.as_ref();
+        for (i, s) in buffer.iter().enumerate() {
+            peak = peak.max(s.abs());
+
+            for phase_taps in taps.iter() {
+                let mut sum = 0.0f32;
+                for (k, tap) in phase_taps.iter().enumerate() {
+                    let index = i as isize + k as isize - half + 1;
+                    if index >= 0 && (index as usize) < buffer.len() {
+                        sum += buffer[index as usize] * tap;
+                    }
+                }
+                peak = peak.max(sum.abs());
+            }
+        }
+    }
+
+    peak
+}
+
+/// Returns the interpolation filter taps for each of the points between two
+/// samples (a Hann-windowed sinc).
+fn true_peak_taps() -> Vec<Vec<f32>> {
+    let num_taps = 2 * TRUE_PEAK_HALF_TAPS;
+    (1..TRUE_PEAK_OVERSAMPLING)
+        .map(|phase| {
+            let offset = phase as f64 / TRUE_PEAK_OVERSAMPLING as f64;
+            (0..num_taps)
+                .map(|k| {
+                    // The distance of tap `k` from the interpolated point.
+                    let x = k as f64 - TRUE_PEAK_HALF_TAPS as f64 + 1.0 - offset;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+                    };
+                    let window =
+                        0.5 + 0.5 * (std::f64::consts::PI * x / (TRUE_PEAK_HALF_TAPS as f64)).cos();
+                    (sinc * window) as f32
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Converts a linear gain to decibels.
+pub fn gain_to_db(gain: f32) -> f64 {
+    if gain > 0.0 {
+        20.0 * f64::from(gain).log10()
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+fn power_to_lufs(power: f64) -> f64 {
+    if power > 0.0 {
+        -0.691 + 10.0 * power.log10()
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// The two-stage "K" weighting filter from BS.1770 (a high shelf modelling the
+/// head, followed by a high pass).
+///
+/// The coefficients are derived for any sample rate instead of using the
+/// 48kHz coefficients from the standard.
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: SampleRate) -> Self {
+        let fs = sample_rate.0;
+
+        let shelf = {
+            let f0 = 1681.974450955533;
+            let gain_db = 3.999843853973347;
+            let q = 0.7071752369554196;
+
+            let k = (std::f64::consts::PI * f0 / fs).tan();
+            let vh = 10.0f64.powf(gain_db / 20.0);
+            let vb = vh.powf(0.4996667741545416);
+            let a0 = 1.0 + k / q + k * k;
+
+            Biquad::new(
+                [
+                    (vh + vb * k / q + k * k) / a0,
+                    2.0 * (k * k - vh) / a0,
+                    (vh - vb * k / q + k * k) / a0,
+                ],
+                [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            )
+        };
+
+        let high_pass = {
+            let f0 = 38.13547087602444;
+            let q = 0.5003270373238773;
+
+            let k = (std::f64::consts::PI * f0 / fs).tan();
+            let a0 = 1.0 + k / q + k * k;
+
+            Biquad::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0])
+        };
+
+        Self { shelf, high_pass }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
+
+/// A biquad filter in direct form I.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    /// `b` are the feed-forward and `a` the feedback coefficients, normalized so
+    /// that `a0` is 1.
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, x1: 0.0, x2: 0.0, y1: 0.0, y2: 0.0 }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x1 + self.b[2] * self.x2
+            - self.a[0] * self.y1
+            - self.a[1] * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1 kHz sine at `dbfs` (relative to a full scale sine) that lasts
+    /// `secs` seconds.
+    fn sine_1k(dbfs: f64, secs: f64, sample_rate: SampleRate) -> Vec<f32> {
+        let amplitude = 10.0f64.powf(dbfs / 20.0);
+        let num_frames = (secs * sample_rate.0) as usize;
+        (0..num_frames)
+            .map(|i| {
+                let phase = i as f64 * 1000.0 * std::f64::consts::TAU / sample_rate.0;
+                (phase.sin() * amplitude) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stereo_sine_at_minus_23_dbfs_measures_minus_23_lufs() {
+        // The first test case of EBU Tech 3341, at rates where the hop length
+        // (100 ms) is and isn't a whole number of frames.
+        for sample_rate in [48_000.0, 44_100.0, 96_000.0, 22_050.0, 11_025.0] {
+            let sample_rate = SampleRate(sample_rate);
+            let sine = sine_1k(-23.0, 20.0, sample_rate);
+            let lufs = integrated_loudness(&[&sine, &sine], sample_rate).unwrap();
+            assert!((lufs + 23.0).abs() < 0.5, "{} Hz: {} LUFS", sample_rate.0, lufs);
+        }
+    }
+
+    #[test]
+    fn mono_is_3_lu_quieter_than_the_same_signal_in_stereo() {
+        let sample_rate = SampleRate(48_000.0);
+        let sine = sine_1k(-23.0, 5.0, sample_rate);
+        let mono = integrated_loudness(&[&sine], sample_rate).unwrap();
+        let stereo = integrated_loudness(&[&sine, &sine], sample_rate).unwrap();
+        assert!((stereo - mono - 3.01).abs() < 0.01, "{} {}", mono, stereo);
+    }
+
+    #[test]
+    fn silence_and_short_audio_have_no_loudness() {
+        let sample_rate = SampleRate(48_000.0);
+        assert_eq!(integrated_loudness(&[vec![0.0; 48_000]], sample_rate), None);
+        assert_eq!(integrated_loudness(&[sine_1k(-23.0, 0.3, sample_rate)], sample_rate), None);
+    }
+}
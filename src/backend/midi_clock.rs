@@ -0,0 +1,294 @@
+//! Sending MIDI clock to external devices so they follow the transport.
+//!
+//! The audio thread runs a `MidiClockGenerator` for every block, which works
+//! out where the clock ticks fall inside the block and pushes them (with the
+//! time they are due) into a queue. A separate sender thread takes them from
+//! the queue and sends them to the MIDI output port as close to on time as
+//! possible.
+
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::error::Error;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use meadowlark_core_types::time::SampleRate;
+
+/// MIDI clock sends 24 ticks per quarter note.
+pub const MIDI_CLOCK_PPQN: f64 = 24.0;
+
+/// The number of messages the queue between the audio thread and the sender
+/// thread can hold.
+pub const MIDI_CLOCK_QUEUE_SIZE: usize = 1024;
+
+/// The sender thread sleeps until this long before a message is due, and then
+/// waits in a busy loop for better timing.
+const SPIN_BEFORE_DEADLINE: Duration = Duration::from_micros(500);
+
+/// A MIDI system realtime or system common message used for clock sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiClockMessage {
+    Tick,
+    Start,
+    Continue,
+    Stop,
+    /// Song position pointer, in 16th notes from the start of the song.
+    SongPosition(u16),
+}
+
+impl MidiClockMessage {
+    /// The raw bytes of this message. Returns the buffer and the number of
+    /// bytes used.
+    pub fn bytes(&self) -> ([u8; 3], usize) {
+        match self {
+            MidiClockMessage::Tick => ([0xF8, 0, 0], 1),
+            MidiClockMessage::Start => ([0xFA, 0, 0], 1),
+            MidiClockMessage::Continue => ([0xFB, 0, 0], 1),
+            MidiClockMessage::Stop => ([0xFC, 0, 0], 1),
+            MidiClockMessage::SongPosition(pos) => {
+                let pos = pos & 0x3FFF;
+                ([0xF2, (pos & 0x7F) as u8, (pos >> 7) as u8], 3)
+            }
+        }
+    }
+}
+
+/// A message and the time it should be sent at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedMidiClockMessage {
+    pub deadline: Instant,
+    pub message: MidiClockMessage,
+}
+
+/// A section of constant tempo, starting at `start_beats`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoSegment {
+    pub start_beats: f64,
+    pub bpm: f64,
+}
+
+/// Returns the number of seconds between `from_beats` and `to_beats` (where
+/// `from_beats <= to_beats`) according to `tempo_map`, which is sorted by start
+/// and starts at beat 0.
+pub fn beats_to_secs(tempo_map: &[TempoSegment], from_beats: f64, to_beats: f64) -> f64 {
+    let mut secs = 0.0;
+    for (i, segment) in tempo_map.iter().enumerate() {
+        let segment_end = tempo_map.get(i + 1).map(|s| s.start_beats).unwrap_or(f64::INFINITY);
+
+        let start = from_beats.max(segment.start_beats);
+        let end = to_beats.min(segment_end);
+        if end > start {
+            secs += (end - start) * 60.0 / segment.bpm.max(f64::EPSILON);
+        }
+    }
+    secs
+}
+
+/// Returns the musical position (in beats) that is `secs` seconds after
+/// `from_beats` according to `tempo_map`.
+pub fn secs_to_beats(tempo_map: &[TempoSegment], from_beats: f64, secs: f64) -> f64 {
+    let mut beats = from_beats;
+    let mut secs_left = secs;
+    for (i, segment) in tempo_map.iter().enumerate() {
+        let segment_end = tempo_map.get(i + 1).map(|s| s.start_beats).unwrap_or(f64::INFINITY);
+        if segment_end <= beats {
+            continue;
+        }
+
+        let beats_per_sec = segment.bpm / 60.0;
+        let segment_secs = (segment_end - beats) / beats_per_sec;
+        if secs_left <= segment_secs {
+            return beats + secs_left * beats_per_sec;
+        }
+        secs_left -= segment_secs;
+        beats = segment_end;
+    }
+    beats
+}
+
+/// Works out when MIDI clock messages are due while the transport plays.
+///
+/// This runs on the audio thread and never allocates.
+pub struct MidiClockGenerator {
+    tx: Producer<TimedMidiClockMessage>,
+    /// The index of the next tick to send, counted from the start of the song.
+    next_tick: u64,
+    running: bool,
+}
+
+impl MidiClockGenerator {
+    /// Sends "start" (from the start of the song) or "song position" +
+    /// "continue" (from anywhere else) at `deadline`.
+    pub fn start(&mut self, position_beats: f64, deadline: Instant) {
+        self.locate(position_beats, deadline);
+        self.running = true;
+    }
+
+    /// Sends "stop" at `deadline`.
+    pub fn stop(&mut self, deadline: Instant) {
+        if self.running {
+            self.push(deadline, MidiClockMessage::Stop);
+            self.running = false;
+        }
+    }
+
+    /// Tells the external device that playback jumped to `position_beats` (i.e.
+    /// when the transport loops back).
+    pub fn locate(&mut self, position_beats: f64, deadline: Instant) {
+        // The song position is in 16th notes, so playback resumes at the next
+        // 16th note.
+        let sixteenths = (position_beats * 4.0).ceil().max(0.0) as u64;
+        self.next_tick = sixteenths * 6;
+
+        if self.running {
+            self.push(deadline, MidiClockMessage::Stop);
+        }
+        if sixteenths == 0 {
+            self.push(deadline, MidiClockMessage::Start);
+        } else {
+            self.push(deadline, MidiClockMessage::SongPosition(sixteenths.min(0x3FFF) as u16));
+            self.push(deadline, MidiClockMessage::Continue);
+        }
+    }
+
+    /// Queues the clock ticks that fall inside a block of `num_frames` frames
+    /// that starts at `block_start_beats`. `block_deadline` is when the first
+    /// frame of the block is heard.
+    ///
+    /// Tempo changes inside the block are taken into account.
+    pub fn process_block(
+        &mut self,
+        block_start_beats: f64,
+        num_frames: usize,
+        sample_rate: SampleRate,
+        tempo_map: &[TempoSegment],
+        block_deadline: Instant,
+    ) {
+        if !self.running {
+            return;
+        }
+
+        for (tick, offset_frames) in
+            tick_positions(self.next_tick, block_start_beats, num_frames, sample_rate, tempo_map)
+        {
+            let offset = Duration::from_secs_f64(offset_frames as f64 / sample_rate.0);
+            self.push(block_deadline + offset, MidiClockMessage::Tick);
+            self.next_tick = tick + 1;
+        }
+    }
+
+    fn push(&mut self, deadline: Instant, message: MidiClockMessage) {
+        // If the sender thread fell behind, the message is dropped. Sending it
+        // late would be worse.
+        let _ = self.tx.push(TimedMidiClockMessage { deadline, message });
+    }
+}
+
+/// Returns the ticks (starting at tick `first_tick`) that fall inside a block
+/// of `num_frames` frames starting at `block_start_beats`, along with the frame
+/// in the block each one falls on.
+pub fn tick_positions(
+    first_tick: u64,
+    block_start_beats: f64,
+    num_frames: usize,
+    sample_rate: SampleRate,
+    tempo_map: &[TempoSegment],
+) -> impl Iterator<Item = (u64, usize)> + '_ {
+    let block_end_beats =
+        secs_to_beats(tempo_map, block_start_beats, num_frames as f64 / sample_rate.0);
+
+    // Skip ticks that were missed (i.e. after a jump without a locate).
+    let first_tick = first_tick.max((block_start_beats * MIDI_CLOCK_PPQN).ceil().max(0.0) as u64);
+
+    (first_tick..)
+        .map(|tick| (tick, tick as f64 / MIDI_CLOCK_PPQN))
+        .take_while(move |(_, beats)| *beats < block_end_beats)
+        .map(move |(tick, beats)| {
+            let secs = beats_to_secs(tempo_map, block_start_beats, beats);
+            let frame = ((secs * sample_rate.0).round() as usize).min(num_frames.saturating_sub(1));
+            (tick, frame)
+        })
+}
+
+/// A MIDI output that clock messages can be sent to.
+pub trait MidiClockOutput: Send {
+    fn send(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>>;
+}
+
+impl MidiClockOutput for midir::MidiOutputConnection {
+    fn send(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+        midir::MidiOutputConnection::send(self, bytes)?;
+        Ok(())
+    }
+}
+
+/// Opens the MIDI output port with the name `port_name` for sending clock.
+pub fn open_midi_clock_output(
+    port_name: &str,
+) -> Result<midir::MidiOutputConnection, Box<dyn Error>> {
+    let midi_out = midir::MidiOutput::new("Meadowlark MIDI clock")?;
+    let port = midi_out
+        .ports()
+        .into_iter()
+        .find(|port| midi_out.port_name(port).map(|name| name == port_name).unwrap_or(false))
+        .ok_or_else(|| format!("MIDI output port {:?} not found", port_name))?;
+
+    midi_out.connect(&port, "clock").map_err(|e| e.to_string().into())
+}
+
+/// The thread that sends the queued clock messages to the MIDI output. The
+/// thread stops when this is dropped.
+pub struct MidiClockSender {
+    thread: Option<JoinHandle<()>>,
+    stop_tx: Option<crossbeam::channel::Sender<()>>,
+}
+
+impl Drop for MidiClockSender {
+    fn drop(&mut self) {
+        drop(self.stop_tx.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Creates the generator for the audio thread and starts the sender thread
+/// that sends its messages to `output`.
+pub fn spawn_midi_clock(
+    mut output: Box<dyn MidiClockOutput>,
+) -> Result<(MidiClockGenerator, MidiClockSender), Box<dyn Error>> {
+    let (tx, mut rx): (_, Consumer<TimedMidiClockMessage>) = RingBuffer::new(MIDI_CLOCK_QUEUE_SIZE);
+    let (stop_tx, stop_rx) = crossbeam::channel::bounded::<()>(0);
+
+    let thread = std::thread::Builder::new().name("midi clock".into()).spawn(move || loop {
+        let msg = match rx.peek() {
+            Ok(msg) => *msg,
+            Err(_) => {
+                // Nothing queued. Wait a little (or stop if the sender was
+                // dropped).
+                match stop_rx.recv_timeout(Duration::from_millis(1)) {
+                    Err(crossbeam::channel::RecvTimeoutError::Timeout) => continue,
+                    _ => return,
+                }
+            }
+        };
+
+        let now = Instant::now();
+        if msg.deadline > now + SPIN_BEFORE_DEADLINE {
+            std::thread::sleep(msg.deadline - now - SPIN_BEFORE_DEADLINE);
+        }
+        while Instant::now() < msg.deadline {
+            std::hint::spin_loop();
+        }
+
+        let _ = rx.pop();
+        let (bytes, len) = msg.message.bytes();
+        if let Err(e) = output.send(&bytes[0..len]) {
+            log::error!("Failed to send MIDI clock: {}", e);
+        }
+    })?;
+
+    Ok((
+        MidiClockGenerator { tx, next_tick: 0, running: false },
+        MidiClockSender { thread: Some(thread), stop_tx: Some(stop_tx) },
+    ))
+}
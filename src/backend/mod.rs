@@ -25,4 +25,27 @@
 //! [`Rusty DAW Engine`]: https://github.com/RustyDAW/rusty-daw-engine
 //! [`CLAP`]: https://github.com/free-audio/clap
 
+pub mod automation;
+pub mod clip_block;
+pub mod correlation;
+pub mod count_in;
+pub mod decode;
+pub mod dither;
+pub mod engine;
+pub mod headless;
+pub mod interpolation;
+pub mod loudness;
+pub mod midi_clock;
+pub mod pan;
+pub mod recorder;
+pub mod rt_log;
+pub mod smoothed_gain;
 pub mod system_io;
+pub mod tempo_detect;
+#[cfg(test)]
+pub mod test_nodes;
+pub mod time_stretch;
+pub mod timeline;
+pub mod track_activity;
+pub mod transport_clock;
+pub mod wav_export;
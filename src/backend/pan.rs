@@ -0,0 +1,71 @@
+//! Pan laws.
+//!
+//! A pan law decides how loud a mono signal panned to the center is compared to
+//! the same signal panned hard to one side. Each function takes the pan in the
+//! range [0.0, 1.0] (where 0.0 is hard left, 0.5 is the center and 1.0 is hard
+//! right) and returns the `(left, right)` gains.
+
+use std::f64::consts::FRAC_PI_2;
+
+/// Both sides are at -3 dB in the center, which keeps the summed power
+/// constant across the whole range. This sounds even on speakers.
+pub fn constant_power(pan: f64) -> (f64, f64) {
+    let pan = pan.clamp(0.0, 1.0);
+    ((pan * FRAC_PI_2).cos(), (pan * FRAC_PI_2).sin())
+}
+
+/// Both sides are at -6 dB in the center, which keeps the summed amplitude
+/// constant. A panned signal keeps its level when the mix is summed to mono.
+pub fn linear_taper(pan: f64) -> (f64, f64) {
+    let pan = pan.clamp(0.0, 1.0);
+    (1.0 - pan, pan)
+}
+
+/// Both sides are at -4.5 dB in the center, halfway (in decibels) between
+/// `constant_power()` and `linear_taper()`.
+pub fn compromise(pan: f64) -> (f64, f64) {
+    let (power_l, power_r) = constant_power(pan);
+    let (linear_l, linear_r) = linear_taper(pan);
+    ((power_l * linear_l).sqrt(), (power_r * linear_r).sqrt())
+}
+
+/// Both sides are at unity in the center, and only the side opposite to the
+/// pan is turned down. This is a balance control rather than a panner, and is
+/// meant for stereo signals.
+pub fn balance(pan: f64) -> (f64, f64) {
+    let pan = pan.clamp(0.0, 1.0);
+    ((2.0 * (1.0 - pan)).min(1.0), (2.0 * pan).min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db((left, right): (f64, f64)) -> (f64, f64) {
+        (20.0 * left.log10(), 20.0 * right.log10())
+    }
+
+    #[test]
+    fn center_levels_match_the_laws() {
+        for (law, center_db) in [
+            (constant_power as fn(f64) -> (f64, f64), -3.01),
+            (compromise, -4.52),
+            (linear_taper, -6.02),
+            (balance, 0.0),
+        ] {
+            let (left, right) = db(law(0.5));
+            assert!((left - center_db).abs() < 0.01, "{} != {}", left, center_db);
+            assert!((right - center_db).abs() < 0.01, "{} != {}", right, center_db);
+        }
+    }
+
+    #[test]
+    fn hard_pans_only_play_on_one_side() {
+        for law in [constant_power, compromise, linear_taper, balance] {
+            let (left, right) = law(0.0);
+            assert!((left - 1.0).abs() < 1e-12 && right.abs() < 1e-12);
+            let (left, right) = law(1.0);
+            assert!(left.abs() < 1e-12 && (right - 1.0).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,751 @@
+//! Recording the hardware inputs into audio files.
+//!
+//! The input stream hands every block of input to an `InputCapture`, which
+//! pushes it into a ring buffer while recording. A writer thread started by the
+//! `Recorder` pops the input and streams each take (the inputs of one
+//! record-armed track) into its own WAV file, so that the audio thread never
+//! touches the disk.
+//!
+//! Every block of input is tagged with the frame of the output stream it lines
+//! up with. The writer looks up which timeline frame the player played at that
+//! frame in the `TransportClock` (see `backend::transport_clock`), and only
+//! keeps the frames during which the transport moved after the start it was
+//! told to record. So the input captured before the player picked up the start
+//! of the transport, or during a count-in (see `backend::count_in`), is thrown
+//! away, and the first recorded frame lands exactly where the transport
+//! started.
+//!
+//! Only the frames inside the punch range are kept, and every take is faded in
+//! and out over `RECORD_DECLICK_SECS` so that its boundaries don't click.
+//!
+//! While looping, every pass over the loop is recorded into a file of its own.
+//! The writer starts the next pass wherever the timeline frames of the input
+//! stop following on from each other, which is where the player jumped back to
+//! the loop start. The program layer stacks the passes into a take folder.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use meadowlark_core_types::time::SampleRate;
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use super::transport_clock::TransportClock;
+use super::wav_export::WavStreamWriter;
+
+/// How much input the ring buffer between the input stream and the writer
+/// thread can hold in seconds. The writer thread only falls this far behind if
+/// the disk stalls.
+pub const INPUT_RING_SECS: f64 = 2.0;
+
+/// The length of the fades at the start and the end of every take.
+pub const RECORD_DECLICK_SECS: f64 = 0.002;
+
+/// How long the writer thread waits for more input when the ring is empty.
+const WRITER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The most blocks of input the ring buffer can hold the tags of.
+const MAX_CAPTURE_RUNS: usize = 4096;
+
+/// A block of input in the ring buffer.
+#[derive(Debug, Clone, Copy)]
+struct CaptureRun {
+    /// The frame of the output stream that the first frame of the block lines
+    /// up with.
+    stream_frame: u64,
+    num_frames: usize,
+}
+
+/// A take to record.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TakeSettings {
+    /// The id of the track the take is recorded on.
+    pub track: u64,
+    /// The first hardware input of the take, where 0 is the first input.
+    pub first_input: usize,
+    /// The number of hardware inputs (starting at `first_input`) recorded into
+    /// the take.
+    pub num_channels: usize,
+    /// Where to write the take to. The passes over the loop after the first
+    /// one are written next to it (see `loop_pass_path()`).
+    pub path: PathBuf,
+}
+
+/// A take that was recorded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedTake {
+    /// The id of the track the take was recorded on.
+    pub track: u64,
+    pub path: PathBuf,
+    /// The frame on the timeline where the take starts.
+    pub start: u64,
+    /// The length of the take in frames.
+    pub num_frames: u64,
+    /// The pass over the loop the take was recorded in, starting at 0. Without
+    /// a loop, every track has one take, recorded in the pass 0.
+    pub pass: usize,
+}
+
+/// The path of the file that the pass `pass` over the loop of the take at
+/// `path` is written to. The first pass is written to `path` itself.
+pub fn loop_pass_path(path: &Path, pass: usize) -> PathBuf {
+    if pass == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_pass{}.{}", stem, pass + 1, extension.to_string_lossy()),
+        None => format!("{}_pass{}", stem, pass + 1),
+    };
+    path.with_file_name(name)
+}
+
+/// The state shared between the `InputCapture` and the `Recorder`.
+#[derive(Default)]
+struct CaptureShared {
+    /// True while the input is pushed into the ring.
+    capturing: AtomicBool,
+    /// Set by the `Recorder` to tell the writer thread to finish the takes
+    /// once the ring is empty.
+    stop: AtomicBool,
+    /// The number of input frames that were dropped because the ring was full.
+    num_dropped_frames: AtomicU64,
+}
+
+/// Creates the input capture for the input stream, and the recorder to control
+/// it with. The input has `num_input_channels` channels, and is lined up with
+/// the output stream of the timeline player that runs `clock`.
+pub fn recorder(
+    num_input_channels: usize,
+    sample_rate: SampleRate,
+    clock: Arc<TransportClock>,
+) -> (Recorder, InputCapture) {
+    let num_input_channels = num_input_channels.max(1);
+    let capacity = (INPUT_RING_SECS * sample_rate.0).ceil() as usize * num_input_channels;
+    let (producer, consumer) = RingBuffer::<f32>::new(capacity);
+    let (run_producer, run_consumer) = RingBuffer::<CaptureRun>::new(MAX_CAPTURE_RUNS);
+    let shared = Arc::new(CaptureShared::default());
+
+    (
+        Recorder {
+            shared: Arc::clone(&shared),
+            consumer: Some(CaptureConsumer { samples: consumer, runs: run_consumer }),
+            clock: Arc::clone(&clock),
+            num_input_channels,
+            sample_rate,
+            writer: None,
+        },
+        InputCapture {
+            producer,
+            run_producer,
+            shared,
+            clock,
+            next_stream_frame: None,
+            num_input_channels,
+        },
+    )
+}
+
+/// Captures the input on the input stream's audio thread.
+pub struct InputCapture {
+    producer: Producer<f32>,
+    run_producer: Producer<CaptureRun>,
+    shared: Arc<CaptureShared>,
+    clock: Arc<TransportClock>,
+    /// The frame of the output stream that the next frame of input lines up
+    /// with, once the first block of input arrived.
+    next_stream_frame: Option<u64>,
+    num_input_channels: usize,
+}
+
+impl InputCapture {
+    /// Called by the input stream with every block of `input` (interleaved,
+    /// with the number of channels the recorder was created with).
+    ///
+    /// The first block is taken to line up with the last frames the output
+    /// stream processed, and every block after it follows on from the one
+    /// before. Both streams run at the same sample rate, so they stay lined up.
+    ///
+    /// This is realtime safe.
+    pub fn process_interleaved(&mut self, input: impl ExactSizeIterator<Item = f32>) {
+        let num_frames = input.len() / self.num_input_channels;
+        let clock = &self.clock;
+        let stream_frame = *self
+            .next_stream_frame
+            .get_or_insert_with(|| clock.stream_frame().saturating_sub(num_frames as u64));
+        self.next_stream_frame = Some(stream_frame + num_frames as u64);
+
+        if !self.shared.capturing.load(Ordering::Acquire) || num_frames == 0 {
+            return;
+        }
+
+        // Only whole blocks are pushed, so the ring never gets out of step with
+        // the channels or with the tags of the blocks.
+        let num_samples = num_frames * self.num_input_channels;
+        if self.producer.slots() < num_samples || self.run_producer.is_full() {
+            self.shared.num_dropped_frames.fetch_add(num_frames as u64, Ordering::Relaxed);
+            return;
+        }
+        if let Ok(chunk) = self.producer.write_chunk_uninit(num_samples) {
+            chunk.fill_from_iter(input);
+        }
+        let _ = self.run_producer.push(CaptureRun { stream_frame, num_frames });
+    }
+}
+
+/// The end of the ring buffer of the input and the tags of its blocks.
+struct CaptureConsumer {
+    samples: Consumer<f32>,
+    runs: Consumer<CaptureRun>,
+}
+
+impl CaptureConsumer {
+    /// Throws away everything in the ring buffer.
+    fn clear(&mut self) {
+        while self.runs.pop().is_ok() {}
+        if let Ok(chunk) = self.samples.read_chunk(self.samples.slots()) {
+            chunk.commit_all();
+        }
+    }
+}
+
+/// The result of the writer thread: the ring buffer (for the next recording)
+/// and the recorded takes.
+type WriterResult = (CaptureConsumer, Result<Vec<RecordedTake>, String>);
+
+/// Records the input captured by an `InputCapture`.
+pub struct Recorder {
+    shared: Arc<CaptureShared>,
+    /// The end of the ring buffer, while no writer thread owns it.
+    consumer: Option<CaptureConsumer>,
+    clock: Arc<TransportClock>,
+    num_input_channels: usize,
+    sample_rate: SampleRate,
+    writer: Option<JoinHandle<WriterResult>>,
+}
+
+impl Recorder {
+    pub fn num_input_channels(&self) -> usize {
+        self.num_input_channels
+    }
+
+    /// Returns `true` while recording.
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// The number of input frames that were dropped since the recorder was
+    /// created because the writer thread fell behind.
+    pub fn num_dropped_frames(&self) -> u64 {
+        self.shared.num_dropped_frames.load(Ordering::Relaxed)
+    }
+
+    /// Starts recording `takes` from the start of the transport with the number
+    /// `transport_start` on (see `TimelineHandle::num_starts()`). Only the
+    /// frames during which the transport moved after that start are kept, so
+    /// call this right after starting the transport. If `punch` is set, only
+    /// the frames on the timeline in the range `[punch.0, punch.1)` are kept.
+    ///
+    /// The files of the takes are created right away, so this fails if any of
+    /// them can't be written to.
+    pub fn start(
+        &mut self,
+        takes: Vec<TakeSettings>,
+        transport_start: u64,
+        punch: Option<(u64, u64)>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.writer.is_some() {
+            return Err("Already recording".into());
+        }
+        if let Some(take) = takes.iter().find(|t| {
+            t.num_channels == 0 || t.first_input + t.num_channels > self.num_input_channels
+        }) {
+            return Err(format!(
+                "The inputs of the take {} are not available on the input device",
+                take.path.display()
+            )
+            .into());
+        }
+        let mut consumer = self.consumer.take().ok_or("The recorder lost its input")?;
+
+        let declick_frames = (RECORD_DECLICK_SECS * self.sample_rate.0).round() as usize;
+        let mut writers = Vec::with_capacity(takes.len());
+        for settings in takes.into_iter() {
+            match TakeWriter::new(settings, self.sample_rate, declick_frames) {
+                Ok(writer) => writers.push(writer),
+                Err(e) => {
+                    self.consumer = Some(consumer);
+                    return Err(e);
+                }
+            }
+        }
+
+        // Throw away whatever was left over from the last recording.
+        consumer.clear();
+
+        let shared = Arc::clone(&self.shared);
+        let clock = Arc::clone(&self.clock);
+        let num_input_channels = self.num_input_channels;
+        let (range_start, range_end) = punch.unwrap_or((0, u64::MAX));
+        let filter = FrameFilter { transport_start, range_start, range_end };
+        shared.stop.store(false, Ordering::Release);
+
+        let res = std::thread::Builder::new().name("recorder".into()).spawn(move || {
+            loop {
+                // Read the stop flag before draining, so that nothing pushed
+                // before the recording stopped is lost.
+                let stop = shared.stop.load(Ordering::Acquire);
+
+                let run = match consumer.runs.peek() {
+                    Ok(run) => *run,
+                    Err(_) if stop => break,
+                    Err(_) => {
+                        std::thread::sleep(WRITER_POLL_INTERVAL);
+                        continue;
+                    }
+                };
+                // Wait until the player got through the frames of the output
+                // stream that the block lines up with. The input that is left
+                // when recording stops is from after the transport stopped.
+                if clock.stream_frame() < run.stream_frame + run.num_frames as u64 {
+                    if stop {
+                        break;
+                    }
+                    std::thread::sleep(WRITER_POLL_INTERVAL);
+                    continue;
+                }
+                let _ = consumer.runs.pop();
+
+                let result = match consumer.samples.read_chunk(run.num_frames * num_input_channels)
+                {
+                    Ok(chunk) => {
+                        let (first, second) = chunk.as_slices();
+                        // A slice boundary never splits a frame, since the
+                        // capacity is a multiple of the channel count.
+                        let frames = first
+                            .chunks_exact(num_input_channels)
+                            .chain(second.chunks_exact(num_input_channels));
+                        let result = write_block(frames, run, &clock, &filter, &mut writers);
+                        chunk.commit_all();
+                        result
+                    }
+                    Err(_) => Err("The input ring lost a block".into()),
+                };
+                if let Err(e) = result {
+                    return (consumer, Err(e.to_string()));
+                }
+            }
+
+            let mut recorded = Vec::with_capacity(writers.len());
+            for writer in writers.into_iter() {
+                match writer.finish() {
+                    Ok(passes) => recorded.extend(passes),
+                    Err(e) => return (consumer, Err(e.to_string())),
+                }
+            }
+            (consumer, Ok(recorded))
+        });
+
+        match res {
+            Ok(writer) => {
+                self.writer = Some(writer);
+                self.shared.capturing.store(true, Ordering::Release);
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to start the recorder thread: {}", e).into()),
+        }
+    }
+
+    /// Stops recording, and waits until the writer thread has finished the
+    /// files of the takes. Returns the recorded takes, with one take per pass
+    /// over the loop for every track, in the order of the passes.
+    pub fn stop(&mut self) -> Result<Vec<RecordedTake>, Box<dyn Error>> {
+        self.shared.capturing.store(false, Ordering::Release);
+        self.shared.stop.store(true, Ordering::Release);
+
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => return Ok(Vec::new()),
+        };
+        match writer.join() {
+            Ok((consumer, result)) => {
+                self.consumer = Some(consumer);
+                Ok(result?)
+            }
+            Err(_) => Err("The recorder thread panicked".into()),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Finish the files so that they are not left without their sizes.
+        let _ = self.stop();
+    }
+}
+
+/// Which frames of the input are recorded.
+struct FrameFilter {
+    /// The number of the start of the transport that is recorded.
+    transport_start: u64,
+    /// The punch range on the timeline.
+    range_start: u64,
+    range_end: u64,
+}
+
+/// Writes the `frames` of the block of input `run` into the takes of
+/// `writers`, at the timeline frames that `clock` played at the same time.
+///
+/// The player must have gone through the frames of the output stream that the
+/// block lines up with.
+fn write_block<'a>(
+    mut frames: impl Iterator<Item = &'a [f32]>,
+    run: CaptureRun,
+    clock: &TransportClock,
+    filter: &FrameFilter,
+    writers: &mut [TakeWriter],
+) -> Result<(), Box<dyn Error>> {
+    let mut stream_frame = run.stream_frame;
+    let run_end = run.stream_frame + run.num_frames as u64;
+    while stream_frame < run_end {
+        let transport = match clock.run_at(stream_frame) {
+            Some(transport) => transport,
+            None => break,
+        };
+        let len = transport.num_frames.min(run_end - stream_frame);
+        for (i, input) in frames.by_ref().take(len as usize).enumerate() {
+            let frame = match transport.timeline_frame {
+                Some(frame) if transport.start == filter.transport_start => frame + i as u64,
+                _ => continue,
+            };
+            if frame >= filter.range_start && frame < filter.range_end {
+                for writer in writers.iter_mut() {
+                    writer.push_frame(input, frame)?;
+                }
+            }
+        }
+        stream_frame += len;
+    }
+    Ok(())
+}
+
+/// Writes one take on the writer thread, with a file for every pass over the
+/// loop.
+struct TakeWriter {
+    settings: TakeSettings,
+    sample_rate: SampleRate,
+    /// The file of the current pass.
+    writer: WavStreamWriter,
+    /// The timeline frame of the first frame of the current pass, once there
+    /// is one.
+    start: Option<u64>,
+    /// The timeline frame that follows on from the last frame.
+    next_frame: Option<u64>,
+    /// The passes that are finished.
+    passes: Vec<RecordedTake>,
+    declick_frames: usize,
+    /// The number of frames that were faded in so far.
+    num_faded_in: usize,
+    /// The last `declick_frames` frames (interleaved), which are held back until
+    /// it is known whether they are the end of the take and need to be faded
+    /// out.
+    tail: Vec<f32>,
+}
+
+impl TakeWriter {
+    fn new(
+        settings: TakeSettings,
+        sample_rate: SampleRate,
+        declick_frames: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let writer = WavStreamWriter::create(&settings.path, settings.num_channels, sample_rate)?;
+        let tail = Vec::with_capacity((declick_frames + 1) * settings.num_channels);
+        Ok(Self {
+            settings,
+            sample_rate,
+            writer,
+            start: None,
+            next_frame: None,
+            passes: Vec::new(),
+            declick_frames,
+            num_faded_in: 0,
+            tail,
+        })
+    }
+
+    /// Adds a frame of the input (with every input channel) at the timeline
+    /// frame `frame`. If the frame doesn't follow on from the last one, the
+    /// transport looped, and the frame starts the next pass.
+    fn push_frame(&mut self, input: &[f32], frame: u64) -> Result<(), Box<dyn Error>> {
+        if matches!(self.next_frame, Some(next) if next != frame) {
+            self.next_pass()?;
+        }
+        self.next_frame = Some(frame + 1);
+        self.start.get_or_insert(frame);
+
+        let gain = if self.num_faded_in < self.declick_frames {
+            self.num_faded_in += 1;
+            self.num_faded_in as f32 / (self.declick_frames + 1) as f32
+        } else {
+            1.0
+        };
+        let first = self.settings.first_input;
+        self.tail.extend(input[first..first + self.settings.num_channels].iter().map(|s| s * gain));
+
+        let num_channels = self.settings.num_channels;
+        if self.tail.len() > self.declick_frames * num_channels {
+            self.writer.write_frames(&self.tail[..num_channels])?;
+            self.tail.drain(..num_channels);
+        }
+        Ok(())
+    }
+
+    /// Finishes the file of the current pass, and starts the file of the next
+    /// one, which is faded in again.
+    fn next_pass(&mut self) -> Result<(), Box<dyn Error>> {
+        self.write_tail()?;
+        let take = self.pass_take();
+        let path = loop_pass_path(&self.settings.path, take.pass + 1);
+        let writer = WavStreamWriter::create(&path, self.settings.num_channels, self.sample_rate)?;
+        std::mem::replace(&mut self.writer, writer).finish()?;
+        self.passes.push(take);
+        self.start = None;
+        self.num_faded_in = 0;
+        Ok(())
+    }
+
+    /// Finishes the file of the last pass, and returns the takes of all passes.
+    fn finish(mut self) -> Result<Vec<RecordedTake>, Box<dyn Error>> {
+        self.write_tail()?;
+        let take = self.pass_take();
+        self.writer.finish()?;
+        self.passes.push(take);
+        Ok(self.passes)
+    }
+
+    /// Fades out the held back frames, and writes them to the file of the
+    /// current pass.
+    fn write_tail(&mut self) -> Result<(), Box<dyn Error>> {
+        let num_channels = self.settings.num_channels;
+        let num_tail_frames = self.tail.len() / num_channels;
+        for (i, frame) in self.tail.chunks_exact_mut(num_channels).enumerate() {
+            let gain = (num_tail_frames - i) as f32 / (num_tail_frames + 1) as f32;
+            for s in frame.iter_mut() {
+                *s *= gain;
+            }
+        }
+        self.writer.write_frames(&self.tail)?;
+        self.tail.clear();
+        Ok(())
+    }
+
+    /// The take of the current pass, with everything written so far.
+    fn pass_take(&self) -> RecordedTake {
+        let pass = self.passes.len();
+        RecordedTake {
+            track: self.settings.track,
+            path: loop_pass_path(&self.settings.path, pass),
+            start: self.start.unwrap_or(0),
+            num_frames: self.writer.num_frames(),
+            pass,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::count_in::CountIn;
+    use crate::backend::timeline::{self, LoopRange, TimelineHandle, TimelineMsg, TimelinePlayer};
+
+    const SAMPLE_RATE: SampleRate = SampleRate(48_000.0);
+
+    /// Reads the samples of a 32-bit float WAV file written by
+    /// `WavStreamWriter`.
+    fn read_float_wav(path: &std::path::Path) -> Vec<f32> {
+        let bytes = std::fs::read(path).unwrap();
+        let data = bytes.windows(4).position(|w| w == b"data").unwrap();
+        let len = u32::from_le_bytes(bytes[data + 4..data + 8].try_into().unwrap()) as usize;
+        bytes[data + 8..data + 8 + len]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect()
+    }
+
+    fn take(name: &str, first_input: usize, num_channels: usize) -> TakeSettings {
+        let dir = std::env::temp_dir().join("meadowlark-recorder-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        TakeSettings { track: 7, first_input, num_channels, path: dir.join(name) }
+    }
+
+    /// A timeline player and a recorder whose input is lined up with it.
+    fn streams(
+        num_input_channels: usize,
+    ) -> (TimelineHandle, TimelinePlayer, Recorder, InputCapture) {
+        let (handle, player) = timeline::timeline(SAMPLE_RATE.0);
+        let (recorder, capture) = recorder(num_input_channels, SAMPLE_RATE, handle.clock());
+        (handle, player, recorder, capture)
+    }
+
+    /// Runs the output stream and the input stream for `num_blocks` blocks of
+    /// `block_frames` frames. Every block of input comes right after the block
+    /// of output it lines up with, and `input` returns the input frame at each
+    /// frame of the streams.
+    fn run_streams(
+        player: &mut TimelinePlayer,
+        capture: &mut InputCapture,
+        block_frames: usize,
+        num_blocks: usize,
+        input: impl Fn(u64) -> Vec<f32>,
+    ) {
+        for _ in 0..num_blocks {
+            let stream_frame = player_stream_frame(capture);
+            player.process_interleaved(&mut vec![0.0; block_frames * 2], 2);
+            let block: Vec<f32> =
+                (stream_frame..stream_frame + block_frames as u64).flat_map(&input).collect();
+            capture.process_interleaved(block.into_iter());
+        }
+    }
+
+    /// The frame of the streams that the next block starts at.
+    fn player_stream_frame(capture: &InputCapture) -> u64 {
+        capture.next_stream_frame.unwrap_or_else(|| capture.clock.stream_frame())
+    }
+
+    #[test]
+    fn takes_are_declicked_and_punched() {
+        let (mut handle, mut player, mut recorder, mut capture) = streams(2);
+        let settings = take("punched.wav", 1, 1);
+        handle.send(TimelineMsg::Play { from: 1000 });
+        recorder.start(vec![settings.clone()], handle.num_starts(), Some((1200, 2200))).unwrap();
+
+        // A constant input on the second channel, in uneven blocks.
+        run_streams(&mut player, &mut capture, 123, 25, |_| vec![0.0, 0.5]);
+        let takes = recorder.stop().unwrap();
+
+        assert_eq!(
+            takes,
+            vec![RecordedTake {
+                track: 7,
+                path: settings.path.clone(),
+                start: 1200,
+                num_frames: 1000,
+                pass: 0
+            }]
+        );
+
+        let samples = read_float_wav(&settings.path);
+        assert_eq!(samples.len(), 1000);
+        let declick_frames = (RECORD_DECLICK_SECS * SAMPLE_RATE.0).round() as usize;
+        assert!(samples[0] > 0.0 && samples[0] < 0.01);
+        assert!(samples[..declick_frames].windows(2).all(|w| w[1] > w[0]));
+        assert!(samples[declick_frames..1000 - declick_frames].iter().all(|s| *s == 0.5));
+        assert!(samples[999] > 0.0 && samples[999] < 0.01);
+    }
+
+    #[test]
+    fn takes_start_where_the_transport_started_while_it_was_moving() {
+        let (mut handle, mut player, mut recorder, mut capture) = streams(1);
+        // The input holds the number of the frame of the streams.
+        let block = |start: u64| (start..start + 100).map(|frame| frame as f32).collect::<Vec<_>>();
+        let mut out = vec![0.0; 100 * 2];
+
+        // The transport is already moving when recording starts from another
+        // position, and the input of the last block that played the old
+        // position only comes in after recording started.
+        handle.send(TimelineMsg::Play { from: 0 });
+        for i in 0..5 {
+            player.process_interleaved(&mut out, 2);
+            if i < 4 {
+                capture.process_interleaved(block(i * 100).into_iter());
+            }
+        }
+        handle.send(TimelineMsg::Play { from: 10_000 });
+        let settings = take("moving.wav", 0, 1);
+        recorder.start(vec![settings.clone()], handle.num_starts(), None).unwrap();
+        capture.process_interleaved(block(400).into_iter());
+        for i in 5..15 {
+            player.process_interleaved(&mut out, 2);
+            capture.process_interleaved(block(i * 100).into_iter());
+        }
+        let takes = recorder.stop().unwrap();
+
+        // The player picked up the new start at frame 500 of the streams.
+        assert_eq!(takes[0].start, 10_000);
+        assert_eq!(takes[0].num_frames, 1000);
+        let samples = read_float_wav(&settings.path);
+        let declick_frames = (RECORD_DECLICK_SECS * SAMPLE_RATE.0).round() as usize;
+        assert_eq!(samples[declick_frames], (500 + declick_frames) as f32);
+    }
+
+    #[test]
+    fn takes_start_at_the_punch_in_after_the_count_in() {
+        let (mut handle, mut player, mut recorder, mut capture) = streams(1);
+        // One bar of 4/4 at 2880 bpm is 4000 frames.
+        let count_in = CountIn::new(1, 4.0, 2880.0, SAMPLE_RATE.0);
+        handle.send(TimelineMsg::CountIn { from: 2000, count_in });
+        let settings = take("count_in.wav", 0, 1);
+        recorder.start(vec![settings.clone()], handle.num_starts(), Some((2100, 2600))).unwrap();
+
+        // The input holds the number of the frame of the streams.
+        run_streams(&mut player, &mut capture, 123, 60, |frame| vec![frame as f32]);
+        let takes = recorder.stop().unwrap();
+
+        assert_eq!(takes[0].start, 2100);
+        assert_eq!(takes[0].num_frames, 500);
+        // The transport started moving at frame 4000 of the streams, so the
+        // punch-in played at frame 4100.
+        let samples = read_float_wav(&settings.path);
+        let declick_frames = (RECORD_DECLICK_SECS * SAMPLE_RATE.0).round() as usize;
+        assert_eq!(samples[declick_frames], (4100 + declick_frames) as f32);
+    }
+
+    #[test]
+    fn every_pass_over_the_loop_is_a_take_of_its_own() {
+        let (mut handle, mut player, mut recorder, mut capture) = streams(1);
+        handle.send(TimelineMsg::SetLoop(Some(LoopRange { start: 1000, end: 2000 })));
+        handle.send(TimelineMsg::Play { from: 1500 });
+        let settings = take("looped.wav", 0, 1);
+        recorder.start(vec![settings.clone()], handle.num_starts(), None).unwrap();
+
+        // The input holds the number of the frame of the streams.
+        run_streams(&mut player, &mut capture, 128, 23, |frame| vec![frame as f32]);
+        let takes = recorder.stop().unwrap();
+
+        // 2944 frames: half a loop, two whole loops and the rest of the last
+        // pass.
+        let passes: Vec<(u64, u64, usize)> =
+            takes.iter().map(|t| (t.start, t.num_frames, t.pass)).collect();
+        assert_eq!(passes, vec![(1500, 500, 0), (1000, 1000, 1), (1000, 1000, 2), (1000, 444, 3)]);
+        assert_eq!(takes[0].path, settings.path);
+        assert_eq!(takes[3].path, loop_pass_path(&settings.path, 3));
+
+        // Every pass starts where the transport jumped back, and is declicked
+        // on its own.
+        let declick_frames = (RECORD_DECLICK_SECS * SAMPLE_RATE.0).round() as usize;
+        for (take, stream_start) in takes.iter().zip([0, 500, 1500, 2500]) {
+            let samples = read_float_wav(&take.path);
+            assert_eq!(samples.len() as u64, take.num_frames);
+            assert_eq!(samples[declick_frames], (stream_start + declick_frames) as f32);
+        }
+    }
+
+    #[test]
+    fn nothing_is_captured_while_not_recording() {
+        let (mut handle, mut player, mut recorder, mut capture) = streams(1);
+        handle.send(TimelineMsg::Play { from: 0 });
+        run_streams(&mut player, &mut capture, 512, 1, |_| vec![1.0]);
+
+        let settings = take("empty.wav", 0, 1);
+        recorder.start(vec![settings.clone()], handle.num_starts(), None).unwrap();
+        let takes = recorder.stop().unwrap();
+        run_streams(&mut player, &mut capture, 512, 1, |_| vec![1.0]);
+
+        assert_eq!(takes[0].num_frames, 0);
+        assert!(read_float_wav(&settings.path).is_empty());
+        assert!(!recorder.is_recording());
+    }
+}
@@ -0,0 +1,157 @@
+//! Logging from the audio thread.
+//!
+//! The audio thread can't call into `log` (which may lock or allocate), so it
+//! pushes small fixed-size records into a lock-free queue instead. The program
+//! layer drains the queue periodically and turns the records into log entries
+//! and notifications.
+
+use crossbeam::queue::ArrayQueue;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The number of records the queue can hold before new records are dropped.
+pub const RT_LOG_CAPACITY: usize = 256;
+
+/// Something that happened on the audio thread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RtEvent {
+    /// The audio callback was not called in time, so the output had a gap.
+    Xrun,
+    /// Samples that were NaN or infinite were replaced with silence.
+    NanSanitized { num_samples: u32 },
+    /// The timeline player couldn't send replaced state back to be
+    /// deallocated, because the program layer isn't collecting it. The player
+    /// holds off on new messages until it can.
+    GarbageDeferred,
+    /// Versions of clips that were still fading out after a rate change were
+    /// cut off, since the clips changed faster than the fades could finish.
+    FadesCutOff { track: u64, num_fades: u32 },
+    /// Any other event, identified by `code`.
+    Other { code: u32, value: f64 },
+}
+
+/// A single record in the audio thread log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RtLogRecord {
+    /// The number of the processed block this happened in, counted from the
+    /// start of the stream.
+    pub block: u64,
+    pub event: RtEvent,
+}
+
+/// The writing end of the audio thread log. This can be cloned and used from
+/// several threads at once.
+#[derive(Clone)]
+pub struct RtLogger {
+    queue: Arc<ArrayQueue<RtLogRecord>>,
+    num_dropped: Arc<AtomicU64>,
+}
+
+impl RtLogger {
+    /// Adds a record to the log.
+    ///
+    /// This never blocks or allocates. If the log is full, the record is
+    /// dropped and counted instead.
+    pub fn log(&self, block: u64, event: RtEvent) {
+        if self.queue.push(RtLogRecord { block, event }).is_err() {
+            self.num_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The reading end of the audio thread log.
+pub struct RtLogReader {
+    queue: Arc<ArrayQueue<RtLogRecord>>,
+    num_dropped: Arc<AtomicU64>,
+}
+
+impl RtLogReader {
+    /// Calls `f` with every record in the log, oldest first, and removes them.
+    ///
+    /// Returns the number of records that were dropped because the log was full
+    /// since the last call.
+    pub fn drain(&mut self, mut f: impl FnMut(RtLogRecord)) -> u64 {
+        while let Some(record) = self.queue.pop() {
+            f(record);
+        }
+        self.num_dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Creates a new audio thread log that holds up to `capacity` records.
+pub fn rt_log(capacity: usize) -> (RtLogger, RtLogReader) {
+    let queue = Arc::new(ArrayQueue::new(capacity.max(1)));
+    let num_dropped = Arc::new(AtomicU64::new(0));
+
+    (
+        RtLogger { queue: Arc::clone(&queue), num_dropped: Arc::clone(&num_dropped) },
+        RtLogReader { queue, num_dropped },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_drained_in_order_and_overflow_is_counted() {
+        let (logger, mut reader) = rt_log(4);
+        for block in 0..6 {
+            logger.log(block, RtEvent::Xrun);
+        }
+
+        let mut blocks = Vec::new();
+        assert_eq!(reader.drain(|record| blocks.push(record.block)), 2);
+        assert_eq!(blocks, [0, 1, 2, 3]);
+
+        // The count of dropped records starts over after every drain.
+        logger.log(6, RtEvent::GarbageDeferred);
+        let mut events = Vec::new();
+        assert_eq!(reader.drain(|record| events.push(record.event)), 0);
+        assert_eq!(events, [RtEvent::GarbageDeferred]);
+    }
+
+    #[test]
+    fn several_producers_can_log_at_once() {
+        const NUM_PRODUCERS: u32 = 4;
+        const NUM_RECORDS: u32 = 20_000;
+
+        let (logger, mut reader) = rt_log(64);
+        let producers: Vec<_> = (0..NUM_PRODUCERS)
+            .map(|producer| {
+                let logger = logger.clone();
+                std::thread::spawn(move || {
+                    for i in 0..NUM_RECORDS {
+                        logger.log(u64::from(i), RtEvent::Other { code: producer, value: 0.0 });
+                    }
+                })
+            })
+            .collect();
+
+        // Drain while the producers are logging, so that the queue is
+        // contended from both ends.
+        let mut last_blocks = [None; NUM_PRODUCERS as usize];
+        let mut num_received = 0;
+        let mut num_dropped = 0;
+        let mut check = |record: RtLogRecord| {
+            let producer = match record.event {
+                RtEvent::Other { code, .. } => code as usize,
+                event => panic!("unexpected event {:?}", event),
+            };
+            // The records of each producer come out in the order they were
+            // logged in.
+            assert!(last_blocks[producer] < Some(record.block));
+            last_blocks[producer] = Some(record.block);
+            num_received += 1;
+        };
+        while producers.iter().any(|p| !p.is_finished()) {
+            num_dropped += reader.drain(&mut check);
+        }
+        num_dropped += reader.drain(&mut check);
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        assert_eq!(num_received + num_dropped, u64::from(NUM_PRODUCERS * NUM_RECORDS));
+    }
+}
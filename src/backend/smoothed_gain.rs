@@ -0,0 +1,90 @@
+//! A gain stage that ramps to new values instead of jumping to them.
+//!
+//! This is used for the input trim of a timeline track, which is applied to the
+//! summed audio of the track's clips before its volume and pan, and for the
+//! gains of the track's pan. Metering and
+//! automation can tap the audio before and after `SmoothedGain::process()`.
+
+/// The time in seconds it takes a `SmoothedGain` to reach a new value.
+pub const GAIN_SMOOTHING_SECS: f64 = 0.02;
+
+/// A linear gain that ramps to its target over `GAIN_SMOOTHING_SECS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmoothedGain {
+    current: f32,
+    target: f32,
+    /// The amount `current` moves by every frame while ramping.
+    step: f32,
+    ramp_frames: usize,
+    frames_left: usize,
+}
+
+impl SmoothedGain {
+    /// Creates a gain of `gain_db` decibels that starts out at its target.
+    pub fn new(gain_db: f32, sample_rate: f64) -> Self {
+        let gain = db_to_gain(gain_db);
+        Self {
+            current: gain,
+            target: gain,
+            step: 0.0,
+            ramp_frames: (GAIN_SMOOTHING_SECS * sample_rate).round().max(1.0) as usize,
+            frames_left: 0,
+        }
+    }
+
+    /// Starts ramping from the current gain to `gain_db` decibels.
+    pub fn set_target_db(&mut self, gain_db: f32) {
+        self.set_target(db_to_gain(gain_db));
+    }
+
+    /// Starts ramping from the current gain to the linear gain `target`.
+    pub fn set_target(&mut self, target: f32) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        self.frames_left = self.ramp_frames;
+        self.step = (self.target - self.current) / self.ramp_frames as f32;
+    }
+
+    /// Jumps to the target instead of ramping to it.
+    pub fn finish(&mut self) {
+        self.current = self.target;
+        self.frames_left = 0;
+    }
+
+    /// Returns `true` if the gain is still ramping to its target.
+    pub fn is_smoothing(&self) -> bool {
+        self.frames_left > 0
+    }
+
+    /// Returns `true` if the gain is at unity and not ramping, in which case
+    /// `process()` leaves the audio as it is.
+    pub fn is_unity(&self) -> bool {
+        !self.is_smoothing() && self.current == 1.0
+    }
+
+    /// Applies the gain to `buffers` (one buffer per channel, all of the same
+    /// length).
+    pub fn process(&mut self, buffers: &mut [&mut [f32]]) {
+        if self.is_unity() {
+            return;
+        }
+        let num_frames = buffers.iter().map(|b| b.len()).min().unwrap_or(0);
+
+        for frame in 0..num_frames {
+            if self.frames_left > 0 {
+                self.frames_left -= 1;
+                self.current =
+                    if self.frames_left == 0 { self.target } else { self.current + self.step };
+            }
+            for buffer in buffers.iter_mut() {
+                buffer[frame] *= self.current;
+            }
+        }
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
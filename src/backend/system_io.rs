@@ -1,12 +1,27 @@
 use std::error::Error;
+use std::fmt::Debug;
+use std::sync::Arc;
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::Stream;
+use cpal::{Sample, SampleFormat, Stream};
 use dropseed::DSEngineAudioThread;
 use meadowlark_core_types::time::SampleRate;
-use rtrb::{Producer, RingBuffer};
+use rtrb::{Producer, PushError, RingBuffer};
 
-const HANDLE_TO_STREAM_MSG_SIZE: usize = 8;
+use super::recorder::{self, RecordedTake, Recorder};
+use super::rt_log::{self, RtEvent, RtLogReader, RtLogRecord, RT_LOG_CAPACITY};
+use super::timeline::{self, TimelineHandle};
+use super::transport_clock::TransportClock;
+
+/// The number of messages the ring buffer to the stream holds.
+///
+/// Every restart of the engine sends two messages (`DropEngineAudioThread` and
+/// `NewEngineAudioThread`), and while the stream is stalled (i.e. while the
+/// device is being reconfigured), a crash recovery can go through all of its
+/// `MAX_ENGINE_RESTART_ATTEMPTS` in a row. Eight slots left little headroom for
+/// that, so this leaves plenty of room, and collapsing messages in
+/// `CollapsingSender` stays a last resort.
+const HANDLE_TO_STREAM_MSG_SIZE: usize = 32;
 
 #[derive(Debug)]
 enum HandleToStreamMsg {
@@ -14,10 +29,71 @@ enum HandleToStreamMsg {
     DropEngineAudioThread,
 }
 
+/// Sends messages to the stream where only the newest one matters, since each
+/// of them replaces the state the stream had.
+struct CollapsingSender<T> {
+    tx: Producer<T>,
+
+    /// A message that could not be sent because the ring buffer was full (i.e.
+    /// because the stream callback stalled). A new message replaces the
+    /// pending one instead of being queued behind it.
+    pending_msg: Option<T>,
+
+    /// The number of messages that were replaced by a newer message before they
+    /// could be sent.
+    num_collapsed_msgs: u64,
+}
+
+impl<T: Debug> CollapsingSender<T> {
+    fn new(tx: Producer<T>) -> Self {
+        Self { tx, pending_msg: None, num_collapsed_msgs: 0 }
+    }
+
+    /// Tries to send the pending message. Returns `true` if no message is
+    /// pending anymore.
+    fn flush_pending(&mut self) -> bool {
+        if let Some(msg) = self.pending_msg.take() {
+            if let Err(PushError::Full(msg)) = self.tx.push(msg) {
+                self.pending_msg = Some(msg);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn send(&mut self, msg: T) {
+        if !self.flush_pending() {
+            // The pending message hasn't reached the stream yet, and this one
+            // supersedes it.
+            log::warn!(
+                "Audio stream is not keeping up, replacing pending message {:?}",
+                &self.pending_msg
+            );
+            self.pending_msg = Some(msg);
+            self.num_collapsed_msgs += 1;
+            return;
+        }
+
+        if let Err(PushError::Full(msg)) = self.tx.push(msg) {
+            log::warn!("Audio stream is not keeping up, sending message later");
+            self.pending_msg = Some(msg);
+        }
+    }
+}
+
 pub struct SystemIOStreamHandle {
     cpal_stream: Stream,
-    to_stream_tx: Producer<HandleToStreamMsg>,
+    to_stream_tx: CollapsingSender<HandleToStreamMsg>,
     sample_rate: SampleRate,
+    device_name: Option<String>,
+
+    rt_log_reader: RtLogReader,
+
+    timeline: TimelineHandle,
+
+    /// The input stream and the recorder it feeds, once the input device was
+    /// opened.
+    input: Option<(Stream, Recorder)>,
 }
 
 impl SystemIOStreamHandle {
@@ -25,14 +101,74 @@ impl SystemIOStreamHandle {
         self.sample_rate
     }
 
+    /// The name of the audio device, if the driver reports one.
+    pub fn device_name(&self) -> Option<&str> {
+        self.device_name.as_deref()
+    }
+
+    /// The handle of the timeline player, which the stream runs after the
+    /// engine. The timeline keeps playing while the engine is restarted.
+    pub fn timeline(&mut self) -> &mut TimelineHandle {
+        &mut self.timeline
+    }
+
+    /// The recorder of the default input device. The device is opened when
+    /// the engine is activated, so that its input is lined up with the output
+    /// long before recording starts. If that failed, it is tried again here.
+    pub fn recorder(&mut self) -> Result<&mut Recorder, Box<dyn Error>> {
+        let input = match self.input.take() {
+            Some(input) => input,
+            None => temp_spawn_cpal_default_input(self.sample_rate, self.timeline.clock())?,
+        };
+        Ok(&mut self.input.insert(input).1)
+    }
+
+    /// Stops the recorder (if the input device was opened) and returns the
+    /// recorded takes.
+    pub fn stop_recording(&mut self) -> Result<Vec<RecordedTake>, Box<dyn Error>> {
+        match &mut self.input {
+            Some((_, recorder)) => recorder.stop(),
+            None => Ok(Vec::new()),
+        }
+    }
+
     pub fn engine_activated(&mut self, engine_audio_thread: DSEngineAudioThread) {
-        self.to_stream_tx
-            .push(HandleToStreamMsg::NewEngineAudioThread(engine_audio_thread))
-            .unwrap();
+        self.to_stream_tx.send(HandleToStreamMsg::NewEngineAudioThread(engine_audio_thread));
+
+        if self.input.is_none() {
+            match temp_spawn_cpal_default_input(self.sample_rate, self.timeline.clock()) {
+                Ok(input) => self.input = Some(input),
+                // Playback works without an input device.
+                Err(e) => log::warn!("Failed to open the audio input: {}", e),
+            }
+        }
     }
 
     pub fn engine_deactivated(&mut self) {
-        self.to_stream_tx.push(HandleToStreamMsg::DropEngineAudioThread).unwrap();
+        self.to_stream_tx.send(HandleToStreamMsg::DropEngineAudioThread);
+    }
+
+    /// Calls `f` with every record the audio thread logged since the last call.
+    ///
+    /// Returns the number of records that were dropped because the log was
+    /// full.
+    pub fn drain_rt_log(&mut self, f: impl FnMut(RtLogRecord)) -> u64 {
+        self.rt_log_reader.drain(f)
+    }
+
+    /// The number of messages to the stream that were replaced by a newer
+    /// message before they could be sent.
+    pub fn num_collapsed_msgs(&self) -> u64 {
+        self.to_stream_tx.num_collapsed_msgs
+    }
+
+    /// Tries to send the message that could not be sent earlier because the
+    /// ring buffer was full.
+    ///
+    /// This should be called periodically (i.e. on every engine poll). Returns
+    /// `true` if no message is pending anymore.
+    pub fn flush_pending(&mut self) -> bool {
+        self.to_stream_tx.flush_pending()
     }
 }
 
@@ -48,7 +184,8 @@ pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box
         .default_output_device()
         .ok_or("CPAL: no default audio out device found".to_string())?;
 
-    log::info!("Selected default CPAL output device: {:?}", &device.name());
+    let device_name = device.name().ok();
+    log::info!("Selected default CPAL output device: {:?}", &device_name);
 
     let config = device.default_output_config()?;
 
@@ -56,12 +193,32 @@ pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box
     let sample_rate: SampleRate = config.sample_rate().0.into();
 
     let mut engine_audio_thread: Option<DSEngineAudioThread> = None;
+    let (timeline, mut timeline_player) = timeline::timeline(sample_rate.0);
+
+    let (rt_logger, rt_log_reader) = rt_log::rt_log(RT_LOG_CAPACITY);
+    timeline_player.set_rt_logger(rt_logger.clone());
+    let mut block: u64 = 0;
+    let mut prev_callback: Option<cpal::StreamInstant> = None;
 
     log::info!("Starting CPAL stream with config {:?}...", &config);
 
     let cpal_stream = device.build_output_stream(
         &config.into(),
-        move |audio_buffer: &mut [f32], _: &cpal::OutputCallbackInfo| {
+        move |audio_buffer: &mut [f32], info: &cpal::OutputCallbackInfo| {
+            block += 1;
+
+            // A callback that comes much later than the length of the previous
+            // buffer means the output had a gap.
+            let callback = info.timestamp().callback;
+            if let Some(elapsed) = prev_callback.and_then(|prev| callback.duration_since(&prev)) {
+                let expected_secs =
+                    (audio_buffer.len() / num_out_channels.max(1)) as f64 / sample_rate.0;
+                if elapsed.as_secs_f64() > expected_secs * 1.5 {
+                    rt_logger.log(block, RtEvent::Xrun);
+                }
+            }
+            prev_callback = Some(callback);
+
             while let Ok(msg) = from_handle_rx.pop() {
                 match msg {
                     HandleToStreamMsg::NewEngineAudioThread(new_engine_audio_thread) => {
@@ -76,6 +233,22 @@ pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box
             if let Some(engine_audio_thread) = &mut engine_audio_thread {
                 engine_audio_thread
                     .process_cpal_interleaved_output_only(num_out_channels, audio_buffer);
+            } else {
+                audio_buffer.fill(0.0);
+            }
+
+            timeline_player.process_interleaved(audio_buffer, num_out_channels);
+
+            // Never send NaN or infinite samples to the speakers.
+            let mut num_sanitized = 0;
+            for s in audio_buffer.iter_mut() {
+                if !s.is_finite() {
+                    *s = 0.0;
+                    num_sanitized += 1;
+                }
+            }
+            if num_sanitized > 0 {
+                rt_logger.log(block, RtEvent::NanSanitized { num_samples: num_sanitized });
             }
         },
         |e| {
@@ -88,5 +261,117 @@ pub fn temp_spawn_cpal_default_output_only() -> Result<SystemIOStreamHandle, Box
 
     log::info!("Successfully started CPAL stream");
 
-    Ok(SystemIOStreamHandle { cpal_stream, to_stream_tx, sample_rate })
+    Ok(SystemIOStreamHandle {
+        cpal_stream,
+        to_stream_tx: CollapsingSender::new(to_stream_tx),
+        sample_rate,
+        device_name,
+        rt_log_reader,
+        timeline,
+        input: None,
+    })
+}
+
+/// Opens the default input device at `sample_rate` and starts capturing from
+/// it into a recorder, lined up with the output stream that runs `clock`.
+///
+/// This is temporary, like `temp_spawn_cpal_default_output_only()`.
+fn temp_spawn_cpal_default_input(
+    sample_rate: SampleRate,
+    clock: Arc<TransportClock>,
+) -> Result<(Stream, Recorder), Box<dyn Error>> {
+    let cpal_host = cpal::default_host();
+
+    let device = cpal_host
+        .default_input_device()
+        .ok_or("CPAL: no default audio in device found".to_string())?;
+
+    log::info!("Selected default CPAL input device: {:?}", device.name().ok());
+
+    // The input must run at the same sample rate as the output, or the
+    // recording would drift against the timeline.
+    let cpal_sample_rate = cpal::SampleRate(sample_rate.as_u32());
+    let config = device
+        .supported_input_configs()?
+        .find(|config| {
+            config.min_sample_rate() <= cpal_sample_rate
+                && cpal_sample_rate <= config.max_sample_rate()
+        })
+        .ok_or(format!(
+            "CPAL: the default audio in device does not support the sample rate {}",
+            cpal_sample_rate.0
+        ))?
+        .with_sample_rate(cpal_sample_rate);
+
+    let sample_format = config.sample_format();
+    let num_in_channels = usize::from(config.channels());
+    let (recorder, mut capture) = recorder::recorder(num_in_channels, sample_rate, clock);
+
+    log::info!("Starting CPAL input stream with config {:?}...", &config);
+
+    let config: cpal::StreamConfig = config.into();
+    let err_fn = |e| {
+        // TODO: Better handling of the system IO stream crashing.
+        log::error!("CPAL input stream error: {}", e);
+    };
+    let input_stream = match sample_format {
+        SampleFormat::F32 => device.build_input_stream(
+            &config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                capture.process_interleaved(data.iter().copied());
+            },
+            err_fn,
+        )?,
+        SampleFormat::I16 => device.build_input_stream(
+            &config,
+            move |data: &[i16], _: &cpal::InputCallbackInfo| {
+                capture.process_interleaved(data.iter().map(|s| s.to_f32()));
+            },
+            err_fn,
+        )?,
+        SampleFormat::U16 => device.build_input_stream(
+            &config,
+            move |data: &[u16], _: &cpal::InputCallbackInfo| {
+                capture.process_interleaved(data.iter().map(|s| s.to_f32()));
+            },
+            err_fn,
+        )?,
+    };
+
+    input_stream.play()?;
+
+    log::info!("Successfully started CPAL input stream");
+
+    Ok((input_stream, recorder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_ring_collapses_to_the_newest_message_and_flushes_in_order() {
+        let (tx, mut rx) = RingBuffer::<u32>::new(2);
+        let mut sender = CollapsingSender::new(tx);
+
+        for msg in 1..=5 {
+            sender.send(msg);
+        }
+        // 3 is pending, and 4 and 5 each replaced the one before.
+        assert_eq!(sender.pending_msg, Some(5));
+        assert_eq!(sender.num_collapsed_msgs, 2);
+        assert!(!sender.flush_pending());
+
+        // The stream catches up with the messages in the ring, and the newest
+        // state follows them.
+        assert_eq!(rx.pop(), Ok(1));
+        assert!(sender.flush_pending());
+        let received: Vec<u32> = std::iter::from_fn(|| rx.pop().ok()).collect();
+        assert_eq!(received, [2, 5]);
+
+        // Once the ring has room, messages go straight through again.
+        sender.send(6);
+        assert_eq!(sender.pending_msg, None);
+        assert_eq!(rx.pop(), Ok(6));
+    }
 }
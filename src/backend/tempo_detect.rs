@@ -0,0 +1,234 @@
+//! Offline tempo detection for audio clips.
+
+use crossbeam::channel::{self, Receiver, Sender};
+use meadowlark_core_types::time::SampleRate;
+use std::path::PathBuf;
+
+use super::decode;
+
+/// The distance between two frames of the onset envelope in samples (at
+/// 44.1kHz, this is about 11.6ms).
+const ONSET_HOP_SECS: f64 = 512.0 / 44_100.0;
+
+/// The range of tempos that are searched.
+const MIN_BPM: f64 = 50.0;
+const MAX_BPM: f64 = 240.0;
+
+/// Tempos in this range are preferred when a tempo could also be read as half
+/// or double time.
+const PREFERRED_MIN_BPM: f64 = 80.0;
+const PREFERRED_MAX_BPM: f64 = 160.0;
+
+/// How far (as a fraction) a tempo may be outside of the preferred range
+/// before it is folded into it, so that i.e. 161 BPM isn't halved.
+const PREFERRED_RANGE_TOLERANCE: f64 = 0.03;
+
+/// The number of beats over which the repetition of the onsets is measured.
+const COMB_LENGTH: usize = 4;
+
+/// The resolution (in frames) of the tempo search.
+const LAG_STEP: f64 = 0.25;
+
+/// A faster tempo that divides the best one is preferred if it fits at least
+/// this well relative to it.
+const FASTER_TEMPO_RATIO: f64 = 0.75;
+
+/// Clips are only fitted to the project tempo if their tempo was estimated
+/// with at least this confidence.
+pub const MIN_TEMPO_FIT_CONFIDENCE: f64 = 0.3;
+
+/// An estimated tempo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoEstimate {
+    pub bpm: f64,
+    /// How clearly the audio has this tempo, in the range [0.0, 1.0].
+    pub confidence: f64,
+}
+
+/// Returns how strongly new sounds start at each frame of `buffers` (one buffer
+/// per channel), as the increase in energy from the previous frame.
+///
+/// Returns the envelope and the length of one frame in seconds.
+pub fn onset_envelope<T: AsRef<[f32]>>(buffers: &[T], sample_rate: SampleRate) -> (Vec<f32>, f64) {
+    let hop = ((ONSET_HOP_SECS * sample_rate.0).round() as usize).max(1);
+    let num_frames = buffers.iter().map(|b| b.as_ref().len()).min().unwrap_or(0);
+    let num_hops = num_frames / hop;
+
+    let mut prev_energy = 0.0f32;
+    let mut envelope = Vec::with_capacity(num_hops);
+    for i in 0..num_hops {
+        let mut sum = 0.0f32;
+        for buffer in buffers.iter() {
+            for s in buffer.as_ref()[i * hop..(i + 1) * hop].iter() {
+                sum += s * s;
+            }
+        }
+        let energy = (1.0 + 1000.0 * sum / hop as f32).ln();
+
+        envelope.push((energy - prev_energy).max(0.0));
+        prev_energy = energy;
+    }
+
+    (envelope, hop as f64 / sample_rate.0)
+}
+
+/// Estimates the tempo of `buffers` (one buffer per channel).
+///
+/// Returns `None` if the audio is too short or has no clear onsets.
+pub fn detect_tempo<T: AsRef<[f32]>>(
+    buffers: &[T],
+    sample_rate: SampleRate,
+) -> Option<TempoEstimate> {
+    let (mut envelope, frame_secs) = onset_envelope(buffers, sample_rate);
+
+    let mean = envelope.iter().sum::<f32>() / envelope.len().max(1) as f32;
+    for x in envelope.iter_mut() {
+        *x -= mean;
+    }
+
+    let lag_for_bpm = |bpm: f64| 60.0 / bpm / frame_secs;
+    let min_lag = lag_for_bpm(MAX_BPM).max(1.0);
+    let max_lag = lag_for_bpm(MIN_BPM);
+    let max_comb_lag = (max_lag * COMB_LENGTH as f64).ceil() as usize + 2;
+    // At least two beats of the slowest tempo are needed.
+    if envelope.len() < (max_lag * 2.0).ceil() as usize || envelope.len() <= max_comb_lag {
+        return None;
+    }
+
+    let energy: f32 = envelope.iter().map(|x| x * x).sum();
+    if energy <= f32::EPSILON {
+        return None;
+    }
+
+    // The normalized autocorrelation of the envelope at every lag.
+    let autocorrelation: Vec<f64> = (0..=max_comb_lag)
+        .map(|lag| {
+            let sum: f32 = envelope.iter().zip(envelope[lag..].iter()).map(|(a, b)| a * b).sum();
+            // Correct for the shorter overlap at longer lags.
+            f64::from(sum / energy) * envelope.len() as f64 / (envelope.len() - lag) as f64
+        })
+        .collect();
+
+    // The onsets only land on whole frames, so the peaks of the autocorrelation
+    // are smeared over neighboring lags.
+    let peak_near = |lag: f64| {
+        let center = lag.round() as usize;
+        (center.saturating_sub(1)..=(center + 1).min(max_comb_lag))
+            .map(|lag| autocorrelation[lag])
+            .fold(f64::MIN, f64::max)
+    };
+
+    // How well the onsets repeat every `lag` frames, measured over several
+    // beats so that a single strong multiple of the beat doesn't win.
+    let comb = |lag: f64| {
+        (1..=COMB_LENGTH).map(|k| peak_near(lag * k as f64)).sum::<f64>() / COMB_LENGTH as f64
+    };
+
+    let mut best = (0.0, f64::MIN);
+    let mut lag = min_lag;
+    while lag <= max_lag {
+        let score = comb(lag);
+        if score > best.1 {
+            best = (lag, score);
+        }
+        lag += LAG_STEP;
+    }
+    let (mut best_lag, mut best_score) = best;
+    if best_lag <= 0.0 || best_score <= 0.0 {
+        return None;
+    }
+
+    // A slow tempo repeats whenever a faster one does, so pick the fastest tempo
+    // that fits almost as well (i.e. not 58 BPM for a beat at 174 BPM).
+    'faster: loop {
+        for divisor in [2.0, 3.0] {
+            let lag = best_lag / divisor;
+            if lag >= min_lag {
+                let score = comb(lag);
+                if score >= best_score * FASTER_TEMPO_RATIO {
+                    best_lag = lag;
+                    best_score = score;
+                    continue 'faster;
+                }
+            }
+        }
+        break;
+    }
+
+    // Refine the lag with a parabola through the autocorrelation around its
+    // last multiple, which is the most precise.
+    let multiple = (best_lag * COMB_LENGTH as f64).round() as usize;
+    let peak = (multiple.saturating_sub(1)..=(multiple + 1).min(max_comb_lag - 1))
+        .max_by(|a, b| autocorrelation[*a].total_cmp(&autocorrelation[*b]))
+        .unwrap_or(multiple)
+        .max(1);
+    let (a, b, c) = (autocorrelation[peak - 1], autocorrelation[peak], autocorrelation[peak + 1]);
+    let denom = a - 2.0 * b + c;
+    let offset =
+        if denom.abs() > f64::EPSILON { (0.5 * (a - c) / denom).clamp(-0.5, 0.5) } else { 0.0 };
+    let refined_lag = (peak as f64 + offset) / COMB_LENGTH as f64;
+    let mut bpm = 60.0 / (refined_lag * frame_secs);
+
+    // Prefer a typical tempo over its half or double time.
+    while bpm < PREFERRED_MIN_BPM * (1.0 - PREFERRED_RANGE_TOLERANCE) && bpm * 2.0 <= MAX_BPM {
+        bpm *= 2.0;
+    }
+    while bpm > PREFERRED_MAX_BPM * (1.0 + PREFERRED_RANGE_TOLERANCE) && bpm / 2.0 >= MIN_BPM {
+        bpm /= 2.0;
+    }
+
+    Some(TempoEstimate { bpm, confidence: best_score.clamp(0.0, 1.0) })
+}
+
+/// The result of analyzing the tempo of an audio file in the background.
+#[derive(Debug, Clone)]
+pub struct TempoAnalysis {
+    pub path: PathBuf,
+    /// The estimated tempo, `None` if the file has no clear tempo, or an error
+    /// message if the file could not be decoded.
+    pub result: Result<Option<TempoEstimate>, String>,
+}
+
+/// Analyzes the tempo of audio files on background threads, so that decoding
+/// long files doesn't block the UI.
+pub struct TempoAnalyzer {
+    tx: Sender<TempoAnalysis>,
+    rx: Receiver<TempoAnalysis>,
+}
+
+impl TempoAnalyzer {
+    pub fn new() -> Self {
+        let (tx, rx) = channel::unbounded();
+        Self { tx, rx }
+    }
+
+    /// Starts analyzing the audio file at `path`. The result can be collected
+    /// with `poll()`.
+    pub fn analyze(&self, path: PathBuf) {
+        let tx = self.tx.clone();
+        let res = std::thread::Builder::new().name("tempo analysis".into()).spawn(move || {
+            let result = decode::decode_file(&path)
+                .map(|decoded| {
+                    detect_tempo(&decoded.buffers, SampleRate(f64::from(decoded.sample_rate)))
+                })
+                .map_err(|e| e.to_string());
+
+            // The receiver is gone if the program is shutting down.
+            let _ = tx.send(TempoAnalysis { path, result });
+        });
+        if let Err(e) = res {
+            log::error!("Failed to spawn tempo analysis thread: {}", e);
+        }
+    }
+
+    /// Returns the analyses that finished since the last call.
+    pub fn poll(&self) -> impl Iterator<Item = TempoAnalysis> + '_ {
+        self.rx.try_iter()
+    }
+}
+
+impl Default for TempoAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
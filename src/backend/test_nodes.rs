@@ -0,0 +1,132 @@
+//! Controllable nodes for testing the engine end to end, and other pieces that
+//! tests of the backend share.
+//!
+//! A `HeadlessEngine` runs a chain of these after its audio graph and its
+//! timeline, so that tests can feed a known signal through the engine, change
+//! its level while the engine runs, and read back what came out. The settings
+//! of the nodes are shared with the test through atomics, like the parameters
+//! of a plugin.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::smoothed_gain::SmoothedGain;
+use super::timeline::ClipSource;
+
+/// Plays the source audio of a clip from its start at its original speed.
+pub struct Unwarped;
+
+impl ClipSource for Unwarped {
+    fn position_and_gain(&self, frame: u64) -> (f64, f32) {
+        (frame as f64, 1.0)
+    }
+
+    fn read(&self, source: &[f32], pos: f64, _rate: f64) -> f32 {
+        source.get(pos as usize).copied().unwrap_or(0.0)
+    }
+}
+
+/// Returns a 440 Hz sine at half of full scale, sampled at 48 kHz.
+pub fn sine(num_frames: usize) -> Vec<f32> {
+    (0..num_frames)
+        .map(|i| (i as f64 * 440.0 * std::f64::consts::TAU / 48_000.0).sin() as f32 * 0.5)
+        .collect()
+}
+
+/// A node in the test chain of a `HeadlessEngine`.
+pub trait TestNode: Send {
+    /// Processes a block in place. `buffers` holds one buffer per output
+    /// channel, all of the same length.
+    fn process(&mut self, buffers: &mut [Vec<f32>]);
+}
+
+/// An `f32` that can be set from the test while the engine runs.
+#[derive(Debug, Default)]
+pub struct SharedParam(AtomicU32);
+
+impl SharedParam {
+    pub fn new(value: f32) -> Arc<Self> {
+        Arc::new(Self(AtomicU32::new(value.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Replaces its input with a sine tone on every channel.
+pub struct ToneNode {
+    pub hz: Arc<SharedParam>,
+    pub amplitude: Arc<SharedParam>,
+    /// The phase of the next frame in cycles.
+    phase: f64,
+    sample_rate: f64,
+}
+
+impl ToneNode {
+    pub fn new(hz: f32, amplitude: f32, sample_rate: f64) -> Self {
+        Self {
+            hz: SharedParam::new(hz),
+            amplitude: SharedParam::new(amplitude),
+            phase: 0.0,
+            sample_rate,
+        }
+    }
+}
+
+impl TestNode for ToneNode {
+    fn process(&mut self, buffers: &mut [Vec<f32>]) {
+        let step = f64::from(self.hz.get()) / self.sample_rate;
+        let amplitude = self.amplitude.get();
+        let num_frames = buffers.iter().map(|b| b.len()).min().unwrap_or(0);
+        for frame in 0..num_frames {
+            let s = (self.phase * std::f64::consts::TAU).sin() as f32 * amplitude;
+            for buffer in buffers.iter_mut() {
+                buffer[frame] = s;
+            }
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+/// Applies a gain in decibels, which ramps to new settings like the input trim
+/// of a timeline track.
+pub struct GainNode {
+    pub gain_db: Arc<SharedParam>,
+    gain: SmoothedGain,
+}
+
+impl GainNode {
+    pub fn new(gain_db: f32, sample_rate: f64) -> Self {
+        Self { gain_db: SharedParam::new(gain_db), gain: SmoothedGain::new(gain_db, sample_rate) }
+    }
+}
+
+impl TestNode for GainNode {
+    fn process(&mut self, buffers: &mut [Vec<f32>]) {
+        self.gain.set_target_db(self.gain_db.get());
+        let mut buffers: Vec<&mut [f32]> = buffers.iter_mut().map(|b| b.as_mut_slice()).collect();
+        self.gain.process(&mut buffers);
+    }
+}
+
+/// Records its input into a buffer per channel that the test can read, and
+/// passes it on unchanged.
+#[derive(Default)]
+pub struct CaptureNode {
+    pub captured: Arc<Mutex<Vec<Vec<f32>>>>,
+}
+
+impl TestNode for CaptureNode {
+    fn process(&mut self, buffers: &mut [Vec<f32>]) {
+        let mut captured = self.captured.lock().unwrap();
+        captured.resize(buffers.len(), Vec::new());
+        for (captured, buffer) in captured.iter_mut().zip(buffers.iter()) {
+            captured.extend_from_slice(buffer);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Offline time stretching that keeps the pitch (WSOLA).
+
+use meadowlark_core_types::time::SampleRate;
+
+/// The length of one grain in seconds.
+const GRAIN_SECS: f64 = 0.04;
+
+/// How far (in seconds) the start of a grain may be moved to line up its
+/// waveform with the previous grain.
+const SEARCH_SECS: f64 = 0.01;
+
+/// Only every n-th sample and offset is compared when searching for the best
+/// alignment, which is much faster and good enough.
+const SEARCH_STRIDE: usize = 4;
+
+/// Stretches `buffers` (one buffer per channel, all of the same length) by
+/// `ratio` without changing the pitch, so the output is `ratio` times as long as
+/// the input.
+///
+/// All channels are stretched with the same grain positions so they stay in
+/// phase with each other.
+pub fn time_stretch(buffers: &[Vec<f32>], ratio: f64, sample_rate: SampleRate) -> Vec<Vec<f32>> {
+    let in_len = buffers.iter().map(|b| b.len()).min().unwrap_or(0);
+    let out_len = (in_len as f64 * ratio).round() as usize;
+    if in_len == 0 || out_len == 0 || ratio <= 0.0 {
+        return vec![Vec::new(); buffers.len()];
+    }
+    if (ratio - 1.0).abs() < 1e-9 {
+        return buffers.iter().map(|b| b[0..in_len].to_vec()).collect();
+    }
+
+    let grain_len = (((GRAIN_SECS * sample_rate.0) as usize) / 2 * 2).max(4);
+    let synthesis_hop = grain_len / 2;
+    let analysis_hop = synthesis_hop as f64 / ratio;
+    let search_len = (SEARCH_SECS * sample_rate.0) as isize;
+
+    let window: Vec<f32> = (0..grain_len)
+        .map(|i| {
+            0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / grain_len as f64).cos() as f32
+        })
+        .collect();
+
+    // Only the sum of the channels is used to find the alignment.
+    let mono: Vec<f32> = (0..in_len).map(|i| buffers.iter().map(|b| b[i]).sum::<f32>()).collect();
+    let sample =
+        |pos: isize| if pos >= 0 && (pos as usize) < in_len { mono[pos as usize] } else { 0.0 };
+
+    let mut out = vec![vec![0.0f32; out_len + grain_len]; buffers.len()];
+    let mut window_sum = vec![0.0f32; out_len + grain_len];
+
+    let mut prev_pos: Option<isize> = None;
+    let mut grain = 0;
+    loop {
+        let out_pos = grain * synthesis_hop;
+        if out_pos >= out_len {
+            break;
+        }
+        let nominal = (grain as f64 * analysis_hop).round() as isize;
+
+        // Find the grain start near `nominal` whose waveform best continues the
+        // previous grain.
+        let pos = match prev_pos {
+            None => nominal,
+            Some(prev_pos) => {
+                let continuation = prev_pos + synthesis_hop as isize;
+                let mut best = (nominal, f32::MIN);
+                let mut delta = -search_len;
+                while delta <= search_len {
+                    let candidate = nominal + delta;
+                    let mut corr = 0.0;
+                    for i in (0..grain_len as isize).step_by(SEARCH_STRIDE) {
+                        corr += sample(candidate + i) * sample(continuation + i);
+                    }
+                    if corr > best.1 {
+                        best = (candidate, corr);
+                    }
+                    delta += SEARCH_STRIDE as isize;
+                }
+                best.0
+            }
+        };
+
+        for (channel, buffer) in buffers.iter().enumerate() {
+            for i in 0..grain_len {
+                let src = pos + i as isize;
+                if src >= 0 && (src as usize) < in_len {
+                    out[channel][out_pos + i] += buffer[src as usize] * window[i];
+                }
+            }
+        }
+        for i in 0..grain_len {
+            window_sum[out_pos + i] += window[i];
+        }
+
+        prev_pos = Some(pos);
+        grain += 1;
+    }
+
+    for buffer in out.iter_mut() {
+        buffer.truncate(out_len);
+        for (s, w) in buffer.iter_mut().zip(window_sum.iter()) {
+            if *w > 1e-3 {
+                *s /= *w;
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,2543 @@
+//! The playback of the timeline.
+//!
+//! The program layer keeps a `TimelineHandle` and sends it the clips and
+//! settings of every track whenever they change. The `TimelinePlayer` on the
+//! other end runs on the audio thread right after the engine's audio graph, and
+//! adds the tracks to the output. It is run by the system IO stream and by
+//! `HeadlessEngine`, and offline by `render_timeline()`.
+//!
+//! Each track sums its clips, and then applies its input stage (the input trim
+//! and the polarity), its volume and the gains of its pan to the sum. The
+//! volume and the pan follow the automation lanes of the track while it has
+//! any (see `backend::automation`).
+//!
+//! The player also runs the correlation meter (see `backend::correlation`) on
+//! its output, which is the master output, or on the output of one track.
+//!
+//! When the playback rate of a clip changes while it plays (i.e. while its
+//! pitch is dragged), the clip crossfades from the old rate to the new one
+//! instead of jumping to another position in its source audio. Flipping the
+//! polarity of a playing clip ramps its gain through zero, and the gain trims
+//! of its channels ramp to their new values one channel at a time.
+//!
+//! While looping, the playhead jumps back to the loop start whenever it reaches
+//! the loop end. The block is split at the loop end, so the jump lands on the
+//! exact frame no matter how the blocks are aligned. Playback that starts after
+//! the loop end plays on without looping.
+//!
+//! The jump is crossfaded with a length of its own (see `DeclickTimes`). The
+//! crossfade is centered on the loop point: before the jump, the audio before the loop end fades out while the
+//! audio just before the loop start fades in, and after it the audio after the
+//! loop end fades out while the loop start fades in. It is clamped so that it
+//! never reaches before the start of the timeline or takes more than half of
+//! the loop.
+//!
+//! Starting and stopping the transport fades the tracks in and out, and
+//! starting to play from another position while playing (a seek) crossfades
+//! from the old position to the new one. Each of these transitions has a fade
+//! time of its own, so that a long loop crossfade doesn't slow down seeking.
+//!
+//! Playback can start with a count-in (see `backend::count_in`), during which
+//! the playhead stays where it is and only the metronome plays.
+//!
+//! The player counts the frames of the output stream, and publishes which
+//! timeline frame played at each of them in a `TransportClock` (see
+//! `backend::transport_clock`), which the recorder lines its takes up with.
+//!
+//! When the tempo changes while playing, the playhead moves to the frame where
+//! its bar and beat are at the new tempo, and every clip crossfades from where
+//! it was playing to its new position.
+//!
+//! A track skips the blocks that none of its clips intersect while nothing is
+//! fading, and reports in `TrackSilenceFlags` and `TrackClipCounts` what it did
+//! in its last block.
+//!
+//! Everything that is replaced on the audio thread (i.e. the clips of a track)
+//! is sent back to the handle, so that nothing is deallocated on the audio
+//! thread.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use rtrb::{Consumer, Producer, PushError, RingBuffer};
+
+use super::automation::{fader_gain, AutomationEvaluator, TrackAutomation};
+use super::clip_block::{clip_block_span, ClipIntervalIndex};
+use super::correlation::{CorrelationMeter, SharedCorrelation};
+use super::count_in::CountIn;
+use super::engine::MAX_FRAMES;
+use super::rt_log::{RtEvent, RtLogger};
+use super::smoothed_gain::{SmoothedGain, GAIN_SMOOTHING_SECS};
+use super::track_activity::{TrackClipCounts, TrackSilenceFlags};
+use super::transport_clock::TransportClock;
+
+/// The most tracks a timeline plays. Room for this many is reserved up front,
+/// so that adding a track never allocates on the audio thread.
+pub const MAX_TRACKS: usize = 256;
+
+const MSG_CAPACITY: usize = 1024;
+
+/// The most versions of a clip that fade out at once after its rate changed
+/// several times in a row. If there are more, the quietest one is cut off.
+const MAX_FADING_CLIPS: usize = 8;
+
+/// The loop region of the timeline in frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopRange {
+    pub start: u64,
+    /// The end of the loop region (exclusive). The playhead jumps back to
+    /// `start` when it reaches this frame.
+    pub end: u64,
+}
+
+/// The lengths of the fades that hide the jumps of the transport in frames, set
+/// with `TimelineMsg::SetDeclick`. A length of 0 makes the transport jump
+/// without a fade.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeclickTimes {
+    /// The fade in when the transport starts, and the fade out when it stops.
+    pub start_stop: u64,
+    /// The crossfade when playback starts from another position while playing.
+    pub seek: u64,
+    /// The crossfade around the loop point (see `LoopRange`).
+    pub loop_point: u64,
+}
+
+/// Reads the source audio of a clip.
+///
+/// This is implemented by the program layer, which knows about the warp
+/// markers, the fades and the gain envelope of its clips. The audio thread only
+/// plays the result.
+pub trait ClipSource: Send + Sync {
+    /// Returns the position in the source audio (in frames at the engine's
+    /// sample rate) and the linear gain at `frame` frames after the start of the
+    /// clip.
+    fn position_and_gain(&self, frame: u64) -> (f64, f32);
+
+    /// Reads `source` (one channel of the source audio) at the fractional
+    /// position `pos`, where `rate` is the number of source frames advanced per
+    /// frame.
+    fn read(&self, source: &[f32], pos: f64, rate: f64) -> f32;
+}
+
+/// An audio clip on a track, ready to be played.
+#[derive(Clone)]
+pub struct TimelineClip {
+    /// The id of the clip in the project.
+    pub id: u64,
+    /// The frame on the timeline where the clip starts.
+    pub start: u64,
+    /// The frame on the timeline where the clip ends (exclusive).
+    pub end: u64,
+    /// The source audio of the clip, one buffer per channel (at most two).
+    pub audio: Arc<Vec<Vec<f32>>>,
+    pub source: Arc<dyn ClipSource>,
+    /// The playback rate of the clip, where 1.0 is the original speed.
+    pub rate: f64,
+    /// `-1.0` if the polarity of the clip is inverted, or `1.0` otherwise.
+    pub polarity: f32,
+    /// The linear gain trim of each channel of the source audio.
+    pub trims: [f32; 2],
+}
+
+impl TimelineClip {
+    /// The position in the source audio that plays at the timeline frame
+    /// `playhead`, or `None` if the clip doesn't play there.
+    fn source_position_at(&self, playhead: u64) -> Option<f64> {
+        if (self.start..self.end).contains(&playhead) {
+            Some(self.source.position_and_gain(playhead - self.start).0)
+        } else {
+            None
+        }
+    }
+
+    /// Adds the frames of this clip that fall in the block of `out` (one buffer
+    /// per channel) starting at the timeline frame `playhead`, with the gains
+    /// of `fade` and `gains` applied on top.
+    ///
+    /// Mono clips are played on every channel. Returns `false` if the clip is
+    /// silent during the block.
+    fn mix(
+        &self,
+        playhead: u64,
+        out: &mut [&mut [f32]],
+        fade: &LinearRamp,
+        gains: &ClipGains,
+    ) -> bool {
+        let block_frames = out.iter().map(|b| b.len()).min().unwrap_or(0);
+        let span = match clip_block_span(
+            playhead,
+            block_frames,
+            self.start,
+            self.end,
+            0,
+            self.end.saturating_sub(self.start),
+        ) {
+            Some(span) if !self.audio.is_empty() => span,
+            _ => return false,
+        };
+
+        for i in 0..span.len {
+            let (pos, gain) = self.source.position_and_gain(span.source_frame + i as u64);
+            let frame = span.out_offset + i;
+            let gain = gain * gains.polarity.at(frame) * fade.at(frame);
+            for (channel, out) in out.iter_mut().enumerate() {
+                let source_channel = channel.min(self.audio.len() - 1);
+                out[frame] += self.source.read(&self.audio[source_channel], pos, self.rate)
+                    * gain
+                    * gains.trims[source_channel.min(1)].at(frame);
+            }
+        }
+
+        true
+    }
+}
+
+/// Renders `clips` into `num_channels` (one or two) buffers of `num_frames`
+/// frames, starting at the timeline frame `start`.
+///
+/// The clips are played by a `TimelineTrack` at unity gain, block by block,
+/// exactly like the player plays them. Use this to render clips offline.
+pub fn render_clips(
+    clips: &[TimelineClip],
+    sample_rate: f64,
+    start: u64,
+    num_frames: usize,
+    num_channels: usize,
+) -> Vec<Vec<f32>> {
+    let mut track = TimelineTrack::new(0, sample_rate);
+    track.clips = TrackClips::new(clips.to_vec(), sample_rate);
+
+    let mut out = vec![Vec::with_capacity(num_frames); num_channels.clamp(1, 2)];
+    let mut frame = 0;
+    while frame < num_frames {
+        let len = (num_frames - frame).min(MAX_FRAMES as usize);
+        track.begin_block();
+        let playing = track.process(start + frame as u64, len, None);
+        for (out, buffer) in out.iter_mut().zip(track.buffers.iter()) {
+            if playing {
+                out.extend_from_slice(&buffer[..len]);
+            } else {
+                out.resize(out.len() + len, 0.0);
+            }
+        }
+        frame += len;
+    }
+    out
+}
+
+/// A timeline track with the settings of its channel, to be rendered offline
+/// with `render_timeline()`.
+pub struct OfflineTrack {
+    /// The id of the channel the track plays into.
+    pub id: u64,
+    pub clips: Vec<TimelineClip>,
+    pub trim_db: f32,
+    pub phase_invert: bool,
+    /// The linear gains of the left and the right channel of the track's pan.
+    pub pan_gains: [f32; 2],
+    /// The linear gain of the track's fader.
+    pub volume: f32,
+    pub automation: TrackAutomation,
+}
+
+impl OfflineTrack {
+    /// Returns the `TimelineTrack` that plays this track, which starts out at
+    /// its settings instead of ramping to them.
+    fn into_track(self, sample_rate: f64) -> TimelineTrack {
+        let mut track = TimelineTrack::new(self.id, sample_rate);
+        track.clips = TrackClips::new(self.clips, sample_rate);
+        track.set_input(self.trim_db, self.phase_invert);
+        track.set_pan_gains(self.pan_gains[0], self.pan_gains[1]);
+        track.set_volume(self.volume);
+        track.set_automation(self.automation);
+
+        let [left_pan, right_pan] = &mut track.pan;
+        for gain in [&mut track.input_trim, &mut track.volume, left_pan, right_pan] {
+            gain.finish();
+        }
+        track
+    }
+}
+
+/// Renders the timeline frames `frames` of `tracks` into a buffer per channel
+/// (left and right).
+///
+/// The tracks are played by a `TimelinePlayer` from `frames.start` on, exactly
+/// like the transport plays them, and their outputs are summed. Rendering each
+/// track on its own gives stems that line up with each other and add up to the
+/// render of all of them. At most `MAX_TRACKS` tracks are played.
+///
+/// The render fades in and out over `fade` frames like the transport does when
+/// it starts and stops (see `DeclickTimes::start_stop`), with the fade out
+/// ending on the last frame. The fades take at most half of the render each.
+pub fn render_timeline(
+    tracks: Vec<OfflineTrack>,
+    sample_rate: f64,
+    frames: Range<u64>,
+    fade: u64,
+) -> Vec<Vec<f32>> {
+    let num_frames = frames.end.saturating_sub(frames.start) as usize;
+    let fade = (fade as usize).min(num_frames / 2);
+
+    let (mut handle, mut player) = timeline(sample_rate);
+    for track in tracks.into_iter().take(MAX_TRACKS) {
+        handle.send(TimelineMsg::AddTrack(track.into_track(sample_rate)));
+    }
+    handle.send(TimelineMsg::SetDeclick(DeclickTimes {
+        start_stop: fade as u64,
+        ..DeclickTimes::default()
+    }));
+    handle.send(TimelineMsg::Play { from: frames.start });
+
+    let mut out = vec![0.0; num_frames * 2];
+    let (playing, stopping) = out.split_at_mut((num_frames - fade) * 2);
+    for block in playing.chunks_mut(MAX_FRAMES as usize * 2) {
+        player.process_interleaved(block, 2);
+    }
+    handle.send(TimelineMsg::Stop);
+    for block in stopping.chunks_mut(MAX_FRAMES as usize * 2) {
+        player.process_interleaved(block, 2);
+    }
+    vec![out.iter().step_by(2).copied().collect(), out.iter().skip(1).step_by(2).copied().collect()]
+}
+
+/// A value that moves linearly to a target over a number of frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LinearRamp {
+    value: f32,
+    target: f32,
+    /// The amount `value` moves by every frame while ramping.
+    step: f32,
+    frames_left: usize,
+}
+
+impl LinearRamp {
+    /// Creates a ramp that starts out at its target `value`.
+    fn new(value: f32) -> Self {
+        Self { value, target: value, step: 0.0, frames_left: 0 }
+    }
+
+    /// Creates a ramp from `from` to `to` over `frames` frames.
+    fn between(from: f32, to: f32, frames: usize) -> Self {
+        let frames = frames.max(1);
+        Self { value: from, target: to, step: (to - from) / frames as f32, frames_left: frames }
+    }
+
+    /// Returns the value `frame` frames from now.
+    fn at(&self, frame: usize) -> f32 {
+        if frame >= self.frames_left {
+            self.target
+        } else {
+            self.value + self.step * frame as f32
+        }
+    }
+
+    /// Moves the ramp `frames` frames ahead.
+    fn advance(&mut self, frames: usize) {
+        self.value = self.at(frames);
+        self.frames_left = self.frames_left.saturating_sub(frames);
+    }
+
+    /// Returns `true` if the ramp stays at zero.
+    fn is_silent(&self) -> bool {
+        self.frames_left == 0 && self.target == 0.0
+    }
+}
+
+/// A crossfade between the audio at the playhead and the audio at another
+/// position of the timeline, i.e. around the loop point or after a seek.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Crossfade {
+    /// The timeline frame of the other position.
+    other: u64,
+    /// The gain of the audio at the playhead.
+    gain: LinearRamp,
+    /// The gain of the audio at the other position.
+    other_gain: LinearRamp,
+    /// Whether this is the crossfade around the loop point, which goes on
+    /// across the jump of the playhead.
+    around_loop: bool,
+}
+
+impl Crossfade {
+    /// The crossfade around the loop point of `range` with `half` frames on
+    /// either side of it, at the timeline frame `playhead` before the jump.
+    /// Returns `None` if the playhead is not in the first half.
+    fn before_loop_end(range: LoopRange, half: u64, playhead: u64) -> Option<Self> {
+        let start = range.end.checked_sub(half)?;
+        if half == 0 || !(start..range.end).contains(&playhead) {
+            return None;
+        }
+
+        let total = (half * 2) as f32;
+        let elapsed = (playhead - start) as f32;
+        let frames_left = (half * 2 - (playhead - start)) as usize;
+        Some(Self {
+            other: playhead - (range.end - range.start),
+            gain: LinearRamp::between(1.0 - elapsed / total, 0.0, frames_left),
+            other_gain: LinearRamp::between(elapsed / total, 1.0, frames_left),
+            around_loop: true,
+        })
+    }
+
+    /// The crossfade over `frames` frames from the timeline frame `from`,
+    /// where the playhead was before it seeked, to the playhead.
+    fn after_seek(from: u64, frames: u64) -> Self {
+        Self {
+            other: from,
+            gain: LinearRamp::between(0.0, 1.0, frames as usize),
+            other_gain: LinearRamp::between(1.0, 0.0, frames as usize),
+            around_loop: false,
+        }
+    }
+
+    /// Swaps the two positions when the playhead jumps from `playhead` to the
+    /// other position.
+    fn jump_from(&mut self, playhead: u64) {
+        self.other = playhead;
+        std::mem::swap(&mut self.gain, &mut self.other_gain);
+    }
+
+    fn advance(&mut self, frames: usize) {
+        self.other += frames as u64;
+        self.gain.advance(frames);
+        self.other_gain.advance(frames);
+    }
+
+    /// Returns `true` once only the audio at the playhead is left.
+    fn is_over(&self) -> bool {
+        self.other_gain.is_silent()
+    }
+}
+
+/// The polarity and the channel trims of a clip, which ramp to their new values
+/// when they change while the clip plays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ClipGains {
+    /// Ramps from 1.0 to -1.0 or back when the polarity is flipped.
+    polarity: LinearRamp,
+    /// The trims of the left and right channel, which ramp independently.
+    trims: [LinearRamp; 2],
+}
+
+impl ClipGains {
+    fn new(clip: &TimelineClip) -> Self {
+        Self {
+            polarity: LinearRamp::new(clip.polarity),
+            trims: [LinearRamp::new(clip.trims[0]), LinearRamp::new(clip.trims[1])],
+        }
+    }
+
+    /// Starts ramping to the gains of `clip` over `frames` frames. Gains that
+    /// didn't change keep going as they are.
+    fn ramp_to(&mut self, clip: &TimelineClip, frames: usize) {
+        let targets = [clip.polarity, clip.trims[0], clip.trims[1]];
+        for (ramp, target) in self.ramps_mut().into_iter().zip(targets) {
+            if ramp.target != target {
+                *ramp = LinearRamp::between(ramp.value, target, frames);
+            }
+        }
+    }
+
+    /// Jumps to the targets of all ramps.
+    fn finish(&mut self) {
+        for ramp in self.ramps_mut() {
+            *ramp = LinearRamp::new(ramp.target);
+        }
+    }
+
+    fn advance(&mut self, frames: usize) {
+        for ramp in self.ramps_mut() {
+            ramp.advance(frames);
+        }
+    }
+
+    fn ramps_mut(&mut self) -> [&mut LinearRamp; 3] {
+        let [left, right] = &mut self.trims;
+        [&mut self.polarity, left, right]
+    }
+}
+
+/// A version of a clip that fades out after the clip's rate changed.
+#[derive(Clone)]
+struct FadingClip {
+    clip: TimelineClip,
+    fade: LinearRamp,
+    /// The number of frames this version plays ahead of the playhead, since
+    /// the playhead moved after the tempo changed.
+    offset: i64,
+}
+
+/// The playback state of a clip on the audio thread, which carries over when
+/// the clips of its track are replaced.
+#[derive(Clone)]
+struct ClipVoice {
+    /// The gain of the clip itself, which ramps up from zero after its rate
+    /// changed.
+    fade: LinearRamp,
+    /// The gains of the clip, which also apply to its versions that fade out.
+    gains: ClipGains,
+    /// The versions of the clip from before its rate changed.
+    ///
+    /// These are never dropped on the audio thread: a fade that finished is
+    /// only marked as silent, and the clones are deallocated along with the
+    /// `TrackClips` they are in.
+    fading_out: [Option<FadingClip>; MAX_FADING_CLIPS],
+}
+
+impl ClipVoice {
+    fn new(clip: &TimelineClip) -> Self {
+        Self {
+            fade: LinearRamp::new(1.0),
+            gains: ClipGains::new(clip),
+            fading_out: Default::default(),
+        }
+    }
+
+    /// Crossfades from `old_clip` (played by `old`) to the clip of this voice
+    /// over `frames` frames, where `offset` is the number of frames the
+    /// playhead moved back by (i.e. after a tempo change).
+    ///
+    /// The versions of the clip that were still fading out in `old` keep
+    /// fading out from their current gain, so the gains of all versions always
+    /// add up to one. The gain of a version that is cut off for lack of room is
+    /// given to the new version right away.
+    ///
+    /// Returns the number of versions that were cut off.
+    fn crossfade_from(
+        &mut self,
+        old_clip: &TimelineClip,
+        old: &ClipVoice,
+        offset: i64,
+        frames: usize,
+    ) -> u32 {
+        let mut cut_gain = 0.0;
+        let mut num_cut = 0;
+        let previous = old.fading_out.iter().flatten().map(|f| (&f.clip, f.fade.value, f.offset));
+        let versions = std::iter::once((old_clip, old.fade.value, 0)).chain(previous);
+        for (clip, gain, old_offset) in versions {
+            if gain <= 0.0 {
+                continue;
+            }
+
+            // Take a free slot, or else the one of the quietest version if it
+            // is quieter than this one.
+            let slot = match self.fading_out.iter().position(|f| f.is_none()) {
+                Some(slot) => slot,
+                None => {
+                    let (slot, quietest) = self
+                        .fading_out
+                        .iter()
+                        .flatten()
+                        .map(|f| f.fade.value)
+                        .enumerate()
+                        .fold((0, f32::MAX), |a, b| if b.1 < a.1 { b } else { a });
+                    num_cut += 1;
+                    if quietest >= gain {
+                        cut_gain += gain;
+                        continue;
+                    }
+                    cut_gain += quietest;
+                    slot
+                }
+            };
+            self.fading_out[slot] = Some(FadingClip {
+                clip: clip.clone(),
+                fade: LinearRamp::between(gain, 0.0, frames),
+                offset: old_offset + offset,
+            });
+        }
+        self.fade = LinearRamp::between(cut_gain, 1.0, frames);
+        num_cut
+    }
+
+    /// Ends all fades at once, i.e. when the playhead jumps.
+    fn stop_fades(&mut self) {
+        self.fade = LinearRamp::new(1.0);
+        self.gains.finish();
+        for fading in self.fading_out.iter_mut().flatten() {
+            fading.fade = LinearRamp::new(0.0);
+        }
+    }
+}
+
+/// The clips of a timeline track, ready to be sent to the player with
+/// `TimelineMsg::SetClips`.
+pub struct TrackClips {
+    clips: Vec<TimelineClip>,
+    voices: Vec<ClipVoice>,
+    /// The regions of the timeline the clips are on.
+    intervals: ClipIntervalIndex,
+    /// The ids of the clips with their indices, sorted by id, so that the
+    /// clips that replace these can find their old versions quickly.
+    ids: Vec<(u64, usize)>,
+    /// The length of a crossfade after a rate change, and of the ramps of the
+    /// gains.
+    crossfade_frames: usize,
+    /// The number of frames until all fades and ramps of the clips are over.
+    /// The track doesn't skip any blocks before that.
+    ramp_frames_left: usize,
+}
+
+impl TrackClips {
+    /// This allocates, so call it outside of the audio thread.
+    pub fn new(clips: Vec<TimelineClip>, sample_rate: f64) -> Self {
+        let mut ids: Vec<(u64, usize)> =
+            clips.iter().enumerate().map(|(index, c)| (c.id, index)).collect();
+        ids.sort_unstable();
+        Self {
+            voices: clips.iter().map(ClipVoice::new).collect(),
+            intervals: ClipIntervalIndex::new(clips.iter().map(|c| (c.start, c.end)).collect()),
+            ids,
+            clips,
+            crossfade_frames: (GAIN_SMOOTHING_SECS * sample_rate).round().max(1.0) as usize,
+            ramp_frames_left: 0,
+        }
+    }
+
+    /// Carries the playback state of the clips over from `old`, the clips
+    /// these replace. If the transport is playing at `playhead`, the clips
+    /// whose rate changed or whose source position under the playhead jumped
+    /// (i.e. a clip slipped or moved while playing) crossfade to the new
+    /// version, and the gains that changed ramp to their new values.
+    ///
+    /// Returns the number of fading versions that were cut off (see
+    /// `ClipVoice::crossfade_from()`).
+    fn take_over(&mut self, old: &TrackClips, playhead: Option<u64>) -> u32 {
+        let playhead = match playhead {
+            Some(playhead) => playhead,
+            None => return 0,
+        };
+        self.ramp_frames_left = self.crossfade_frames;
+        let mut num_cut = 0;
+        for (clip, voice) in self.clips.iter().zip(self.voices.iter_mut()) {
+            let index = match old.index_of(clip.id) {
+                Some(index) => index,
+                None => continue,
+            };
+            let (old_clip, old_voice) = (&old.clips[index], &old.voices[index]);
+            let jumped = old_clip.source_position_at(playhead) != clip.source_position_at(playhead);
+            if old_clip.rate == clip.rate && !jumped {
+                voice.clone_from(old_voice);
+            } else {
+                num_cut += voice.crossfade_from(old_clip, old_voice, 0, self.crossfade_frames);
+                voice.gains = old_voice.gains;
+            }
+            voice.gains.ramp_to(clip, self.crossfade_frames);
+        }
+        num_cut
+    }
+
+    /// Like `take_over()`, but after the tempo changed and the playhead moved
+    /// back by `offset` frames. Every clip crossfades from where it was
+    /// playing, since the positions of all of them changed.
+    fn retime_from(&mut self, old: &TrackClips, offset: i64) -> u32 {
+        self.ramp_frames_left = self.crossfade_frames;
+        let mut num_cut = 0;
+        for (clip, voice) in self.clips.iter().zip(self.voices.iter_mut()) {
+            let index = match old.index_of(clip.id) {
+                Some(index) => index,
+                None => continue,
+            };
+            let old_voice = &old.voices[index];
+            num_cut +=
+                voice.crossfade_from(&old.clips[index], old_voice, offset, self.crossfade_frames);
+            voice.gains = old_voice.gains;
+            voice.gains.ramp_to(clip, self.crossfade_frames);
+        }
+        num_cut
+    }
+
+    /// Returns the index of the clip with the id `id`.
+    fn index_of(&self, id: u64) -> Option<usize> {
+        let i = self.ids.binary_search_by_key(&id, |(id, _)| *id).ok()?;
+        Some(self.ids[i].1)
+    }
+}
+
+/// The automation of a track, and the state of its evaluation.
+struct AutomationPlayback {
+    automation: TrackAutomation,
+    /// The evaluators of the gain and the pan lane.
+    evaluators: [AutomationEvaluator; 2],
+    /// The values of the gain and the pan lane for each frame of the current
+    /// part of the block.
+    values: [Vec<f64>; 2],
+}
+
+/// A timeline track, which plays the clips of a mixer channel.
+pub struct TimelineTrack {
+    /// The id of the channel the track plays into.
+    pub id: u64,
+    clips: TrackClips,
+    input_trim: SmoothedGain,
+    /// The volume of the track's fader, which is used while the track has no
+    /// gain automation.
+    volume: SmoothedGain,
+    /// Boxed to keep `TimelineMsg::AddTrack` small.
+    automation: Box<AutomationPlayback>,
+    /// The polarity of the track's input.
+    phase_invert: bool,
+    /// The polarity that takes effect at the start of the next block.
+    next_phase_invert: bool,
+    /// The gains of the left and the right channel of the track's pan.
+    pan: [SmoothedGain; 2],
+    /// The output of the track, one buffer per channel.
+    buffers: [Vec<f32>; 2],
+    /// Whether the track skipped every part of the current block.
+    block_silent: bool,
+    /// The most clips the track played in a part of the current block.
+    block_clips: u32,
+}
+
+impl TimelineTrack {
+    /// Creates a track without clips. This allocates, so call it outside of the
+    /// audio thread and send the track with `TimelineMsg::AddTrack`.
+    pub fn new(id: u64, sample_rate: f64) -> Self {
+        Self {
+            id,
+            clips: TrackClips::new(Vec::new(), sample_rate),
+            input_trim: SmoothedGain::new(0.0, sample_rate),
+            volume: SmoothedGain::new(0.0, sample_rate),
+            automation: Box::new(AutomationPlayback {
+                automation: TrackAutomation::default(),
+                evaluators: [
+                    AutomationEvaluator::new(sample_rate, None),
+                    AutomationEvaluator::new(sample_rate, None),
+                ],
+                values: [vec![0.0; MAX_FRAMES as usize], vec![0.0; MAX_FRAMES as usize]],
+            }),
+            phase_invert: false,
+            next_phase_invert: false,
+            pan: [SmoothedGain::new(0.0, sample_rate), SmoothedGain::new(0.0, sample_rate)],
+            buffers: [vec![0.0; MAX_FRAMES as usize], vec![0.0; MAX_FRAMES as usize]],
+            block_silent: true,
+            block_clips: 0,
+        }
+    }
+
+    /// Sets the input trim (which ramps to its new value) and the polarity
+    /// (which flips at the start of the next block).
+    pub fn set_input(&mut self, trim_db: f32, phase_invert: bool) {
+        self.input_trim.set_target_db(trim_db);
+        self.next_phase_invert = phase_invert;
+    }
+
+    /// Sets the linear gains of the left and the right channel of the track's
+    /// pan (see `PanLaw::gains()`), which ramp to their new values.
+    pub fn set_pan_gains(&mut self, left: f32, right: f32) {
+        self.pan[0].set_target(left);
+        self.pan[1].set_target(right);
+    }
+
+    /// Sets the linear gain of the track's fader, which ramps to its new value.
+    pub fn set_volume(&mut self, gain: f32) {
+        self.volume.set_target(gain);
+    }
+
+    /// Replaces the automation of the track, and returns the old one. The
+    /// values of the lanes ramp from where they were to the new lanes.
+    fn set_automation(&mut self, automation: TrackAutomation) -> TrackAutomation {
+        for evaluator in self.automation.evaluators.iter_mut() {
+            evaluator.set_corner_smoothing(automation.corner_smoothing);
+            evaluator.lane_changed();
+        }
+        std::mem::replace(&mut self.automation.automation, automation)
+    }
+
+    /// Makes the automation start right on its lanes the next time the track
+    /// plays, i.e. when the transport starts or stops.
+    fn reset_automation(&mut self) {
+        for evaluator in self.automation.evaluators.iter_mut() {
+            evaluator.reset();
+        }
+    }
+
+    /// Called at the start of every block, before the first `process()`.
+    ///
+    /// The polarity is only flipped here, so that it never changes in the
+    /// middle of a block.
+    fn begin_block(&mut self) {
+        self.phase_invert = self.next_phase_invert;
+        self.block_silent = true;
+        self.block_clips = 0;
+    }
+
+    /// Renders `len` frames (at most `MAX_FRAMES`) starting at the timeline
+    /// frame `playhead` into the track's buffers, crossfaded with the frames at
+    /// another position if there is a `crossfade`.
+    ///
+    /// Returns `false` if no clip intersects the frames and nothing is fading
+    /// or ramping, in which case the track is silent and its buffers are left
+    /// as they are.
+    fn process(&mut self, playhead: u64, len: usize, crossfade: Option<&Crossfade>) -> bool {
+        // The automation is evaluated even when the track is silent, so that
+        // it carries on smoothly when the next clip starts.
+        let AutomationPlayback { automation, evaluators, values } = &mut *self.automation;
+        let lanes = [&automation.gain, &automation.pan];
+        for ((evaluator, lane), values) in evaluators.iter_mut().zip(lanes).zip(values.iter_mut()) {
+            evaluator.process_block(lane, playhead, &mut values[..len]);
+        }
+
+        if self.clips.ramp_frames_left == 0
+            && !self.input_trim.is_smoothing()
+            && !self.volume.is_smoothing()
+            && !self.pan.iter().any(|pan| pan.is_smoothing())
+            && !self.clips.intervals.intersects(playhead, len)
+            && !matches!(crossfade, Some(c) if self.clips.intervals.intersects(c.other, len))
+        {
+            return false;
+        }
+        self.block_silent = false;
+
+        let [left, right] = &mut self.buffers;
+        let (left, right) = (&mut left[..len], &mut right[..len]);
+        left.fill(0.0);
+        right.fill(0.0);
+
+        let mut buffers = [left, right];
+        let mut num_clips = 0;
+        let TrackClips { clips, voices, ramp_frames_left, .. } = &mut self.clips;
+        for (clip, voice) in clips.iter().zip(voices.iter_mut()) {
+            num_clips += u32::from(clip.mix(playhead, &mut buffers, &voice.fade, &voice.gains));
+            voice.fade.advance(len);
+
+            for fading in voice.fading_out.iter_mut().flatten() {
+                if !fading.fade.is_silent() {
+                    let playhead = playhead.saturating_add_signed(fading.offset);
+                    num_clips += u32::from(fading.clip.mix(
+                        playhead,
+                        &mut buffers,
+                        &fading.fade,
+                        &voice.gains,
+                    ));
+                    fading.fade.advance(len);
+                }
+            }
+        }
+        if let Some(crossfade) = crossfade {
+            for buffer in buffers.iter_mut() {
+                for (i, s) in buffer.iter_mut().enumerate() {
+                    *s *= crossfade.gain.at(i);
+                }
+            }
+            for (clip, voice) in clips.iter().zip(voices.iter()) {
+                num_clips += u32::from(clip.mix(
+                    crossfade.other,
+                    &mut buffers,
+                    &crossfade.other_gain,
+                    &voice.gains,
+                ));
+            }
+        }
+        for voice in voices.iter_mut() {
+            voice.gains.advance(len);
+        }
+        *ramp_frames_left = ramp_frames_left.saturating_sub(len);
+        self.block_clips = self.block_clips.max(num_clips);
+
+        // The input stage, right after the clips are summed.
+        self.input_trim.process(&mut buffers);
+        if self.phase_invert {
+            for buffer in buffers.iter_mut() {
+                for s in buffer.iter_mut() {
+                    *s = -*s;
+                }
+            }
+        }
+
+        let AutomationPlayback { automation, values: [gain_values, pan_values], .. } =
+            &*self.automation;
+        if automation.gain.is_empty() {
+            self.volume.process(&mut buffers);
+        } else {
+            for (i, value) in gain_values[..len].iter().enumerate() {
+                let gain = fader_gain(*value);
+                for buffer in buffers.iter_mut() {
+                    buffer[i] *= gain;
+                }
+            }
+        }
+
+        if automation.pan.is_empty() {
+            let [left_pan, right_pan] = &mut self.pan;
+            left_pan.process(&mut buffers[..1]);
+            right_pan.process(&mut buffers[1..]);
+        } else {
+            let [left, right] = &mut buffers;
+            for (i, value) in pan_values[..len].iter().enumerate() {
+                let (left_gain, right_gain) = (automation.pan_law)(*value);
+                left[i] *= left_gain as f32;
+                right[i] *= right_gain as f32;
+            }
+        }
+
+        true
+    }
+}
+
+/// A message from the `TimelineHandle` to the `TimelinePlayer`.
+pub enum TimelineMsg {
+    /// Adds a track. Tracks with the same id as an existing track are ignored.
+    AddTrack(TimelineTrack),
+    RemoveTrack(u64),
+    /// Replaces the clips of the track with the id `track`.
+    SetClips {
+        track: u64,
+        clips: TrackClips,
+    },
+    /// Sets the input stage of the track with the id `track`.
+    SetInput {
+        track: u64,
+        trim_db: f32,
+        phase_invert: bool,
+    },
+    /// Sets the linear gains of the pan of the track with the id `track`.
+    SetPan {
+        track: u64,
+        left_gain: f32,
+        right_gain: f32,
+    },
+    /// Sets the linear gain of the fader of the track with the id `track`.
+    SetVolume {
+        track: u64,
+        gain: f32,
+    },
+    /// Replaces the automation of the track with the id `track`.
+    SetAutomation {
+        track: u64,
+        automation: TrackAutomation,
+    },
+    /// Sets the loop region, or turns looping off if `None`. Loop regions that
+    /// end before they start are ignored.
+    SetLoop(Option<LoopRange>),
+    /// Sets the lengths of the fades at the jumps of the transport.
+    SetDeclick(DeclickTimes),
+    /// Moves the correlation meter to the output of the track with the given
+    /// id, or to the output of the player (the master output) if `None`.
+    SetCorrelationBus(Option<u64>),
+    /// Replaces the clips of several tracks at once after the tempo changed.
+    ///
+    /// While playing, the playhead is multiplied by `playhead_scale` (the old
+    /// tempo divided by the new one) so that it stays on the same bar and
+    /// beat, and the clips crossfade from where they were playing.
+    Retime {
+        playhead_scale: f64,
+        tracks: Vec<(u64, TrackClips)>,
+    },
+    /// Starts playing from the timeline frame `from`.
+    Play {
+        from: u64,
+    },
+    /// Runs `count_in` while the playhead stays at the timeline frame `from`,
+    /// and then starts playing from there.
+    CountIn {
+        from: u64,
+        count_in: CountIn,
+    },
+    /// Stops playing, or cancels the count-in if it is still running.
+    Stop,
+}
+
+/// Something the player replaced, sent back to be deallocated.
+enum Garbage {
+    Track(TimelineTrack),
+    Clips(TrackClips),
+    Automation(TrackAutomation),
+    /// The list of a `TimelineMsg::Retime`, which now holds the clips that
+    /// were replaced.
+    Retime(Vec<(u64, TrackClips)>),
+}
+
+/// The state of the player, shared with the handle.
+#[derive(Default)]
+struct TimelineStatus {
+    playing: AtomicBool,
+    playhead: AtomicU64,
+    counting_in: AtomicBool,
+    count_in_elapsed: AtomicU64,
+    looping: AtomicBool,
+    loop_start: AtomicU64,
+    loop_end: AtomicU64,
+}
+
+impl TimelineStatus {
+    fn set_loop(&self, range: Option<LoopRange>) {
+        self.looping.store(range.is_some(), Ordering::Relaxed);
+        if let Some(range) = range {
+            self.loop_start.store(range.start, Ordering::Relaxed);
+            self.loop_end.store(range.end, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Creates a timeline player for the audio thread, and the handle to control it
+/// with.
+pub fn timeline(sample_rate: f64) -> (TimelineHandle, TimelinePlayer) {
+    let (to_player, from_handle) = RingBuffer::<TimelineMsg>::new(MSG_CAPACITY);
+    let (to_handle, from_player) = RingBuffer::<Garbage>::new(MSG_CAPACITY);
+    let status = Arc::new(TimelineStatus::default());
+    let silence = TrackSilenceFlags::new(MAX_TRACKS);
+    let clip_counts = TrackClipCounts::new(MAX_TRACKS);
+    let correlation = SharedCorrelation::default();
+    let clock = Arc::new(TransportClock::default());
+
+    (
+        TimelineHandle {
+            to_player,
+            from_player,
+            status: Arc::clone(&status),
+            silence: silence.clone(),
+            clip_counts: clip_counts.clone(),
+            correlation: correlation.clone(),
+            clock: Arc::clone(&clock),
+            num_starts: 0,
+            sample_rate,
+        },
+        TimelinePlayer {
+            from_handle,
+            to_handle,
+            status,
+            silence,
+            clip_counts,
+            correlation,
+            correlation_meter: CorrelationMeter::new(sample_rate),
+            correlation_track: None,
+            tracks: Vec::with_capacity(MAX_TRACKS),
+            playing: false,
+            playhead: 0,
+            count_in: CountIn::default(),
+            loop_range: None,
+            declick: DeclickTimes::default(),
+            crossfade: None,
+            transport_fade: LinearRamp::new(1.0),
+            stopping: false,
+            clock,
+            stream_frame: 0,
+            num_starts: 0,
+            pending_garbage: None,
+            rt_logger: None,
+            block: 0,
+        },
+    )
+}
+
+/// Controls a `TimelinePlayer` from outside of the audio thread.
+pub struct TimelineHandle {
+    to_player: Producer<TimelineMsg>,
+    from_player: Consumer<Garbage>,
+    status: Arc<TimelineStatus>,
+    silence: TrackSilenceFlags,
+    clip_counts: TrackClipCounts,
+    correlation: SharedCorrelation,
+    clock: Arc<TransportClock>,
+    /// The number of `Play` and `CountIn` messages that were sent.
+    num_starts: u64,
+    sample_rate: f64,
+}
+
+impl TimelineHandle {
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Sends `msg` to the player. Returns `false` if the player is not keeping
+    /// up, in which case the message is dropped and the caller should send the
+    /// current state again later.
+    pub fn send(&mut self, msg: TimelineMsg) -> bool {
+        self.collect_garbage();
+        let starts_count_in = matches!(msg, TimelineMsg::CountIn { .. });
+        let starts = starts_count_in || matches!(msg, TimelineMsg::Play { .. });
+        let new_loop = match &msg {
+            TimelineMsg::SetLoop(range) => Some(range.filter(|r| r.start < r.end)),
+            _ => None,
+        };
+        match self.to_player.push(msg) {
+            Ok(()) => {
+                // Report the count-in and the loop region right away, so that
+                // they don't look like they were changed back before the player
+                // picked them up.
+                if starts_count_in {
+                    self.status.count_in_elapsed.store(0, Ordering::Relaxed);
+                    self.status.counting_in.store(true, Ordering::Relaxed);
+                }
+                if let Some(range) = new_loop {
+                    self.status.set_loop(range);
+                }
+                if starts {
+                    self.num_starts += 1;
+                }
+                true
+            }
+            Err(PushError::Full(_)) => {
+                log::warn!("Timeline player is not keeping up, dropped a message");
+                false
+            }
+        }
+    }
+
+    /// Deallocates everything the player replaced since the last call.
+    pub fn collect_garbage(&mut self) {
+        while let Ok(garbage) = self.from_player.pop() {
+            match garbage {
+                Garbage::Track(track) => drop(track),
+                Garbage::Clips(clips) => drop(clips),
+                Garbage::Automation(automation) => drop(automation),
+                Garbage::Retime(tracks) => drop(tracks),
+            }
+        }
+    }
+
+    /// Returns `true` if the player is playing.
+    pub fn is_playing(&self) -> bool {
+        self.status.playing.load(Ordering::Relaxed)
+    }
+
+    /// The timeline frame of the playhead at the end of the last block.
+    pub fn playhead(&self) -> u64 {
+        self.status.playhead.load(Ordering::Relaxed)
+    }
+
+    /// The number of frames of the running count-in that have played, or
+    /// `None` if there is no count-in running.
+    pub fn count_in_elapsed(&self) -> Option<u64> {
+        if self.status.counting_in.load(Ordering::Relaxed) {
+            Some(self.status.count_in_elapsed.load(Ordering::Relaxed))
+        } else {
+            None
+        }
+    }
+
+    /// The loop region of the player, or `None` if it isn't looping.
+    pub fn loop_range(&self) -> Option<LoopRange> {
+        if self.status.looping.load(Ordering::Relaxed) {
+            Some(LoopRange {
+                start: self.status.loop_start.load(Ordering::Relaxed),
+                end: self.status.loop_end.load(Ordering::Relaxed),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The number of times the transport was started with `TimelineMsg::Play`
+    /// or `TimelineMsg::CountIn` so far. This is the number of the last start
+    /// in the `TransportRun`s of `clock()`.
+    pub fn num_starts(&self) -> u64 {
+        self.num_starts
+    }
+
+    /// The clock of the output stream, which tells which timeline frame played
+    /// at each frame of the stream.
+    pub fn clock(&self) -> Arc<TransportClock> {
+        Arc::clone(&self.clock)
+    }
+
+    /// Whether each track was silent in the last block, indexed by the order
+    /// the tracks were added in (without the removed ones).
+    pub fn silence_flags(&self) -> &TrackSilenceFlags {
+        &self.silence
+    }
+
+    /// The number of clips each track played in the last block, indexed like
+    /// `silence_flags()`.
+    pub fn clip_counts(&self) -> &TrackClipCounts {
+        &self.clip_counts
+    }
+
+    /// The last reading of the correlation meter (see
+    /// `TimelineMsg::SetCorrelationBus`).
+    pub fn correlation(&self) -> f32 {
+        self.correlation.get()
+    }
+}
+
+/// Plays the timeline on the audio thread.
+pub struct TimelinePlayer {
+    from_handle: Consumer<TimelineMsg>,
+    to_handle: Producer<Garbage>,
+    status: Arc<TimelineStatus>,
+    silence: TrackSilenceFlags,
+    clip_counts: TrackClipCounts,
+    correlation: SharedCorrelation,
+    correlation_meter: CorrelationMeter,
+    /// The id of the track the correlation meter measures, or `None` for the
+    /// output of the player.
+    correlation_track: Option<u64>,
+    tracks: Vec<TimelineTrack>,
+    playing: bool,
+    playhead: u64,
+    /// The count-in that runs before the playhead starts moving.
+    count_in: CountIn,
+    loop_range: Option<LoopRange>,
+    declick: DeclickTimes,
+    /// The crossfade that is running, if any.
+    crossfade: Option<Crossfade>,
+    /// The gain of all tracks, which ramps up when the transport starts and
+    /// down when it stops.
+    transport_fade: LinearRamp,
+    /// Whether the transport stops once `transport_fade` reached zero.
+    stopping: bool,
+    clock: Arc<TransportClock>,
+    /// The number of frames of the output stream before the current block.
+    stream_frame: u64,
+    /// The number of `Play` and `CountIn` messages that were received.
+    num_starts: u64,
+    /// Garbage that didn't fit into the ring to the handle. It is sent again
+    /// in the next block, and no messages are polled until it went out.
+    pending_garbage: Option<Garbage>,
+    rt_logger: Option<RtLogger>,
+    /// The number of the current block, counted from the start of the stream
+    /// (see `RtLogRecord::block`).
+    block: u64,
+}
+
+impl TimelinePlayer {
+    /// Logs what goes wrong on the audio thread to `rt_logger`. Blocks are
+    /// counted from the first call to `process_interleaved()`.
+    pub fn set_rt_logger(&mut self, rt_logger: RtLogger) {
+        self.rt_logger = Some(rt_logger);
+    }
+
+    /// Adds the next block of the timeline to the interleaved `out` buffer with
+    /// `num_channels` channels. Tracks are stereo, so only the first two
+    /// channels are written to.
+    ///
+    /// This is realtime safe.
+    pub fn process_interleaved(&mut self, out: &mut [f32], num_channels: usize) {
+        self.block += 1;
+        self.poll_messages();
+
+        if num_channels == 0 {
+            return;
+        }
+        let num_frames = out.len() / num_channels;
+
+        for track in self.tracks.iter_mut() {
+            track.begin_block();
+        }
+
+        let mut frame = 0;
+        if self.playing && self.count_in.is_counting() {
+            frame = self.count_in.process_interleaved(out, num_channels);
+        }
+        while frame < num_frames && self.playing {
+            let mut len = (num_frames - frame).min(MAX_FRAMES as usize);
+            // End the part at the loop end, so that the playhead jumps back on
+            // the exact frame, and where the crossfade before it starts.
+            let loop_range = self.loop_range.filter(|r| self.playhead < r.end);
+            if let Some(range) = loop_range {
+                let half = self.loop_crossfade_half(range);
+                if self.crossfade.is_none() {
+                    self.crossfade = Crossfade::before_loop_end(range, half, self.playhead);
+                }
+                let crossfade_start = range.end - half;
+                let split =
+                    if self.playhead < crossfade_start { crossfade_start } else { range.end };
+                len = len.min((split - self.playhead) as usize);
+            }
+            // End the part where a seek crossfade ends, so that the loop
+            // crossfade can start right after it.
+            if let Some(crossfade) = self.crossfade.filter(|c| !c.around_loop) {
+                len = len.min(crossfade.other_gain.frames_left.max(1));
+            }
+            if self.stopping {
+                len = len.min(self.transport_fade.frames_left.max(1));
+            }
+
+            self.clock.publish(
+                self.stream_frame + frame as u64,
+                len as u64,
+                self.playhead,
+                self.num_starts,
+            );
+
+            let fade = self.transport_fade;
+            for track in self.tracks.iter_mut() {
+                let metered = self.correlation_track == Some(track.id);
+                if !track.process(self.playhead, len, self.crossfade.as_ref()) {
+                    if metered {
+                        self.correlation_meter.process_silence(len);
+                    }
+                    continue;
+                }
+                if metered {
+                    self.correlation_meter
+                        .process(&track.buffers[0][..len], &track.buffers[1][..len]);
+                }
+
+                let out = &mut out[frame * num_channels..(frame + len) * num_channels];
+                for (i, out) in out.chunks_exact_mut(num_channels).enumerate() {
+                    for (channel, out) in out.iter_mut().take(2).enumerate() {
+                        *out += track.buffers[channel][i] * fade.at(i);
+                    }
+                }
+            }
+
+            self.playhead += len as u64;
+            frame += len;
+            self.transport_fade.advance(len);
+            if let Some(crossfade) = &mut self.crossfade {
+                crossfade.advance(len);
+            }
+            match loop_range {
+                Some(range) if self.playhead == range.end => {
+                    self.playhead = range.start;
+                    match &mut self.crossfade {
+                        Some(crossfade) if crossfade.around_loop => crossfade.jump_from(range.end),
+                        // A seek crossfade that is still running is cut off,
+                        // since the position it fades out from is left behind.
+                        _ => self.crossfade = None,
+                    }
+                }
+                _ => {
+                    if matches!(self.crossfade, Some(c) if c.is_over()) {
+                        self.crossfade = None;
+                    }
+                }
+            }
+            if self.stopping && self.transport_fade.is_silent() {
+                self.stop();
+            }
+        }
+
+        match self.correlation_track {
+            None => self.correlation_meter.process_interleaved(out, num_channels),
+            // The metered track is silent while stopped.
+            Some(_) => self.correlation_meter.process_silence(num_frames - frame),
+        }
+        self.correlation.set(self.correlation_meter.value());
+
+        for (index, track) in self.tracks.iter().enumerate() {
+            self.silence.set_silent(index, track.block_silent);
+            self.clip_counts.set_count(index, track.block_clips);
+        }
+        self.status.playing.store(self.playing, Ordering::Relaxed);
+        self.status.playhead.store(self.playhead, Ordering::Relaxed);
+        self.status.count_in_elapsed.store(self.count_in.elapsed_frames(), Ordering::Relaxed);
+        self.status.counting_in.store(self.count_in.is_counting(), Ordering::Relaxed);
+
+        self.stream_frame += num_frames as u64;
+        self.clock.set_stream_frame(self.stream_frame);
+    }
+
+    fn poll_messages(&mut self) {
+        if let Some(garbage) = self.pending_garbage.take() {
+            self.dispose(garbage);
+        }
+        // Every message leaves at most one piece of garbage, so there is
+        // always room for it in the pending slot.
+        while self.pending_garbage.is_none() {
+            let msg = match self.from_handle.pop() {
+                Ok(msg) => msg,
+                Err(_) => break,
+            };
+            match msg {
+                TimelineMsg::AddTrack(track) => {
+                    if self.tracks.len() < MAX_TRACKS && self.track_mut(track.id).is_none() {
+                        self.tracks.push(track);
+                    } else {
+                        self.dispose(Garbage::Track(track));
+                    }
+                }
+                TimelineMsg::RemoveTrack(id) => {
+                    if let Some(index) = self.tracks.iter().position(|t| t.id == id) {
+                        let track = self.tracks.remove(index);
+                        self.dispose(Garbage::Track(track));
+
+                        // The tracks after it move down by one.
+                        self.silence.set_silent(self.tracks.len(), true);
+                        self.clip_counts.set_count(self.tracks.len(), 0);
+                    }
+                }
+                TimelineMsg::SetClips { track, mut clips } => {
+                    let playhead = if self.playing { Some(self.playhead) } else { None };
+                    match self.track_mut(track) {
+                        Some(timeline_track) => {
+                            let num_cut = clips.take_over(&timeline_track.clips, playhead);
+                            let old = std::mem::replace(&mut timeline_track.clips, clips);
+                            self.log_fades_cut_off(track, num_cut);
+                            self.dispose(Garbage::Clips(old));
+                        }
+                        None => self.dispose(Garbage::Clips(clips)),
+                    }
+                }
+                TimelineMsg::Retime { playhead_scale, mut tracks } => {
+                    let old_playhead = self.playhead;
+                    self.crossfade = None;
+                    if self.playing {
+                        self.playhead = (self.playhead as f64 * playhead_scale).round() as u64;
+                    }
+                    let offset = old_playhead as i64 - self.playhead as i64;
+                    let playing = self.playing;
+
+                    // The replaced clips are swapped into the list, so that
+                    // they go back to the handle all at once.
+                    for (track, clips) in tracks.iter_mut() {
+                        if let Some(timeline_track) = self.track_mut(*track) {
+                            let mut num_cut = 0;
+                            if playing {
+                                num_cut = clips.retime_from(&timeline_track.clips, offset);
+                            }
+                            std::mem::swap(&mut timeline_track.clips, clips);
+                            self.log_fades_cut_off(*track, num_cut);
+                        }
+                    }
+                    self.dispose(Garbage::Retime(tracks));
+                }
+                TimelineMsg::SetInput { track, trim_db, phase_invert } => {
+                    if let Some(track) = self.track_mut(track) {
+                        track.set_input(trim_db, phase_invert);
+                    }
+                }
+                TimelineMsg::SetPan { track, left_gain, right_gain } => {
+                    if let Some(track) = self.track_mut(track) {
+                        track.set_pan_gains(left_gain, right_gain);
+                    }
+                }
+                TimelineMsg::SetVolume { track, gain } => {
+                    if let Some(track) = self.track_mut(track) {
+                        track.set_volume(gain);
+                    }
+                }
+                TimelineMsg::SetAutomation { track, automation } => {
+                    let old = match self.track_mut(track) {
+                        Some(track) => track.set_automation(automation),
+                        None => automation,
+                    };
+                    self.dispose(Garbage::Automation(old));
+                }
+                TimelineMsg::SetLoop(range) => {
+                    let range = range.filter(|r| r.start < r.end);
+                    if range != self.loop_range {
+                        // A crossfade toward the old loop start would go on
+                        // forever.
+                        self.crossfade = None;
+                        self.loop_range = range;
+                        self.status.set_loop(range);
+                    }
+                }
+                TimelineMsg::SetDeclick(declick) => {
+                    self.declick = declick;
+                }
+                TimelineMsg::SetCorrelationBus(track) => {
+                    self.correlation_track = track;
+                    self.correlation_meter.reset();
+                }
+                TimelineMsg::Play { from } => {
+                    self.num_starts += 1;
+                    self.start_playing(from);
+                    self.count_in.cancel();
+                }
+                TimelineMsg::CountIn { from, count_in } => {
+                    self.num_starts += 1;
+                    self.count_in = count_in;
+                    self.start_playing(from);
+                }
+                TimelineMsg::Stop => {
+                    // The count-in only plays the metronome, so it stops at
+                    // once.
+                    if self.playing && !self.count_in.is_counting() && self.declick.start_stop > 0 {
+                        self.stopping = true;
+                        self.transport_fade = LinearRamp::between(
+                            self.transport_fade.value,
+                            0.0,
+                            self.declick.start_stop as usize,
+                        );
+                    } else {
+                        self.stop();
+                    }
+                    self.count_in.cancel();
+                }
+            }
+        }
+    }
+
+    /// Starts playing from `from`. If the transport is playing already, this
+    /// is a seek, which crossfades from the old position to `from`. Otherwise
+    /// the tracks fade in.
+    fn start_playing(&mut self, from: u64) {
+        let seeks = self.playing && !self.stopping && !self.count_in.is_counting();
+        self.crossfade = match self.declick.seek {
+            frames if seeks && frames > 0 => Some(Crossfade::after_seek(self.playhead, frames)),
+            _ => None,
+        };
+        if !seeks {
+            let from_gain = if self.playing { self.transport_fade.value } else { 0.0 };
+            self.transport_fade = match self.declick.start_stop {
+                0 => LinearRamp::new(1.0),
+                frames => LinearRamp::between(from_gain, 1.0, frames as usize),
+            };
+        }
+        self.playing = true;
+        self.stopping = false;
+        self.playhead = from;
+        for track in self.tracks.iter_mut() {
+            for voice in track.clips.voices.iter_mut() {
+                voice.stop_fades();
+            }
+            track.clips.ramp_frames_left = 0;
+            track.reset_automation();
+        }
+    }
+
+    fn stop(&mut self) {
+        self.playing = false;
+        self.stopping = false;
+        self.crossfade = None;
+        self.transport_fade = LinearRamp::new(1.0);
+        for track in self.tracks.iter_mut() {
+            track.reset_automation();
+        }
+    }
+
+    /// The number of frames the loop crossfade takes on either side of the loop
+    /// point of `range`. It can't reach before the start of the timeline, and
+    /// takes at most half of the loop.
+    fn loop_crossfade_half(&self, range: LoopRange) -> u64 {
+        (self.declick.loop_point / 2).min(range.start).min((range.end - range.start) / 2)
+    }
+
+    fn track_mut(&mut self, id: u64) -> Option<&mut TimelineTrack> {
+        self.tracks.iter_mut().find(|t| t.id == id)
+    }
+
+    /// Sends `garbage` back to the handle to be deallocated there. If the handle
+    /// is not keeping up, the garbage waits in the pending slot instead.
+    fn dispose(&mut self, garbage: Garbage) {
+        if let Err(PushError::Full(garbage)) = self.to_handle.push(garbage) {
+            debug_assert!(self.pending_garbage.is_none());
+            self.pending_garbage = Some(garbage);
+            self.log(RtEvent::GarbageDeferred);
+        }
+    }
+
+    fn log_fades_cut_off(&self, track: u64, num_fades: u32) {
+        if num_fades > 0 {
+            self.log(RtEvent::FadesCutOff { track, num_fades });
+        }
+    }
+
+    fn log(&self, event: RtEvent) {
+        if let Some(rt_logger) = &self.rt_logger {
+            rt_logger.log(self.block, event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::automation::{AutomationLane, LaneCurve, LanePoint};
+    use crate::backend::rt_log::{rt_log, RtLogRecord, RT_LOG_CAPACITY};
+    use crate::backend::test_nodes::{sine, Unwarped};
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+
+    /// Plays the source audio from its start at `rate` times its original
+    /// speed.
+    struct Resampled(f64);
+
+    impl ClipSource for Resampled {
+        fn position_and_gain(&self, frame: u64) -> (f64, f32) {
+            (frame as f64 * self.0, 1.0)
+        }
+
+        fn read(&self, source: &[f32], pos: f64, _rate: f64) -> f32 {
+            source.get(pos as usize).copied().unwrap_or(0.0)
+        }
+    }
+
+    /// Plays the source audio from `self.0` frames into it, like a clip
+    /// whose content slipped.
+    struct Slipped(u64);
+
+    impl ClipSource for Slipped {
+        fn position_and_gain(&self, frame: u64) -> (f64, f32) {
+            ((frame + self.0) as f64, 1.0)
+        }
+
+        fn read(&self, source: &[f32], pos: f64, _rate: f64) -> f32 {
+            source.get(pos as usize).copied().unwrap_or(0.0)
+        }
+    }
+
+    fn clip(start: u64, audio: Vec<Vec<f32>>) -> TimelineClip {
+        let len = audio[0].len() as u64;
+        TimelineClip {
+            id: 1,
+            start,
+            end: start + len,
+            audio: Arc::new(audio),
+            source: Arc::new(Unwarped),
+            rate: 1.0,
+            polarity: 1.0,
+            trims: [1.0, 1.0],
+        }
+    }
+
+    fn resampled(audio: &Arc<Vec<Vec<f32>>>, rate: f64) -> TimelineClip {
+        TimelineClip {
+            id: 1,
+            start: 0,
+            end: 48_000,
+            audio: Arc::clone(audio),
+            source: Arc::new(Resampled(rate)),
+            rate,
+            polarity: 1.0,
+            trims: [1.0, 1.0],
+        }
+    }
+
+    /// Plays the clip of `resampled()` and changes its rate to each of
+    /// `rates` in turn, every `interval` frames. Returns the left channel.
+    fn render_rate_changes(audio: Vec<f32>, rates: &[f64], interval: usize) -> Vec<f32> {
+        let audio = Arc::new(vec![audio]);
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let clips = TrackClips::new(vec![resampled(&audio, 1.0)], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; interval * 2];
+        player.process_interleaved(&mut out, 2);
+        for rate in rates {
+            let clips = TrackClips::new(vec![resampled(&audio, *rate)], SAMPLE_RATE);
+            handle.send(TimelineMsg::SetClips { track: 1, clips });
+            let mut block = vec![0.0; interval * 2];
+            player.process_interleaved(&mut block, 2);
+            out.extend_from_slice(&block);
+        }
+        out.iter().step_by(2).copied().collect()
+    }
+
+    /// Plays a track with `clips` and the given input stage for `num_frames`
+    /// frames, and returns the interleaved stereo output.
+    fn render_track(
+        clips: Vec<TimelineClip>,
+        trim_db: f32,
+        phase_invert: bool,
+        num_frames: usize,
+    ) -> Vec<f32> {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        let mut track = TimelineTrack::new(1, SAMPLE_RATE);
+        // Start out at the trim instead of ramping to it.
+        track.input_trim = SmoothedGain::new(trim_db, SAMPLE_RATE);
+        track.set_input(trim_db, phase_invert);
+        handle.send(TimelineMsg::AddTrack(track));
+        handle.send(TimelineMsg::SetClips { track: 1, clips: TrackClips::new(clips, SAMPLE_RATE) });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; num_frames * 2];
+        for block in out.chunks_mut(256 * 2) {
+            player.process_interleaved(block, 2);
+        }
+        out
+    }
+
+    #[test]
+    fn inverted_track_is_the_negated_track() {
+        let clips = vec![clip(100, vec![sine(4000), sine(4000)])];
+        let normal = render_track(clips.clone(), 0.0, false, 5000);
+        let inverted = render_track(clips, 0.0, true, 5000);
+
+        assert!(normal.iter().any(|s| s.abs() > 0.1));
+        for (normal, inverted) in normal.iter().zip(inverted.iter()) {
+            assert_eq!(*inverted, -*normal);
+        }
+    }
+
+    #[test]
+    fn trim_of_minus_6_db_halves_the_amplitude() {
+        let clips = vec![clip(0, vec![sine(4000)])];
+        let normal = render_track(clips.clone(), 0.0, false, 4000);
+        let trimmed = render_track(clips, -6.02, false, 4000);
+
+        for (normal, trimmed) in normal.iter().zip(trimmed.iter()) {
+            assert!((trimmed - normal * 0.5).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn polarity_only_flips_at_block_boundaries() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let clips = TrackClips::new(vec![clip(0, vec![vec![0.5; 2048]])], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; 1024 * 2];
+        player.process_interleaved(&mut out, 2);
+        assert!(out.iter().all(|s| *s == 0.5));
+
+        handle.send(TimelineMsg::SetInput { track: 1, trim_db: 0.0, phase_invert: true });
+        let mut out = vec![0.0; 1024 * 2];
+        player.process_interleaved(&mut out, 2);
+        assert!(out.iter().all(|s| *s == -0.5));
+    }
+
+    #[test]
+    fn pan_gains_ramp_to_their_new_values() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let clips = TrackClips::new(vec![clip(0, vec![vec![0.5; 4096]])], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::SetPan { track: 1, left_gain: 0.25, right_gain: 1.5 });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; 4096 * 2];
+        for block in out.chunks_mut(256 * 2) {
+            player.process_interleaved(block, 2);
+        }
+
+        // Both sides start at unity and ramp without jumping.
+        let ramp_frames = (GAIN_SMOOTHING_SECS * SAMPLE_RATE) as usize;
+        for (channel, target) in [(0, 0.125), (1, 0.75)] {
+            let side: Vec<f32> = out.iter().skip(channel).step_by(2).copied().collect();
+            assert!((side[0] - 0.5).abs() < 0.01);
+            let max_step = side.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+            assert!(max_step < 0.001, "{}", max_step);
+            assert!(side[ramp_frames..].iter().all(|s| (s - target).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn the_correlation_meter_follows_the_chosen_bus() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        // One track with the same audio on both sides, and a quieter one with
+        // the right side inverted.
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(2, SAMPLE_RATE)));
+        let mono = vec![clip(0, vec![sine(48_000), sine(48_000)])];
+        handle.send(TimelineMsg::SetClips { track: 1, clips: TrackClips::new(mono, SAMPLE_RATE) });
+        let quiet: Vec<f32> = sine(48_000).iter().map(|s| s * 0.5).collect();
+        let inverted: Vec<f32> = quiet.iter().map(|s| -s).collect();
+        let wide = vec![clip(0, vec![quiet, inverted])];
+        handle.send(TimelineMsg::SetClips { track: 2, clips: TrackClips::new(wide, SAMPLE_RATE) });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let play = |player: &mut TimelinePlayer| {
+            for _ in 0..16 {
+                player.process_interleaved(&mut [0.0; 512 * 2], 2);
+            }
+        };
+
+        // On the master output, the first track outweighs the second one on
+        // both sides, so the sides are still in phase.
+        play(&mut player);
+        assert!((handle.correlation() - 1.0).abs() < 1e-4, "{}", handle.correlation());
+
+        handle.send(TimelineMsg::SetCorrelationBus(Some(2)));
+        play(&mut player);
+        assert!((handle.correlation() + 1.0).abs() < 1e-4, "{}", handle.correlation());
+
+        handle.send(TimelineMsg::SetCorrelationBus(Some(1)));
+        play(&mut player);
+        assert!((handle.correlation() - 1.0).abs() < 1e-4, "{}", handle.correlation());
+    }
+
+    #[test]
+    fn rate_changes_crossfade_instead_of_jumping() {
+        let out = render_rate_changes(sine(48_000), &[1.25], 1024);
+        let max_step = out.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        // A 440 Hz sine at half scale moves by at most ~0.03 per source frame,
+        // and `Resampled` skips up to two source frames at 1.25 times the
+        // rate. Jumping to the position of the new rate would move by ~0.8
+        // here.
+        assert!(max_step < 0.07, "{}", max_step);
+
+        // After the crossfade, only the new rate plays.
+        let crossfade_frames = (GAIN_SMOOTHING_SECS * SAMPLE_RATE) as usize;
+        let source = sine(48_000);
+        for (frame, s) in out.iter().enumerate().skip(1024 + crossfade_frames) {
+            assert_eq!(*s, source[(frame as f64 * 1.25) as usize]);
+        }
+    }
+
+    #[test]
+    fn crossfades_keep_the_level_while_the_rate_is_dragged() {
+        // Change the rate faster than a crossfade takes, like while dragging.
+        let rates = [1.1, 1.2, 1.3, 1.4, 1.5, 1.6, 1.7, 1.8, 1.9, 2.0];
+        let out = render_rate_changes(vec![0.5; 48_000], &rates, 300);
+        for s in out.iter() {
+            assert!((s - 0.5).abs() < 1e-4, "{}", s);
+        }
+    }
+
+    #[test]
+    fn fades_that_are_cut_off_are_logged() {
+        let audio = Arc::new(vec![vec![0.5; 48_000]]);
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        let (rt_logger, mut rt_log_reader) = rt_log(RT_LOG_CAPACITY);
+        player.set_rt_logger(rt_logger);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        // Change the rate more often than there is room for fading versions,
+        // all within one crossfade.
+        let mut out = vec![0.0; 10 * 2];
+        for i in 0..=MAX_FADING_CLIPS + 2 {
+            let rate = 1.0 + i as f64 / 10.0;
+            let clips = TrackClips::new(vec![resampled(&audio, rate)], SAMPLE_RATE);
+            handle.send(TimelineMsg::SetClips { track: 1, clips });
+            player.process_interleaved(&mut out, 2);
+        }
+
+        let mut num_fades = 0;
+        rt_log_reader.drain(|record| match record.event {
+            RtEvent::FadesCutOff { track: 1, num_fades: n } => num_fades += n,
+            event => panic!("unexpected event {:?}", event),
+        });
+        // The first change leaves one fading version, and every later one
+        // adds one more.
+        assert_eq!(num_fades, 2);
+    }
+
+    #[test]
+    fn clips_whose_rate_did_not_change_play_on() {
+        let out = render_rate_changes(sine(48_000), &[1.0, 1.0], 1000);
+        let source = sine(48_000);
+        assert_eq!(out, source[..3000]);
+    }
+
+    #[test]
+    fn slipping_a_playing_clip_crossfades_to_the_new_position() {
+        let source = Arc::new(vec![sine(48_000)]);
+        let slipped = |offset| TimelineClip {
+            source: Arc::new(Slipped(offset)),
+            end: 40_000,
+            ..clip(0, source.to_vec())
+        };
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let clips = TrackClips::new(vec![slipped(0)], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; 1024 * 2];
+        player.process_interleaved(&mut out, 2);
+        // Slip the content by a bit more than a sixth of the sine's period.
+        let clips = TrackClips::new(vec![slipped(19)], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        let mut block = vec![0.0; 4096 * 2];
+        player.process_interleaved(&mut block, 2);
+        out.extend_from_slice(&block);
+        let out: Vec<f32> = out.iter().step_by(2).copied().collect();
+
+        let max_step = out.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        // Jumping to the new position would move by up to ~0.5 here.
+        assert!(max_step < 0.04, "{}", max_step);
+
+        // After the crossfade, only the slipped content plays.
+        let crossfade_frames = (GAIN_SMOOTHING_SECS * SAMPLE_RATE) as usize;
+        for (frame, s) in out.iter().enumerate().skip(1024 + crossfade_frames) {
+            assert_eq!(*s, source[0][frame + 19]);
+        }
+    }
+
+    #[test]
+    fn tempo_changes_move_the_playhead_and_crossfade() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let clips = TrackClips::new(vec![clip(0, vec![sine(48_000)])], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; 4850 * 2];
+        player.process_interleaved(&mut out, 2);
+
+        // Double the tempo, which moves the playhead from 4850 to 2425, where
+        // the sine is at another phase.
+        let clips = TrackClips::new(vec![clip(0, vec![sine(48_000)])], SAMPLE_RATE);
+        handle.send(TimelineMsg::Retime { playhead_scale: 0.5, tracks: vec![(1, clips)] });
+        let mut block = vec![0.0; 2000 * 2];
+        player.process_interleaved(&mut block, 2);
+        out.extend_from_slice(&block);
+        let out: Vec<f32> = out.iter().step_by(2).copied().collect();
+
+        assert_eq!(handle.playhead(), 2425 + 2000);
+        let max_step = out.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        assert!(max_step < 0.04, "max step {}", max_step);
+        // Once the crossfade is over, the clip plays from its new position.
+        assert_eq!(out[4850 + 1500..], sine(48_000)[2425 + 1500..2425 + 2000]);
+    }
+
+    #[test]
+    fn skipping_blocks_without_clips_renders_the_same() {
+        let clips = vec![
+            clip(1000, vec![sine(3000)]),
+            TimelineClip { id: 2, ..clip(20_000, vec![sine(3000)]) },
+        ];
+        let rendered = render_track(clips.clone(), 0.0, false, 30_000);
+        let expected = render_clips(&clips, SAMPLE_RATE, 0, 30_000, 2);
+
+        for (i, frame) in rendered.chunks_exact(2).enumerate() {
+            assert_eq!(frame, [expected[0][i], expected[1][i]]);
+        }
+    }
+
+    #[test]
+    fn tracks_report_their_activity_in_the_last_block() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(2, SAMPLE_RATE)));
+        let clips =
+            vec![clip(0, vec![sine(1000)]), TimelineClip { id: 2, ..clip(500, vec![sine(1000)]) }];
+        handle.send(TimelineMsg::SetClips { track: 1, clips: TrackClips::new(clips, SAMPLE_RATE) });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut block = vec![0.0; 256 * 2];
+        player.process_interleaved(&mut block, 2);
+        assert!(!handle.silence_flags().was_silent(0));
+        assert!(handle.silence_flags().was_silent(1));
+        assert_eq!(handle.clip_counts().count(0), 1);
+
+        // Frames 512 to 768, where the clips overlap.
+        player.process_interleaved(&mut block, 2);
+        player.process_interleaved(&mut block, 2);
+        assert_eq!(handle.clip_counts().count(0), 2);
+        assert_eq!(handle.clip_counts().total(), 2);
+
+        handle.send(TimelineMsg::Play { from: 5000 });
+        block.fill(0.0);
+        player.process_interleaved(&mut block, 2);
+        assert!(handle.silence_flags().was_silent(0));
+        assert_eq!(handle.clip_counts().total(), 0);
+        assert!(block.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn playback_starts_on_the_frame_after_the_count_in() {
+        // Half a bar of 4/4 at 120 bpm, so the count-in is 48000 frames.
+        let count_in = CountIn::new(1, 2.0, 120.0, SAMPLE_RATE);
+        let total = count_in.total_frames() as usize;
+        assert_eq!(total, 48_000);
+
+        for block_frames in [1, 7, 64, 512, 513, 1000, 4096] {
+            let (mut handle, mut player) = timeline(SAMPLE_RATE);
+            handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+            let clips = vec![clip(10_000, vec![vec![0.25; 20_000], vec![0.25; 20_000]])];
+            handle.send(TimelineMsg::SetClips {
+                track: 1,
+                clips: TrackClips::new(clips, SAMPLE_RATE),
+            });
+            handle.send(TimelineMsg::CountIn { from: 10_000, count_in });
+            assert_eq!(handle.count_in_elapsed(), Some(0));
+
+            let mut out = vec![0.0; (total + 5000) * 2];
+            for block in out.chunks_mut(block_frames * 2) {
+                player.process_interleaved(block, 2);
+                // The playhead doesn't move during the count-in.
+                if handle.count_in_elapsed().is_some() {
+                    assert_eq!(handle.playhead(), 10_000);
+                }
+            }
+
+            assert_eq!(handle.count_in_elapsed(), None);
+            assert_eq!(handle.playhead(), 15_000, "blocks of {}", block_frames);
+            let left: Vec<f32> = out.iter().step_by(2).copied().collect();
+            assert!(left[..total].iter().any(|s| s.abs() > 0.1), "blocks of {}", block_frames);
+            assert!(left[total - 100..total].iter().all(|s| *s == 0.0));
+            assert!(left[total..].iter().all(|s| *s == 0.25), "blocks of {}", block_frames);
+        }
+    }
+
+    #[test]
+    fn stopping_cancels_the_count_in() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        let count_in = CountIn::new(1, 4.0, 120.0, SAMPLE_RATE);
+        handle.send(TimelineMsg::CountIn { from: 0, count_in });
+        let mut block = vec![0.0; 256 * 2];
+        player.process_interleaved(&mut block, 2);
+        assert_eq!(handle.count_in_elapsed(), Some(256));
+
+        handle.send(TimelineMsg::Stop);
+        player.process_interleaved(&mut block, 2);
+        assert_eq!(handle.count_in_elapsed(), None);
+        assert!(!handle.is_playing());
+
+        // Playing again doesn't resume the count-in.
+        handle.send(TimelineMsg::Play { from: 0 });
+        block.fill(0.0);
+        player.process_interleaved(&mut block, 2);
+        assert_eq!(handle.playhead(), 256);
+        assert!(block.iter().all(|s| *s == 0.0));
+    }
+
+    /// Plays `audio` from the start of the timeline while looping over
+    /// `range` with a crossfade of `crossfade_frames`, in blocks of
+    /// `block_frames` frames. Returns the left channel.
+    fn render_loop(
+        audio: Vec<f32>,
+        range: Option<LoopRange>,
+        crossfade_frames: u64,
+        from: u64,
+        num_frames: usize,
+        block_frames: usize,
+    ) -> Vec<f32> {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let clips = TrackClips::new(vec![clip(0, vec![audio])], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::SetLoop(range));
+        handle.send(TimelineMsg::SetDeclick(DeclickTimes {
+            loop_point: crossfade_frames,
+            ..DeclickTimes::default()
+        }));
+        handle.send(TimelineMsg::Play { from });
+
+        let mut out = vec![0.0; num_frames * 2];
+        for block in out.chunks_mut(block_frames * 2) {
+            player.process_interleaved(block, 2);
+        }
+        out.iter().step_by(2).copied().collect()
+    }
+
+    /// Like `render_loop()` without a crossfade, with a clip whose frames hold
+    /// their own frame number.
+    fn render_numbered(
+        range: Option<LoopRange>,
+        from: u64,
+        num_frames: usize,
+        block_frames: usize,
+    ) -> Vec<f32> {
+        let numbered = (0..10_000).map(|i| i as f32).collect();
+        render_loop(numbered, range, 0, from, num_frames, block_frames)
+    }
+
+    #[test]
+    fn looping_jumps_back_on_the_loop_end_at_any_block_size() {
+        let range = LoopRange { start: 1000, end: 3000 };
+        let expected: Vec<f32> =
+            (500..3000).chain((1000..3000).cycle()).take(7000).map(|f| f as f32).collect();
+
+        // Block sizes that end right before, on and right after the loop end,
+        // and ones that contain the whole loop.
+        for block_frames in [1, 7, 64, 511, 512, 513, 1999, 2000, 2001, 2500, 4096] {
+            let left = render_numbered(Some(range), 500, 7000, block_frames);
+            for (frame, (s, expected)) in left.iter().zip(expected.iter()).enumerate() {
+                assert_eq!(s, expected, "frame {}, blocks of {}", frame, block_frames);
+            }
+        }
+    }
+
+    #[test]
+    fn playback_after_the_loop_end_does_not_loop() {
+        let range = LoopRange { start: 1000, end: 3000 };
+        let left = render_numbered(Some(range), 3500, 2000, 512);
+        let expected: Vec<f32> = (3500..5500).map(|f| f as f32).collect();
+        assert_eq!(left, expected);
+    }
+
+    #[test]
+    fn the_loop_crossfade_is_centered_on_the_loop_point() {
+        let range = LoopRange { start: 1000, end: 3000 };
+        let numbered: Vec<f32> = (0..10_000).map(|i| i as f32).collect();
+        // 200 frames on either side of the loop point, where the audio at the
+        // playhead is mixed with the audio one loop length away.
+        let expected = |frame: u64, wrapped: bool| -> f32 {
+            let frame = frame as f32;
+            if (2800.0..3000.0).contains(&frame) {
+                let t = (frame - 2800.0) / 400.0;
+                frame * (1.0 - t) + (frame - 2000.0) * t
+            } else if wrapped && (1000.0..1200.0).contains(&frame) {
+                let t = (frame - 800.0) / 400.0;
+                frame * t + (frame + 2000.0) * (1.0 - t)
+            } else {
+                frame
+            }
+        };
+        let playheads = (0..3000).map(|f| (f, false)).chain((1000..3000).map(|f| (f, true)));
+        let expected: Vec<f32> = playheads.map(|(f, wrapped)| expected(f, wrapped)).collect();
+
+        for block_frames in [1, 7, 64, 199, 200, 201, 512, 2000, 4096] {
+            let left = render_loop(numbered.clone(), Some(range), 400, 0, 5000, block_frames);
+            for (frame, (s, expected)) in left.iter().zip(expected.iter()).enumerate() {
+                assert!(
+                    (s - expected).abs() < 0.05,
+                    "frame {}: {} instead of {}, blocks of {}",
+                    frame,
+                    s,
+                    expected,
+                    block_frames
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn the_loop_crossfade_keeps_the_level() {
+        let range = LoopRange { start: 2000, end: 6000 };
+        for block_frames in [64, 333, 512] {
+            let left = render_loop(vec![0.5; 10_000], Some(range), 1000, 0, 20_000, block_frames);
+            for s in left.iter() {
+                assert!((s - 0.5).abs() < 1e-4, "{}", s);
+            }
+        }
+    }
+
+    #[test]
+    fn the_loop_crossfade_is_clamped_to_the_pre_roll() {
+        // Only 100 frames come before the loop start, so the crossfade takes
+        // 100 frames on either side of the loop point.
+        let range = LoopRange { start: 100, end: 3000 };
+        let numbered: Vec<f32> = (0..10_000).map(|i| i as f32).collect();
+        let left = render_loop(numbered, Some(range), 4800, 0, 3200, 256);
+        assert_eq!(left[..2900], (0..2900).map(|f| f as f32).collect::<Vec<_>>());
+        // Halfway through, the audio at the loop end and at the loop start are
+        // mixed equally.
+        assert!((left[3000] - (100.0 + 3000.0) / 2.0).abs() < 0.05, "{}", left[3000]);
+        assert_eq!(left[3100..], (200..300).map(|f| f as f32).collect::<Vec<_>>());
+    }
+
+    /// Creates a player with a track that plays `audio` from the start of the
+    /// timeline, with the fades of `declick`.
+    fn player_with_fades(
+        audio: Vec<f32>,
+        declick: DeclickTimes,
+    ) -> (TimelineHandle, TimelinePlayer) {
+        let (mut handle, player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let clips = TrackClips::new(vec![clip(0, vec![audio])], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::SetDeclick(declick));
+        (handle, player)
+    }
+
+    /// Processes `num_frames` frames in blocks of 100 frames, and returns the
+    /// left channel.
+    fn render_blocks(player: &mut TimelinePlayer, num_frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; num_frames * 2];
+        for block in out.chunks_mut(200) {
+            player.process_interleaved(block, 2);
+        }
+        out.iter().step_by(2).copied().collect()
+    }
+
+    #[test]
+    fn starting_and_stopping_fade_the_tracks() {
+        let declick = DeclickTimes { start_stop: 480, ..DeclickTimes::default() };
+        let (mut handle, mut player) = player_with_fades(vec![0.5; 10_000], declick);
+        handle.send(TimelineMsg::Play { from: 0 });
+        let left = render_blocks(&mut player, 1000);
+        for (frame, s) in left.iter().enumerate() {
+            let expected = 0.5 * (frame as f32 / 480.0).min(1.0);
+            assert!((s - expected).abs() < 1e-5, "frame {}: {}", frame, s);
+        }
+
+        // The transport keeps playing until the fade out is over.
+        handle.send(TimelineMsg::Stop);
+        let left = render_blocks(&mut player, 1000);
+        for (frame, s) in left.iter().enumerate() {
+            let expected = 0.5 * (1.0 - frame as f32 / 480.0).max(0.0);
+            assert!((s - expected).abs() < 1e-5, "frame {}: {}", frame, s);
+        }
+        assert!(!handle.is_playing());
+        assert_eq!(handle.playhead(), 1480);
+    }
+
+    #[test]
+    fn a_long_loop_crossfade_and_a_short_seek_crossfade_coexist() {
+        let range = LoopRange { start: 1000, end: 6000 };
+        let declick = DeclickTimes { seek: 100, loop_point: 2000, ..DeclickTimes::default() };
+        let numbered: Vec<f32> = (0..10_000).map(|i| i as f32).collect();
+        let (mut handle, mut player) = player_with_fades(numbered, declick);
+        handle.send(TimelineMsg::SetLoop(Some(range)));
+        handle.send(TimelineMsg::Play { from: 0 });
+        render_blocks(&mut player, 2000);
+
+        // Seek from frame 2000 to frame 3000, and play through the loop point.
+        handle.send(TimelineMsg::Play { from: 3000 });
+        let left = render_blocks(&mut player, 5000);
+        let expected = |k: usize| -> f32 {
+            let frame = (3000 + k) as f32;
+            if k < 100 {
+                // Only the 100 frames of the seek crossfade.
+                let t = k as f32 / 100.0;
+                (2000 + k) as f32 * (1.0 - t) + frame * t
+            } else if (5000.0..6000.0).contains(&frame) {
+                // The loop crossfade takes 1000 frames on either side of the
+                // loop point.
+                let t = (frame - 5000.0) / 2000.0;
+                frame * (1.0 - t) + (frame - 5000.0) * t
+            } else if (6000.0..7000.0).contains(&frame) {
+                // After the jump, the playhead is one loop length back.
+                let playhead = frame - 5000.0;
+                let t = playhead / 2000.0;
+                playhead * t + frame * (1.0 - t)
+            } else if frame >= 7000.0 {
+                frame - 5000.0
+            } else {
+                frame
+            }
+        };
+        for (k, s) in left.iter().enumerate() {
+            assert!(
+                (s - expected(k)).abs() < 0.05,
+                "frame {}: {} instead of {}",
+                k,
+                s,
+                expected(k)
+            );
+        }
+    }
+
+    #[test]
+    fn the_loop_region_is_reported_back() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        assert_eq!(handle.loop_range(), None);
+
+        let range = LoopRange { start: 1000, end: 3000 };
+        handle.send(TimelineMsg::SetLoop(Some(range)));
+        assert_eq!(handle.loop_range(), Some(range));
+        player.process_interleaved(&mut [0.0; 256 * 2], 2);
+        assert_eq!(handle.loop_range(), Some(range));
+
+        // Empty loop regions are ignored.
+        handle.send(TimelineMsg::SetLoop(Some(LoopRange { start: 3000, end: 3000 })));
+        player.process_interleaved(&mut [0.0; 256 * 2], 2);
+        assert_eq!(handle.loop_range(), None);
+    }
+
+    /// A lane through `points`, given as `(frame, value)` pairs.
+    fn lane(points: &[(f64, f64)]) -> AutomationLane {
+        AutomationLane::new(
+            points
+                .iter()
+                .map(|(frame, value)| LanePoint {
+                    frame: *frame,
+                    value: *value,
+                    curve: LaneCurve::Linear,
+                })
+                .collect(),
+        )
+    }
+
+    /// Plays a constant signal of 0.5 through a track with `automation` in
+    /// blocks of `block_frames` frames, looping over `range`, and returns the
+    /// interleaved stereo output.
+    fn render_automated(
+        automation: TrackAutomation,
+        range: Option<LoopRange>,
+        num_frames: usize,
+        block_frames: usize,
+    ) -> Vec<f32> {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let audio = vec![vec![0.5; num_frames]];
+        let clips = TrackClips::new(vec![clip(0, audio)], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::SetAutomation { track: 1, automation });
+        handle.send(TimelineMsg::SetLoop(range));
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; num_frames * 2];
+        for block in out.chunks_mut(block_frames * 2) {
+            player.process_interleaved(block, 2);
+        }
+        out
+    }
+
+    #[test]
+    fn stems_start_at_their_settings_and_add_up_to_the_mix() {
+        let tracks = || {
+            vec![
+                OfflineTrack {
+                    id: 1,
+                    clips: vec![clip(0, vec![vec![0.5; 20_000]])],
+                    trim_db: 0.0,
+                    phase_invert: false,
+                    pan_gains: [1.0, 1.0],
+                    volume: 0.5,
+                    automation: TrackAutomation::default(),
+                },
+                OfflineTrack {
+                    id: 2,
+                    clips: vec![clip(5_000, vec![sine(20_000), sine(20_000)])],
+                    trim_db: -6.0,
+                    phase_invert: true,
+                    pan_gains: [0.25, 1.5],
+                    volume: 1.0,
+                    automation: TrackAutomation {
+                        gain: lane(&[(0.0, 0.0), (20_000.0, 1.0)]),
+                        ..Default::default()
+                    },
+                },
+            ]
+        };
+        let frames = 1_000..30_000;
+        let mix = render_timeline(tracks(), SAMPLE_RATE, frames.clone(), 0);
+        let stems: Vec<Vec<Vec<f32>>> = tracks()
+            .into_iter()
+            .map(|track| render_timeline(vec![track], SAMPLE_RATE, frames.clone(), 0))
+            .collect();
+
+        // The volume doesn't ramp up from unity at the start.
+        assert_eq!(stems[0][0][0], 0.25);
+        assert!(stems[1][1].iter().any(|s| s.abs() > 0.01));
+        for channel in 0..2 {
+            assert_eq!(mix[channel].len(), 29_000);
+            for (frame, s) in mix[channel].iter().enumerate() {
+                let sum = stems[0][channel][frame] + stems[1][channel][frame];
+                assert!((s - sum).abs() < 1e-6, "frame {}: {} != {}", frame, s, sum);
+            }
+        }
+    }
+
+    #[test]
+    fn a_rendered_range_sounds_like_playing_it() {
+        // A bar of 4/4 at 120 BPM.
+        const BAR: u64 = 96_000;
+        // Bars 5 to 8, with clips that cross its start and its end, one inside
+        // of it and one before it.
+        let frames = 4 * BAR..8 * BAR;
+        let clips = vec![
+            clip(BAR, vec![sine(BAR as usize)]),
+            clip(4 * BAR - 20_000, vec![sine(40_000)]),
+            clip(5 * BAR, vec![sine(BAR as usize)]),
+            clip(8 * BAR - 30_000, vec![sine(60_000)]),
+        ];
+        let clips: Vec<TimelineClip> = clips
+            .into_iter()
+            .enumerate()
+            .map(|(id, clip)| TimelineClip { id: id as u64, ..clip })
+            .collect();
+        let track = || OfflineTrack {
+            id: 1,
+            clips: clips.clone(),
+            trim_db: 0.0,
+            phase_invert: false,
+            pan_gains: [1.0, 1.0],
+            volume: 1.0,
+            automation: TrackAutomation::default(),
+        };
+        let num_frames = (frames.end - frames.start) as usize;
+        let fade = 480;
+
+        let declick = DeclickTimes { start_stop: fade as u64, ..DeclickTimes::default() };
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(track().into_track(SAMPLE_RATE)));
+        handle.send(TimelineMsg::SetDeclick(declick));
+        handle.send(TimelineMsg::Play { from: frames.start });
+        let played = render_blocks(&mut player, num_frames);
+
+        let rendered = render_timeline(vec![track()], SAMPLE_RATE, frames, fade as u64);
+        assert_eq!(rendered[0].len(), num_frames);
+        assert_eq!(rendered[0][0], 0.0);
+        // The partial clips play up to the end of the first one and from the
+        // start of the last one.
+        assert!(rendered[0][..20_000].iter().any(|s| s.abs() > 0.4));
+        assert!(rendered[0][20_000..BAR as usize].iter().all(|s| *s == 0.0));
+        assert!(rendered[0][num_frames - 30_000..].iter().any(|s| s.abs() > 0.4));
+
+        // Up to the fade out, the render is what playing the range sounds
+        // like, and then it fades out by the last frame.
+        let fade_start = num_frames - fade;
+        for (frame, (played, rendered)) in played.iter().zip(&rendered[0]).enumerate() {
+            let gain = 1.0 - frame.saturating_sub(fade_start) as f32 / fade as f32;
+            let expected = played * gain;
+            assert!((rendered - expected).abs() < 1e-5, "frame {}: {}", frame, rendered);
+        }
+    }
+
+    #[test]
+    fn looped_volume_ramps_render_the_same_at_any_block_size() {
+        // A volume ramp from 0.0 to 1.0 over 4 beats at 120 BPM, looping from
+        // beat 3 back to beat 1.
+        let automation =
+            TrackAutomation { gain: lane(&[(0.0, 0.0), (96_000.0, 1.0)]), ..Default::default() };
+        let range = Some(LoopRange { start: 24_000, end: 72_000 });
+
+        let small = render_automated(automation.clone(), range, 4 * 48_000, 64);
+        let large = render_automated(automation, range, 4 * 48_000, 1024);
+        assert_eq!(small, large);
+
+        let left: Vec<f32> = small.iter().step_by(2).copied().collect();
+        // The loop jumps from the end of the ramp at beat 3 (0.75) back to
+        // beat 1 (0.25), which is spread over 96 frames instead of clicking.
+        let max_step = left.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        assert!(max_step <= 0.5 * 0.5 / 96.0 + 1e-6, "max step {}", max_step);
+        // After the declick, the volume follows the lane from beat 1 on.
+        let after_loop = 72_000 + 96;
+        assert!((left[after_loop] - 0.5 * 24_096.0 / 96_000.0).abs() < 1e-6);
+    }
+
+    /// Plays a constant signal of 0.5 through a gain lane that ramps from 0.0
+    /// to 1.0 over every 24000 frames and then jumps back, in blocks of 256
+    /// frames. Returns the left channel.
+    fn render_sawtooth(corner_smoothing: Option<f64>) -> Vec<f32> {
+        let points: Vec<(f64, f64)> = (0..8)
+            .flat_map(|tooth| {
+                let start = f64::from(tooth) * 24_000.0;
+                [(start, 0.0), (start + 24_000.0, 1.0)]
+            })
+            .collect();
+        let automation =
+            TrackAutomation { gain: lane(&points), corner_smoothing, ..Default::default() };
+        let out = render_automated(automation, None, 8 * 24_000, 256);
+        out.iter().step_by(2).copied().collect()
+    }
+
+    #[test]
+    fn sawtooth_automation_is_continuous_across_block_edges() {
+        let left = render_sawtooth(None);
+
+        // The sawtooth rises by 0.5 every 24000 frames, and each of its jumps
+        // back down is spread over the 96 frames of `AUTOMATION_DECLICK_SECS`,
+        // no matter where it falls in a block.
+        let max_step = 0.5 / 96.0 + 1e-6;
+        for (frame, w) in left.windows(2).enumerate() {
+            let step = (w[1] - w[0]).abs();
+            assert!(step <= max_step, "step of {} at frame {}", step, frame + 1);
+        }
+
+        // Away from the jumps, the output is right on the lane.
+        assert!((left[12_000] - 0.25).abs() < 1e-6);
+        assert!((left[24_000 + 96] - 0.5 * 96.0 / 24_000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn corner_smoothing_rounds_off_the_sawtooth_jumps() {
+        let left = render_sawtooth(Some(0.001));
+
+        // A one-pole lowpass with a time constant of 48 frames moves by at
+        // most 1/48 of the jump in a frame.
+        let max_step = left.windows(2).map(|w| (w[1] - w[0]).abs()).fold(0.0, f32::max);
+        assert!(max_step < 0.5 / 48.0, "max step {}", max_step);
+    }
+
+    #[test]
+    fn pan_automation_follows_the_pan_law() {
+        // Pan from hard left to hard right over 10000 frames.
+        let automation = TrackAutomation {
+            pan: lane(&[(0.0, 0.0), (10_000.0, 1.0)]),
+            pan_law: crate::backend::pan::linear_taper,
+            ..Default::default()
+        };
+        let out = render_automated(automation, None, 10_000, 256);
+        for (frame, out) in out.chunks_exact(2).enumerate().step_by(500) {
+            let pan = frame as f32 / 10_000.0;
+            assert!((out[0] - 0.5 * (1.0 - pan)).abs() < 1e-6, "frame {}", frame);
+            assert!((out[1] - 0.5 * pan).abs() < 1e-6, "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn clip_and_inverted_copy_cancel_out() {
+        let mut inverted = clip(100, vec![sine(4000), sine(4000)]);
+        inverted.id = 2;
+        inverted.polarity = -1.0;
+        let clips = vec![clip(100, vec![sine(4000), sine(4000)]), inverted];
+
+        let out = render_track(clips, 0.0, false, 5000);
+        assert!(out.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn polarity_flips_ramp_through_zero() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let dc = clip(0, vec![vec![0.5; 8000]]);
+        let clips = TrackClips::new(vec![dc.clone()], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; 1000 * 2];
+        player.process_interleaved(&mut out, 2);
+        assert!(out.iter().all(|s| *s == 0.5));
+
+        let clips = TrackClips::new(vec![TimelineClip { polarity: -1.0, ..dc }], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        let mut out = vec![0.0; 2000 * 2];
+        player.process_interleaved(&mut out, 2);
+        let left: Vec<f32> = out.iter().step_by(2).copied().collect();
+
+        let ramp_frames = (GAIN_SMOOTHING_SECS * SAMPLE_RATE) as usize;
+        assert!(left[0] > 0.49);
+        assert!(left.windows(2).all(|w| w[1] < w[0] || w[1] == -0.5));
+        assert!(left.windows(2).all(|w| w[0] - w[1] < 1.1 / ramp_frames as f32));
+        assert!(left[ramp_frames / 2].abs() < 1e-3);
+        assert!(left[ramp_frames..].iter().all(|s| *s == -0.5));
+    }
+
+    #[test]
+    fn channel_trims_ramp_independently() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let dc = clip(0, vec![vec![0.5; 8000], vec![0.5; 8000]]);
+        let clips = TrackClips::new(vec![dc.clone()], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; 1000 * 2];
+        player.process_interleaved(&mut out, 2);
+
+        // Only the left trim changes.
+        let clips =
+            TrackClips::new(vec![TimelineClip { trims: [0.5, 1.0], ..dc.clone() }], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        let mut out = vec![0.0; 2000 * 2];
+        player.process_interleaved(&mut out, 2);
+
+        let ramp_frames = (GAIN_SMOOTHING_SECS * SAMPLE_RATE) as usize;
+        let left: Vec<f32> = out.iter().step_by(2).copied().collect();
+        assert!(left[0] > 0.49);
+        assert!(left[..ramp_frames].windows(2).all(|w| w[1] < w[0]));
+        assert!(left[ramp_frames..].iter().all(|s| *s == 0.25));
+        assert!(out.iter().skip(1).step_by(2).all(|s| *s == 0.5));
+
+        // The right trim changes while the left one is still ramping.
+        let clips =
+            TrackClips::new(vec![TimelineClip { trims: [0.5, 1.0], ..dc.clone() }], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        let mut out = vec![0.0; 100 * 2];
+        player.process_interleaved(&mut out, 2);
+        let clips = TrackClips::new(vec![TimelineClip { trims: [0.5, 2.0], ..dc }], SAMPLE_RATE);
+        handle.send(TimelineMsg::SetClips { track: 1, clips });
+        let mut out = vec![0.0; 2000 * 2];
+        player.process_interleaved(&mut out, 2);
+
+        assert!(out.iter().step_by(2).all(|s| *s == 0.25));
+        let right: Vec<f32> = out.iter().skip(1).step_by(2).copied().collect();
+        assert!(right[..ramp_frames].windows(2).all(|w| w[1] > w[0]));
+        assert!(right[ramp_frames..].iter().all(|s| *s == 1.0));
+    }
+
+    #[test]
+    fn garbage_waits_for_the_handle_instead_of_being_dropped() {
+        let (mut handle, mut player) = timeline(SAMPLE_RATE);
+        let (rt_logger, mut rt_log_reader) = rt_log(RT_LOG_CAPACITY);
+        player.set_rt_logger(rt_logger);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE)));
+        let mut out = vec![0.0; 64 * 2];
+        player.process_interleaved(&mut out, 2);
+
+        // Every duplicate track is sent back, but the handle doesn't collect
+        // the garbage, so the ring fills up and the last one has to wait.
+        for _ in 0..2 {
+            for _ in 0..MSG_CAPACITY {
+                let duplicate = TimelineMsg::AddTrack(TimelineTrack::new(1, SAMPLE_RATE));
+                assert!(handle.to_player.push(duplicate).is_ok());
+            }
+            player.process_interleaved(&mut out, 2);
+        }
+        assert!(player.pending_garbage.is_some());
+        let mut events = Vec::new();
+        rt_log_reader.drain(|record| events.push(record));
+        assert_eq!(events, [RtLogRecord { block: 3, event: RtEvent::GarbageDeferred }]);
+
+        // No messages are polled until the pending garbage went out.
+        assert!(handle.to_player.push(TimelineMsg::Play { from: 0 }).is_ok());
+        player.process_interleaved(&mut out, 2);
+        assert!(!handle.is_playing());
+
+        assert_eq!(handle.from_player.slots(), MSG_CAPACITY);
+        handle.collect_garbage();
+        player.process_interleaved(&mut out, 2);
+        assert!(handle.is_playing());
+        assert!(player.pending_garbage.is_none());
+        assert_eq!(handle.from_player.slots(), MSG_CAPACITY);
+    }
+}
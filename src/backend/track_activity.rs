@@ -0,0 +1,91 @@
+//! What each timeline track did in its last block.
+//!
+//! A track that no clip intersects (and that is not fading out) skips its
+//! processing and marks itself as silent, in which case nothing of it is mixed
+//! into the output. The flags are shared with the rest of the
+//! program so that the graph can later skip the sends and mixers downstream of
+//! silent tracks as well.
+//!
+//! Each track also reports how many clips it processed in its last block, to
+//! help find the dense regions behind CPU spikes.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// One flag per track, shared between the audio thread and the rest of the
+/// program. Cloning this shares the same flags.
+#[derive(Debug, Clone)]
+pub struct TrackSilenceFlags {
+    flags: Arc<[AtomicBool]>,
+}
+
+impl TrackSilenceFlags {
+    /// Creates the flags for `num_tracks` tracks, all of which start out
+    /// silent.
+    pub fn new(num_tracks: usize) -> Self {
+        Self { flags: (0..num_tracks).map(|_| AtomicBool::new(true)).collect() }
+    }
+
+    pub fn num_tracks(&self) -> usize {
+        self.flags.len()
+    }
+
+    /// Called by the track on the audio thread after every block.
+    pub fn set_silent(&self, track: usize, silent: bool) {
+        if let Some(flag) = self.flags.get(track) {
+            flag.store(silent, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns `true` if the track at `track` didn't write any audio in its
+    /// last block. Tracks that don't exist are silent.
+    pub fn was_silent(&self, track: usize) -> bool {
+        self.flags.get(track).map(|flag| flag.load(Ordering::Relaxed)).unwrap_or(true)
+    }
+}
+
+/// The number of clips each track processed in its last block, shared between
+/// the audio thread and the rest of the program. Cloning this shares the same
+/// counts.
+///
+/// Each track only stores one relaxed atomic per block, and the project-wide
+/// total is only summed up when it is read, so this costs next to nothing
+/// while nobody is looking.
+#[derive(Debug, Clone)]
+pub struct TrackClipCounts {
+    counts: Arc<[AtomicU32]>,
+}
+
+impl TrackClipCounts {
+    /// Creates the counts for `num_tracks` tracks, all of which start at zero.
+    pub fn new(num_tracks: usize) -> Self {
+        Self { counts: (0..num_tracks).map(|_| AtomicU32::new(0)).collect() }
+    }
+
+    pub fn num_tracks(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Called by the track on the audio thread after every block. A track that
+    /// skipped the block as silent stores zero.
+    pub fn set_count(&self, track: usize, num_clips: u32) {
+        if let Some(count) = self.counts.get(track) {
+            count.store(num_clips, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the number of clips the track at `track` processed in its last
+    /// block. Tracks that don't exist processed none.
+    pub fn count(&self, track: usize) -> u32 {
+        self.counts.get(track).map(|count| count.load(Ordering::Relaxed)).unwrap_or(0)
+    }
+
+    /// Returns the number of clips processed by all tracks in their last
+    /// block.
+    ///
+    /// The tracks store their counts independently, so this may mix counts
+    /// from two neighbouring blocks.
+    pub fn total(&self) -> u32 {
+        self.counts.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+    }
+}
@@ -0,0 +1,283 @@
+//! The position of the transport at every frame of the output stream.
+//!
+//! The `TimelinePlayer` counts the frames of the output stream, and publishes
+//! the parts of the stream during which the transport moved along with the
+//! timeline frames they played. The recorder reads these back for the frames
+//! of the input it captured (see `backend::recorder`), so that every recorded
+//! frame lands on the timeline frame that played at the same time, no matter
+//! when the recording was started by the program layer, how the blocks of the
+//! two streams are aligned, or where the playhead looped.
+//!
+//! A part of the stream is only ever written by the audio thread of the output
+//! stream. It is published under a sequence lock, so that a reader on another
+//! thread never sees a part that is halfway written. The last
+//! `CLOCK_HISTORY` parts are kept, which is many seconds unless the playhead
+//! jumps all the time.
+
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+/// The number of parts of the stream that are kept.
+pub const CLOCK_HISTORY: usize = 256;
+
+/// What the transport did from a frame of the output stream on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportRun {
+    /// The timeline frame that played at the first frame, or `None` if the
+    /// transport stood still (i.e. while stopped or counting in).
+    pub timeline_frame: Option<u64>,
+    /// Which start of the transport the frames belong to (see
+    /// `TimelineHandle::num_starts()`).
+    pub start: u64,
+    /// The number of frames the transport kept doing this for.
+    pub num_frames: u64,
+}
+
+/// A part of the output stream during which the transport moved forward
+/// without jumping.
+#[derive(Default)]
+struct Part {
+    /// Odd while the part is being written.
+    seq: AtomicU64,
+    /// The number of the part, which tells it apart from the part that was in
+    /// the same slot `CLOCK_HISTORY` parts earlier.
+    number: AtomicU64,
+    stream_start: AtomicU64,
+    stream_end: AtomicU64,
+    timeline_start: AtomicU64,
+    start: AtomicU64,
+}
+
+/// A consistent copy of a `Part`.
+#[derive(Debug, Clone, Copy)]
+struct PartValues {
+    stream_start: u64,
+    stream_end: u64,
+    timeline_start: u64,
+    start: u64,
+}
+
+/// The position of the transport at every frame of the output stream, shared
+/// between the `TimelinePlayer` and the recorder.
+pub struct TransportClock {
+    /// The number of frames of the output stream the player has processed.
+    stream_frame: AtomicU64,
+    num_parts: AtomicU64,
+    parts: Box<[Part]>,
+}
+
+impl Default for TransportClock {
+    fn default() -> Self {
+        Self {
+            stream_frame: AtomicU64::new(0),
+            num_parts: AtomicU64::new(0),
+            parts: (0..CLOCK_HISTORY).map(|_| Part::default()).collect(),
+        }
+    }
+}
+
+impl TransportClock {
+    /// The number of frames of the output stream the player has processed.
+    pub fn stream_frame(&self) -> u64 {
+        self.stream_frame.load(Ordering::Acquire)
+    }
+
+    /// Called by the player at the end of every block, after it published the
+    /// parts of the block.
+    pub(super) fn set_stream_frame(&self, stream_frame: u64) {
+        self.stream_frame.store(stream_frame, Ordering::Release);
+    }
+
+    /// Called by the player when the transport played `num_frames` frames from
+    /// the timeline frame `timeline_frame` on, starting at the frame
+    /// `stream_frame` of the output stream. `start` is the number of the start
+    /// of the transport.
+    ///
+    /// This is realtime safe, and must only be called from one thread.
+    pub(super) fn publish(
+        &self,
+        stream_frame: u64,
+        num_frames: u64,
+        timeline_frame: u64,
+        start: u64,
+    ) {
+        let num_parts = self.num_parts.load(Ordering::Relaxed);
+        if num_parts > 0 {
+            let last = &self.parts[(num_parts - 1) as usize % CLOCK_HISTORY];
+            let values = Self::read_own(last);
+            let continues = values.stream_end == stream_frame
+                && values.timeline_start + (stream_frame - values.stream_start) == timeline_frame
+                && values.start == start;
+            if continues {
+                Self::write(
+                    last,
+                    num_parts - 1,
+                    PartValues { stream_end: stream_frame + num_frames, ..values },
+                );
+                return;
+            }
+        }
+
+        let part = &self.parts[num_parts as usize % CLOCK_HISTORY];
+        Self::write(
+            part,
+            num_parts,
+            PartValues {
+                stream_start: stream_frame,
+                stream_end: stream_frame + num_frames,
+                timeline_start: timeline_frame,
+                start,
+            },
+        );
+        self.num_parts.store(num_parts + 1, Ordering::Release);
+    }
+
+    /// Returns what the transport did from the frame `stream_frame` of the
+    /// output stream on, or `None` if the player hasn't processed that frame
+    /// yet.
+    ///
+    /// Frames from before the oldest part that is kept are reported as frames
+    /// where the transport stood still.
+    pub fn run_at(&self, stream_frame: u64) -> Option<TransportRun> {
+        let known_end = self.stream_frame();
+        if stream_frame >= known_end {
+            return None;
+        }
+
+        let num_parts = self.num_parts.load(Ordering::Acquire);
+        let oldest = num_parts.saturating_sub(CLOCK_HISTORY as u64 - 1);
+        let mut next_start = known_end;
+        for number in (oldest..num_parts).rev() {
+            let part = match self.read(number) {
+                Some(part) => part,
+                // The slot was reused for a newer part.
+                None => break,
+            };
+            if part.stream_start <= stream_frame {
+                if stream_frame < part.stream_end {
+                    return Some(TransportRun {
+                        timeline_frame: Some(
+                            part.timeline_start + (stream_frame - part.stream_start),
+                        ),
+                        start: part.start,
+                        num_frames: part.stream_end.min(known_end) - stream_frame,
+                    });
+                }
+                return Some(TransportRun {
+                    timeline_frame: None,
+                    start: part.start,
+                    num_frames: next_start.min(known_end) - stream_frame,
+                });
+            }
+            next_start = part.stream_start;
+        }
+
+        Some(TransportRun {
+            timeline_frame: None,
+            start: 0,
+            num_frames: next_start.min(known_end) - stream_frame,
+        })
+    }
+
+    fn write(part: &Part, number: u64, values: PartValues) {
+        let seq = part.seq.load(Ordering::Relaxed);
+        part.seq.store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        part.number.store(number, Ordering::Relaxed);
+        part.stream_start.store(values.stream_start, Ordering::Relaxed);
+        part.stream_end.store(values.stream_end, Ordering::Relaxed);
+        part.timeline_start.store(values.timeline_start, Ordering::Relaxed);
+        part.start.store(values.start, Ordering::Relaxed);
+        part.seq.store(seq + 2, Ordering::Release);
+    }
+
+    /// Reads a part on the thread that writes the parts, which needs no lock.
+    fn read_own(part: &Part) -> PartValues {
+        PartValues {
+            stream_start: part.stream_start.load(Ordering::Relaxed),
+            stream_end: part.stream_end.load(Ordering::Relaxed),
+            timeline_start: part.timeline_start.load(Ordering::Relaxed),
+            start: part.start.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reads the part with the given number, or returns `None` if its slot
+    /// holds a newer part already.
+    fn read(&self, number: u64) -> Option<PartValues> {
+        let part = &self.parts[number as usize % CLOCK_HISTORY];
+        loop {
+            let seq = part.seq.load(Ordering::Acquire);
+            if seq % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let read_number = part.number.load(Ordering::Relaxed);
+            let values = Self::read_own(part);
+            fence(Ordering::Acquire);
+            if part.seq.load(Ordering::Relaxed) == seq {
+                return if read_number == number { Some(values) } else { None };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contiguous_parts_are_merged() {
+        let clock = TransportClock::default();
+        clock.publish(100, 50, 1000, 1);
+        clock.publish(150, 50, 1050, 1);
+        clock.set_stream_frame(200);
+
+        assert_eq!(
+            clock.run_at(0),
+            Some(TransportRun { timeline_frame: None, start: 0, num_frames: 100 })
+        );
+        assert_eq!(
+            clock.run_at(120),
+            Some(TransportRun { timeline_frame: Some(1020), start: 1, num_frames: 80 })
+        );
+        assert_eq!(clock.run_at(200), None);
+    }
+
+    #[test]
+    fn jumps_start_a_new_part() {
+        let clock = TransportClock::default();
+        // Loops from frame 2000 back to 1000 in the middle of a block, and then
+        // stops for a block.
+        clock.publish(0, 40, 1960, 1);
+        clock.publish(40, 24, 1000, 1);
+        clock.set_stream_frame(64);
+        clock.set_stream_frame(128);
+
+        assert_eq!(
+            clock.run_at(39),
+            Some(TransportRun { timeline_frame: Some(1999), start: 1, num_frames: 1 })
+        );
+        assert_eq!(
+            clock.run_at(40),
+            Some(TransportRun { timeline_frame: Some(1000), start: 1, num_frames: 24 })
+        );
+        assert_eq!(
+            clock.run_at(64),
+            Some(TransportRun { timeline_frame: None, start: 1, num_frames: 64 })
+        );
+    }
+
+    #[test]
+    fn old_parts_are_forgotten() {
+        let clock = TransportClock::default();
+        for i in 0..CLOCK_HISTORY as u64 * 2 {
+            // Every part jumps back to the same timeline frame.
+            clock.publish(i * 10, 10, 0, 1);
+        }
+        clock.set_stream_frame(CLOCK_HISTORY as u64 * 20);
+
+        let run = clock.run_at(5).unwrap();
+        assert_eq!(run.timeline_frame, None);
+        let run = clock.run_at(CLOCK_HISTORY as u64 * 20 - 5).unwrap();
+        assert_eq!(run.timeline_frame, Some(5));
+    }
+}
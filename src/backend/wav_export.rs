@@ -0,0 +1,561 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use meadowlark_core_types::time::SampleRate;
+
+use super::decode::downmix_to_stereo;
+use super::dither::{DitherConfig, Ditherer};
+use super::loudness::{self, LoudnessMeasurement};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// The sample format of an exported WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavSampleFormat {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl WavSampleFormat {
+    pub fn bits_per_sample(&self) -> u16 {
+        match self {
+            WavSampleFormat::Int16 => 16,
+            WavSampleFormat::Int24 => 24,
+            WavSampleFormat::Float32 => 32,
+        }
+    }
+}
+
+/// The number of channels in an exported WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WavChannels {
+    Mono,
+    Stereo,
+}
+
+impl WavChannels {
+    pub fn count(&self) -> usize {
+        match self {
+            WavChannels::Mono => 1,
+            WavChannels::Stereo => 2,
+        }
+    }
+}
+
+/// How multiple channels are summed into one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonoDownmixLaw {
+    /// Sum all channels without any attenuation.
+    Sum,
+    /// Attenuate the sum by 3dB per doubling of channels (so the power stays the
+    /// same for uncorrelated channels).
+    EqualPower,
+    /// Average all channels (-6dB for stereo, so a centered signal keeps its
+    /// level).
+    Average,
+}
+
+impl MonoDownmixLaw {
+    /// The gain that is applied to the sum of `num_channels` channels.
+    pub fn gain(&self, num_channels: usize) -> f32 {
+        let n = num_channels.max(1) as f32;
+        match self {
+            MonoDownmixLaw::Sum => 1.0,
+            MonoDownmixLaw::EqualPower => 1.0 / n.sqrt(),
+            MonoDownmixLaw::Average => 1.0 / n,
+        }
+    }
+}
+
+impl Default for MonoDownmixLaw {
+    fn default() -> Self {
+        MonoDownmixLaw::Average
+    }
+}
+
+/// What to do when normalizing to the target loudness would push the true peak
+/// above the ceiling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeakHandling {
+    /// Apply the full gain anyway. The report notes that the output clips.
+    Allow,
+    /// Reduce the gain so that the true peak stays at the ceiling. The output
+    /// then ends up quieter than the target.
+    ReduceGain,
+}
+
+/// Settings for normalizing an export to a target loudness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessNormalization {
+    /// The integrated loudness of the exported file in LUFS (i.e. -14.0).
+    pub target_lufs: f64,
+    /// The highest true peak the exported file should have in dBTP.
+    pub true_peak_ceiling_dbtp: f64,
+    pub peak_handling: PeakHandling,
+}
+
+impl Default for LoudnessNormalization {
+    fn default() -> Self {
+        Self {
+            target_lufs: -14.0,
+            true_peak_ceiling_dbtp: -1.0,
+            peak_handling: PeakHandling::Allow,
+        }
+    }
+}
+
+/// What was done when normalizing an export.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalizationReport {
+    /// The loudness of the audio before normalization.
+    pub measured: LoudnessMeasurement,
+    /// The gain that was applied in decibels.
+    pub gain_db: f64,
+    /// The true peak of the exported audio in dBTP.
+    pub true_peak_dbtp: f64,
+    /// True if the gain was reduced to stay below the true peak ceiling.
+    pub gain_reduced: bool,
+    /// True if the true peak of the exported audio is above the ceiling. The
+    /// UI should warn about this and offer to reduce the gain or the target.
+    pub exceeds_ceiling: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WavExportOptions {
+    pub format: WavSampleFormat,
+    pub channels: WavChannels,
+    /// Only used when exporting to mono.
+    pub downmix_law: MonoDownmixLaw,
+    /// Only used for the integer formats.
+    pub dither: DitherConfig,
+    /// If set, the audio is measured first and then written with the gain
+    /// that brings it to the target loudness.
+    pub normalize: Option<LoudnessNormalization>,
+}
+
+impl Default for WavExportOptions {
+    fn default() -> Self {
+        Self {
+            format: WavSampleFormat::Int24,
+            channels: WavChannels::Stereo,
+            downmix_law: MonoDownmixLaw::default(),
+            dither: DitherConfig::default(),
+            normalize: None,
+        }
+    }
+}
+
+impl WavExportOptions {
+    /// The options for writing rendered clips of `num_channels` channels
+    /// without changing their layout: mono stays mono, and everything else is
+    /// written as stereo. The integer formats are dithered.
+    pub fn for_channels(format: WavSampleFormat, num_channels: usize) -> Self {
+        let channels = if num_channels == 1 { WavChannels::Mono } else { WavChannels::Stereo };
+        Self { format, channels, ..Default::default() }
+    }
+}
+
+/// Writes the rendered `buffers` (one buffer per channel, all of the same
+/// length) to a WAV file at `path`.
+///
+/// If `buffers` has one channel and the output is stereo, it is copied to both
+/// channels, and if it has more than two, they are mixed down with
+/// `decode::downmix_to_stereo()`. If the output is mono, all channels are
+/// summed using `options.downmix_law`.
+///
+/// If `options.normalize` is set, the report of the normalization is returned.
+pub fn write_wav(
+    path: &Path,
+    buffers: &[Vec<f32>],
+    sample_rate: SampleRate,
+    options: &WavExportOptions,
+) -> Result<Option<NormalizationReport>, Box<dyn Error>> {
+    if buffers.is_empty() {
+        return Err("Cannot export a WAV file with no channels".into());
+    }
+    let num_frames = buffers[0].len();
+    if buffers.iter().any(|b| b.len() != num_frames) {
+        return Err("All channels of an exported WAV file must have the same length".into());
+    }
+
+    let mut out_buffers = map_channels(buffers, options.channels, options.downmix_law);
+    let num_channels = out_buffers.len();
+
+    let report = match &options.normalize {
+        Some(normalize) => Some(normalize_buffers(&mut out_buffers, sample_rate, normalize)?),
+        None => None,
+    };
+
+    let bits_per_sample = options.format.bits_per_sample();
+    let block_align = num_channels * usize::from(bits_per_sample / 8);
+    let sample_rate = sample_rate.as_u32();
+
+    let (format_tag, fmt_len) = match options.format {
+        WavSampleFormat::Float32 => (WAVE_FORMAT_IEEE_FLOAT, 18u32),
+        _ => (WAVE_FORMAT_PCM, 16u32),
+    };
+    // Non-PCM formats need a "fact" chunk.
+    let fact_len = if format_tag == WAVE_FORMAT_PCM { 0 } else { 12 };
+
+    // Chunks are padded to an even length (i.e. 24-bit mono with an odd number
+    // of frames), but the pad byte is not part of the chunk's size.
+    let data_len = u32::try_from(num_frames * block_align).ok();
+    let riff_len = data_len
+        .and_then(|len| len.checked_add(len % 2))
+        .and_then(|len| len.checked_add(4 + 8 + fmt_len + fact_len + 8));
+    let (data_len, riff_len) = match (data_len, riff_len) {
+        (Some(data_len), Some(riff_len)) => (data_len, riff_len),
+        _ => return Err("The exported audio is too long for a WAV file".into()),
+    };
+
+    let mut w = BufWriter::new(File::create(path)?);
+
+    w.write_all(b"RIFF")?;
+    w.write_all(&riff_len.to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+
+    w.write_all(b"fmt ")?;
+    w.write_all(&fmt_len.to_le_bytes())?;
+    w.write_all(&format_tag.to_le_bytes())?;
+    w.write_all(&(num_channels as u16).to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    w.write_all(&(block_align as u16).to_le_bytes())?;
+    w.write_all(&bits_per_sample.to_le_bytes())?;
+    if fmt_len == 18 {
+        w.write_all(&0u16.to_le_bytes())?;
+    }
+
+    if fact_len != 0 {
+        w.write_all(b"fact")?;
+        w.write_all(&4u32.to_le_bytes())?;
+        w.write_all(&(num_frames as u32).to_le_bytes())?;
+    }
+
+    w.write_all(b"data")?;
+    w.write_all(&data_len.to_le_bytes())?;
+
+    match options.format {
+        WavSampleFormat::Float32 => {
+            for frame in 0..num_frames {
+                for buffer in out_buffers.iter() {
+                    w.write_all(&buffer[frame].to_le_bytes())?;
+                }
+            }
+        }
+        WavSampleFormat::Int16 | WavSampleFormat::Int24 => {
+            let mut ditherer =
+                Ditherer::new(options.dither, u32::from(bits_per_sample), num_channels);
+            let bytes_per_sample = usize::from(bits_per_sample / 8);
+
+            for frame in 0..num_frames {
+                for (channel, buffer) in out_buffers.iter().enumerate() {
+                    let sample = ditherer.process(buffer[frame], channel);
+                    w.write_all(&sample.to_le_bytes()[0..bytes_per_sample])?;
+                }
+            }
+        }
+    }
+    if data_len % 2 == 1 {
+        w.write_all(&[0])?;
+    }
+
+    w.flush()?;
+
+    Ok(report)
+}
+
+/// Applies the gain that brings `buffers` to the target loudness.
+fn normalize_buffers(
+    buffers: &mut [Vec<f32>],
+    sample_rate: SampleRate,
+    normalize: &LoudnessNormalization,
+) -> Result<NormalizationReport, Box<dyn Error>> {
+    let measured = loudness::measure_loudness(buffers, sample_rate);
+    let integrated_lufs = measured
+        .integrated_lufs
+        .ok_or("Cannot normalize the export because it is silent or too short to measure")?;
+
+    let mut gain_db = normalize.target_lufs - integrated_lufs;
+    let mut true_peak_dbtp = measured.true_peak_dbtp + gain_db;
+    let mut gain_reduced = false;
+
+    if true_peak_dbtp > normalize.true_peak_ceiling_dbtp
+        && normalize.peak_handling == PeakHandling::ReduceGain
+    {
+        gain_db -= true_peak_dbtp - normalize.true_peak_ceiling_dbtp;
+        true_peak_dbtp = normalize.true_peak_ceiling_dbtp;
+        gain_reduced = true;
+    }
+
+    let gain = 10.0f64.powf(gain_db / 20.0) as f32;
+    for buffer in buffers.iter_mut() {
+        for s in buffer.iter_mut() {
+            *s *= gain;
+        }
+    }
+
+    Ok(NormalizationReport {
+        measured,
+        gain_db,
+        true_peak_dbtp,
+        gain_reduced,
+        exceeds_ceiling: true_peak_dbtp > normalize.true_peak_ceiling_dbtp,
+    })
+}
+
+/// Converts the rendered channels into the channel layout of the exported file.
+fn map_channels(
+    buffers: &[Vec<f32>],
+    channels: WavChannels,
+    downmix_law: MonoDownmixLaw,
+) -> Vec<Vec<f32>> {
+    match channels {
+        WavChannels::Mono => {
+            if buffers.len() == 1 {
+                return vec![buffers[0].clone()];
+            }
+
+            let gain = downmix_law.gain(buffers.len());
+            let mut mono = vec![0.0; buffers[0].len()];
+            for buffer in buffers.iter() {
+                for (out, s) in mono.iter_mut().zip(buffer.iter()) {
+                    *out += *s;
+                }
+            }
+            for s in mono.iter_mut() {
+                *s *= gain;
+            }
+            vec![mono]
+        }
+        WavChannels::Stereo => {
+            if buffers.len() == 1 {
+                vec![buffers[0].clone(), buffers[0].clone()]
+            } else {
+                downmix_to_stereo(buffers)
+            }
+        }
+    }
+}
+
+/// Writes a 32-bit float WAV file while its audio is still coming in, i.e.
+/// while recording.
+///
+/// The header is written with empty sizes up front, and filled in by
+/// `finish()`. A file that was never finished (i.e. after a crash) still has
+/// all of its audio, but reads as empty until it is repaired.
+pub struct WavStreamWriter {
+    w: BufWriter<File>,
+    num_channels: usize,
+    num_frames: u64,
+}
+
+impl WavStreamWriter {
+    /// The offset of the size in the "RIFF" chunk header.
+    const RIFF_LEN_OFFSET: u64 = 4;
+    /// The offset of the frame count in the "fact" chunk.
+    const FACT_FRAMES_OFFSET: u64 = 12 + 8 + 18 + 8;
+    /// The offset of the size in the "data" chunk header.
+    const DATA_LEN_OFFSET: u64 = 12 + 8 + 18 + 12 + 4;
+
+    /// Creates the file at `path` (replacing it if it exists) and writes the
+    /// header.
+    pub fn create(
+        path: &Path,
+        num_channels: usize,
+        sample_rate: SampleRate,
+    ) -> Result<Self, Box<dyn Error>> {
+        if num_channels == 0 || num_channels > usize::from(u16::MAX) {
+            return Err(format!("Cannot write a WAV file with {} channels", num_channels).into());
+        }
+
+        let block_align = num_channels * 4;
+        let sample_rate = sample_rate.as_u32();
+
+        let mut w = BufWriter::new(File::create(path)?);
+
+        w.write_all(b"RIFF")?;
+        w.write_all(&0u32.to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_all(&18u32.to_le_bytes())?;
+        w.write_all(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes())?;
+        w.write_all(&(num_channels as u16).to_le_bytes())?;
+        w.write_all(&sample_rate.to_le_bytes())?;
+        w.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        w.write_all(&(block_align as u16).to_le_bytes())?;
+        w.write_all(&32u16.to_le_bytes())?;
+        w.write_all(&0u16.to_le_bytes())?;
+
+        w.write_all(b"fact")?;
+        w.write_all(&4u32.to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?;
+
+        w.write_all(b"data")?;
+        w.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self { w, num_channels, num_frames: 0 })
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// The number of frames written so far.
+    pub fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    /// Appends `frames` (interleaved, with `num_channels()` samples per frame)
+    /// to the file. A partial frame at the end is ignored.
+    pub fn write_frames(&mut self, frames: &[f32]) -> Result<(), Box<dyn Error>> {
+        for frame in frames.chunks_exact(self.num_channels) {
+            for s in frame.iter() {
+                self.w.write_all(&s.to_le_bytes())?;
+            }
+            self.num_frames += 1;
+        }
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header and closes the file. Returns the number
+    /// of frames in the file.
+    pub fn finish(mut self) -> Result<u64, Box<dyn Error>> {
+        let data_len = u32::try_from(self.num_frames * self.num_channels as u64 * 4)
+            .ok()
+            .filter(|len| len.checked_add(4 + 8 + 18 + 12 + 8).is_some())
+            .ok_or("The recorded audio is too long for a WAV file")?;
+        let num_frames = self.num_frames as u32;
+
+        self.w.flush()?;
+        let mut file = self.w.into_inner().map_err(|e| e.into_error())?;
+
+        file.seek(SeekFrom::Start(Self::RIFF_LEN_OFFSET))?;
+        file.write_all(&(4 + 8 + 18 + 12 + 8 + data_len).to_le_bytes())?;
+        file.seek(SeekFrom::Start(Self::FACT_FRAMES_OFFSET))?;
+        file.write_all(&num_frames.to_le_bytes())?;
+        file.seek(SeekFrom::Start(Self::DATA_LEN_OFFSET))?;
+        file.write_all(&data_len.to_le_bytes())?;
+        file.sync_all()?;
+
+        Ok(self.num_frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::backend::decode::decode_file;
+
+    const SAMPLE_RATE: SampleRate = SampleRate(48_000.0);
+
+    fn export_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join("meadowlark-wav-export-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    fn options(format: WavSampleFormat, channels: WavChannels) -> WavExportOptions {
+        WavExportOptions { format, channels, ..WavExportOptions::default() }
+    }
+
+    #[test]
+    fn float_wavs_round_trip() {
+        let path = export_path("round_trip.wav");
+        let buffers: Vec<Vec<f32>> = vec![
+            (0..1000).map(|i| (i as f32 * 0.01).sin() * 0.9).collect(),
+            (0..1000).map(|i| -1.0 + i as f32 / 500.0).collect(),
+        ];
+        write_wav(
+            &path,
+            &buffers,
+            SAMPLE_RATE,
+            &options(WavSampleFormat::Float32, WavChannels::Stereo),
+        )
+        .unwrap();
+
+        let decoded = decode_file(&path).unwrap();
+        assert_eq!(decoded.sample_rate, 48_000);
+        assert_eq!(decoded.buffers, buffers);
+    }
+
+    #[test]
+    fn odd_data_chunks_are_padded() {
+        let path = export_path("padded.wav");
+        // Three frames of 24-bit mono are nine bytes of audio.
+        let buffers = vec![vec![0.25, -0.25, 0.5]];
+        write_wav(
+            &path,
+            &buffers,
+            SAMPLE_RATE,
+            &options(WavSampleFormat::Int24, WavChannels::Mono),
+        )
+        .unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 9 + 1);
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 9);
+        assert_eq!(bytes[53], 0);
+
+        assert_eq!(decode_file(&path).unwrap().buffers[0].len(), 3);
+    }
+
+    #[test]
+    fn drag_exported_16_bit_clips_are_dithered() {
+        // A sine with a peak of less than half an LSB, which rounds to silence
+        // without dither.
+        let lsb = 1.0 / 32_768.0;
+        let sine: Vec<f32> = (0..48_000).map(|i| (i as f32 * 0.01).sin() * 0.4 * lsb).collect();
+        let buffers = vec![sine.clone()];
+
+        let path = export_path("dithered.wav");
+        let options = WavExportOptions::for_channels(WavSampleFormat::Int16, 1);
+        write_wav(&path, &buffers, SAMPLE_RATE, &options).unwrap();
+        let dithered = decode_file(&path).unwrap().buffers;
+        assert_eq!(dithered.len(), 1);
+
+        let path = export_path("undithered.wav");
+        let options = WavExportOptions {
+            dither: DitherConfig { enabled: false, ..DitherConfig::default() },
+            ..options
+        };
+        write_wav(&path, &buffers, SAMPLE_RATE, &options).unwrap();
+        let undithered = decode_file(&path).unwrap().buffers;
+        assert!(undithered[0].iter().all(|s| *s == 0.0));
+
+        // The dithered file is noise of at most a couple of LSBs, with the sine
+        // still in it.
+        assert!(dithered[0].iter().all(|s| s.abs() <= 2.0 * lsb));
+        assert!(dithered[0].iter().any(|s| *s != 0.0));
+        let correlation: f32 = dithered[0].iter().zip(sine.iter()).map(|(d, s)| d * s).sum();
+        assert!(correlation > 0.0, "{}", correlation);
+    }
+
+    #[test]
+    fn surround_is_mixed_down_to_stereo() {
+        let path = export_path("surround.wav");
+        // 5.1 with only the center channel.
+        let mut buffers = vec![vec![0.0; 100]; 6];
+        buffers[2] = vec![0.5; 100];
+        write_wav(
+            &path,
+            &buffers,
+            SAMPLE_RATE,
+            &options(WavSampleFormat::Float32, WavChannels::Stereo),
+        )
+        .unwrap();
+
+        let decoded = decode_file(&path).unwrap();
+        assert_eq!(decoded.buffers, downmix_to_stereo(&buffers));
+        assert!(decoded.buffers[0]
+            .iter()
+            .all(|s| (s - 0.5 * std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6));
+    }
+}
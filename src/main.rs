@@ -10,6 +10,11 @@ mod ui;
 
 fn main() -> Result<(), Box<dyn Error>> {
     setup_logging()?;
+    ui::diagnostics::install_panic_hook();
+
+    if std::env::args().any(|arg| arg == "--headless") {
+        return backend::headless::run_headless_example(10.0);
+    }
 
     ui::run_ui()
 }
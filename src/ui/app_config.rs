@@ -0,0 +1,378 @@
+//! Settings that apply to the whole application rather than to a single
+//! project.
+//!
+//! These are stored per-user, so opening someone else's project doesn't change
+//! them.
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::ui::keymap::KeymapConfig;
+use crate::ui::PanelState;
+
+/// The current version of the app config format. Bump this when a change to
+/// the format needs a migration.
+pub const APP_CONFIG_VERSION: u32 = 1;
+
+/// Where the app config is stored if the platform has no config directory.
+pub const FALLBACK_APP_CONFIG_PATH: &str = "./meadowlark_config.json";
+
+/// The maximum number of entries in the list of recently opened projects.
+pub const MAX_RECENT_PROJECTS: usize = 10;
+
+/// How long the layout has to stay the same before it is saved.
+pub const LAYOUT_SAVE_DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// How long the config file has to stay unchanged after an external edit
+/// before it is reloaded.
+pub const APP_CONFIG_WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Returns the path of the app config file in the platform's config directory
+/// (i.e. `~/.config/meadowlark/config.json` on Linux).
+pub fn app_config_path() -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join("meadowlark").join("config.json"))
+        .unwrap_or_else(|| PathBuf::from(FALLBACK_APP_CONFIG_PATH))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    /// The version of the format this config was saved with.
+    pub version: u32,
+
+    pub layout: LayoutConfig,
+
+    pub keymap: KeymapConfig,
+
+    /// The recently opened or saved projects, most recent first.
+    pub recent_projects: Vec<RecentProject>,
+}
+
+impl AppConfig {
+    /// Loads the config from `path`, or returns the default config if the file
+    /// doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let mut config: AppConfig = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+        if config.version > APP_CONFIG_VERSION {
+            log::warn!(
+                "App config {:?} was saved by a newer version (format {}), some settings may be lost",
+                path,
+                config.version
+            );
+        }
+        // TODO: Migrate older formats here once there are any.
+        config.version = APP_CONFIG_VERSION;
+
+        Ok(config)
+    }
+
+    /// Loads the config from `path`, falling back to the default config if the
+    /// file can't be read or is corrupt.
+    ///
+    /// A corrupt file is renamed with a `.bak` extension so that it isn't
+    /// overwritten the next time the config is saved. The error is returned
+    /// along with the default config so it can be shown to the user.
+    pub fn load_or_default(path: &Path) -> (Self, Option<String>) {
+        match Self::load(path) {
+            Ok(config) => (config, None),
+            Err(e) => {
+                let mut message = format!("Failed to load app config {:?}: {}", path, e);
+
+                if path.exists() {
+                    let backup_path = path.with_extension("json.bak");
+                    if std::fs::rename(path, &backup_path).is_ok() {
+                        message = format!("{}. It was moved to {:?}", message, backup_path);
+                    }
+                }
+
+                (Self::default(), Some(message))
+            }
+        }
+    }
+
+    /// Saves the config to `path`.
+    ///
+    /// The config is written to a temporary file first, which then replaces the
+    /// old file. This way a crash while saving can't leave a half-written
+    /// config behind.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Returns the sections that differ between this config and `other`.
+    pub fn changed_sections(&self, other: &AppConfig) -> Vec<AppConfigSection> {
+        let mut sections = Vec::new();
+        if self.layout != other.layout {
+            sections.push(AppConfigSection::Layout);
+        }
+        if self.keymap != other.keymap {
+            sections.push(AppConfigSection::Keymap);
+        }
+        if self.recent_projects != other.recent_projects {
+            sections.push(AppConfigSection::RecentProjects);
+        }
+        sections
+    }
+
+    /// Moves the project at `path` to the top of the recently opened projects,
+    /// dropping the oldest entry if there are more than `MAX_RECENT_PROJECTS`.
+    pub fn add_recent_project(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let last_opened =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        self.recent_projects.retain(|p| p.path != path);
+        self.recent_projects.push(RecentProject { path, last_opened });
+        self.recent_projects.sort_by(|a, b| b.last_opened.cmp(&a.last_opened));
+        self.recent_projects.truncate(MAX_RECENT_PROJECTS);
+    }
+
+    /// Returns the recently opened projects whose files still exist, most recent
+    /// first.
+    pub fn existing_recent_projects(&self) -> Vec<RecentProject> {
+        let mut projects: Vec<RecentProject> =
+            self.recent_projects.iter().filter(|p| p.path.is_file()).cloned().collect();
+        projects.sort_by(|a, b| b.last_opened.cmp(&a.last_opened));
+        projects
+    }
+}
+
+/// An entry in the list of recently opened projects.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentProject {
+    pub path: PathBuf,
+    /// When the project was last opened or saved, in seconds since the Unix
+    /// epoch.
+    pub last_opened: u64,
+}
+
+/// A section of the app config. Parts of the program only need to react to
+/// changes in the sections they use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppConfigSection {
+    Layout,
+    Keymap,
+    RecentProjects,
+}
+
+/// Watches the app config file for changes made outside of the program (i.e.
+/// when a user edits it by hand).
+pub struct AppConfigWatcher {
+    path: PathBuf,
+    rx: Receiver<DebouncedEvent>,
+    // The watcher stops when this is dropped.
+    _watcher: RecommendedWatcher,
+}
+
+impl AppConfigWatcher {
+    /// Starts watching the config file at `path`.
+    pub fn new(path: &Path) -> Result<Self, Box<dyn Error>> {
+        // Watch the directory instead of the file, because saving replaces the
+        // file.
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        std::fs::create_dir_all(&dir)?;
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher: RecommendedWatcher = Watcher::new(tx, APP_CONFIG_WATCH_DEBOUNCE)?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        let file_name = path.file_name().ok_or("The app config path has no file name")?;
+
+        Ok(Self { path: dir.join(file_name), rx, _watcher: watcher })
+    }
+
+    /// Returns `true` if the config file was changed since the last call.
+    ///
+    /// This also returns `true` after the program saved the config itself.
+    /// Comparing the reloaded config with `AppConfig::changed_sections()`
+    /// shows that nothing changed in that case.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        for event in self.rx.try_iter() {
+            let path = match &event {
+                DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => path,
+                DebouncedEvent::Rename(_, to) => to,
+                _ => continue,
+            };
+            if path.file_name() == self.path.file_name() {
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            version: APP_CONFIG_VERSION,
+            layout: LayoutConfig::default(),
+            keymap: KeymapConfig::default(),
+            recent_projects: Vec::new(),
+        }
+    }
+}
+
+/// The layout of the UI that is restored on the next launch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+    pub panels: PanelState,
+
+    /// The zoom levels that the timeline starts with.
+    pub timeline_horizontal_zoom: f64,
+    pub timeline_vertical_zoom: f64,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            panels: PanelState::default(),
+            timeline_horizontal_zoom: 1.0,
+            timeline_vertical_zoom: 1.0,
+        }
+    }
+}
+
+/// Decides when a changed layout is saved, so that dragging a panel doesn't
+/// write the app config file on every frame.
+#[derive(Debug, Default)]
+pub struct LayoutSaveDebounce {
+    /// A layout that differs from the saved one, and the time it was first
+    /// seen.
+    pending: Option<(LayoutConfig, Instant)>,
+}
+
+impl LayoutSaveDebounce {
+    /// Called regularly with the current `layout` and the `saved` one. Returns
+    /// `true` once `layout` differs from `saved` and has stayed the same for
+    /// `LAYOUT_SAVE_DEBOUNCE`, in which case it should be saved now.
+    pub fn poll(&mut self, layout: &LayoutConfig, saved: &LayoutConfig, now: Instant) -> bool {
+        if layout == saved {
+            self.pending = None;
+            return false;
+        }
+
+        match &self.pending {
+            Some((pending, since)) if pending == layout => {
+                now.saturating_duration_since(*since) >= LAYOUT_SAVE_DEBOUNCE
+            }
+            _ => {
+                self.pending = Some((layout.clone(), now));
+                false
+            }
+        }
+    }
+
+    /// Forgets the pending layout, i.e. after it was saved.
+    pub fn reset(&mut self) {
+        self.pending = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("meadowlark-app-config-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(path.with_extension("json.bak"));
+        path
+    }
+
+    fn zoomed_layout(zoom: f64) -> LayoutConfig {
+        LayoutConfig { timeline_horizontal_zoom: zoom, ..LayoutConfig::default() }
+    }
+
+    #[test]
+    fn layout_is_saved_once_it_stops_changing() {
+        let saved = LayoutConfig::default();
+        let mut debounce = LayoutSaveDebounce::default();
+        let t0 = Instant::now();
+        let ms = Duration::from_millis;
+
+        assert!(!debounce.poll(&zoomed_layout(2.0), &saved, t0));
+        assert!(!debounce.poll(&zoomed_layout(2.0), &saved, t0 + ms(500)));
+        // Changing the layout again restarts the wait.
+        assert!(!debounce.poll(&zoomed_layout(3.0), &saved, t0 + ms(600)));
+        assert!(!debounce.poll(&zoomed_layout(3.0), &saved, t0 + ms(1500)));
+        assert!(debounce.poll(&zoomed_layout(3.0), &saved, t0 + ms(1600)));
+    }
+
+    #[test]
+    fn layout_that_is_changed_back_is_not_saved() {
+        let saved = LayoutConfig::default();
+        let mut debounce = LayoutSaveDebounce::default();
+        let t0 = Instant::now();
+
+        assert!(!debounce.poll(&zoomed_layout(2.0), &saved, t0));
+        assert!(!debounce.poll(&saved, &saved, t0 + Duration::from_millis(100)));
+        assert!(!debounce.poll(&saved, &saved, t0 + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn missing_config_loads_the_defaults() {
+        let path = config_path("missing.json");
+        assert_eq!(AppConfig::load(&path).unwrap(), AppConfig::default());
+    }
+
+    #[test]
+    fn config_round_trips() {
+        let path = config_path("round_trip.json");
+        let mut layout = zoomed_layout(4.0);
+        layout.panels.hide_browser = true;
+        let config = AppConfig { layout, ..AppConfig::default() };
+        config.save(&path).unwrap();
+
+        assert_eq!(AppConfig::load(&path).unwrap(), config);
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn missing_settings_fall_back_to_their_defaults() {
+        let path = config_path("partial.json");
+        std::fs::write(&path, r#"{ "layout": { "timeline_vertical_zoom": 0.5 } }"#).unwrap();
+
+        let config = AppConfig::load(&path).unwrap();
+        assert_eq!(config.layout.timeline_vertical_zoom, 0.5);
+        assert_eq!(config.layout.timeline_horizontal_zoom, 1.0);
+        assert_eq!(config.layout.panels, PanelState::default());
+        assert_eq!(config.keymap, KeymapConfig::default());
+    }
+
+    #[test]
+    fn corrupt_config_is_backed_up_and_replaced_by_the_defaults() {
+        let path = config_path("corrupt.json");
+        std::fs::write(&path, r#"{ "layout": "#).unwrap();
+
+        let (config, message) = AppConfig::load_or_default(&path);
+        assert_eq!(config, AppConfig::default());
+        assert!(message.unwrap().contains("corrupt.json.bak"));
+        assert!(!path.exists());
+        let backup = std::fs::read_to_string(path.with_extension("json.bak")).unwrap();
+        assert_eq!(backup, r#"{ "layout": "#);
+    }
+}
@@ -0,0 +1,251 @@
+//! Diagnostics dumps that users can attach to crash reports.
+//!
+//! A dump is a folder in `diagnostics_dir()` with one text file per section.
+//! Each section is written on its own, so a section that fails to be written
+//! (or that is not available, like the engine state after a panic) doesn't
+//! keep the rest from being written.
+//!
+//! Dumps are written from the UI thread or from the panic hook, never from the
+//! audio thread.
+
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use meadowlark_core_types::time::SampleRate;
+
+/// The number of audio thread log records that are kept for a dump.
+pub const RT_TRACE_LEN: usize = 256;
+
+/// What goes into a diagnostics dump.
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsDump {
+    /// Why the dump was written (i.e. "engine-crash", "panic", or "user").
+    pub reason: String,
+    /// The last save state of the audio graph that the engine recovered,
+    /// formatted with `{:#?}`.
+    pub engine_state: Option<String>,
+    /// The notifications shown to the user, oldest first.
+    pub notifications: Vec<String>,
+    /// The last records from the audio thread log, oldest first.
+    pub rt_trace: Vec<String>,
+    /// The app config as it is saved to disk.
+    pub app_config: Option<String>,
+    /// Information about the system, as `(name, value)` pairs.
+    pub environment: Vec<(String, String)>,
+}
+
+impl DiagnosticsDump {
+    pub fn new(reason: &str) -> Self {
+        Self { reason: reason.into(), ..Default::default() }
+    }
+
+    /// Replaces every occurrence of one of `paths` in the dump with just its
+    /// file name, so the dump doesn't reveal the user's folder layout.
+    pub fn redact_paths(&mut self, paths: &[PathBuf]) {
+        // Longer paths first, so that a path is not partly replaced by the
+        // redaction of its parent.
+        let mut paths: Vec<(String, String)> = paths
+            .iter()
+            .filter(|path| path.is_absolute())
+            .map(|path| {
+                let file_name =
+                    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                (path.to_string_lossy().into_owned(), format!("<redacted>/{}", file_name))
+            })
+            .collect();
+        paths.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        paths.dedup();
+
+        let redact = |text: &mut String| {
+            for (path, redacted) in paths.iter() {
+                if text.contains(path.as_str()) {
+                    *text = text.replace(path.as_str(), redacted);
+                }
+            }
+        };
+
+        if let Some(engine_state) = &mut self.engine_state {
+            redact(engine_state);
+        }
+        if let Some(app_config) = &mut self.app_config {
+            redact(app_config);
+        }
+        self.notifications.iter_mut().for_each(redact);
+        for (_, value) in self.environment.iter_mut() {
+            redact(value);
+        }
+    }
+
+    /// Writes the dump to a new folder in `dir` and returns the path of the
+    /// folder, along with the names of the sections that could not be
+    /// written.
+    ///
+    /// This only fails if the folder itself can't be created.
+    pub fn write(&self, dir: &Path) -> Result<(PathBuf, Vec<&'static str>), Box<dyn Error>> {
+        let timestamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S");
+        let mut folder = dir.join(format!("{}_{}", timestamp, self.reason));
+        // Two dumps in the same second get their own folders.
+        let mut n = 1;
+        while folder.exists() {
+            n += 1;
+            folder = dir.join(format!("{}_{}_{}", timestamp, self.reason, n));
+        }
+        std::fs::create_dir_all(&folder)?;
+
+        let not_available = String::from("Not available\n");
+
+        let mut environment = String::new();
+        let _ = writeln!(environment, "reason: {}", self.reason);
+        for (name, value) in self.environment.iter() {
+            let _ = writeln!(environment, "{}: {}", name, value);
+        }
+
+        let sections: [(&'static str, String); 5] = [
+            ("environment.txt", environment),
+            (
+                "engine_state.txt",
+                self.engine_state.clone().unwrap_or_else(|| not_available.clone()),
+            ),
+            ("notifications.txt", lines(&self.notifications)),
+            ("rt_trace.txt", lines(&self.rt_trace)),
+            ("app_config.json", self.app_config.clone().unwrap_or_else(|| not_available.clone())),
+        ];
+
+        let mut failed = Vec::new();
+        for (name, contents) in sections.iter() {
+            if let Err(e) = std::fs::write(folder.join(name), contents) {
+                log::error!("Failed to write {} of diagnostics dump {:?}: {}", name, &folder, e);
+                failed.push(*name);
+            }
+        }
+
+        Ok((folder, failed))
+    }
+}
+
+/// Returns the information about the system that goes into every dump.
+pub fn environment_info(
+    sample_rate: Option<SampleRate>,
+    device_name: Option<&str>,
+) -> Vec<(String, String)> {
+    let mut info = vec![
+        (String::from("version"), String::from(env!("CARGO_PKG_VERSION"))),
+        (String::from("os"), String::from(std::env::consts::OS)),
+        (String::from("arch"), String::from(std::env::consts::ARCH)),
+        (String::from("debug build"), cfg!(debug_assertions).to_string()),
+    ];
+    if let Some(sample_rate) = sample_rate {
+        info.push((String::from("sample rate"), format!("{}", sample_rate.as_u32())));
+    }
+    if let Some(device_name) = device_name {
+        info.push((String::from("audio device"), String::from(device_name)));
+    }
+    info
+}
+
+/// Returns the directory that diagnostics dumps are written to.
+pub fn diagnostics_dir() -> PathBuf {
+    dirs::data_local_dir().unwrap_or_else(std::env::temp_dir).join("meadowlark").join("diagnostics")
+}
+
+/// Installs a panic hook that writes a diagnostics dump with the panic message
+/// and the environment before the default hook runs.
+///
+/// The state of the program is not available from the hook, so these dumps
+/// only have the environment and the panic itself (as the only notification).
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let mut dump = DiagnosticsDump::new("panic");
+        dump.environment = environment_info(None, None);
+        dump.notifications.push(format!("Panic: {}", info));
+
+        match dump.write(&diagnostics_dir()) {
+            Ok((folder, _)) => log::error!("{}. Wrote diagnostics to {:?}", info, &folder),
+            Err(e) => log::error!("{}. Failed to write diagnostics: {}", info, e),
+        }
+
+        default_hook(info);
+    }));
+}
+
+fn lines(lines: &[String]) -> String {
+    let mut text = String::new();
+    for line in lines.iter() {
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("meadowlark-diagnostics-test").join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read(folder: &Path, name: &str) -> String {
+        std::fs::read_to_string(folder.join(name)).unwrap()
+    }
+
+    #[test]
+    fn dumps_have_every_section() {
+        let sample = PathBuf::from("/home/user/Music/Samples/kick.wav");
+        let mut dump = DiagnosticsDump::new("engine-crash");
+        dump.engine_state = Some(format!("GraphState {{ clip: {:?} }}", &sample));
+        dump.notifications =
+            vec![String::from("Loaded project"), format!("Failed to load {}", sample.display())];
+        dump.rt_trace = vec![String::from("[12] xrun"), String::from("[13] engine stopped")];
+        dump.app_config = Some(String::from("{\"sample_rate\": 48000}"));
+        dump.environment = environment_info(Some(SampleRate(48_000.0)), Some("Test Device"));
+        dump.redact_paths(&[sample]);
+
+        let dir = dump_dir("sections");
+        let (folder, failed) = dump.write(&dir).unwrap();
+        assert!(failed.is_empty());
+        assert!(folder.starts_with(&dir));
+        assert!(folder.file_name().unwrap().to_string_lossy().ends_with("_engine-crash"));
+
+        let environment = read(&folder, "environment.txt");
+        assert!(environment.starts_with("reason: engine-crash\n"));
+        assert!(environment.contains("sample rate: 48000\n"));
+        assert!(environment.contains("audio device: Test Device\n"));
+        assert!(environment.contains(&format!("version: {}\n", env!("CARGO_PKG_VERSION"))));
+
+        assert_eq!(
+            read(&folder, "engine_state.txt"),
+            "GraphState { clip: \"<redacted>/kick.wav\" }"
+        );
+        assert_eq!(
+            read(&folder, "notifications.txt"),
+            "Loaded project\nFailed to load <redacted>/kick.wav\n"
+        );
+        assert_eq!(read(&folder, "rt_trace.txt"), "[12] xrun\n[13] engine stopped\n");
+        assert_eq!(read(&folder, "app_config.json"), "{\"sample_rate\": 48000}");
+    }
+
+    #[test]
+    fn missing_sections_are_marked_and_the_rest_is_written() {
+        // A panic dump has no engine state or app config.
+        let mut dump = DiagnosticsDump::new("panic");
+        dump.notifications.push(String::from("Panic: at src/main.rs:1:1"));
+
+        let dir = dump_dir("missing");
+        let (first, failed) = dump.write(&dir).unwrap();
+        assert!(failed.is_empty());
+        assert_eq!(read(&first, "engine_state.txt"), "Not available\n");
+        assert_eq!(read(&first, "app_config.json"), "Not available\n");
+        assert_eq!(read(&first, "rt_trace.txt"), "");
+        assert_eq!(read(&first, "notifications.txt"), "Panic: at src/main.rs:1:1\n");
+
+        // A second dump right after doesn't overwrite the first one.
+        let (second, _) = dump.write(&dir).unwrap();
+        assert_ne!(first, second);
+        assert!(first.join("notifications.txt").exists());
+    }
+}
@@ -0,0 +1,427 @@
+//! The application-wide keyboard shortcuts.
+//!
+//! Every shortcut triggers an [`Action`]. The user can rebind them in the app
+//! config file, where each binding is stored as a pair of strings like
+//! `{ "chord": "Ctrl+Shift+S", "action": "SaveProject" }`.
+//!
+//! Panel-specific shortcuts (i.e. moving lanes in the timeline) are still defined
+//! by the keymap of each panel.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use vizia::prelude::*;
+
+use crate::ui::{PanelEvent, UiEvent};
+
+/// A command that can be bound to a keyboard shortcut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // ----- Transport -----
+    Play,
+    Stop,
+    ToggleLoop,
+    ToggleRecord,
+
+    // ----- Project -----
+    SaveProject,
+    LoadProject,
+
+    // ----- Edit -----
+    Undo,
+    Redo,
+    DeleteSelection,
+
+    // ----- View -----
+    ZoomIn,
+    ZoomOut,
+    ToggleBrowser,
+    TogglePianoRoll,
+    ToggleClips,
+}
+
+impl Action {
+    pub const ALL: [Action; 14] = [
+        Action::Play,
+        Action::Stop,
+        Action::ToggleLoop,
+        Action::ToggleRecord,
+        Action::SaveProject,
+        Action::LoadProject,
+        Action::Undo,
+        Action::Redo,
+        Action::DeleteSelection,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::ToggleBrowser,
+        Action::TogglePianoRoll,
+        Action::ToggleClips,
+    ];
+
+    /// The name of this action in the config file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Play => "Play",
+            Action::Stop => "Stop",
+            Action::ToggleLoop => "ToggleLoop",
+            Action::ToggleRecord => "ToggleRecord",
+            Action::SaveProject => "SaveProject",
+            Action::LoadProject => "LoadProject",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::DeleteSelection => "DeleteSelection",
+            Action::ZoomIn => "ZoomIn",
+            Action::ZoomOut => "ZoomOut",
+            Action::ToggleBrowser => "ToggleBrowser",
+            Action::TogglePianoRoll => "TogglePianoRoll",
+            Action::ToggleClips => "ToggleClips",
+        }
+    }
+
+    /// Returns the action with the given name in the config file.
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|action| action.name() == name)
+    }
+
+    /// The callback that emits the event(s) for this action.
+    fn on_action(&self) -> fn(&mut EventContext) {
+        match self {
+            Action::Play => |cx| cx.emit(UiEvent::Play),
+            Action::Stop => |cx| cx.emit(UiEvent::Stop),
+            Action::ToggleLoop => |cx| cx.emit(UiEvent::ToggleLoop),
+            Action::ToggleRecord => |cx| cx.emit(UiEvent::ToggleRecord),
+            Action::SaveProject => |cx| cx.emit(UiEvent::SaveProject),
+            Action::LoadProject => |cx| cx.emit(UiEvent::LoadProject),
+            Action::Undo => |cx| cx.emit(UiEvent::Undo),
+            Action::Redo => |cx| cx.emit(UiEvent::Redo),
+            Action::DeleteSelection => |cx| cx.emit(UiEvent::DeleteSelectedClips),
+            Action::ZoomIn => |cx| cx.emit(UiEvent::ZoomInVertically),
+            Action::ZoomOut => |cx| cx.emit(UiEvent::ZoomOutVertically),
+            Action::ToggleBrowser => |cx| cx.emit(PanelEvent::ToggleBrowser),
+            Action::TogglePianoRoll => |cx| cx.emit(PanelEvent::TogglePianoRoll),
+            Action::ToggleClips => |cx| cx.emit(PanelEvent::ToggleClips),
+        }
+    }
+}
+
+/// The default bindings, used when the app config file doesn't have any.
+const DEFAULT_BINDINGS: [(&str, Action); 14] = [
+    ("Enter", Action::Play),
+    ("Shift+Enter", Action::Stop),
+    ("L", Action::ToggleLoop),
+    ("R", Action::ToggleRecord),
+    ("Ctrl+S", Action::SaveProject),
+    ("Ctrl+Shift+O", Action::LoadProject),
+    ("Ctrl+Z", Action::Undo),
+    ("Ctrl+Shift+Z", Action::Redo),
+    ("Backspace", Action::DeleteSelection),
+    ("Ctrl+Equal", Action::ZoomIn),
+    ("Ctrl+Minus", Action::ZoomOut),
+    ("Ctrl+B", Action::ToggleBrowser),
+    ("Ctrl+P", Action::TogglePianoRoll),
+    ("Ctrl+K", Action::ToggleClips),
+];
+
+/// The names of the keys that can be used in a chord.
+const KEY_NAMES: [(&str, Code); 66] = [
+    ("A", Code::KeyA),
+    ("B", Code::KeyB),
+    ("C", Code::KeyC),
+    ("D", Code::KeyD),
+    ("E", Code::KeyE),
+    ("F", Code::KeyF),
+    ("G", Code::KeyG),
+    ("H", Code::KeyH),
+    ("I", Code::KeyI),
+    ("J", Code::KeyJ),
+    ("K", Code::KeyK),
+    ("L", Code::KeyL),
+    ("M", Code::KeyM),
+    ("N", Code::KeyN),
+    ("O", Code::KeyO),
+    ("P", Code::KeyP),
+    ("Q", Code::KeyQ),
+    ("R", Code::KeyR),
+    ("S", Code::KeyS),
+    ("T", Code::KeyT),
+    ("U", Code::KeyU),
+    ("V", Code::KeyV),
+    ("W", Code::KeyW),
+    ("X", Code::KeyX),
+    ("Y", Code::KeyY),
+    ("Z", Code::KeyZ),
+    ("0", Code::Digit0),
+    ("1", Code::Digit1),
+    ("2", Code::Digit2),
+    ("3", Code::Digit3),
+    ("4", Code::Digit4),
+    ("5", Code::Digit5),
+    ("6", Code::Digit6),
+    ("7", Code::Digit7),
+    ("8", Code::Digit8),
+    ("9", Code::Digit9),
+    ("F1", Code::F1),
+    ("F2", Code::F2),
+    ("F3", Code::F3),
+    ("F4", Code::F4),
+    ("F5", Code::F5),
+    ("F6", Code::F6),
+    ("F7", Code::F7),
+    ("F8", Code::F8),
+    ("F9", Code::F9),
+    ("F10", Code::F10),
+    ("F11", Code::F11),
+    ("F12", Code::F12),
+    ("Space", Code::Space),
+    ("Enter", Code::Enter),
+    ("Escape", Code::Escape),
+    ("Tab", Code::Tab),
+    ("Backspace", Code::Backspace),
+    ("Delete", Code::Delete),
+    ("Home", Code::Home),
+    ("End", Code::End),
+    ("PageUp", Code::PageUp),
+    ("PageDown", Code::PageDown),
+    ("Up", Code::ArrowUp),
+    ("Down", Code::ArrowDown),
+    ("Left", Code::ArrowLeft),
+    ("Right", Code::ArrowRight),
+    ("Equal", Code::Equal),
+    ("Minus", Code::Minus),
+    ("Comma", Code::Comma),
+    ("Period", Code::Period),
+];
+
+/// A key together with the modifiers that must be held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    pub modifiers: Modifiers,
+    pub code: Code,
+}
+
+impl Chord {
+    /// Parses a chord like "Ctrl+Shift+S". Modifier and key names are not case
+    /// sensitive.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut modifiers = Modifiers::empty();
+        let mut code = None;
+
+        for part in s.split('+').map(str::trim) {
+            if code.is_some() {
+                return Err(format!("\"{}\": the key must come after all modifiers", s));
+            }
+
+            match part.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers |= Modifiers::CTRL,
+                "shift" => modifiers |= Modifiers::SHIFT,
+                "alt" | "option" => modifiers |= Modifiers::ALT,
+                "super" | "cmd" | "logo" => modifiers |= Modifiers::LOGO,
+                _ => {
+                    code = Some(
+                        KEY_NAMES
+                            .iter()
+                            .find(|(name, _)| name.eq_ignore_ascii_case(part))
+                            .map(|(_, code)| *code)
+                            .ok_or_else(|| format!("\"{}\": unknown key \"{}\"", s, part))?,
+                    );
+                }
+            }
+        }
+
+        let code = code.ok_or_else(|| format!("\"{}\": no key given", s))?;
+
+        Ok(Self { modifiers, code })
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(Modifiers::CTRL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(Modifiers::SHIFT) {
+            write!(f, "Shift+")?;
+        }
+        if self.modifiers.contains(Modifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if self.modifiers.contains(Modifiers::LOGO) {
+            write!(f, "Super+")?;
+        }
+
+        let name = KEY_NAMES.iter().find(|(_, code)| *code == self.code).map(|(name, _)| *name);
+        write!(f, "{}", name.unwrap_or("?"))
+    }
+}
+
+/// A single binding as it is stored in the app config file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyBindingConfig {
+    pub chord: String,
+    pub action: String,
+}
+
+/// The user's keyboard shortcuts as they are stored in the app config file.
+///
+/// The names are kept as strings so that a config file with unknown actions
+/// (i.e. one written by a newer version) can still be loaded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeymapConfig {
+    pub bindings: Vec<KeyBindingConfig>,
+}
+
+impl KeymapConfig {
+    /// Parses all bindings.
+    ///
+    /// Bindings with an unknown chord or action are skipped. If the same chord
+    /// is bound more than once, then only the first binding is used. A message
+    /// for each of these problems is returned along with the valid bindings.
+    pub fn resolve(&self) -> (Vec<(Chord, Action)>, Vec<String>) {
+        let mut bindings: Vec<(Chord, Action)> = Vec::new();
+        let mut problems = Vec::new();
+
+        for binding in self.bindings.iter() {
+            let chord = match Chord::parse(&binding.chord) {
+                Ok(chord) => chord,
+                Err(e) => {
+                    problems.push(format!("Invalid key binding {}", e));
+                    continue;
+                }
+            };
+
+            let action = match Action::from_name(&binding.action) {
+                Some(action) => action,
+                None => {
+                    problems.push(format!(
+                        "Unknown action \"{}\" bound to {}",
+                        &binding.action, &binding.chord
+                    ));
+                    continue;
+                }
+            };
+
+            if let Some((_, existing)) = bindings.iter().find(|(c, _)| *c == chord) {
+                if *existing != action {
+                    problems.push(format!(
+                        "{} is bound to both {} and {}; using {}",
+                        chord,
+                        existing.name(),
+                        action.name(),
+                        existing.name()
+                    ));
+                }
+                continue;
+            }
+
+            bindings.push((chord, action));
+        }
+
+        (bindings, problems)
+    }
+}
+
+impl Default for KeymapConfig {
+    fn default() -> Self {
+        Self {
+            bindings: DEFAULT_BINDINGS
+                .iter()
+                .map(|(chord, action)| KeyBindingConfig {
+                    chord: String::from(*chord),
+                    action: String::from(action.name()),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Builds the application-wide keymap from the resolved `bindings`.
+pub fn app_keymap(cx: &mut Context, bindings: &[(Chord, Action)]) {
+    Keymap::from(
+        bindings
+            .iter()
+            .map(|(chord, action)| {
+                (
+                    KeyChord::new(chord.modifiers, chord.code),
+                    KeymapEntry::new(*action, action.on_action()),
+                )
+            })
+            .collect::<Vec<_>>(),
+    )
+    .build(cx);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(chord: &str, action: &str) -> KeyBindingConfig {
+        KeyBindingConfig { chord: String::from(chord), action: String::from(action) }
+    }
+
+    #[test]
+    fn chords_parse_regardless_of_case() {
+        assert_eq!(
+            Chord::parse("ctrl+SHIFT+s"),
+            Ok(Chord { modifiers: Modifiers::CTRL | Modifiers::SHIFT, code: Code::KeyS })
+        );
+        assert_eq!(
+            Chord::parse(" Cmd + Alt + PageUp "),
+            Ok(Chord { modifiers: Modifiers::LOGO | Modifiers::ALT, code: Code::PageUp })
+        );
+        assert_eq!(Chord::parse("F5"), Ok(Chord { modifiers: Modifiers::empty(), code: Code::F5 }));
+    }
+
+    #[test]
+    fn invalid_chords_are_rejected() {
+        for chord in ["", "Ctrl", "Ctrl+", "Ctrl+Hyper", "S+Ctrl", "Ctrl+A+B"] {
+            assert!(Chord::parse(chord).is_err(), "{}", chord);
+        }
+    }
+
+    #[test]
+    fn default_chords_display_as_they_are_written() {
+        for (chord, _) in DEFAULT_BINDINGS.iter() {
+            assert_eq!(Chord::parse(chord).unwrap().to_string(), *chord);
+        }
+    }
+
+    #[test]
+    fn every_action_is_found_by_its_name() {
+        for action in Action::ALL.iter() {
+            assert_eq!(Action::from_name(action.name()), Some(*action));
+        }
+        assert_eq!(Action::from_name("play"), None);
+    }
+
+    #[test]
+    fn default_keymap_resolves_without_problems() {
+        let (bindings, problems) = KeymapConfig::default().resolve();
+        assert_eq!(bindings.len(), DEFAULT_BINDINGS.len());
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn invalid_and_conflicting_bindings_are_reported_and_skipped() {
+        let config = KeymapConfig {
+            bindings: vec![
+                binding("Ctrl+S", "SaveProject"),
+                binding("ctrl+s", "Play"),
+                // Binding a chord to the same action twice is not a conflict.
+                binding("Ctrl+S", "SaveProject"),
+                binding("Ctrl+Q", "Frobnicate"),
+                binding("Hyper+X", "Play"),
+                binding("Space", "Play"),
+            ],
+        };
+
+        let (bindings, problems) = config.resolve();
+        assert_eq!(
+            bindings,
+            vec![
+                (Chord::parse("Ctrl+S").unwrap(), Action::SaveProject),
+                (Chord::parse("Space").unwrap(), Action::Play),
+            ]
+        );
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].contains("Ctrl+S is bound to both SaveProject and Play"));
+    }
+}
@@ -8,7 +8,10 @@ use std::sync::{
 use std::{error::Error, time::Duration};
 use vizia::prelude::*;
 
+pub mod app_config;
+pub mod diagnostics;
 pub mod icons;
+pub mod keymap;
 
 pub mod state;
 pub use state::*;
@@ -49,7 +52,11 @@ pub fn run_ui() -> Result<(), Box<dyn Error>> {
         cx.add_stylesheet("src/ui/resources/themes/default_theme/browser.css")
             .expect("Failed to find default stylesheet");
 
-        UiData::new().unwrap().build(cx);
+        let ui_data = UiData::new().unwrap();
+        let key_bindings = ui_data.key_bindings.clone();
+        ui_data.build(cx);
+
+        keymap::app_keymap(cx, &key_bindings);
 
         VStack::new(cx, |cx| {
             // TODO - Move to menu bar
@@ -4,7 +4,7 @@ mod keymap;
 use keymap::*;
 
 use crate::ui::state::{
-    ChannelEvent, ChannelState, ClipState, PanelEvent, PanelState, UiData, UiState,
+    ChannelEvent, ChannelId, ChannelState, ClipState, PanelEvent, PanelState, UiData, UiState,
 };
 use crate::ui::Panel;
 
@@ -87,7 +87,12 @@ pub fn channels(cx: &mut Context) {
                                         UiState::channels.index(0).then(ChannelState::selected),
                                     ),
                                 )
-                                .on_press(move |cx| cx.emit(ChannelEvent::SelectChannel(0)));
+                                .on_press(move |cx| {
+                                    let master = UiData::state
+                                        .then(UiState::channels.index(0).then(ChannelState::id))
+                                        .get(cx);
+                                    cx.emit(ChannelEvent::SelectChannel(master));
+                                });
 
                                 // Other Channels
                                 List::new(
@@ -187,7 +192,7 @@ fn clips(cx: &mut Context) {
 }
 
 pub struct Channel {
-    channel_index: usize,
+    channel_id: ChannelId,
 }
 
 impl Channel {
@@ -199,11 +204,13 @@ impl Channel {
     ) where
         <L as Lens>::Source: Model,
     {
-        Self { channel_index: index }
+        let channel_id = root.clone().index(index).get(cx).id;
+        Self { channel_id }
             .build(cx, |cx| {
                 let new_root = root.clone();
                 Binding::new(cx, root.index(index), move |cx, chnl| {
                     let data = chnl.get(cx);
+                    let id = data.id;
 
                     let col: Color = data.color.into();
 
@@ -222,7 +229,7 @@ impl Channel {
                     .class("channel")
                     .toggle_class("selected", data.selected)
                     .on_press(move |cx| {
-                        cx.emit(ChannelEvent::SelectChannel(index));
+                        cx.emit(ChannelEvent::SelectChannel(id));
                         // println!("Start Drag: {}", index);
                         // cx.emit(ChannelEvent::DragChannel(index));
                     });
@@ -251,7 +258,7 @@ impl View for Channel {
         event.map(|window_event, meta| match window_event {
             WindowEvent::MouseDoubleClick(button) if *button == MouseButton::Left => {
                 println!("Received double click event");
-                cx.emit(ChannelEvent::SelectChannelGroup(self.channel_index));
+                cx.emit(ChannelEvent::SelectChannelGroup(self.channel_id));
             }
 
             _ => {}
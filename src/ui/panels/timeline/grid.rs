@@ -38,11 +38,7 @@ impl View for TimelineGrid {
             let mut lane_y = 0.0;
             for (index, lane) in timeline_grid.lane_states.lanes.iter().enumerate() {
                 let lane_height = (DEFAULT_LANE_HEIGHT_PX
-                    * if let Some(height) = lane.height {
-                        height as f32
-                    } else {
-                        timeline_grid.lane_height as f32
-                    }
+                    * lane.total_height(timeline_grid.lane_height) as f32
                     + TIMELINE_GAP_BETWEEN_LANES)
                     * zoom_y as f32;
 
@@ -1,5 +1,5 @@
 use crate::ui::{
-    state::{LaneState, LaneStates, TimelineGridState},
+    state::{LaneState, LaneStates, TimelineGridState, MINIMIZED_LANE_HEIGHT},
     UiData, UiEvent, UiState,
 };
 use vizia::prelude::*;
@@ -46,18 +46,23 @@ pub fn lane_header(cx: &mut Context) {
                 cx.focus();
             })
             .bind(item.then(LaneState::height), move |handle, height| {
-                let factor = match height.get(handle.cx) {
-                    Some(height) => height as f32,
-                    None => 1.0,
-                };
-                handle.bind(
-                    UiData::state
-                        .then(UiState::timeline_grid.then(TimelineGridState::vertical_zoom_level)),
-                    move |handle, zoom_y| {
-                        let zoom_y = zoom_y.get(handle.cx) as f32;
-                        handle.height(Pixels(factor * DEFAULT_LANE_HEIGHT_PX * zoom_y));
-                    },
-                );
+                let height = height.get(handle.cx);
+                handle.bind(item.then(LaneState::minimized), move |handle, minimized| {
+                    let factor = match (minimized.get(handle.cx), height) {
+                        (true, _) => MINIMIZED_LANE_HEIGHT as f32,
+                        (false, Some(height)) => height as f32,
+                        (false, None) => 1.0,
+                    };
+                    handle.bind(
+                        UiData::state.then(
+                            UiState::timeline_grid.then(TimelineGridState::vertical_zoom_level),
+                        ),
+                        move |handle, zoom_y| {
+                            let zoom_y = zoom_y.get(handle.cx) as f32;
+                            handle.height(Pixels(factor * DEFAULT_LANE_HEIGHT_PX * zoom_y));
+                        },
+                    );
+                });
             })
             .class("lane_header")
             .toggle_class("selected", item.then(LaneState::selected))
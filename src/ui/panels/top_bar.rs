@@ -1,7 +1,7 @@
 use vizia::prelude::*;
 
 use crate::ui::icons::IconCode;
-use crate::ui::state::PanelEvent;
+use crate::ui::state::{PanelEvent, TransportState, UiData, UiEvent, UiState};
 use crate::ui::{Icon, Meter, MeterHandle};
 
 #[derive(Lens)]
@@ -32,9 +32,29 @@ pub fn top_bar(cx: &mut Context) {
             .class("top_play_left");
 
             HStack::new(cx, |cx| {
-                Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Play, 24.0, 23.0));
-                Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Stop, 24.0, 23.0));
-                Button::new(cx, |_| {}, |cx| Icon::new(cx, IconCode::Record, 24.0, 23.0));
+                Button::new(
+                    cx,
+                    |cx| cx.emit(UiEvent::Play),
+                    |cx| Icon::new(cx, IconCode::Play, 24.0, 23.0),
+                )
+                .toggle_class(
+                    "active",
+                    UiData::state.then(UiState::transport.then(TransportState::is_playing)),
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(UiEvent::Stop),
+                    |cx| Icon::new(cx, IconCode::Stop, 24.0, 23.0),
+                );
+                Button::new(
+                    cx,
+                    |cx| cx.emit(UiEvent::ToggleRecord),
+                    |cx| Icon::new(cx, IconCode::Record, 24.0, 23.0),
+                )
+                .toggle_class(
+                    "active",
+                    UiData::state.then(UiState::transport.then(TransportState::is_recording)),
+                );
             })
             .class("top_play_center")
             .top(Stretch(1.0))
@@ -0,0 +1,343 @@
+use super::core_types::WMusicalTime;
+use super::AutomationParam;
+use crate::backend::automation::LaneCurve;
+use meadowlark_core_types::time::MusicalTime;
+use serde::{Deserialize, Serialize};
+use vizia::prelude::*;
+
+/// The largest deviation (in normalized units) from a straight line for which
+/// a recorded point is dropped as redundant.
+pub const TOUCH_RECORD_THIN_EPSILON: f64 = 0.002;
+
+/// The shape of the segment from an automation point to the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub enum AutomationCurve {
+    /// A straight line.
+    Linear,
+    /// Keep the value of this point until the next one, then jump.
+    Hold,
+    /// Ease out of this point and into the next one.
+    SCurve,
+    /// Bend the line so that most of the change happens near the next point
+    /// (for positive tension) or near this point (for negative tension), like
+    /// the tension of a gain envelope point.
+    Tension(f32),
+}
+
+impl AutomationCurve {
+    /// Maps the linear position `t` in the range [0.0, 1.0] between two points
+    /// to the position along this curve.
+    pub fn shape(&self, t: f64) -> f64 {
+        LaneCurve::from(*self).shape(t)
+    }
+}
+
+impl From<AutomationCurve> for LaneCurve {
+    fn from(curve: AutomationCurve) -> Self {
+        match curve {
+            AutomationCurve::Linear => LaneCurve::Linear,
+            AutomationCurve::Hold => LaneCurve::Hold,
+            AutomationCurve::SCurve => LaneCurve::SCurve,
+            AutomationCurve::Tension(tension) => LaneCurve::Tension(tension),
+        }
+    }
+}
+
+impl Default for AutomationCurve {
+    fn default() -> Self {
+        AutomationCurve::Linear
+    }
+}
+
+/// A point of an automation lane.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub struct AutomationPoint {
+    pub time: WMusicalTime,
+    /// The normalized value of the parameter in the range [0.0, 1.0].
+    pub value: f64,
+    /// The shape of the segment from this point to the next one.
+    #[serde(default)]
+    pub curve: AutomationCurve,
+}
+
+impl AutomationPoint {
+    pub fn new(time: MusicalTime, value: f64) -> Self {
+        Self { time: time.into(), value: value.clamp(0.0, 1.0), curve: AutomationCurve::Linear }
+    }
+
+    fn beats(&self) -> f64 {
+        self.time.get().as_beats_f64()
+    }
+}
+
+/// How an automation lane behaves during playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum AutomationMode {
+    /// The parameter follows the lane.
+    Read,
+    /// The parameter follows the lane, except while its control is held, in
+    /// which case the movements of the control are recorded into the lane.
+    Touch,
+}
+
+impl Default for AutomationMode {
+    fn default() -> Self {
+        AutomationMode::Read
+    }
+}
+
+/// The automation of one parameter of a channel.
+#[derive(Debug, Clone, PartialEq, Data, Serialize, Deserialize)]
+pub struct AutomationLaneState {
+    pub param: AutomationParam,
+    #[serde(default)]
+    pub mode: AutomationMode,
+    /// The points of this lane, sorted by time. Two points may share the same
+    /// time to make a jump.
+    pub points: Vec<AutomationPoint>,
+}
+
+impl AutomationLaneState {
+    pub fn new(param: AutomationParam) -> Self {
+        Self { param, mode: AutomationMode::Read, points: Vec::new() }
+    }
+
+    /// Returns the value of this lane at `time` (in beats), interpolating
+    /// linearly between points, or `None` if the lane has no points.
+    pub fn value_at(&self, time: f64) -> Option<f64> {
+        // Of several points at the same time, the last one wins.
+        self.value_around(time, |p| p.beats() <= time)
+    }
+
+    /// Like `value_at()`, but if there are points at `time`, this returns the
+    /// value just before them.
+    pub fn value_before(&self, time: f64) -> Option<f64> {
+        self.value_around(time, |p| p.beats() < time)
+    }
+
+    /// Interpolates between the last point for which `is_before` is true and
+    /// the point after it.
+    fn value_around(
+        &self,
+        time: f64,
+        is_before: impl FnMut(&AutomationPoint) -> bool,
+    ) -> Option<f64> {
+        let first = self.points.first()?;
+
+        let next = self.points.partition_point(is_before);
+        if next == 0 {
+            return Some(first.value);
+        }
+        if next == self.points.len() {
+            return self.points.last().map(|p| p.value);
+        }
+
+        let a = &self.points[next - 1];
+        let b = &self.points[next];
+        let span = b.beats() - a.beats();
+        if span <= 0.0 {
+            return Some(b.value);
+        }
+        let t = a.curve.shape((time - a.beats()) / span);
+        Some(a.value + (b.value - a.value) * t)
+    }
+
+    /// Adds `point` to this lane and returns its index. If there are points at
+    /// the same time already, the new point goes after them.
+    pub fn add_point(&mut self, point: AutomationPoint) -> usize {
+        let index = self.points.partition_point(|p| p.beats() <= point.beats());
+        self.points.insert(index, point);
+        index
+    }
+
+    /// Moves the point at `index` to `time` and sets its value, keeping its
+    /// curve. Returns the new index of the point, or `None` if there is no
+    /// point at `index`.
+    pub fn move_point(&mut self, index: usize, time: MusicalTime, value: f64) -> Option<usize> {
+        let curve = self.remove_point(index)?.curve;
+        Some(self.add_point(AutomationPoint { curve, ..AutomationPoint::new(time, value) }))
+    }
+
+    /// Removes the point at `index` and returns it.
+    pub fn remove_point(&mut self, index: usize) -> Option<AutomationPoint> {
+        if index < self.points.len() {
+            Some(self.points.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Sets the shape of the segment that starts at the point at `index`.
+    /// Returns `false` if there is no point at `index`.
+    pub fn set_point_curve(&mut self, index: usize, curve: AutomationCurve) -> bool {
+        match self.points.get_mut(index) {
+            Some(point) => {
+                point.curve = curve;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces the points in the range covered by `points` (which must be
+    /// sorted by time) with `points`.
+    ///
+    /// The values the lane had at the edges of the range are kept as points at
+    /// the same times, so the rest of the lane is unchanged and jumps to and
+    /// from the new points instead of ramping.
+    pub fn replace_range(&mut self, points: &[AutomationPoint]) {
+        let (start, end) = match (points.first(), points.last()) {
+            (Some(first), Some(last)) => (first.beats(), last.beats()),
+            _ => return,
+        };
+
+        let value_before = self.value_before(start);
+        let value_after = self.value_at(end);
+
+        let first_inside = self.points.partition_point(|p| p.beats() < start);
+        let first_after = self.points.partition_point(|p| p.beats() <= end);
+
+        let mut new_points = Vec::with_capacity(points.len() + 2);
+        if let Some(value) = value_before {
+            new_points.push(AutomationPoint::new(points[0].time.get(), value));
+        }
+        new_points.extend_from_slice(points);
+        if let Some(value) = value_after {
+            new_points.push(AutomationPoint::new(points[points.len() - 1].time.get(), value));
+        }
+
+        self.points.splice(first_inside..first_after, new_points);
+    }
+}
+
+/// Records the movements of a control into segments of automation points while
+/// the control is held (see `AutomationMode::Touch`).
+///
+/// The position jumps back when the transport loops (or jumps when the user
+/// seeks), so a new segment is started whenever the position doesn't move
+/// forward. Each segment is written into the lane on its own, so that no ramp
+/// is drawn across the jump.
+#[derive(Debug, Clone)]
+pub struct TouchRecorder {
+    pub channel: usize,
+    pub param: AutomationParam,
+    segments: Vec<Vec<AutomationPoint>>,
+    last_position: Option<f64>,
+}
+
+impl TouchRecorder {
+    pub fn new(channel: usize, param: AutomationParam) -> Self {
+        Self { channel, param, segments: Vec::new(), last_position: None }
+    }
+
+    /// Records `value` (normalized) at `position` (in beats). Call this once per
+    /// block while the transport is playing.
+    pub fn record(&mut self, position: f64, value: f64) {
+        let is_jump = match self.last_position {
+            Some(last_position) => position <= last_position,
+            None => true,
+        };
+        if is_jump {
+            self.segments.push(Vec::new());
+        }
+        self.last_position = Some(position);
+
+        if let Some(segment) = self.segments.last_mut() {
+            segment.push(AutomationPoint::new(MusicalTime::from_beats_f64(position), value));
+        }
+    }
+
+    /// Stops recording and returns the recorded segments with the redundant
+    /// points removed, in the order they were recorded.
+    pub fn finish(self) -> Vec<Vec<AutomationPoint>> {
+        self.segments
+            .into_iter()
+            .map(|segment| thin_points(&segment, TOUCH_RECORD_THIN_EPSILON))
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    }
+}
+
+/// Removes the points of `points` that lie within `epsilon` of the straight
+/// line between their neighbors. The first and last points are always kept.
+pub fn thin_points(points: &[AutomationPoint], epsilon: f64) -> Vec<AutomationPoint> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+
+    let mut thinned = vec![points[0]];
+    for i in 1..points.len() - 1 {
+        let a = thinned[thinned.len() - 1];
+        let b = points[i];
+        let c = points[i + 1];
+
+        let span = c.beats() - a.beats();
+        let expected = if span > 0.0 {
+            a.value + (c.value - a.value) * (b.beats() - a.beats()) / span
+        } else {
+            c.value
+        };
+        if (b.value - expected).abs() > epsilon {
+            thinned.push(b);
+        }
+    }
+    thinned.push(points[points.len() - 1]);
+
+    thinned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(beats: f64, value: f64) -> AutomationPoint {
+        AutomationPoint::new(MusicalTime::from_beats_f64(beats), value)
+    }
+
+    fn beats_and_values(lane: &AutomationLaneState) -> Vec<(f64, f64)> {
+        lane.points.iter().map(|p| (p.beats(), p.value)).collect()
+    }
+
+    #[test]
+    fn touch_recording_splits_at_a_loop_back() {
+        let mut recorder = TouchRecorder::new(0, AutomationParam::Gain);
+        // A steady ramp from beat 0 to beat 4, then the transport loops back
+        // to beat 2 and the control is held still until beat 3.
+        for i in 0..=16 {
+            let beats = f64::from(i) * 0.25;
+            recorder.record(beats, beats / 8.0);
+        }
+        for i in 0..=4 {
+            recorder.record(2.0 + f64::from(i) * 0.25, 0.8);
+        }
+
+        let segments = recorder.finish();
+        assert_eq!(
+            segments,
+            vec![vec![point(0.0, 0.0), point(4.0, 0.5)], vec![point(2.0, 0.8), point(3.0, 0.8)]]
+        );
+
+        let mut lane = AutomationLaneState::new(AutomationParam::Gain);
+        lane.points = vec![point(0.0, 0.2), point(8.0, 0.2)];
+        for segment in segments.iter() {
+            lane.replace_range(segment);
+        }
+
+        // The second pass overwrites beats 2 to 3 of the first, and the lane
+        // jumps to and from the recorded values at the edges of each pass.
+        assert_eq!(
+            beats_and_values(&lane),
+            vec![
+                (0.0, 0.2),
+                (0.0, 0.0),
+                (2.0, 0.25),
+                (2.0, 0.8),
+                (3.0, 0.8),
+                (3.0, 0.375),
+                (4.0, 0.5),
+                (4.0, 0.2),
+                (8.0, 0.2),
+            ]
+        );
+    }
+}
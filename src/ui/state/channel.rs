@@ -1,15 +1,21 @@
 use std::path::PathBuf;
 
+use super::automation::AutomationLaneState;
 use super::clip::{AudioClipState, AutomationClipState, PianoRollClipState};
 use super::hrack_effect::HRackEffectState;
+use super::ids::ChannelId;
+use super::AutomationParam;
+use serde::{Deserialize, Serialize};
 use vizia::prelude::*;
 
-#[derive(Debug, Lens, Clone, Data)]
+use crate::backend::pan;
+
+#[derive(Debug, Lens, Clone, PartialEq, Data, Serialize, Deserialize)]
 pub enum ChannelBaseColor {
     /// This is an index into a bunch of preset colors that are defined
     /// by the current theme.
     Preset(u16),
-    Color(Color),
+    Color(#[serde(with = "super::color_serde")] Color),
 }
 
 impl From<ChannelBaseColor> for Color {
@@ -28,8 +34,13 @@ impl From<Color> for ChannelBaseColor {
 }
 
 /// A "channel" refers to a mixer channel.
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct ChannelState {
+    /// The id of this channel, which stays the same when other channels are
+    /// added, removed, or reordered.
+    #[serde(default)]
+    pub id: ChannelId,
+
     /// The channel name
     pub name: String,
 
@@ -57,10 +68,15 @@ pub struct ChannelState {
     // implement container effects.
     pub effects: Vec<HRackEffectState>,
 
-    /// The index to the channel that this channel is routed to.
-    ///
-    /// The master channel is always at index 0.
-    pub routed_to: usize,
+    /// Where the output of this channel is routed to.
+    pub routed_to: OutputAssignment,
+
+    /// The gain trim applied to the channel's input before any processing, in
+    /// decibels.
+    pub input_trim_db: f32,
+
+    /// True if the polarity of the channel's input is inverted.
+    pub phase_invert: bool,
 
     /// The normalized value of the channel's output gain in the range [0.0, 1.0].
     pub out_gain_normalized: f64,
@@ -68,6 +84,10 @@ pub struct ChannelState {
     /// The normalized value of the channel's output pan in the range [0.0, 1.0].
     pub out_pan_normalized: f64,
 
+    /// How the channel's output pan turns down each side.
+    #[serde(default)]
+    pub pan_law: PanLaw,
+
     /// The currently displayed value for the channel's output gain (i.e. "-12.0dB").
     pub out_gain_display: String,
 
@@ -79,12 +99,130 @@ pub struct ChannelState {
 
     /// True if this channel is currently being muted.
     pub muted: bool,
+
+    /// True if this channel records its input when the transport records.
+    #[serde(default)]
+    pub record_armed: bool,
+
+    /// The hardware inputs that feed this channel when it is record-armed.
+    #[serde(default)]
+    pub input: InputAssignment,
+
+    /// The automation of this channel's parameters, with at most one lane per
+    /// parameter.
+    #[serde(default)]
+    pub automation_lanes: Vec<AutomationLaneState>,
     // TODO: Sends
 }
 
+/// Where the output of a channel is routed to.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub enum OutputAssignment {
+    /// The master channel (always at index 0).
+    Master,
+    /// The group channel at the given index.
+    Group(usize),
+    /// A pair of hardware output channels, where 0 is the first pair (outputs
+    /// 1 and 2), 1 is the second pair (outputs 3 and 4), and so on.
+    Hardware { channel_pair: u16 },
+}
+
+/// Which hardware inputs feed a channel when it is record-armed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum InputAssignment {
+    None,
+    /// A single hardware input channel, where 0 is the first input.
+    Mono(u16),
+    /// A pair of hardware input channels, starting at the given channel (where
+    /// 0 is the first input).
+    StereoPair(u16),
+}
+
+impl InputAssignment {
+    /// The number of hardware input channels the engine must have for this
+    /// assignment to be valid.
+    pub fn num_required_channels(&self) -> u32 {
+        match self {
+            InputAssignment::None => 0,
+            InputAssignment::Mono(channel) => u32::from(*channel) + 1,
+            InputAssignment::StereoPair(first_channel) => u32::from(*first_channel) + 2,
+        }
+    }
+}
+
+impl Default for InputAssignment {
+    fn default() -> Self {
+        InputAssignment::None
+    }
+}
+
+/// How a pan turns down each side of a channel (see `backend::pan`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum PanLaw {
+    /// -3 dB in the center.
+    ConstantPower,
+    /// -4.5 dB in the center.
+    Compromise,
+    /// -6 dB in the center.
+    LinearTaper,
+    /// 0 dB in the center, only the opposite side is turned down.
+    Balance,
+}
+
+impl PanLaw {
+    /// Returns the `(left, right)` gains for the normalized `pan` in the range
+    /// [0.0, 1.0].
+    pub fn gains(&self, pan: f64) -> (f64, f64) {
+        self.law()(pan)
+    }
+
+    /// Returns the function of this pan law in `backend::pan`.
+    pub fn law(&self) -> fn(f64) -> (f64, f64) {
+        match self {
+            PanLaw::ConstantPower => pan::constant_power,
+            PanLaw::Compromise => pan::compromise,
+            PanLaw::LinearTaper => pan::linear_taper,
+            PanLaw::Balance => pan::balance,
+        }
+    }
+}
+
+impl Default for PanLaw {
+    fn default() -> Self {
+        PanLaw::ConstantPower
+    }
+}
+
+pub const MIN_INPUT_TRIM_DB: f32 = -24.0;
+pub const MAX_INPUT_TRIM_DB: f32 = 24.0;
+
+impl ChannelState {
+    /// Returns the automation lane of `param`, adding an empty one if there is
+    /// none yet.
+    pub fn automation_lane_mut(&mut self, param: &AutomationParam) -> &mut AutomationLaneState {
+        let index = match self.automation_lanes.iter().position(|lane| &lane.param == param) {
+            Some(index) => index,
+            None => {
+                self.automation_lanes.push(AutomationLaneState::new(param.clone()));
+                self.automation_lanes.len() - 1
+            }
+        };
+        &mut self.automation_lanes[index]
+    }
+
+    /// The total delay of this channel's effects in samples.
+    ///
+    /// Bypassed effects are included, since plugins keep reporting their delay
+    /// while bypassed so that bypassing them doesn't shift the audio.
+    pub fn latency(&self) -> u32 {
+        self.effects.iter().map(|effect| effect.delay()).sum()
+    }
+}
+
 impl Default for ChannelState {
     fn default() -> Self {
         ChannelState {
+            id: ChannelId::default(),
             name: String::from("Channel"),
             path: PathBuf::from("Channel"),
             color: ChannelBaseColor::Color(Color::red()),
@@ -95,23 +233,35 @@ impl Default for ChannelState {
             piano_roll_clips: vec![],
             automation_clips: vec![],
             effects: vec![],
-            routed_to: 0,
+            routed_to: OutputAssignment::Master,
+            input_trim_db: 0.0,
+            phase_invert: false,
             out_gain_normalized: 1.0,
             out_pan_normalized: 0.5,
+            pan_law: PanLaw::default(),
             out_gain_display: String::from("0dB"),
             out_pan_display: String::from("0"),
             soloed: false,
             muted: false,
+            record_armed: false,
+            input: InputAssignment::None,
+            automation_lanes: vec![],
         }
     }
 }
 
 #[derive(PartialEq, Clone)]
 pub enum ChannelEvent {
-    SelectChannel(usize),
-    SelectChannelGroup(usize),
+    SelectChannel(ChannelId),
+    SelectChannelGroup(ChannelId),
     AddChannel,
     RemoveChannel,
+    SetInputTrim(ChannelId, f32),
+    SetPanLaw(ChannelId, PanLaw),
+    TogglePhaseInvert(ChannelId),
+    ToggleRecordArm(ChannelId),
+    RemoveEffect { channel: ChannelId, index: usize },
+    MoveEffect { channel: ChannelId, from: usize, to: usize },
     // DragChannel(usize),
     // DropChannel(usize),
 }
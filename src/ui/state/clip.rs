@@ -1,9 +1,51 @@
+use super::channel::ChannelBaseColor;
+use super::comp::CompClipState;
 use super::core_types::{WMusicalTime, WSeconds, WSuperFrames};
+use super::ids::ClipId;
+use super::timeline_grid::{sanitize_bpm, MAX_PROJECT_LENGTH_BEATS};
+use meadowlark_core_types::time::{MusicalTime, Seconds, SuperFrames};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use vizia::prelude::*;
 
-#[derive(Debug, Lens, Clone, Data)]
+use crate::backend::{decode, interpolation};
+
+/// The number of super frames in one second.
+pub(super) const SUPER_FRAMES_PER_SECOND: f64 = 282_240_000.0;
+
+pub const MIN_PITCH_SEMITONES: f32 = -24.0;
+pub const MAX_PITCH_SEMITONES: f32 = 24.0;
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct ClipState {
+    /// The id of this clip, which stays the same when other clips are added or
+    /// removed. This is assigned by `UiState::add_clip()`.
+    #[serde(default)]
+    pub id: ClipId,
+
+    /// The internal name of this clip. This never changes, so renaming a clip
+    /// only changes its `label`.
     pub name: String,
+
+    /// The name shown on the timeline. If this is `None`, then `name` is shown
+    /// instead.
+    #[serde(default)]
+    pub label: Option<String>,
+
+    /// Free-form notes about this clip, shown in the inspector.
+    #[serde(default)]
+    pub notes: String,
+
+    /// Where this clip is stacked among the clips it overlaps. Clips with a
+    /// higher value are on top. New clips are put on top of the existing ones.
+    #[serde(default)]
+    pub z_order: u64,
+
+    /// The color of this clip on the timeline. If this is `None`, then the
+    /// color of its channel is used.
+    #[serde(default)]
+    pub color: Option<ChannelBaseColor>,
+
     pub timeline_start: ClipStart,
     pub length: WMusicalTime,
 
@@ -12,38 +54,874 @@ pub struct ClipState {
     pub type_: ClipType,
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+impl ClipState {
+    /// The name to show for this clip on the timeline.
+    pub fn display_label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+
+    /// The audio the timeline player plays for this clip: the clip itself if
+    /// it is an audio clip, or the active take of a take folder (see
+    /// `CompClipState::active_take()`).
+    pub fn played_audio(&self) -> Option<&AudioClipState> {
+        match &self.type_ {
+            ClipType::Audio(audio_clip) => Some(audio_clip),
+            ClipType::Comp(comp) => {
+                comp.active_take(self.length.get()).and_then(|take| comp.takes.get(take))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the index of the lane this clip is on along with the start and end
+    /// of this clip in beats, or `None` if this clip is not on the timeline.
+    pub fn lane_range_beats(&self) -> Option<(u32, f64, f64)> {
+        match &self.timeline_start {
+            ClipStart::OnLane(on_lane) => {
+                let start = on_lane.timeline_start.get().as_beats_f64();
+                Some((on_lane.lane_index, start, start + self.length.get().as_beats_f64()))
+            }
+            ClipStart::NotInTimeline => None,
+        }
+    }
+
+    /// Moves the start of this clip on the timeline to `new_start` while keeping
+    /// the end of the clip in place.
+    ///
+    /// For audio clips, the clip start offset is adjusted so the audio under the
+    /// rest of the clip stays put. The start is clamped so that it can't be moved
+    /// before the start of the audio data.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    pub fn resize_start(&mut self, new_start: MusicalTime, bpm: f64) {
+        let on_lane = match &mut self.timeline_start {
+            ClipStart::OnLane(on_lane) => on_lane,
+            ClipStart::NotInTimeline => return,
+        };
+
+        let start = on_lane.timeline_start.get().as_beats_f64();
+        let end = start + self.length.get().as_beats_f64();
+        let mut new_start = new_start.as_beats_f64().max(0.0).min(end);
+
+        if let ClipType::Audio(audio_clip) = &mut self.type_ {
+            let offset_beats = super_frames_to_beats(audio_clip.clip_start_offset.get(), bpm);
+            new_start = new_start.max(start - offset_beats);
+
+            audio_clip.clip_start_offset =
+                beats_to_super_frames(offset_beats + new_start - start, bpm).into();
+
+            // Warp markers are relative to the start of the clip, so move them
+            // back to keep them at the same place on the timeline. Markers that
+            // end up before the new start are trimmed off with the audio.
+            let delta = new_start - start;
+            audio_clip.warp_markers.retain(|m| m.time.get().as_beats_f64() >= delta);
+            for marker in audio_clip.warp_markers.iter_mut() {
+                marker.time =
+                    MusicalTime::from_beats_f64(marker.time.get().as_beats_f64() - delta).into();
+            }
+        }
+
+        on_lane.timeline_start = MusicalTime::from_beats_f64(new_start).into();
+        self.length = MusicalTime::from_beats_f64(end - new_start).into();
+    }
+
+    /// Moves the end of this clip on the timeline to `new_end` while keeping the
+    /// start of the clip in place.
+    ///
+    /// For audio clips, the end is clamped so that it can't be moved past the
+    /// end of the audio data if `source_duration` (the duration of the whole
+    /// audio file) is known.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    pub fn resize_end(&mut self, new_end: MusicalTime, bpm: f64, source_duration: Option<Seconds>) {
+        let max_end = MusicalTime::from_beats(MAX_PROJECT_LENGTH_BEATS);
+        let mut new_end = if new_end > max_end { max_end } else { new_end };
+
+        if let ClipStart::OnLane(on_lane) = &self.timeline_start {
+            let start = on_lane.timeline_start.get();
+            if let (ClipType::Audio(audio_clip), Some(duration)) = (&self.type_, source_duration) {
+                let source_beats = audio_clip.effective_source_duration(duration).0 * bpm / 60.0;
+                let source_end = MusicalTime::from_beats_f64(start.as_beats_f64() + source_beats);
+                if new_end > source_end {
+                    new_end = source_end;
+                }
+            }
+            if new_end > start {
+                self.length = (new_end - start).into();
+            }
+        }
+    }
+}
+
+/// The shape of a clip fade.
+///
+/// When two overlapping clips are crossfaded, the crossfade is made up of the
+/// fade-out of the earlier clip and the fade-in of the later clip.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub enum FadeCurve {
+    Linear,
+    /// Keeps the summed power of a crossfade constant.
+    EqualPower,
+}
+
+impl FadeCurve {
+    /// Returns the gain of a fade-in with this shape at `t`, where `t` is the
+    /// normalized position in the fade in the range [0.0, 1.0].
+    ///
+    /// The gain of a fade-out is `fade_in_gain(1.0 - t)`.
+    pub fn fade_in_gain(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => t,
+            FadeCurve::EqualPower => (t * std::f32::consts::FRAC_PI_2).sin(),
+        }
+    }
+}
+
+impl Default for FadeCurve {
+    fn default() -> Self {
+        FadeCurve::EqualPower
+    }
+}
+
+/// How the audio of a clip with more than two channels is played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Data, Serialize, Deserialize)]
+pub enum MultichannelMode {
+    /// Mix all channels down to stereo.
+    Downmix,
+    /// Only play the first two channels.
+    FirstPair,
+}
+
+impl MultichannelMode {
+    /// Reduces `buffers` (one buffer per channel) to at most two channels.
+    pub fn apply(&self, mut buffers: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+        if buffers.len() <= 2 {
+            return buffers;
+        }
+        match self {
+            MultichannelMode::Downmix => decode::downmix_to_stereo(&buffers),
+            MultichannelMode::FirstPair => {
+                buffers.truncate(2);
+                buffers
+            }
+        }
+    }
+}
+
+impl Default for MultichannelMode {
+    fn default() -> Self {
+        MultichannelMode::Downmix
+    }
+}
+
+/// How many channels an audio clip plays, regardless of its audio file.
+///
+/// This is applied after `MultichannelMode`, so the audio has at most two
+/// channels by then.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Data, Serialize, Deserialize)]
+pub enum ClipChannelMode {
+    /// Play mono files as mono and stereo files as stereo.
+    Auto,
+    /// Sum stereo files to mono at -3 dB.
+    ForceMono,
+    /// Play mono files on both channels at -3 dB each, so that the power
+    /// stays the same.
+    ForceStereo,
+}
+
+impl ClipChannelMode {
+    /// The number of channels the clip plays, given the number of channels of
+    /// its audio after `MultichannelMode` is applied.
+    pub fn output_channels(&self, num_channels: usize) -> usize {
+        match self {
+            ClipChannelMode::Auto => num_channels.min(2),
+            ClipChannelMode::ForceMono => 1,
+            ClipChannelMode::ForceStereo => 2,
+        }
+    }
+
+    /// Converts `buffers` (one buffer per channel, at most two) to the number
+    /// of channels given by `output_channels()`.
+    pub fn apply(&self, buffers: Vec<Vec<f32>>) -> Vec<Vec<f32>> {
+        match (self, buffers.len()) {
+            (ClipChannelMode::ForceMono, 2) => {
+                vec![decode::sum_stereo_to_mono(&buffers[0], &buffers[1])]
+            }
+            (ClipChannelMode::ForceStereo, 1) => decode::mono_to_stereo(&buffers[0]),
+            _ => buffers,
+        }
+    }
+}
+
+impl Default for ClipChannelMode {
+    fn default() -> Self {
+        ClipChannelMode::Auto
+    }
+}
+
+/// How the source audio of a clip is read at positions between two samples,
+/// i.e. when the clip is repitched or its sample rate differs from the engine's.
+///
+/// The higher the quality, the more CPU it costs. See `backend::interpolation`
+/// for the cost of each mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum InterpolationQuality {
+    /// Use the closest sample. This is the cheapest, but it aliases.
+    Nearest,
+    /// Interpolate linearly between two samples.
+    Linear,
+    /// Interpolate with a cubic spline over four samples.
+    Cubic,
+    /// Interpolate with a windowed sinc filter. This is transparent, but by
+    /// far the most expensive.
+    Sinc,
+}
+
+impl InterpolationQuality {
+    /// Reads `source` at the fractional position `pos` (in source samples),
+    /// where `rate` is the number of source samples advanced per output sample.
+    pub fn read(&self, source: &[f32], pos: f64, rate: f64) -> f32 {
+        match self {
+            InterpolationQuality::Nearest => interpolation::nearest(source, pos),
+            InterpolationQuality::Linear => interpolation::linear(source, pos),
+            InterpolationQuality::Cubic => interpolation::cubic(source, pos),
+            InterpolationQuality::Sinc => interpolation::sinc(source, pos, rate),
+        }
+    }
+
+    /// Fills `out` by reading `source` starting at `start` (in source samples)
+    /// and advancing by `rate` source samples per output sample.
+    pub fn resample(&self, out: &mut [f32], source: &[f32], start: f64, rate: f64) {
+        match self {
+            InterpolationQuality::Nearest => {
+                interpolation::resample_with(out, source, start, rate, interpolation::nearest)
+            }
+            InterpolationQuality::Linear => {
+                interpolation::resample_with(out, source, start, rate, interpolation::linear)
+            }
+            InterpolationQuality::Cubic => {
+                interpolation::resample_with(out, source, start, rate, interpolation::cubic)
+            }
+            InterpolationQuality::Sinc => {
+                interpolation::resample_with(out, source, start, rate, |source, pos| {
+                    interpolation::sinc(source, pos, rate)
+                })
+            }
+        }
+    }
+}
+
+/// The interpolation quality used for the clips of a project that don't set
+/// their own.
+///
+/// Playback defaults to linear to keep the CPU load low while working, and
+/// export defaults to sinc since it doesn't need to run in real time.
+#[derive(Debug, Lens, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub struct InterpolationSettings {
+    pub playback: InterpolationQuality,
+    pub export: InterpolationQuality,
+}
+
+impl Default for InterpolationSettings {
+    fn default() -> Self {
+        Self { playback: InterpolationQuality::Linear, export: InterpolationQuality::Sinc }
+    }
+}
+
+/// The default length of the automatic fade at the start and end of every clip
+/// in seconds. This is short enough to be inaudible.
+pub const DEFAULT_AUTO_FADE_SECS: f64 = 0.002;
+
+/// A short fade that is applied at the start and end of every audio clip to
+/// avoid clicks when a clip does not start or end at a zero crossing.
+///
+/// This is separate from the fades set by the user. Where a clip has a user
+/// fade, the automatic fade is not applied.
+#[derive(Debug, Lens, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub struct AutoFade {
+    pub enabled: bool,
+    pub length_secs: WSeconds,
+}
+
+impl AutoFade {
+    /// Returns the length of the automatic fade, or `None` if it is disabled.
+    pub fn length(&self) -> Option<Seconds> {
+        if self.enabled && self.length_secs.get().0 > 0.0 {
+            Some(self.length_secs.get())
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for AutoFade {
+    fn default() -> Self {
+        Self { enabled: true, length_secs: Seconds(DEFAULT_AUTO_FADE_SECS).into() }
+    }
+}
+
+/// The edges of an audio clip that continue seamlessly from the clip before it
+/// or into the clip after it, i.e. where a clip was split (see
+/// `AudioClipState::continues_into()`).
+///
+/// The automatic fade is left out on these edges, so that the pieces play like
+/// the clip they were split from. User fades still apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClipJoins {
+    pub start: bool,
+    pub end: bool,
+}
+
+/// What to do when a clip is moved so that it overlaps another clip on the
+/// same lane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverlapPolicy {
+    /// Crossfade the two clips over the overlapping region, up to `max_len`.
+    Crossfade { max_len: Seconds },
+    /// Shorten the earlier clip so that it ends where the moved clip starts.
+    TrimOverlapped,
+    /// Leave both clips as they are.
+    AllowOverlap,
+}
+
+impl Default for OverlapPolicy {
+    fn default() -> Self {
+        OverlapPolicy::TrimOverlapped
+    }
+}
+
+pub(super) fn super_frames_to_beats(super_frames: SuperFrames, bpm: f64) -> f64 {
+    let bpm = sanitize_bpm(bpm);
+    super_frames.0 as f64 / SUPER_FRAMES_PER_SECOND * bpm / 60.0
+}
+
+pub(super) fn beats_to_super_frames(beats: f64, bpm: f64) -> SuperFrames {
+    let bpm = sanitize_bpm(bpm);
+    SuperFrames((beats.max(0.0) * 60.0 / bpm * SUPER_FRAMES_PER_SECOND).round() as u64)
+}
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum ClipType {
     Audio(AudioClipState),
     PianoRoll(PianoRollClipState),
     Automation(AutomationClipState),
+    /// Several takes of audio that are comped together.
+    Comp(CompClipState),
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct AudioClipState {
+    /// The gain applied to this clip in decibels.
+    pub gain_db: f32,
+
     pub fade_in_secs: WSeconds,
 
     pub fade_out_secs: WSeconds,
 
+    /// The shape of the fade-in.
+    pub fade_in_curve: FadeCurve,
+
+    /// The shape of the fade-out.
+    pub fade_out_curve: FadeCurve,
+
     /// The amount of time between the start of the raw waveform data
     /// and the start of the clip.
     ///
     /// TODO
     pub clip_start_offset: WSuperFrames,
-    // TODO: pointer to waveform data
+
+    /// The path to the audio file this clip plays.
+    pub pcm_path: PathBuf,
+
+    /// True if the audio file for this clip could not be found. The clip keeps
+    /// its position and settings until it is relinked to a new file.
+    pub missing: bool,
+
+    /// The amount this clip is repitched by in semitones. This changes the
+    /// playback rate of the clip, so it also changes its audible length.
+    pub pitch_semitones: f32,
+
+    /// The breakpoints of the clip's gain envelope, sorted by time.
+    ///
+    /// This is empty if the clip has no gain envelope.
+    pub gain_envelope: Vec<GainEnvelopePoint>,
+
+    /// True if the samples of this clip are negated (i.e. to fix an
+    /// out-of-phase recording).
+    #[serde(default)]
+    pub invert_polarity: bool,
+
+    /// The gain trim applied to the left channel of this clip in decibels, in
+    /// addition to `gain_db`.
+    ///
+    /// For mono clips, this is applied to the only channel.
+    #[serde(default)]
+    pub gain_l_db: f32,
+
+    /// The gain trim applied to the right channel of this clip in decibels, in
+    /// addition to `gain_db`.
+    ///
+    /// This is ignored for mono clips.
+    #[serde(default)]
+    pub gain_r_db: f32,
+
+    /// The warp markers of this clip, sorted by both source position and
+    /// musical time.
+    ///
+    /// Between two markers, the clip is played at the rate that makes each
+    /// marked source position land exactly on its musical time. Before the
+    /// first and after the last marker, the clip plays at its normal rate.
+    #[serde(default)]
+    pub warp_markers: Vec<WarpMarker>,
+
+    /// How this clip is played if its audio file has more than two channels.
+    #[serde(default)]
+    pub multichannel_mode: MultichannelMode,
+
+    /// Whether this clip plays as mono or stereo.
+    #[serde(default)]
+    pub channel_mode: ClipChannelMode,
+
+    /// The original audio file of this clip if `pcm_path` points to a
+    /// time-stretched copy of it (made by "stretch to fit").
+    #[serde(default)]
+    pub stretched_from: Option<PathBuf>,
+
+    /// The interpolation quality of this clip, overriding the project's
+    /// `InterpolationSettings` for both playback and export.
+    #[serde(default)]
+    pub interpolation: Option<InterpolationQuality>,
+}
+
+impl AudioClipState {
+    /// Creates a clip that plays the audio file at `pcm_path` from the start
+    /// with the default settings.
+    pub fn new(pcm_path: PathBuf) -> Self {
+        Self {
+            gain_db: 0.0,
+            fade_in_secs: Seconds(0.0).into(),
+            fade_out_secs: Seconds(0.0).into(),
+            fade_in_curve: FadeCurve::default(),
+            fade_out_curve: FadeCurve::default(),
+            clip_start_offset: SuperFrames(0).into(),
+            pcm_path,
+            missing: false,
+            pitch_semitones: 0.0,
+            gain_envelope: Vec::new(),
+            invert_polarity: false,
+            gain_l_db: 0.0,
+            gain_r_db: 0.0,
+            warp_markers: Vec::new(),
+            multichannel_mode: MultichannelMode::default(),
+            channel_mode: ClipChannelMode::default(),
+            stretched_from: None,
+            interpolation: None,
+        }
+    }
+
+    /// Sets the amount this clip is repitched by, clamped to the range
+    /// [`MIN_PITCH_SEMITONES`, `MAX_PITCH_SEMITONES`].
+    pub fn set_pitch_semitones(&mut self, semitones: f32) {
+        self.pitch_semitones = semitones.clamp(MIN_PITCH_SEMITONES, MAX_PITCH_SEMITONES);
+    }
+
+    /// The rate this clip is played back at (where 1.0 is the original speed).
+    pub fn playback_rate(&self) -> f64 {
+        2.0f64.powf(f64::from(self.pitch_semitones) / 12.0)
+    }
+
+    /// Returns how long the audio after the clip start offset takes to play with
+    /// the current pitch, given the duration of the whole audio file.
+    ///
+    /// The length of the clip on the timeline is not affected by the pitch. Use
+    /// this to draw where the audio ends inside of the clip.
+    pub fn effective_source_duration(&self, source_duration: Seconds) -> Seconds {
+        let offset_secs = self.clip_start_offset.get().0 as f64 / SUPER_FRAMES_PER_SECOND;
+        Seconds((source_duration.0 - offset_secs).max(0.0) / self.playback_rate())
+    }
+
+    /// The position in the audio file that is heard at the start of the clip.
+    ///
+    /// The waveform of the clip is drawn starting from here.
+    pub fn source_start(&self) -> Seconds {
+        Seconds(self.clip_start_offset.get().0 as f64 / SUPER_FRAMES_PER_SECOND)
+    }
+
+    /// Shifts the audio under the clip by `delta_secs` of timeline time while
+    /// the clip stays where it is on the timeline (a slip edit). A positive
+    /// `delta_secs` moves the audio later, so an earlier part of the file is
+    /// heard.
+    ///
+    /// The clip start offset is clamped so that the clip never reads before the
+    /// start of the audio file, or past its end if `source_duration` is known.
+    /// The warp markers move with the audio. Returns `true` if the offset
+    /// changed.
+    ///
+    /// * `clip_length_secs` - The length of the clip on the timeline.
+    pub fn slip(
+        &mut self,
+        delta_secs: f64,
+        clip_length_secs: f64,
+        source_duration: Option<Seconds>,
+    ) -> bool {
+        let rate = self.playback_rate();
+        let offset = self.clip_start_offset.get().0 as f64;
+
+        // Markers can't be moved before the start of the file either.
+        let min_source =
+            self.warp_markers.iter().map(|m| m.source.get().0 as f64).fold(offset, f64::min);
+        let min_offset = offset - min_source;
+        let max_offset = match source_duration {
+            Some(duration) => {
+                let read_len = clip_length_secs.max(0.0) * rate;
+                ((duration.0 - read_len) * SUPER_FRAMES_PER_SECOND).max(min_offset)
+            }
+            None => f64::MAX,
+        };
+
+        let new_offset =
+            (offset - delta_secs * rate * SUPER_FRAMES_PER_SECOND).clamp(min_offset, max_offset);
+        let new_offset = new_offset.round() as u64;
+        if new_offset == self.clip_start_offset.get().0 {
+            return false;
+        }
+
+        let shift = new_offset as i64 - self.clip_start_offset.get().0 as i64;
+        for marker in self.warp_markers.iter_mut() {
+            let source = (marker.source.get().0 as i64 + shift).max(0);
+            marker.source = SuperFrames(source as u64).into();
+        }
+        self.clip_start_offset = SuperFrames(new_offset).into();
+
+        true
+    }
+
+    /// Adds a warp marker that pins the audio at `source` to `time` (relative to
+    /// the start of the clip).
+    ///
+    /// Returns the index of the new marker, or `None` if the marker would not be
+    /// strictly after the previous marker and strictly before the next one in
+    /// both source position and musical time.
+    pub fn add_warp_marker(&mut self, source: SuperFrames, time: MusicalTime) -> Option<usize> {
+        let index = self.warp_markers.iter().position(|m| m.time.get() > time);
+        let index = index.unwrap_or(self.warp_markers.len());
+
+        if !self.is_valid_warp_marker(index, None, source, time) {
+            return None;
+        }
+
+        self.warp_markers.insert(index, WarpMarker { source: source.into(), time: time.into() });
+        Some(index)
+    }
+
+    /// Moves the warp marker at `index`.
+    ///
+    /// Returns `false` if the marker doesn't exist, or if it would no longer be
+    /// strictly between its neighbors in both source position and musical time.
+    pub fn move_warp_marker(
+        &mut self,
+        index: usize,
+        source: SuperFrames,
+        time: MusicalTime,
+    ) -> bool {
+        if index >= self.warp_markers.len()
+            || !self.is_valid_warp_marker(index, Some(index), source, time)
+        {
+            return false;
+        }
+
+        self.warp_markers[index] = WarpMarker { source: source.into(), time: time.into() };
+        true
+    }
+
+    /// Removes the warp marker at `index`.
+    pub fn remove_warp_marker(&mut self, index: usize) -> Option<WarpMarker> {
+        if index < self.warp_markers.len() {
+            Some(self.warp_markers.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if a marker at `source` and `time` fits at `index`, where
+    /// `replacing` is the index of the marker it replaces (if any).
+    fn is_valid_warp_marker(
+        &self,
+        index: usize,
+        replacing: Option<usize>,
+        source: SuperFrames,
+        time: MusicalTime,
+    ) -> bool {
+        let prev = if index > 0 { self.warp_markers.get(index - 1) } else { None };
+        let next_index = if replacing.is_some() { index + 1 } else { index };
+        let next = self.warp_markers.get(next_index);
+
+        if let Some(prev) = prev {
+            if prev.source.get().0 >= source.0 || prev.time.get() >= time {
+                return false;
+            }
+        }
+        if let Some(next) = next {
+            if next.source.get().0 <= source.0 || next.time.get() <= time {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns the position in the audio file that is heard at `time` (relative
+    /// to the start of the clip), taking the warp markers and the pitch into
+    /// account.
+    ///
+    /// Since the markers are pinned to musical time, this follows tempo
+    /// changes.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    pub fn source_position_at(&self, time: MusicalTime, bpm: f64) -> SuperFrames {
+        let beats = time.as_beats_f64();
+        let markers = &self.warp_markers;
+
+        // Find the segment that contains `time`, and the source position and
+        // time it starts at.
+        let next = markers.iter().position(|m| m.time.get().as_beats_f64() > beats);
+        let (start_source, start_beats) = match (next, markers.last()) {
+            (_, None) => (self.clip_start_offset.get().0 as f64, 0.0),
+            (Some(0), _) => {
+                // Before the first marker, play at the normal rate up to it.
+                let first = &markers[0];
+                let first_beats = first.time.get().as_beats_f64();
+                let offset =
+                    beats_to_super_frames(first_beats - beats, bpm).0 as f64 * self.playback_rate();
+                let source = (first.source.get().0 as f64 - offset).max(0.0);
+                return SuperFrames(source.round() as u64);
+            }
+            (Some(i), _) => {
+                let (a, b) = (&markers[i - 1], &markers[i]);
+                let a_beats = a.time.get().as_beats_f64();
+                let b_beats = b.time.get().as_beats_f64();
+                let t = (beats - a_beats) / (b_beats - a_beats);
+                let a_source = a.source.get().0 as f64;
+                let b_source = b.source.get().0 as f64;
+                return SuperFrames((a_source + (b_source - a_source) * t).round() as u64);
+            }
+            (None, Some(last)) => (last.source.get().0 as f64, last.time.get().as_beats_f64()),
+        };
+
+        let offset =
+            beats_to_super_frames(beats - start_beats, bpm).0 as f64 * self.playback_rate();
+        SuperFrames((start_source + offset).round() as u64)
+    }
+
+    /// Returns `-1.0` if the polarity of this clip is inverted, or `1.0`
+    /// otherwise.
+    ///
+    /// The samples are multiplied by this before `gain_at()` is applied.
+    pub fn polarity(&self) -> f32 {
+        if self.invert_polarity {
+            -1.0
+        } else {
+            1.0
+        }
+    }
+
+    /// Returns the linear gain trim of channel `channel` of this clip, given the
+    /// number of channels in its audio file.
+    ///
+    /// This is applied in addition to `gain_at()`.
+    pub fn channel_trim(&self, channel: usize, num_channels: usize) -> f32 {
+        let trim_db = match channel {
+            0 => self.gain_l_db,
+            1 if num_channels > 1 => self.gain_r_db,
+            _ => 0.0,
+        };
+        10.0f32.powf(trim_db / 20.0)
+    }
+
+    /// Returns the value of the gain envelope in decibels at `time` (relative
+    /// to the start of the clip), or `None` if the clip has no gain envelope.
+    pub fn envelope_gain_db_at(&self, time: MusicalTime) -> Option<f32> {
+        self.envelope_gain_db_at_beats(time.as_beats_f64())
+    }
+
+    fn envelope_gain_db_at_beats(&self, beats: f64) -> Option<f32> {
+        let first = self.gain_envelope.first()?;
+        if beats <= first.time.get().as_beats_f64() {
+            return Some(first.gain_db);
+        }
+
+        for points in self.gain_envelope.windows(2) {
+            let (a, b) = (&points[0], &points[1]);
+            let a_beats = a.time.get().as_beats_f64();
+            let b_beats = b.time.get().as_beats_f64();
+            if beats < b_beats {
+                let t = ((beats - a_beats) / (b_beats - a_beats)) as f32;
+                let t = t.powf(a.tension.exp2());
+
+                return Some(a.gain_db + (b.gain_db - a.gain_db) * t);
+            }
+        }
+
+        self.gain_envelope.last().map(|p| p.gain_db)
+    }
+
+    /// Returns `true` if the audio of `next` picks up exactly where the audio
+    /// of this clip ends, given the length of this clip and the tempo. If
+    /// `next` starts right where this clip ends on the same lane, the two play
+    /// like a single clip.
+    pub fn continues_into(&self, length: MusicalTime, next: &AudioClipState, bpm: f64) -> bool {
+        // The source positions are rounded to super frames, so allow for less
+        // than a frame at any sample rate.
+        const MAX_GAP_SUPER_FRAMES: u64 = (SUPER_FRAMES_PER_SECOND / 192_000.0) as u64;
+
+        let end = self.source_position_at(length, bpm).0;
+        let start = next.source_position_at(MusicalTime::from_beats(0), bpm).0;
+        self.pcm_path == next.pcm_path
+            && self.multichannel_mode == next.multichannel_mode
+            && self.channel_mode == next.channel_mode
+            && self.pitch_semitones == next.pitch_semitones
+            && self.invert_polarity == next.invert_polarity
+            && end.abs_diff(start) < MAX_GAP_SUPER_FRAMES
+    }
+
+    /// Returns the linear gain that is applied to this clip at `time` (relative
+    /// to the start of the clip), given the length of the clip, the tempo, the
+    /// project's automatic fade, and the edges of the clip that join onto
+    /// another one.
+    ///
+    /// This combines the static gain, the fades, and the gain envelope. The
+    /// audio processing and the waveform overlay must both use this so that what
+    /// is drawn matches what is heard.
+    pub fn gain_at(
+        &self,
+        time: MusicalTime,
+        clip_length: MusicalTime,
+        bpm: f64,
+        auto_fade: &AutoFade,
+        joins: ClipJoins,
+    ) -> f32 {
+        self.gain_at_beats(time.as_beats_f64(), clip_length.as_beats_f64(), bpm, auto_fade, joins)
+    }
+
+    fn gain_at_beats(
+        &self,
+        beats: f64,
+        length_beats: f64,
+        bpm: f64,
+        auto_fade: &AutoFade,
+        joins: ClipJoins,
+    ) -> f32 {
+        let secs_per_beat = 60.0 / sanitize_bpm(bpm);
+        let secs = beats * secs_per_beat;
+        let length_secs = length_beats * secs_per_beat;
+
+        let mut gain_db = self.gain_db;
+        if let Some(envelope_db) = self.envelope_gain_db_at_beats(beats) {
+            gain_db += envelope_db;
+        }
+        let mut gain = 10.0f32.powf(gain_db / 20.0);
+
+        let auto_fade_secs = auto_fade.length().map(|s| s.0).unwrap_or(0.0);
+        let auto_fade_in_secs = if joins.start { 0.0 } else { auto_fade_secs };
+        let auto_fade_out_secs = if joins.end { 0.0 } else { auto_fade_secs };
+
+        let (fade_in_secs, fade_in_curve) = if self.fade_in_secs.get().0 > 0.0 {
+            (self.fade_in_secs.get().0, self.fade_in_curve)
+        } else {
+            (auto_fade_in_secs, FadeCurve::Linear)
+        };
+        if fade_in_secs > 0.0 && secs < fade_in_secs {
+            gain *= fade_in_curve.fade_in_gain((secs / fade_in_secs) as f32);
+        }
+
+        let (fade_out_secs, fade_out_curve) = if self.fade_out_secs.get().0 > 0.0 {
+            (self.fade_out_secs.get().0, self.fade_out_curve)
+        } else {
+            (auto_fade_out_secs, FadeCurve::Linear)
+        };
+        if fade_out_secs > 0.0 && length_secs - secs < fade_out_secs {
+            gain *= fade_out_curve.fade_in_gain(((length_secs - secs) / fade_out_secs) as f32);
+        }
+
+        gain
+    }
+
+    /// Returns the linear gain of this clip at `resolution` evenly spaced points
+    /// from the start to the end of the clip (see `gain_at()`).
+    ///
+    /// This is meant for drawing the gain as an overlay on the waveform.
+    pub fn gain_envelope(
+        &self,
+        clip_length: MusicalTime,
+        bpm: f64,
+        auto_fade: &AutoFade,
+        joins: ClipJoins,
+        resolution: usize,
+    ) -> Vec<f32> {
+        let length_beats = clip_length.as_beats_f64();
+        let step = if resolution > 1 { length_beats / (resolution - 1) as f64 } else { 0.0 };
+
+        (0..resolution)
+            .map(|i| self.gain_at_beats(i as f64 * step, length_beats, bpm, auto_fade, joins))
+            .collect()
+    }
+}
+
+/// A warp marker that pins a position in a clip's audio file to a musical
+/// time.
+#[derive(Debug, Lens, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub struct WarpMarker {
+    /// The position in the audio file.
+    pub source: WSuperFrames,
+    /// The musical time relative to the start of the clip.
+    pub time: WMusicalTime,
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+/// A single breakpoint in a clip's gain envelope.
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
+pub struct GainEnvelopePoint {
+    /// The position of this point relative to the start of the clip.
+    ///
+    /// This is in musical time so the envelope follows tempo changes.
+    pub time: WMusicalTime,
+
+    /// The gain at this point in decibels.
+    pub gain_db: f32,
+
+    /// The curve of the segment from this point to the next one, where 0.0
+    /// is linear. Positive values bend the curve so that most of the change
+    /// happens near the next point, and negative values so that it happens
+    /// near this point.
+    pub tension: f32,
+}
+
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct PianoRollClipState {
-    // TODO
+    /// The notes of this clip, sorted by start time.
+    #[serde(default)]
+    pub notes: Vec<NoteState>,
+}
+
+/// A single note in a piano roll clip.
+#[derive(Debug, Lens, Clone, PartialEq, Data, Serialize, Deserialize)]
+pub struct NoteState {
+    /// The start of this note relative to the start of the clip.
+    pub start: WMusicalTime,
+    pub length: WMusicalTime,
+    /// The MIDI note number (0-127).
+    pub pitch: u8,
+    /// The MIDI velocity (1-127).
+    pub velocity: u8,
+    /// The MIDI channel (0-15).
+    pub channel: u8,
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct AutomationClipState {
     // TODO
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum ClipStart {
     OnLane(OnLane),
     /// This means that the clip is not currently on the timeline,
@@ -51,8 +929,8 @@ pub enum ClipStart {
     NotInTimeline,
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct OnLane {
-    lane_index: u32,
-    timeline_start: WMusicalTime,
+    pub lane_index: u32,
+    pub timeline_start: WMusicalTime,
 }
@@ -0,0 +1,77 @@
+use super::ClipState;
+
+/// A clip edit in progress, i.e. while the edge of a clip, a clip, or a gain
+/// handle is being dragged.
+///
+/// The clips are changed in `UiState::clips` (and sent to the engine) as the
+/// drag goes on, so the edit can be heard before it is done. The edit keeps a
+/// copy of the clips from before the drag, which is what gets saved until the
+/// edit is committed, and what the clips are restored to if it is cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct ClipEdit {
+    /// The indices (into `UiState::clips`) of the edited clips along with their
+    /// state from before the edit.
+    originals: Vec<(usize, ClipState)>,
+    /// The number of clips when the edit started. If clips are added or removed
+    /// during the edit, the indices are no longer valid, so the edit can't be
+    /// reverted and is kept as it is.
+    num_clips: usize,
+}
+
+impl ClipEdit {
+    /// Starts an edit of the clips at `indices`. Indices that don't point to a
+    /// clip are skipped.
+    pub fn new(clips: &[ClipState], indices: &[usize]) -> Self {
+        let mut originals: Vec<(usize, ClipState)> = Vec::with_capacity(indices.len());
+        for index in indices.iter() {
+            if originals.iter().any(|(i, _)| i == index) {
+                continue;
+            }
+            if let Some(clip) = clips.get(*index) {
+                originals.push((*index, clip.clone()));
+            }
+        }
+        Self { originals, num_clips: clips.len() }
+    }
+
+    /// Returns `true` if the clip at `index` is part of this edit.
+    pub fn contains(&self, index: usize) -> bool {
+        self.originals.iter().any(|(i, _)| *i == index)
+    }
+
+    /// The indices of the edited clips.
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.originals.iter().map(|(index, _)| *index)
+    }
+
+    /// Returns a copy of `clips` with the edited clips in their state from
+    /// before the edit.
+    pub fn committed_clips(&self, clips: &[ClipState]) -> Vec<ClipState> {
+        let mut clips = clips.to_vec();
+        if clips.len() != self.num_clips {
+            return clips;
+        }
+        for (index, original) in self.originals.iter() {
+            if let Some(clip) = clips.get_mut(*index) {
+                *clip = original.clone();
+            }
+        }
+        clips
+    }
+
+    /// Restores the edited clips in `clips` to their state from before the
+    /// edit. Returns the indices of the restored clips.
+    pub fn revert(self, clips: &mut [ClipState]) -> Vec<usize> {
+        let mut restored = Vec::with_capacity(self.originals.len());
+        if clips.len() != self.num_clips {
+            return restored;
+        }
+        for (index, original) in self.originals.into_iter() {
+            if let Some(clip) = clips.get_mut(index) {
+                *clip = original;
+                restored.push(index);
+            }
+        }
+        restored
+    }
+}
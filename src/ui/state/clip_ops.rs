@@ -0,0 +1,1235 @@
+//! Edits of the clips on the timeline, and the order they are played in.
+
+use meadowlark_core_types::time::{MusicalTime, SampleRate, Seconds, SuperFrames};
+use std::ops::Range;
+
+use super::{
+    AudioClipState, ChannelBaseColor, ClipChannelMode, ClipEdit, ClipJoins, ClipStart, ClipState,
+    ClipType, CompClipState, FadeCurve, InterpolationQuality, MultichannelMode, OverlapPolicy,
+    StateChange, UiState, MAX_PROJECT_LENGTH_BEATS,
+};
+
+impl UiState {
+    /// Returns the indices of the selected clips, in the order they were
+    /// selected.
+    pub fn selected_clips(&self) -> Vec<usize> {
+        self.clip_selection.indices(&self.clips)
+    }
+
+    /// Removes all selected clips.
+    pub fn delete_selected_clips(&mut self) {
+        let selected = self.selected_clips();
+        self.delete_clips(&selected);
+        self.clip_selection.clear();
+    }
+
+    /// Removes the clips at `indices` (the indices from before any clip is
+    /// removed).
+    ///
+    /// Returns the indices that didn't point to a clip. These are skipped, and
+    /// the rest of the clips are still removed.
+    pub fn delete_clips(&mut self, indices: &[usize]) -> Vec<usize> {
+        if indices.iter().any(|index| *index < self.clips.len()) {
+            self.record_undo("Delete clips");
+        }
+        self.remove_clips(indices, true)
+    }
+
+    /// Removes the clips at `indices` without recording an undo entry (see
+    /// `delete_clips()`). If `release_crossfades` is true, the crossfades of the
+    /// neighbors with the removed clips are turned back into ordinary fades.
+    pub(super) fn remove_clips(
+        &mut self,
+        indices: &[usize],
+        release_crossfades: bool,
+    ) -> Vec<usize> {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        let (indices, missing): (Vec<usize>, Vec<usize>) =
+            indices.into_iter().partition(|index| *index < self.clips.len());
+
+        for index in indices.into_iter().rev() {
+            if release_crossfades {
+                self.release_crossfades(index);
+            }
+            let clip = self.clips.remove(index);
+            self.clip_selection.deselect(clip.id);
+            self.changes.push(StateChange::ClipRemoved { index });
+        }
+
+        // TODO: Remove the clips from the engine in the same process swap as the
+        // changed fades. If the playhead is inside a removed clip while it plays,
+        // fade the clip out like a seek instead of cutting it off.
+
+        missing
+    }
+
+    /// Turns the crossfades between the audio clip at `index` and its
+    /// neighbors back into ordinary fades, before the clip is removed.
+    ///
+    /// A neighbor that ends inside the clip, with a fade-out that fits into
+    /// the overlap, was crossfaded with it, so its fade-out is cleared (leaving
+    /// only the automatic fade). The same goes for the fade-in of a neighbor
+    /// that starts inside the clip. Otherwise the neighbor would keep fading
+    /// into silence where the clip used to be.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    fn release_crossfades(&mut self, index: usize) {
+        let (lane, start, end) = match self.clips.get(index).and_then(|c| c.lane_range_beats()) {
+            Some(range) => range,
+            None => return,
+        };
+        let secs_per_beat = 60.0 / self.timeline_grid.bpm;
+
+        for other in 0..self.clips.len() {
+            if other == index {
+                continue;
+            }
+            let (other_lane, other_start, other_end) = match self.clips[other].lane_range_beats() {
+                Some(range) => range,
+                None => continue,
+            };
+            if other_lane != lane {
+                continue;
+            }
+
+            let audio_clip = match &mut self.clips[other].type_ {
+                ClipType::Audio(audio_clip) => audio_clip,
+                _ => continue,
+            };
+
+            let mut changed = false;
+            if other_start < start && other_end > start && other_end <= end {
+                let overlap_secs = (other_end - start) * secs_per_beat;
+                let fade_secs = audio_clip.fade_out_secs.get().0;
+                if fade_secs > 0.0 && fade_secs <= overlap_secs + 1e-9 {
+                    audio_clip.fade_out_secs = Seconds(0.0).into();
+                    audio_clip.fade_out_curve = FadeCurve::default();
+                    changed = true;
+                }
+            }
+            if other_start >= start && other_start < end && other_end >= end {
+                let overlap_secs = (end - other_start) * secs_per_beat;
+                let fade_secs = audio_clip.fade_in_secs.get().0;
+                if fade_secs > 0.0 && fade_secs <= overlap_secs + 1e-9 {
+                    audio_clip.fade_in_secs = Seconds(0.0).into();
+                    audio_clip.fade_in_curve = FadeCurve::default();
+                    changed = true;
+                }
+            }
+
+            if changed {
+                self.changes.push(StateChange::ClipChanged { index: other });
+            }
+        }
+    }
+
+    /// Adds `take` as a new take to the clip at `index` and returns the index of
+    /// the take.
+    ///
+    /// If the clip is an ordinary audio clip, it is first turned into a comp
+    /// with its audio as the first take.
+    pub fn add_take(&mut self, index: usize, take: AudioClipState) -> Option<usize> {
+        let clip = self.clips.get_mut(index)?;
+
+        if let ClipType::Audio(audio_clip) = &clip.type_ {
+            clip.type_ = ClipType::Comp(CompClipState::new(audio_clip.clone(), clip.length.get()));
+        }
+
+        let take_index = match &mut clip.type_ {
+            ClipType::Comp(comp) => comp.add_take(take),
+            _ => return None,
+        };
+
+        self.changes.push(StateChange::ClipChanged { index });
+        Some(take_index)
+    }
+
+    /// Makes the next take of the take folder at `index` heard over the whole
+    /// clip (see `CompClipState::cycle_take()`).
+    pub fn cycle_take(&mut self, index: usize) {
+        if !matches!(self.clips.get(index).map(|c| &c.type_), Some(ClipType::Comp(_))) {
+            return;
+        }
+        self.record_undo("Cycle take");
+        let clip = &mut self.clips[index];
+        if let ClipType::Comp(comp) = &mut clip.type_ {
+            comp.cycle_take(clip.length.get());
+            self.changes.push(StateChange::ClipChanged { index });
+        }
+    }
+
+    /// Makes the take at index `take` heard in `range` (relative to the start
+    /// of the clip) of the comp clip at `index`.
+    pub fn set_comp_region(&mut self, index: usize, range: Range<MusicalTime>, take: usize) {
+        if let Some(ClipType::Comp(comp)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            comp.set_region(range, take);
+            self.changes.push(StateChange::ClipChanged { index });
+        }
+    }
+
+    /// Replaces the comp clip at `index` with one ordinary audio clip per comp
+    /// region.
+    ///
+    /// Returns the indices of the new clips.
+    pub fn flatten_comp(&mut self, index: usize) -> Vec<usize> {
+        let new_clips = match self.clips.get(index) {
+            Some(clip) => match &clip.type_ {
+                ClipType::Comp(comp) => comp.flatten(clip, self.timeline_grid.bpm),
+                _ => return Vec::new(),
+            },
+            None => return Vec::new(),
+        };
+
+        let clip = self.clips.remove(index);
+        self.clip_selection.deselect(clip.id);
+        self.changes.push(StateChange::ClipRemoved { index });
+
+        let mut new_indices = Vec::with_capacity(new_clips.len());
+        for clip in new_clips {
+            new_indices.push(self.add_clip(clip));
+        }
+
+        new_indices
+    }
+
+    /// Moves all selected clips on the timeline later by `delta` (see
+    /// `move_clips_later()`).
+    pub fn nudge_selected_clips_later(&mut self, delta: MusicalTime) {
+        let selected = self.selected_clips();
+        self.move_clips_later(&selected, delta);
+    }
+
+    /// Moves all selected clips on the timeline earlier by `delta` (see
+    /// `move_clips_earlier()`).
+    pub fn nudge_selected_clips_earlier(&mut self, delta: MusicalTime) {
+        let selected = self.selected_clips();
+        self.move_clips_earlier(&selected, delta);
+    }
+
+    /// Moves the clips at `indices` on the timeline later by `delta`.
+    ///
+    /// If this would move any clip past `MAX_PROJECT_LENGTH_BEATS`, then `delta`
+    /// is clamped for all of the clips so that they keep their relative
+    /// positions.
+    ///
+    /// Returns the indices that didn't point to a clip. These are skipped, and
+    /// the rest of the clips are still moved.
+    pub fn move_clips_later(&mut self, indices: &[usize], delta: MusicalTime) -> Vec<usize> {
+        let max_end = MusicalTime::from_beats(MAX_PROJECT_LENGTH_BEATS);
+        let mut delta = delta;
+        for index in indices.iter() {
+            if let Some(clip) = self.clips.get(*index) {
+                if let ClipStart::OnLane(on_lane) = &clip.timeline_start {
+                    let end = on_lane.timeline_start.get() + clip.length.get();
+                    if end >= max_end {
+                        delta = MusicalTime::from_beats(0);
+                    } else if max_end - end < delta {
+                        delta = max_end - end;
+                    }
+                }
+            }
+        }
+
+        self.move_clips(indices, |start| start + delta)
+    }
+
+    /// Moves the clips at `indices` on the timeline earlier by `delta`.
+    ///
+    /// If this would move any clip before the start of the timeline, then
+    /// `delta` is clamped for all of the clips so that they keep their relative
+    /// positions.
+    ///
+    /// Returns the indices that didn't point to a clip. These are skipped, and
+    /// the rest of the clips are still moved.
+    pub fn move_clips_earlier(&mut self, indices: &[usize], delta: MusicalTime) -> Vec<usize> {
+        let mut delta = delta;
+        for index in indices.iter() {
+            if let Some(ClipStart::OnLane(on_lane)) =
+                self.clips.get(*index).map(|clip| &clip.timeline_start)
+            {
+                let start = on_lane.timeline_start.get();
+                if start < delta {
+                    delta = start;
+                }
+            }
+        }
+
+        self.move_clips(indices, |start| start - delta)
+    }
+
+    /// Moves the clips at `indices` as one undo entry.
+    fn move_clips(
+        &mut self,
+        indices: &[usize],
+        new_start: impl Fn(MusicalTime) -> MusicalTime,
+    ) -> Vec<usize> {
+        let before = self.to_project();
+        let mut moved = false;
+        let mut missing = Vec::new();
+        for index in indices.iter() {
+            match self.clips.get_mut(*index).map(|clip| &mut clip.timeline_start) {
+                Some(ClipStart::OnLane(on_lane)) => {
+                    let start = new_start(on_lane.timeline_start.get());
+                    if start != on_lane.timeline_start.get() {
+                        on_lane.timeline_start = start.into();
+                        self.changes.push(StateChange::ClipMoved { index: *index });
+                        moved = true;
+                    }
+                }
+                Some(ClipStart::NotInTimeline) => {}
+                None => missing.push(*index),
+            }
+        }
+
+        if moved {
+            self.undo_history.push("Move clips", before);
+        }
+        missing
+    }
+
+    /// Copies the selected clips so that the copies start right after the end of
+    /// the selection (see `repeat_selected_clips()`).
+    pub fn duplicate_selected_clips_in_place(&mut self) {
+        let before = self.to_project();
+        if self.tile_selected_clips(1) {
+            self.undo_history.push("Duplicate clips", before);
+        }
+    }
+
+    /// Tiles the selected clips `times` times after the end of the selection.
+    ///
+    /// Each repetition is shifted by the span of the selection (from the start of
+    /// the earliest selected clip to the end of the latest one). Copies stay on
+    /// the lane of their original, so a selection across several lanes keeps its
+    /// layout. Overlaps with existing clips are resolved with
+    /// `self.overlap_policy`, and copies that would end past
+    /// `MAX_PROJECT_LENGTH_BEATS` are skipped.
+    ///
+    /// Afterwards the last repetition is selected. This is undone in one step.
+    pub fn repeat_selected_clips(&mut self, times: usize) {
+        let before = self.to_project();
+        if self.tile_selected_clips(times) {
+            self.undo_history.push("Repeat clips", before);
+        }
+    }
+
+    /// Does the work of `repeat_selected_clips()`. Returns `false` if no copy
+    /// was added.
+    fn tile_selected_clips(&mut self, times: usize) -> bool {
+        let mut selected: Vec<(usize, MusicalTime, MusicalTime)> = Vec::new();
+        for index in self.selected_clips() {
+            if let Some(clip) = self.clips.get(index) {
+                if let ClipStart::OnLane(on_lane) = &clip.timeline_start {
+                    let start = on_lane.timeline_start.get();
+                    selected.push((index, start, start + clip.length.get()));
+                }
+            }
+        }
+
+        let first_start = match selected.first() {
+            Some((_, start, _)) => *start,
+            None => return false,
+        };
+        let span_start = selected.iter().map(|(_, start, _)| *start).fold(first_start, |a, b| {
+            if b < a {
+                b
+            } else {
+                a
+            }
+        });
+        let span_end =
+            selected
+                .iter()
+                .map(|(_, _, end)| *end)
+                .fold(span_start, |a, b| if b > a { b } else { a });
+        let span = span_end - span_start;
+        if span == MusicalTime::from_beats(0) {
+            return false;
+        }
+
+        let max_end = MusicalTime::from_beats(MAX_PROJECT_LENGTH_BEATS);
+        let policy = self.overlap_policy;
+        let bpm = self.timeline_grid.bpm;
+
+        let mut offset = MusicalTime::from_beats(0);
+        let mut last_copies = Vec::new();
+        let mut added = false;
+        for _ in 0..times {
+            offset = offset + span;
+            last_copies.clear();
+
+            for (index, start, end) in selected.iter() {
+                if *end + offset > max_end {
+                    continue;
+                }
+
+                let mut copy = self.clips[*index].clone();
+                if let ClipStart::OnLane(on_lane) = &mut copy.timeline_start {
+                    on_lane.timeline_start = (*start + offset).into();
+                }
+
+                let copy_index = self.add_clip(copy);
+                last_copies.push(copy_index);
+                added = true;
+
+                self.resolve_clip_overlap(copy_index, policy, bpm);
+
+                // Let the existing clips that start inside of the copy resolve
+                // their overlap with it too (i.e. so that the copy is trimmed).
+                if let Some((lane, copy_start, copy_end)) =
+                    self.clips[copy_index].lane_range_beats()
+                {
+                    let later_clips: Vec<usize> = (0..copy_index)
+                        .filter(|i| match self.clips[*i].lane_range_beats() {
+                            Some((other_lane, other_start, _)) => {
+                                other_lane == lane
+                                    && other_start > copy_start
+                                    && other_start < copy_end
+                            }
+                            None => false,
+                        })
+                        .collect();
+                    for later in later_clips {
+                        self.resolve_clip_overlap(later, policy, bpm);
+                    }
+                }
+            }
+        }
+
+        if !last_copies.is_empty() {
+            self.clip_selection.clear();
+            for index in last_copies {
+                self.clip_selection.select(self.clips[index].id);
+            }
+        }
+
+        added
+    }
+
+    /// Sets the amount the audio clip at `index` is repitched by in semitones.
+    ///
+    /// While the transport is playing, the timeline player crossfades the clip
+    /// to its new playback rate, so this can be called on every step of a drag.
+    pub fn set_clip_pitch_semitones(&mut self, index: usize, semitones: f32) {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            audio_clip.set_pitch_semitones(semitones);
+            self.changes.push(StateChange::ClipChanged { index });
+        }
+    }
+
+    /// Sets whether the polarity of the audio clip at `index` is inverted.
+    ///
+    /// While the transport is playing, the timeline player ramps the gain of
+    /// the clip through zero instead of flipping it at once.
+    pub fn set_clip_invert_polarity(&mut self, index: usize, invert: bool) {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            if audio_clip.invert_polarity != invert {
+                audio_clip.invert_polarity = invert;
+                self.changes.push(StateChange::ClipChanged { index });
+            }
+        }
+    }
+
+    /// Sets how the audio clip at `index` is played if its audio file has more
+    /// than two channels.
+    pub fn set_clip_multichannel_mode(&mut self, index: usize, mode: MultichannelMode) {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            if audio_clip.multichannel_mode != mode {
+                audio_clip.multichannel_mode = mode;
+                self.changes.push(StateChange::ClipChanged { index });
+            }
+        }
+    }
+
+    /// Sets the interpolation quality of the audio clip at `index`, or makes it
+    /// follow the project's setting if `quality` is `None`.
+    pub fn set_clip_interpolation(&mut self, index: usize, quality: Option<InterpolationQuality>) {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            if audio_clip.interpolation != quality {
+                audio_clip.interpolation = quality;
+                self.changes.push(StateChange::ClipChanged { index });
+            }
+        }
+    }
+
+    /// Returns the interpolation quality the audio clip at `index` is played
+    /// with, either live (`for_export == false`) or when the project is
+    /// exported. Returns `None` if there is no audio clip at `index`.
+    pub fn clip_interpolation(
+        &self,
+        index: usize,
+        for_export: bool,
+    ) -> Option<InterpolationQuality> {
+        match self.clips.get(index).map(|c| &c.type_) {
+            Some(ClipType::Audio(audio_clip)) => {
+                Some(audio_clip.interpolation.unwrap_or(if for_export {
+                    self.interpolation.export
+                } else {
+                    self.interpolation.playback
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Sets whether the audio clip at `index` plays as mono or stereo.
+    pub fn set_clip_channel_mode(&mut self, index: usize, mode: ClipChannelMode) {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            if audio_clip.channel_mode != mode {
+                audio_clip.channel_mode = mode;
+                self.changes.push(StateChange::ClipChanged { index });
+            }
+        }
+
+        // TODO: Send the new mode to the engine, so the timeline track reads the
+        // clip into its buffer with the same conversion as `ClipChannelMode::apply()`.
+    }
+
+    /// Sets the left and right gain trims of the audio clip at `index` in
+    /// decibels.
+    ///
+    /// While the transport is playing, the timeline player ramps each channel
+    /// to its new trim on its own.
+    pub fn set_clip_channel_gain_db(&mut self, index: usize, gain_l_db: f32, gain_r_db: f32) {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            audio_clip.gain_l_db = gain_l_db;
+            audio_clip.gain_r_db = gain_r_db;
+            self.changes.push(StateChange::ClipChanged { index });
+        }
+    }
+
+    /// Adds `clip` on top of all other clips and gives it a new id. Returns the
+    /// index of the new clip.
+    pub fn add_clip(&mut self, mut clip: ClipState) -> usize {
+        clip.id = self.ids.clip_id();
+        clip.z_order = self.next_clip_z_order();
+        let index = self.clips.len();
+        self.clips.push(clip);
+        self.changes.push(StateChange::ClipAdded { index });
+        index
+    }
+
+    fn next_clip_z_order(&self) -> u64 {
+        self.clips.iter().map(|clip| clip.z_order + 1).max().unwrap_or(0)
+    }
+
+    /// Puts the clip at `index` on top of the clips it overlaps, and resolves
+    /// the overlaps with the current overlap policy so that it wins over them.
+    pub fn bring_clip_to_front(&mut self, index: usize) {
+        let z_order = self.next_clip_z_order();
+        if let Some(clip) = self.clips.get_mut(index) {
+            clip.z_order = z_order;
+            self.changes.push(StateChange::ClipChanged { index });
+
+            let bpm = self.timeline_grid.bpm;
+            self.resolve_clip_overlap(index, self.overlap_policy, bpm);
+        }
+    }
+
+    /// Returns the indices of the clips on the timeline in the order the engine
+    /// should process them: sorted by start, then by stacking order (so the
+    /// clip on top comes last). This doesn't depend on the order of
+    /// `UiState::clips`, so a project renders the same after it is saved and
+    /// loaded again.
+    pub fn clips_in_render_order(&self) -> Vec<usize> {
+        let mut indices: Vec<(usize, MusicalTime)> = self
+            .clips
+            .iter()
+            .enumerate()
+            .filter_map(|(index, clip)| match &clip.timeline_start {
+                ClipStart::OnLane(on_lane) => Some((index, on_lane.timeline_start.get())),
+                ClipStart::NotInTimeline => None,
+            })
+            .collect();
+        indices.sort_by(|(a, a_start), (b, b_start)| {
+            a_start
+                .partial_cmp(b_start)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(self.clips[*a].z_order.cmp(&self.clips[*b].z_order))
+                .then(a.cmp(b))
+        });
+        indices.into_iter().map(|(index, _)| index).collect()
+    }
+
+    /// Returns the clips at `indices` that are on the timeline, in render order
+    /// (see `clips_in_render_order()`).
+    pub fn in_render_order(&self, indices: &[usize]) -> Vec<usize> {
+        self.clips_in_render_order().into_iter().filter(|index| indices.contains(index)).collect()
+    }
+
+    /// Returns the edges of the clips at `indices` that join onto another one
+    /// of them (see `ClipJoins`), in the same order as `indices`.
+    ///
+    /// Two audio clips join if one starts on the same lane and channel right
+    /// where the other ends, and its audio continues the audio of the other
+    /// (i.e. they are the two pieces of a split clip).
+    pub fn clip_joins(&self, indices: &[usize]) -> Vec<ClipJoins> {
+        let mut joins = vec![ClipJoins::default(); indices.len()];
+
+        let mut edges: Vec<(usize, u32, f64, f64)> = indices
+            .iter()
+            .enumerate()
+            .filter_map(|(i, index)| {
+                let clip = self.clips.get(*index)?;
+                let (lane, start, end) = clip.lane_range_beats()?;
+                matches!(&clip.type_, ClipType::Audio(_)).then(|| (i, lane, start, end))
+            })
+            .collect();
+        edges.sort_by(|a, b| {
+            (a.1, self.clips[indices[a.0]].channel)
+                .cmp(&(b.1, self.clips[indices[b.0]].channel))
+                .then(a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal))
+        });
+
+        let bpm = self.timeline_grid.bpm;
+        for (a, b) in edges.iter().zip(edges.iter().skip(1)) {
+            let (clip_a, clip_b) = (&self.clips[indices[a.0]], &self.clips[indices[b.0]]);
+            if a.1 != b.1 || clip_a.channel != clip_b.channel || (a.3 - b.2).abs() > 1e-9 {
+                continue;
+            }
+            let continues = match (&clip_a.type_, &clip_b.type_) {
+                (ClipType::Audio(audio_a), ClipType::Audio(audio_b)) => {
+                    audio_a.continues_into(clip_a.length.get(), audio_b, bpm)
+                }
+                _ => false,
+            };
+            if continues {
+                joins[a.0].end = true;
+                joins[b.0].start = true;
+            }
+        }
+        joins
+    }
+
+    /// Starts an edit of the clips at `indices`, i.e. when the user starts
+    /// dragging the edge of a clip.
+    ///
+    /// The clips can then be changed as usual and are heard right away, but the
+    /// change is only saved once `commit_clip_edit()` is called.
+    /// `cancel_clip_edit()` restores the clips instead. An edit that is already
+    /// in progress is committed first.
+    pub fn begin_clip_edit(&mut self, indices: &[usize]) {
+        self.commit_clip_edit();
+        self.clip_edit = Some(ClipEdit::new(&self.clips, indices));
+    }
+
+    /// Keeps the changes made since `begin_clip_edit()`.
+    pub fn commit_clip_edit(&mut self) {
+        // While the edit is in progress, the project still has the clips from
+        // before the edit.
+        let before = self.to_project();
+        if self.clip_edit.take().is_some() {
+            self.undo_history.push("Edit clips", before);
+        }
+    }
+
+    /// Restores the clips to their state from before `begin_clip_edit()`.
+    pub fn cancel_clip_edit(&mut self) {
+        if let Some(clip_edit) = self.clip_edit.take() {
+            for index in clip_edit.revert(&mut self.clips) {
+                self.changes.push(StateChange::ClipMoved { index });
+                self.changes.push(StateChange::ClipChanged { index });
+            }
+
+            // TODO: Send the restored clips to the engine.
+        }
+    }
+
+    /// Moves the start of the clip at `index` while keeping its end in place
+    /// (see `ClipState::resize_start()`).
+    pub fn resize_clip_start(&mut self, index: usize, new_start: MusicalTime) {
+        let bpm = self.timeline_grid.bpm;
+        if let Some(clip) = self.clips.get_mut(index) {
+            clip.resize_start(new_start, bpm);
+            self.changes.push(StateChange::ClipMoved { index });
+        }
+
+        // TODO: Send the new clip range to the engine.
+    }
+
+    /// Moves the end of the clip at `index` while keeping its start in place
+    /// (see `ClipState::resize_end()`).
+    ///
+    /// * `source_duration` - The duration of the clip's audio file, if known.
+    pub fn resize_clip_end(
+        &mut self,
+        index: usize,
+        new_end: MusicalTime,
+        source_duration: Option<Seconds>,
+    ) {
+        let bpm = self.timeline_grid.bpm;
+        if let Some(clip) = self.clips.get_mut(index) {
+            clip.resize_end(new_end, bpm, source_duration);
+            self.changes.push(StateChange::ClipMoved { index });
+        }
+
+        // TODO: Send the new clip range to the engine.
+    }
+
+    /// Shifts the audio under the audio clip at `index` by `delta_beats` while
+    /// the clip stays in place (see `AudioClipState::slip()`).
+    ///
+    /// * `source_duration` - The duration of the clip's audio file, if known.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    pub fn slip_clip(
+        &mut self,
+        index: usize,
+        delta_beats: f64,
+        source_duration: Option<Seconds>,
+    ) -> bool {
+        let bpm = self.timeline_grid.bpm;
+        let slipped = match self.clips.get_mut(index) {
+            Some(clip) => {
+                let length_secs = clip.length.get().as_beats_f64() * 60.0 / bpm;
+                match &mut clip.type_ {
+                    ClipType::Audio(audio_clip) => {
+                        audio_clip.slip(delta_beats * 60.0 / bpm, length_secs, source_duration)
+                    }
+                    _ => false,
+                }
+            }
+            None => false,
+        };
+        // `ClipChanged` resyncs the clips of the channel with the timeline
+        // player, which crossfades a playing clip from the old position to the
+        // new one instead of jumping.
+        if slipped {
+            self.changes.push(StateChange::ClipChanged { index });
+        }
+
+        slipped
+    }
+
+    /// Like `slip_clip()`, but with `delta_frames` given in frames at
+    /// `sample_rate`.
+    pub fn slip_clip_frames(
+        &mut self,
+        index: usize,
+        delta_frames: i64,
+        sample_rate: SampleRate,
+        source_duration: Option<Seconds>,
+    ) -> bool {
+        let delta_secs = delta_frames as f64 / sample_rate.0;
+        let delta_beats = delta_secs * self.timeline_grid.bpm / 60.0;
+        self.slip_clip(index, delta_beats, source_duration)
+    }
+
+    /// Sets the name shown for the clip at `index` on the timeline. If `label`
+    /// is `None`, the clip's name is shown instead.
+    pub fn set_clip_label(&mut self, index: usize, label: Option<String>) {
+        if let Some(clip) = self.clips.get_mut(index) {
+            clip.label = label.filter(|label| !label.is_empty());
+            self.changes.push(StateChange::ClipRenamed { index });
+        }
+    }
+
+    /// Sets the notes of the clip at `index`.
+    pub fn set_clip_notes(&mut self, index: usize, notes: String) {
+        if let Some(clip) = self.clips.get_mut(index) {
+            clip.notes = notes;
+            self.changes.push(StateChange::ClipRenamed { index });
+        }
+    }
+
+    /// Sets the color of all selected clips. If `color` is `None`, they use the
+    /// color of their channel.
+    pub fn set_selected_clips_color(&mut self, color: Option<ChannelBaseColor>) {
+        for index in self.selected_clips() {
+            if let Some(clip) = self.clips.get_mut(index) {
+                clip.color = color.clone();
+                self.changes.push(StateChange::ClipChanged { index });
+            }
+        }
+    }
+
+    /// Adds a warp marker to the audio clip at `index` (see
+    /// `AudioClipState::add_warp_marker()`).
+    pub fn add_clip_warp_marker(
+        &mut self,
+        index: usize,
+        source: SuperFrames,
+        time: MusicalTime,
+    ) -> Option<usize> {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            let marker_index = audio_clip.add_warp_marker(source, time)?;
+            self.changes.push(StateChange::ClipChanged { index });
+            Some(marker_index)
+        } else {
+            None
+        }
+    }
+
+    /// Moves a warp marker of the audio clip at `index` (see
+    /// `AudioClipState::move_warp_marker()`).
+    pub fn move_clip_warp_marker(
+        &mut self,
+        index: usize,
+        marker_index: usize,
+        source: SuperFrames,
+        time: MusicalTime,
+    ) -> bool {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            if audio_clip.move_warp_marker(marker_index, source, time) {
+                self.changes.push(StateChange::ClipChanged { index });
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Removes a warp marker from the audio clip at `index`.
+    pub fn remove_clip_warp_marker(&mut self, index: usize, marker_index: usize) {
+        if let Some(ClipType::Audio(audio_clip)) = self.clips.get_mut(index).map(|c| &mut c.type_) {
+            if audio_clip.remove_warp_marker(marker_index).is_some() {
+                self.changes.push(StateChange::ClipChanged { index });
+            }
+        }
+    }
+
+    /// Sets the gain of all selected audio clips.
+    pub fn set_selected_clips_gain_db(&mut self, gain_db: f32) {
+        let selected = self.selected_clips();
+        self.set_clips_gain_db(&selected, gain_db);
+    }
+
+    /// Sets the gain of the audio clips at `indices`. Clips that are not audio
+    /// clips are left as they are.
+    ///
+    /// Returns the indices that didn't point to a clip. These are skipped, and
+    /// the rest of the clips are still changed.
+    pub fn set_clips_gain_db(&mut self, indices: &[usize], gain_db: f32) -> Vec<usize> {
+        let before = self.to_project();
+        let mut changed = false;
+        let mut missing = Vec::new();
+        for index in indices.iter() {
+            match self.clips.get_mut(*index).map(|clip| &mut clip.type_) {
+                Some(ClipType::Audio(audio_clip)) => {
+                    audio_clip.gain_db = gain_db;
+                    self.changes.push(StateChange::ClipChanged { index: *index });
+                    changed = true;
+                }
+                Some(_) => {}
+                None => missing.push(*index),
+            }
+        }
+
+        if changed {
+            self.undo_history.push("Change clip gain", before);
+        }
+        missing
+    }
+
+    /// Resolves any overlaps between the clip at index `moved` and the other clips
+    /// on its lane according to `policy`.
+    ///
+    /// This should be called after a clip is moved.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    pub fn resolve_clip_overlap(&mut self, moved: usize, policy: OverlapPolicy, bpm: f64) {
+        let (lane, start, end) = match self.clips.get(moved).and_then(|c| c.lane_range_beats()) {
+            Some(range) => range,
+            None => return,
+        };
+
+        for index in 0..self.clips.len() {
+            if index == moved {
+                continue;
+            }
+
+            let (other_lane, other_start, other_end) = match self.clips[index].lane_range_beats() {
+                Some(range) => range,
+                None => continue,
+            };
+            if other_lane != lane || other_end <= start || other_start >= end {
+                continue;
+            }
+
+            match policy {
+                OverlapPolicy::Crossfade { max_len } => {
+                    let (first, second, overlap) = if other_start < start {
+                        (index, moved, other_end.min(end) - start)
+                    } else {
+                        (moved, index, end.min(other_end) - other_start)
+                    };
+                    let fade_secs = Seconds((overlap * 60.0 / bpm).min(max_len.0));
+
+                    if let ClipType::Audio(audio_clip) = &mut self.clips[first].type_ {
+                        audio_clip.fade_out_secs = fade_secs.into();
+                    }
+                    if let ClipType::Audio(audio_clip) = &mut self.clips[second].type_ {
+                        audio_clip.fade_in_secs = fade_secs.into();
+                    }
+                    self.changes.push(StateChange::ClipChanged { index: first });
+                    self.changes.push(StateChange::ClipChanged { index: second });
+                }
+                OverlapPolicy::TrimOverlapped => {
+                    if other_start < start {
+                        self.clips[index].resize_end(MusicalTime::from_beats_f64(start), bpm, None);
+                        self.changes.push(StateChange::ClipChanged { index });
+                    }
+                }
+                OverlapPolicy::AllowOverlap => {}
+            }
+        }
+    }
+
+    /// Sets the length and shape of the crossfade between the audio clip at index
+    /// `first` and the audio clip at index `second` that starts after it.
+    ///
+    /// The length is clamped to the length of the overlap between the two clips.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    pub fn set_crossfade(
+        &mut self,
+        first: usize,
+        second: usize,
+        len: Seconds,
+        curve: FadeCurve,
+        bpm: f64,
+    ) {
+        let (first_range, second_range) = match (
+            self.clips.get(first).and_then(|c| c.lane_range_beats()),
+            self.clips.get(second).and_then(|c| c.lane_range_beats()),
+        ) {
+            (Some(first_range), Some(second_range)) => (first_range, second_range),
+            _ => return,
+        };
+
+        let overlap_secs = (first_range.2.min(second_range.2) - second_range.1) * 60.0 / bpm;
+        if first_range.0 != second_range.0 || overlap_secs <= 0.0 {
+            return;
+        }
+        let fade_secs = Seconds(len.0.clamp(0.0, overlap_secs));
+
+        if let ClipType::Audio(audio_clip) = &mut self.clips[first].type_ {
+            audio_clip.fade_out_secs = fade_secs.into();
+            audio_clip.fade_out_curve = curve;
+        }
+        if let ClipType::Audio(audio_clip) = &mut self.clips[second].type_ {
+            audio_clip.fade_in_secs = fade_secs.into();
+            audio_clip.fade_in_curve = curve;
+        }
+        self.changes.push(StateChange::ClipChanged { index: first });
+        self.changes.push(StateChange::ClipChanged { index: second });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::timeline::ClipSource;
+    use crate::ui::app_config::LayoutConfig;
+    use crate::ui::state::test_clips::{clip, EXTREME_BEATS};
+    use crate::ui::state::{AudioClipPlayback, ClipId, ProjectState};
+    use std::path::PathBuf;
+
+    /// A new project with `clips`, all of them selected.
+    fn state_with_selected_clips(clips: Vec<ClipState>) -> UiState {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        for clip in clips {
+            let index = state.add_clip(clip);
+            state.clip_selection.select(state.clips[index].id);
+        }
+        state
+    }
+
+    fn starts(state: &UiState) -> Vec<Option<f64>> {
+        state.clips.iter().map(|clip| clip.lane_range_beats().map(|(_, start, _)| start)).collect()
+    }
+
+    #[test]
+    fn nudging_before_zero_clamps_the_whole_selection() {
+        let mut state = state_with_selected_clips(vec![clip(0, 4, 2), clip(1, 1, 2)]);
+
+        state.nudge_selected_clips_earlier(MusicalTime::from_beats(2));
+
+        // The second clip stops at zero, and the first one keeps its distance.
+        assert_eq!(starts(&state), vec![Some(3.0), Some(0.0)]);
+
+        state.nudge_selected_clips_earlier(MusicalTime::from_beats(1));
+        assert_eq!(starts(&state), vec![Some(3.0), Some(0.0)]);
+    }
+
+    #[test]
+    fn nudging_past_the_end_clamps_the_whole_selection() {
+        let max = MAX_PROJECT_LENGTH_BEATS;
+        let mut state = state_with_selected_clips(vec![clip(0, max - 3, 2), clip(0, 0, 2)]);
+
+        state.nudge_selected_clips_later(MusicalTime::from_beats(4));
+
+        assert_eq!(starts(&state), vec![Some(f64::from(max - 2)), Some(1.0)]);
+    }
+
+    #[test]
+    fn nudges_never_move_clips_past_the_end() {
+        let max = f64::from(MAX_PROJECT_LENGTH_BEATS);
+        for start in EXTREME_BEATS {
+            for delta in EXTREME_BEATS {
+                let start = start.min(MAX_PROJECT_LENGTH_BEATS - 2);
+                let mut state = state_with_selected_clips(vec![clip(0, start, 2)]);
+                state.nudge_selected_clips_later(MusicalTime::from_beats(delta));
+
+                let (_, start_beats, end_beats) = state.clips[0].lane_range_beats().unwrap();
+                assert!(end_beats <= max, "{} + {} -> {}", start, delta, end_beats);
+                assert_eq!(end_beats - start_beats, 2.0);
+            }
+        }
+    }
+
+    #[test]
+    fn bulk_edits_of_the_selection_are_one_undo_entry_each() {
+        let mut state =
+            state_with_selected_clips(vec![clip(0, 0, 2), clip(1, 4, 2), clip(2, 8, 2)]);
+
+        state.nudge_selected_clips_later(MusicalTime::from_beats(1));
+        state.set_selected_clips_gain_db(-6.0);
+        state.delete_selected_clips();
+        assert!(state.clips.is_empty());
+
+        assert!(state.undo());
+        assert_eq!(state.clips.len(), 3);
+        assert!(state.clips.iter().all(|clip| match &clip.type_ {
+            ClipType::Audio(audio_clip) => audio_clip.gain_db == -6.0,
+            _ => false,
+        }));
+
+        assert!(state.undo());
+        assert!(state.undo());
+        assert_eq!(starts(&state), vec![Some(0.0), Some(4.0), Some(8.0)]);
+        assert!(!state.undo());
+
+        assert!(state.redo());
+        assert_eq!(starts(&state), vec![Some(1.0), Some(5.0), Some(9.0)]);
+    }
+
+    #[test]
+    fn duplicating_and_repeating_are_one_undo_entry_each() {
+        // Two bars of 4/4 with three clips across two lanes.
+        let mut state =
+            state_with_selected_clips(vec![clip(0, 0, 2), clip(1, 3, 2), clip(0, 6, 2)]);
+
+        state.duplicate_selected_clips_in_place();
+        assert_eq!(state.clips.len(), 6);
+        assert_eq!(&starts(&state)[3..], &[Some(8.0), Some(11.0), Some(14.0)]);
+
+        state.repeat_selected_clips(3);
+        assert_eq!(state.clips.len(), 15);
+
+        assert!(state.undo());
+        assert_eq!(state.clips.len(), 6);
+        assert!(state.undo());
+        assert_eq!(state.clips.len(), 3);
+        assert_eq!(starts(&state), vec![Some(0.0), Some(3.0), Some(6.0)]);
+        assert!(!state.undo());
+
+        assert!(state.redo());
+        assert_eq!(state.clips.len(), 6);
+    }
+
+    #[test]
+    fn the_selection_keeps_its_clips_through_undo_and_redo() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let first = state.add_clip(clip(0, 0, 2));
+        let second = state.add_clip(clip(0, 4, 2));
+        let third = state.add_clip(clip(0, 8, 2));
+        let (first_id, third_id) = (state.clips[first].id, state.clips[third].id);
+        state.clip_selection.select(third_id);
+
+        // Removing the first clip moves the selected one to another index...
+        state.delete_clips(&[first]);
+        assert_eq!(state.selected_clips(), vec![second]);
+        assert_eq!(state.clips[second].id, third_id);
+
+        // ...and undoing it moves the selected one back.
+        assert!(state.undo());
+        assert_eq!(state.clips[first].id, first_id);
+        assert_eq!(state.selected_clips(), vec![third]);
+
+        // Clips that are gone after a redo are unselected.
+        state.clip_selection.select(first_id);
+        assert!(state.redo());
+        assert_eq!(state.clip_selection.clips, vec![third_id]);
+        assert_eq!(state.selected_clips(), vec![second]);
+    }
+
+    #[test]
+    fn a_take_folder_plays_only_its_active_take() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let mut folder = clip(0, 0, 4);
+        let pass = |n: usize| AudioClipState::new(PathBuf::from(format!("pass{}.wav", n)));
+        let mut comp = CompClipState::new(pass(1), folder.length.get());
+        comp.add_take(pass(2));
+        comp.add_take(pass(3));
+        comp.set_region(MusicalTime::from_beats(0)..MusicalTime::from_beats(4), 2);
+        folder.type_ = ClipType::Comp(comp);
+        let index = state.add_clip(folder);
+        let played = |state: &UiState| {
+            state.clips[index]
+                .played_audio()
+                .map(|take| take.pcm_path.to_string_lossy().to_string())
+        };
+        assert_eq!(played(&state).as_deref(), Some("pass3.wav"));
+
+        // Cycling wraps around after the last take.
+        state.cycle_take(index);
+        assert_eq!(played(&state).as_deref(), Some("pass1.wav"));
+        state.cycle_take(index);
+        assert_eq!(played(&state).as_deref(), Some("pass2.wav"));
+
+        // Parts of several takes are only heard once the comp is flattened.
+        state.set_comp_region(index, MusicalTime::from_beats(0)..MusicalTime::from_beats(2), 0);
+        assert_eq!(played(&state), None);
+        state.cycle_take(index);
+        assert_eq!(played(&state).as_deref(), Some("pass1.wav"));
+
+        assert!(state.undo());
+        assert_eq!(played(&state), None);
+    }
+
+    #[test]
+    fn renaming_a_clip_keeps_its_identity_and_skips_the_engine() {
+        let mut state = state_with_selected_clips(vec![clip(0, 0, 2)]);
+        let (id, name) = (state.clips[0].id, state.clips[0].name.clone());
+        state.changes.clear();
+
+        state.set_clip_label(0, Some(String::from("Lead vocal")));
+        state.set_clip_notes(0, String::from("Retake the last bar"));
+        assert_eq!(state.clips[0].display_label(), "Lead vocal");
+
+        // An empty label shows the name again.
+        state.set_clip_label(0, Some(String::new()));
+        assert_eq!(state.clips[0].label, None);
+
+        assert_eq!(state.clips[0].id, id);
+        assert_eq!(state.clips[0].name, name);
+        assert_eq!(state.clips[0].notes, "Retake the last bar");
+        assert_eq!(state.changes, vec![StateChange::ClipRenamed { index: 0 }; 3]);
+    }
+
+    #[test]
+    fn clips_saved_without_a_label_or_notes_load_with_their_name() {
+        let mut json = serde_json::to_value(clip(0, 0, 2)).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.insert(String::from("name"), serde_json::json!("Drums"));
+        fields.remove("label");
+        fields.remove("notes");
+
+        let clip: ClipState = serde_json::from_value(json).unwrap();
+        assert_eq!(clip.display_label(), "Drums");
+        assert_eq!(clip.notes, "");
+    }
+
+    /// Renders the audio clip at `index` from `source` (one channel) the way
+    /// the timeline player plays it along with all the other clips, from its
+    /// start on the timeline.
+    fn render_audio_clip(state: &UiState, index: usize, source: &[f32]) -> Vec<f32> {
+        let joins = state.clip_joins(&(0..state.clips.len()).collect::<Vec<_>>())[index];
+        let clip = &state.clips[index];
+        let audio_clip = match &clip.type_ {
+            ClipType::Audio(audio_clip) => audio_clip.clone(),
+            _ => panic!("not an audio clip"),
+        };
+        let bpm = state.timeline_grid.bpm;
+        let num_frames = (clip.length.get().as_beats_f64() * 60.0 / bpm * 48_000.0) as u64;
+        let playback = AudioClipPlayback::new(
+            audio_clip,
+            clip.length.get(),
+            bpm,
+            state.auto_fade,
+            joins,
+            48_000.0,
+            InterpolationQuality::Linear,
+        );
+        (0..num_frames)
+            .map(|frame| {
+                let (pos, gain) = playback.position_and_gain(frame);
+                playback.read(source, pos, 1.0) * gain
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slipping_a_clip_sounds_like_trimming_and_moving_it() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let source: Vec<f32> = (0..10 * 48_000).map(|i| (i as f32 * 0.001).sin()).collect();
+        let slipped = state.add_clip(clip(0, 4, 4));
+        let reference = state.add_clip(clip(1, 0, 6));
+        state.set_clips_gain_db(&[slipped, reference], -3.0);
+
+        // Slipping the audio two beats earlier plays it from two beats in...
+        assert!(state.slip_clip(slipped, -2.0, Some(Seconds(10.0))));
+        // ...like trimming the first two beats off, and moving the rest to
+        // where the slipped clip is.
+        state.resize_clip_start(reference, MusicalTime::from_beats(2));
+        state.move_clips_later(&[reference], MusicalTime::from_beats(2));
+
+        assert_eq!(state.clips[slipped].lane_range_beats(), Some((0, 4.0, 8.0)));
+        assert_eq!(state.clips[reference].lane_range_beats(), Some((1, 4.0, 8.0)));
+        let slipped = render_audio_clip(&state, slipped, &source);
+        let reference = render_audio_clip(&state, reference, &source);
+        assert!(slipped.iter().any(|s| s.abs() > 0.5));
+        assert_eq!(slipped, reference);
+    }
+
+    #[test]
+    fn a_split_clip_sounds_like_the_clip_it_was_split_from() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        assert!(state.auto_fade.length().is_some());
+        let source: Vec<f32> = (0..10 * 48_000).map(|i| (i as f32 * 0.001).sin()).collect();
+        let whole = state.add_clip(clip(0, 0, 4));
+
+        // Split the same clip on another lane at beat 2 (i.e. frame 48000).
+        let first = state.add_clip(clip(1, 0, 4));
+        let second = state.add_clip(clip(1, 0, 4));
+        state.clips[first].length = MusicalTime::from_beats(2).into();
+        state.resize_clip_start(second, MusicalTime::from_beats(2));
+        assert_eq!(state.clips[second].lane_range_beats(), Some((1, 2.0, 4.0)));
+
+        let joins = state.clip_joins(&[whole, first, second]);
+        assert_eq!(joins[0], ClipJoins::default());
+        assert_eq!(joins[1], ClipJoins { start: false, end: true });
+        assert_eq!(joins[2], ClipJoins { start: true, end: false });
+
+        // The automatic fade is only applied at the outer edges, so the pieces
+        // play like the whole clip.
+        let whole = render_audio_clip(&state, whole, &source);
+        let mut split = render_audio_clip(&state, first, &source);
+        split.extend(render_audio_clip(&state, second, &source));
+        assert_eq!(whole.len(), split.len());
+        assert!(whole.iter().zip(split.iter()).all(|(a, b)| (a - b).abs() < 1e-6));
+
+        // Clips that meet but play different parts of the audio keep their
+        // fades.
+        if let ClipType::Audio(audio_clip) = &mut state.clips[second].type_ {
+            audio_clip.clip_start_offset = SuperFrames(0).into();
+        }
+        assert_eq!(state.clip_joins(&[first, second]), vec![ClipJoins::default(); 2]);
+    }
+
+    #[test]
+    fn overlapping_clips_render_in_the_same_order_after_a_reload() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let a = state.add_clip(clip(0, 0, 8));
+        let b = state.add_clip(clip(1, 4, 8));
+        let c = state.add_clip(clip(2, 0, 4));
+        state.bring_clip_to_front(a);
+
+        let ids = |state: &UiState| -> Vec<ClipId> {
+            state.clips_in_render_order().into_iter().map(|index| state.clips[index].id).collect()
+        };
+        let order = ids(&state);
+        assert_eq!(order, vec![state.clips[c].id, state.clips[a].id, state.clips[b].id]);
+
+        // The order of the clip list doesn't matter, only the starts and the
+        // stacking order that are saved with the clips.
+        let json = serde_json::to_string(&state.to_project()).unwrap();
+        let mut project: ProjectState = serde_json::from_str(&json).unwrap();
+        project.clips.reverse();
+        let reloaded = UiState::from_project(project, &LayoutConfig::default());
+        assert_eq!(ids(&reloaded), order);
+    }
+}
@@ -0,0 +1,251 @@
+use super::{ClipId, ClipStart, ClipState};
+use meadowlark_core_types::time::MusicalTime;
+use std::ops::Range;
+use vizia::prelude::*;
+
+/// The set of clips that are currently selected in the timeline.
+///
+/// The clips are held by id, so the selection keeps pointing at the same clips
+/// when others are removed or the project is restored from the undo history.
+#[derive(Debug, Lens, Clone)]
+pub struct ClipSelection {
+    /// The ids of the selected clips, in the order they were selected.
+    pub clips: Vec<ClipId>,
+    /// The last clicked clip. This is used as the start of shift-click range
+    /// selections.
+    pub anchor: Option<ClipId>,
+}
+
+impl ClipSelection {
+    /// Creates a new empty selection.
+    pub fn new() -> Self {
+        Self { clips: Vec::new(), anchor: None }
+    }
+
+    /// Returns `true` if the clip with the id `id` is selected.
+    pub fn is_selected(&self, id: ClipId) -> bool {
+        self.clips.contains(&id)
+    }
+
+    /// Returns `true` if no clips are selected.
+    pub fn is_empty(&self) -> bool {
+        self.clips.is_empty()
+    }
+
+    /// Returns the indices (into `clips`) of the selected clips, in the order
+    /// they were selected. Clips that no longer exist are left out.
+    pub fn indices(&self, clips: &[ClipState]) -> Vec<usize> {
+        self.clips.iter().filter_map(|id| clips.iter().position(|clip| clip.id == *id)).collect()
+    }
+
+    /// Selects the clip with the id `id`.
+    pub fn select(&mut self, id: ClipId) {
+        if !self.is_selected(id) {
+            self.clips.push(id);
+        }
+        self.anchor = Some(id);
+    }
+
+    /// Selects the clip with the id `id` if it is unselected, or unselects it
+    /// if it is selected.
+    pub fn toggle(&mut self, id: ClipId) {
+        if self.is_selected(id) {
+            self.deselect(id);
+        } else {
+            self.select(id);
+        }
+    }
+
+    /// Unselects the clip with the id `id` (i.e. when it was removed).
+    pub fn deselect(&mut self, id: ClipId) {
+        self.clips.retain(|x| *x != id);
+        if self.anchor == Some(id) {
+            self.anchor = None;
+        }
+    }
+
+    /// Unselects the clips that are not in `clips` any more (i.e. after the
+    /// project was restored from the undo history).
+    pub fn retain_existing(&mut self, clips: &[ClipState]) {
+        let exists = |id: &ClipId| clips.iter().any(|clip| clip.id == *id);
+        self.clips.retain(exists);
+        self.anchor = self.anchor.filter(exists);
+    }
+
+    /// Unselects all clips.
+    pub fn clear(&mut self) {
+        self.clips.clear();
+        self.anchor = None;
+    }
+
+    /// Selects every clip in `clips` that sits on a lane inside `lanes` and
+    /// overlaps the time range `time`.
+    pub fn select_in_rect(
+        &mut self,
+        clips: &[ClipState],
+        lanes: Range<u32>,
+        time: Range<MusicalTime>,
+    ) {
+        for clip in clips.iter() {
+            if let ClipStart::OnLane(on_lane) = &clip.timeline_start {
+                let start = on_lane.timeline_start.get();
+                let end = start + clip.length.get();
+
+                if lanes.contains(&on_lane.lane_index) && start < time.end && end > time.start {
+                    self.select(clip.id);
+                }
+            }
+        }
+    }
+
+    /// Selects the clips between the anchor and the clip with the id `to` (i.e.
+    /// on shift-click), replacing the current selection.
+    ///
+    /// This selects every clip on the lanes from the anchor's lane to the lane
+    /// of `to` that overlaps the time from the start of the earlier of the two
+    /// clips to the end of the later one. The anchor stays where it is, so the
+    /// next range starts from it again. If there is no anchor, only `to` is
+    /// selected.
+    pub fn select_range(&mut self, to: ClipId, clips: &[ClipState]) {
+        let span = |id: ClipId| match clips
+            .iter()
+            .find(|clip| clip.id == id)
+            .map(|clip| (clip, &clip.timeline_start))
+        {
+            Some((clip, ClipStart::OnLane(on_lane))) => {
+                let start = on_lane.timeline_start.get();
+                Some((on_lane.lane_index, start, start + clip.length.get()))
+            }
+            _ => None,
+        };
+
+        let anchor = match self.anchor {
+            Some(anchor) => anchor,
+            None => {
+                self.select(to);
+                return;
+            }
+        };
+        let ((anchor_lane, anchor_start, anchor_end), (to_lane, to_start, to_end)) =
+            match (span(anchor), span(to)) {
+                (Some(anchor), Some(to)) => (anchor, to),
+                _ => {
+                    self.select(to);
+                    return;
+                }
+            };
+
+        let lanes = anchor_lane.min(to_lane)..anchor_lane.max(to_lane) + 1;
+        let start = if to_start < anchor_start { to_start } else { anchor_start };
+        let end = if to_end > anchor_end { to_end } else { anchor_end };
+
+        self.clips.clear();
+        self.select_in_rect(clips, lanes, start..end);
+        self.anchor = Some(anchor);
+    }
+}
+
+impl Default for ClipSelection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::state::test_clips::clip;
+
+    fn beats(beats: u32) -> MusicalTime {
+        MusicalTime::from_beats(beats)
+    }
+
+    /// Gives the clips the ids 1, 2, 3, ... in order.
+    fn with_ids(mut clips: Vec<ClipState>) -> Vec<ClipState> {
+        for (i, clip) in clips.iter_mut().enumerate() {
+            clip.id = ClipId(i as u64 + 1);
+        }
+        clips
+    }
+
+    fn ids(ids: &[u64]) -> Vec<ClipId> {
+        ids.iter().map(|id| ClipId(*id)).collect()
+    }
+
+    #[test]
+    fn rect_selects_overlapping_clips_on_the_lanes_in_range() {
+        let clips = with_ids(vec![
+            clip(0, 0, 4),  // ends where the rect starts
+            clip(0, 2, 4),  // overlaps the start
+            clip(1, 6, 2),  // inside
+            clip(1, 10, 4), // starts where the rect ends
+            clip(2, 4, 4),  // on a lane below the rect
+            clip(0, 8, 4),  // overlaps the end
+        ]);
+
+        let mut selection = ClipSelection::new();
+        selection.select_in_rect(&clips, 0..2, beats(4)..beats(10));
+
+        assert_eq!(selection.clips, ids(&[2, 3, 6]));
+        assert_eq!(selection.indices(&clips), vec![1, 2, 5]);
+    }
+
+    #[test]
+    fn rect_adds_to_the_selection() {
+        let clips = with_ids(vec![clip(0, 0, 4), clip(3, 0, 4)]);
+
+        let mut selection = ClipSelection::new();
+        selection.select(ClipId(2));
+        selection.select_in_rect(&clips, 0..1, beats(0)..beats(1));
+
+        assert_eq!(selection.clips, ids(&[2, 1]));
+    }
+
+    #[test]
+    fn range_spans_from_the_anchor_to_the_clicked_clip() {
+        let clips = with_ids(vec![
+            clip(0, 0, 4),
+            clip(1, 4, 4),
+            clip(2, 8, 4),
+            clip(3, 8, 4), // on a lane past the clicked clip
+            clip(1, 12, 4),
+        ]);
+
+        let mut selection = ClipSelection::new();
+        selection.select(ClipId(5));
+        selection.select(ClipId(1));
+        selection.select_range(ClipId(3), &clips);
+        assert_eq!(selection.clips, ids(&[1, 2, 3]));
+        assert_eq!(selection.anchor, Some(ClipId(1)));
+
+        // The next range starts from the same anchor, and replaces the last one.
+        selection.select_range(ClipId(2), &clips);
+        assert_eq!(selection.clips, ids(&[1, 2]));
+    }
+
+    #[test]
+    fn range_without_an_anchor_selects_the_clicked_clip() {
+        let clips = with_ids(vec![clip(0, 0, 4), clip(0, 4, 4)]);
+
+        let mut selection = ClipSelection::new();
+        selection.select_range(ClipId(2), &clips);
+
+        assert_eq!(selection.clips, ids(&[2]));
+        assert_eq!(selection.anchor, Some(ClipId(2)));
+    }
+
+    #[test]
+    fn the_selection_follows_the_clips_when_others_are_removed() {
+        let mut clips = with_ids(vec![clip(0, 0, 4), clip(0, 4, 4), clip(0, 8, 4)]);
+
+        let mut selection = ClipSelection::new();
+        selection.select(ClipId(3));
+        clips.remove(0);
+        assert_eq!(selection.indices(&clips), vec![1]);
+
+        clips.remove(1);
+        selection.retain_existing(&clips);
+        assert!(selection.is_empty());
+        assert_eq!(selection.anchor, None);
+    }
+}
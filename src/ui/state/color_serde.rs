@@ -0,0 +1,15 @@
+//! Serializes a `vizia` `Color` as an `[r, g, b, a]` array.
+//!
+//! Use this with `#[serde(with = "color_serde")]`.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use vizia::prelude::Color;
+
+pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+    [color.r(), color.g(), color.b(), color.a()].serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let [r, g, b, a] = <[u8; 4]>::deserialize(deserializer)?;
+    Ok(Color::rgba(r, g, b, a))
+}
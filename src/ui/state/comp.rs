@@ -0,0 +1,213 @@
+use super::clip::beats_to_super_frames;
+use super::core_types::{WMusicalTime, WSeconds};
+use super::{AudioClipState, ClipId, ClipStart, ClipState, ClipType, OnLane};
+use meadowlark_core_types::time::{MusicalTime, Seconds};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use vizia::prelude::*;
+
+/// The default length of the crossfade between two comp regions in seconds.
+pub const DEFAULT_COMP_CROSSFADE_SECS: f64 = 0.01;
+
+/// A clip slot with several recorded takes, where parts of different takes are
+/// combined ("comped") into what is heard.
+///
+/// Recording over a loop stacks the passes into one of these as a take folder,
+/// with the last pass heard over the whole clip. Only the take that is heard
+/// over the whole clip is played back (see `active_take()`), so a comp of
+/// several takes has to be flattened to be heard.
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
+pub struct CompClipState {
+    /// All takes recorded for this slot. Every take starts at the start of
+    /// the clip.
+    pub takes: Vec<AudioClipState>,
+
+    /// Which take is heard in which part of the clip, sorted by start time.
+    ///
+    /// Parts of the clip that are not covered by a region are silent.
+    pub regions: Vec<CompRegion>,
+
+    /// The length of the crossfade at each boundary between two regions.
+    pub crossfade_secs: WSeconds,
+}
+
+/// A part of a comp clip where one take is heard.
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
+pub struct CompRegion {
+    /// The start of this region relative to the start of the clip.
+    pub start: WMusicalTime,
+    /// The end of this region relative to the start of the clip.
+    pub end: WMusicalTime,
+    /// The index of the take that is heard in this region.
+    pub take: usize,
+}
+
+impl CompClipState {
+    /// Creates a comp with `take` as its only take, heard over the whole
+    /// clip length.
+    pub fn new(take: AudioClipState, clip_length: MusicalTime) -> Self {
+        Self {
+            takes: vec![take],
+            regions: vec![CompRegion {
+                start: MusicalTime::from_beats(0).into(),
+                end: clip_length.into(),
+                take: 0,
+            }],
+            crossfade_secs: Seconds(DEFAULT_COMP_CROSSFADE_SECS).into(),
+        }
+    }
+
+    /// Adds a new take and returns its index. The new take is not heard until
+    /// a region is assigned to it.
+    pub fn add_take(&mut self, take: AudioClipState) -> usize {
+        self.takes.push(take);
+        self.takes.len() - 1
+    }
+
+    /// Makes the take at index `take` heard in `range` (relative to the start
+    /// of the clip), replacing whatever was heard there before.
+    pub fn set_region(&mut self, range: Range<MusicalTime>, take: usize) {
+        if take >= self.takes.len() || range.end <= range.start {
+            return;
+        }
+
+        let mut regions = Vec::with_capacity(self.regions.len() + 2);
+        for region in self.regions.drain(..) {
+            let (start, end) = (region.start.get(), region.end.get());
+            if end <= range.start || start >= range.end {
+                regions.push(region);
+                continue;
+            }
+
+            if start < range.start {
+                regions.push(CompRegion {
+                    start: start.into(),
+                    end: range.start.into(),
+                    take: region.take,
+                });
+            }
+            if end > range.end {
+                regions.push(CompRegion {
+                    start: range.end.into(),
+                    end: end.into(),
+                    take: region.take,
+                });
+            }
+        }
+        regions.push(CompRegion { start: range.start.into(), end: range.end.into(), take });
+        regions.sort_by(|a, b| {
+            a.start.get().partial_cmp(&b.start.get()).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        // Merge neighboring regions that use the same take.
+        let mut merged: Vec<CompRegion> = Vec::with_capacity(regions.len());
+        for region in regions {
+            match merged.last_mut() {
+                Some(last) if last.take == region.take && last.end == region.start => {
+                    last.end = region.end;
+                }
+                _ => merged.push(region),
+            }
+        }
+
+        self.regions = merged;
+    }
+
+    /// Returns the index of the take that is heard over the whole clip of
+    /// length `clip_length`, or `None` if parts of several takes are comped.
+    pub fn active_take(&self, clip_length: MusicalTime) -> Option<usize> {
+        match self.regions.as_slice() {
+            [region]
+                if region.start.get() == MusicalTime::from_beats(0)
+                    && region.end.get() >= clip_length =>
+            {
+                Some(region.take)
+            }
+            _ => None,
+        }
+    }
+
+    /// Makes the take after the active one heard over the whole clip of length
+    /// `clip_length`, wrapping around after the last take. If parts of several
+    /// takes are comped, the first take is made heard.
+    pub fn cycle_take(&mut self, clip_length: MusicalTime) {
+        let take = match self.active_take(clip_length) {
+            Some(take) => (take + 1) % self.takes.len(),
+            None => 0,
+        };
+        self.set_region(MusicalTime::from_beats(0)..clip_length, take);
+    }
+
+    /// Returns the index of the take that is heard at `time` (relative to the
+    /// start of the clip).
+    pub fn take_at(&self, time: MusicalTime) -> Option<usize> {
+        self.regions
+            .iter()
+            .find(|region| time >= region.start.get() && time < region.end.get())
+            .map(|region| region.take)
+    }
+
+    /// Converts this comp into one ordinary audio clip per region, given the
+    /// clip it belongs to.
+    ///
+    /// Each new clip starts playing its take at the position of the region, and
+    /// neighboring clips overlap by the crossfade length with matching fades.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    pub fn flatten(&self, clip: &ClipState, bpm: f64) -> Vec<ClipState> {
+        let on_lane = match &clip.timeline_start {
+            ClipStart::OnLane(on_lane) => on_lane,
+            ClipStart::NotInTimeline => return Vec::new(),
+        };
+        let clip_start = on_lane.timeline_start.get();
+        let crossfade_beats = self.crossfade_secs.get().0 * bpm / 60.0;
+
+        let mut clips = Vec::with_capacity(self.regions.len());
+        for (i, region) in self.regions.iter().enumerate() {
+            let take = match self.takes.get(region.take) {
+                Some(take) => take,
+                None => continue,
+            };
+
+            let start_beats = region.start.get().as_beats_f64();
+            let mut end_beats = region.end.get().as_beats_f64();
+
+            let joins_next =
+                self.regions.get(i + 1).map(|next| next.start == region.end).unwrap_or(false);
+            let joins_prev = i > 0 && self.regions[i - 1].end == region.start;
+
+            if joins_next {
+                end_beats += crossfade_beats;
+            }
+
+            let mut audio_clip = take.clone();
+            audio_clip.clip_start_offset = (audio_clip.clip_start_offset.get()
+                + beats_to_super_frames(start_beats * audio_clip.playback_rate(), bpm))
+            .into();
+            if joins_next {
+                audio_clip.fade_out_secs = self.crossfade_secs;
+            }
+            if joins_prev {
+                audio_clip.fade_in_secs = self.crossfade_secs;
+            }
+
+            clips.push(ClipState {
+                id: ClipId::default(),
+                name: format!("{} ({})", &clip.name, region.take + 1),
+                label: None,
+                notes: clip.notes.clone(),
+                z_order: clip.z_order,
+                color: clip.color.clone(),
+                timeline_start: ClipStart::OnLane(OnLane {
+                    lane_index: on_lane.lane_index,
+                    timeline_start: (clip_start + region.start.get()).into(),
+                }),
+                length: MusicalTime::from_beats_f64(end_beats - start_beats).into(),
+                channel: clip.channel,
+                type_: ClipType::Audio(audio_clip),
+            });
+        }
+
+        clips
+    }
+}
@@ -0,0 +1,118 @@
+//! Debug views of the engine, and the diagnostics dump.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{ChannelId, ClipType, NotificationLogType, UiData};
+use crate::ui::diagnostics::{diagnostics_dir, environment_info, DiagnosticsDump};
+
+impl UiData {
+    /// Returns the description and age of every audio graph request the engine
+    /// has not confirmed yet, oldest first. This is meant for a debug view.
+    pub fn pending_engine_operations(&self) -> Vec<(String, Duration)> {
+        match &self.engine_handles {
+            Some((engine_handles, _)) => engine_handles
+                .journal
+                .lost()
+                .chain(engine_handles.journal.in_flight())
+                .map(|op| (op.description.clone(), op.age()))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the number of clips the timeline track of each channel played
+    /// in the last block, along with the total over all tracks. This is meant
+    /// for a debug view, and costs the audio thread nothing while it is not
+    /// called.
+    pub fn timeline_clip_counts(&mut self) -> (Vec<(ChannelId, u32)>, u32) {
+        let timeline = match self.system_io_stream_handle.as_mut() {
+            Some(system_io_stream_handle) => system_io_stream_handle.timeline(),
+            None => return (Vec::new(), 0),
+        };
+
+        // The player keeps its tracks in the order they were added, like
+        // `timeline_tracks`.
+        let counts = timeline.clip_counts();
+        let per_track = self
+            .timeline_tracks
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (*id, counts.count(index)))
+            .collect();
+        (per_track, counts.total())
+    }
+
+    /// Writes a diagnostics dump with the engine state recovered after a crash,
+    /// the notification log, the last records of the audio thread log, the app
+    /// config, and information about the system (see `DiagnosticsDump`), and
+    /// shows its path in a notification.
+    ///
+    /// If `redact_paths` is true, the paths of the project and of the audio
+    /// files are reduced to their file names.
+    pub fn write_diagnostics(&mut self, reason: &str, redact_paths: bool) -> Option<PathBuf> {
+        let mut dump = DiagnosticsDump::new(reason);
+        dump.engine_state = self
+            .engine_restart
+            .recovered_save_state()
+            .map(|save_state| format!("{:#?}", save_state));
+        dump.notifications = self
+            .notification_log
+            .iter()
+            .map(|notification| match notification {
+                NotificationLogType::Error(message) => format!("Error: {}", message),
+                NotificationLogType::Info(message) => format!("Info: {}", message),
+            })
+            .collect();
+        dump.rt_trace = self
+            .rt_trace
+            .iter()
+            .map(|record| format!("block {}: {:?}", record.block, record.event))
+            .collect();
+        dump.app_config = match serde_json::to_string_pretty(&self.app_config) {
+            Ok(app_config) => Some(app_config),
+            Err(e) => {
+                log::error!("Failed to serialize the app config for diagnostics: {}", e);
+                None
+            }
+        };
+        dump.environment = environment_info(
+            Some(self.sample_rate.get()),
+            self.system_io_stream_handle.as_ref().and_then(|handle| handle.device_name()),
+        );
+
+        if redact_paths {
+            let mut paths: Vec<PathBuf> =
+                self.app_config.recent_projects.iter().map(|p| p.path.clone()).collect();
+            paths.extend(self.project_path.iter().cloned());
+            for clip in self.state.clips.iter() {
+                if let ClipType::Audio(audio_clip) = &clip.type_ {
+                    paths.push(audio_clip.pcm_path.clone());
+                    paths.extend(audio_clip.stretched_from.iter().cloned());
+                }
+            }
+            dump.redact_paths(&paths);
+        }
+
+        match dump.write(&diagnostics_dir()) {
+            Ok((folder, failed)) => {
+                let message = if failed.is_empty() {
+                    format!("Diagnostics were saved to {}", folder.display())
+                } else {
+                    format!(
+                        "Diagnostics were saved to {}, except for {}",
+                        folder.display(),
+                        failed.join(", ")
+                    )
+                };
+                self.notification_log.push(NotificationLogType::Info(message));
+                Some(folder)
+            }
+            Err(e) => {
+                self.notification_log
+                    .push(NotificationLogType::Error(format!("Failed to save diagnostics: {}", e)));
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use dropseed::AudioGraphSaveState;
+use std::time::{Duration, Instant};
+
+/// The number of times in a row the engine is restarted after a crash before
+/// giving up.
+pub const MAX_ENGINE_RESTART_ATTEMPTS: u32 = 3;
+
+/// The delay before the first restart. This doubles with every attempt.
+const ENGINE_RESTART_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// If the engine ran for this long before crashing, then the crash is not
+/// counted as part of a crash loop.
+const ENGINE_STABLE_DURATION: Duration = Duration::from_secs(30);
+
+/// Keeps track of automatic engine restarts after a crash.
+#[derive(Default)]
+pub struct EngineRestartState {
+    attempts: u32,
+    restart_at: Option<Instant>,
+    activated_at: Option<Instant>,
+    recovered_save_state: Option<AudioGraphSaveState>,
+}
+
+impl EngineRestartState {
+    /// Schedules a restart after the engine crashed.
+    ///
+    /// Returns the number of this restart attempt, or `None` if the engine
+    /// crashed too many times in a row and won't be restarted.
+    pub fn on_engine_crashed(
+        &mut self,
+        recovered_save_state: Option<AudioGraphSaveState>,
+    ) -> Option<u32> {
+        if let Some(activated_at) = self.activated_at.take() {
+            if activated_at.elapsed() >= ENGINE_STABLE_DURATION {
+                self.attempts = 0;
+            }
+        }
+
+        if self.attempts >= MAX_ENGINE_RESTART_ATTEMPTS {
+            self.restart_at = None;
+            self.recovered_save_state = None;
+            return None;
+        }
+
+        // Keep the last good save state if the engine crashed before it could
+        // recover one.
+        if recovered_save_state.is_some() {
+            self.recovered_save_state = recovered_save_state;
+        }
+
+        self.restart_at =
+            Some(Instant::now() + ENGINE_RESTART_BASE_DELAY * 2u32.pow(self.attempts));
+        self.attempts += 1;
+
+        Some(self.attempts)
+    }
+
+    /// Returns `true` once when it is time to reactivate the engine.
+    pub fn restart_due(&mut self) -> bool {
+        match self.restart_at {
+            Some(restart_at) if Instant::now() >= restart_at => {
+                self.restart_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The save state the audio graph will be restored from after the engine
+    /// crashed, if any.
+    pub fn recovered_save_state(&self) -> Option<&AudioGraphSaveState> {
+        self.recovered_save_state.as_ref()
+    }
+
+    /// Called when the engine activates. Returns the save state to restore the
+    /// audio graph from, if the engine is being restarted after a crash.
+    pub fn on_engine_activated(&mut self) -> Option<AudioGraphSaveState> {
+        self.activated_at = Some(Instant::now());
+        self.recovered_save_state.take()
+    }
+}
@@ -1,5 +1,17 @@
+use meadowlark_core_types::time::MusicalTime;
+use std::ops::Range;
 use std::path::PathBuf;
 
+use crate::backend::wav_export::WavSampleFormat;
+
+use super::{
+    AutomationCurve, AutomationMode, AutomationParam, ChannelBaseColor, ChannelId, ClipChannelMode,
+    ClipId, InputAssignment, InterpolationQuality, LoopEdge, MultichannelMode, OutputAssignment,
+};
+
+/// Clips and channels are addressed by their ids (see `ids`), so that an event
+/// that is handled after another edit still applies to the clip or channel it
+/// was sent for.
 #[derive(Debug, Clone, PartialEq)]
 pub enum UiEvent {
     // ----- General -----
@@ -9,8 +21,63 @@ pub enum UiEvent {
     SaveProject,
     LoadProject,
 
+    // Edit
+    Undo,
+    Redo,
+
+    // Layout
+    ResetLayout,
+
+    /// Writes a diagnostics dump to attach to a bug report.
+    WriteDiagnostics {
+        redact_paths: bool,
+    },
+
+    // ----- Transport -----
+    Play,
+    Stop,
+    ToggleLoop,
+    SetStartStopFadeSecs(f64),
+    SetSeekCrossfadeSecs(f64),
+    SetLoopCrossfadeSecs(f64),
+    SetLoopRange(MusicalTime, MusicalTime),
+    /// Moves an edge of the loop region while it is dragged. The position is
+    /// snapped to a grid with the given spacing in beats, or not at all if it
+    /// is `None` (i.e. while the snapping modifier key is held).
+    DragLoopEdge(LoopEdge, MusicalTime, Option<f64>),
+    /// The tempo widget was grabbed. The tempo changes until `EndTempoChange`
+    /// are undone as one.
+    BeginTempoChange,
+    SetTempo(f64),
+    /// The tempo widget was released.
+    EndTempoChange,
+    ToggleRecord,
+    /// Limits recording to the punch range, or stops limiting it.
+    TogglePunch,
+    SetPunchRange(MusicalTime, MusicalTime),
+    /// Sets the number of bars the metronome counts in before recording.
+    SetCountInBars(u8),
+
     // ----- Channel Rack -----
-    SelectChannel(usize),
+    SelectChannel(ChannelId),
+    SetChannelOutput(ChannelId, OutputAssignment),
+    SetChannelInput(ChannelId, InputAssignment),
+    /// Moves the correlation meter to the output of the channel.
+    SetCorrelationBus(ChannelId),
+    SetAutomationMode(ChannelId, AutomationParam, AutomationMode),
+    /// Adds a point with a normalized value to an automation lane of a channel.
+    AddAutomationPoint(ChannelId, AutomationParam, MusicalTime, f64),
+    /// Moves the point at the given index of an automation lane of a channel.
+    MoveAutomationPoint(ChannelId, AutomationParam, usize, MusicalTime, f64),
+    RemoveAutomationPoint(ChannelId, AutomationParam, usize),
+    SetAutomationPointCurve(ChannelId, AutomationParam, usize, AutomationCurve),
+    /// The control of a parameter of a channel was grabbed. The value is the
+    /// normalized value of the control.
+    BeginTouchAutomation(ChannelId, AutomationParam, f64),
+    /// The held control was moved to this normalized value.
+    SetTouchAutomationValue(f64),
+    /// The held control was released.
+    EndTouchAutomation,
 
     // ----- Timeline -----
 
@@ -33,16 +100,85 @@ pub enum UiEvent {
     // Zoom
     ZoomInVertically,
     ZoomOutVertically,
+    /// Zooms to fit the whole project into a view that is this many beats wide
+    /// at the default zoom level.
+    ZoomToFitProject(f64),
+    SetProjectLength(MusicalTime),
+    /// Shrinks the project length to the end of the latest clip.
+    TrimProjectLength,
 
     // Height
     IncreaseSelectedLaneHeight,
     DecreaseSelectedLaneHeight,
+    ToggleLaneMinimized(usize),
 
     // Activation
     ActivateSelectedLanes,
     DeactivateSelectedLanes,
     ToggleSelectedLaneActivation,
 
+    // ----- Clips -----
+
+    // Selection
+    SelectClip(ClipId),
+    ToggleClipSelection(ClipId),
+    ClearClipSelection,
+
+    // Editing
+    DeleteSelectedClips,
+    /// Starts a drag of the given clips. The clips can be changed with the
+    /// other events until `CommitClipEdit` or `CancelClipEdit` is sent.
+    BeginClipEdit(Vec<ClipId>),
+    CommitClipEdit,
+    CancelClipEdit,
+    ResizeClipStart(ClipId, MusicalTime),
+    ResizeClipEnd(ClipId, MusicalTime),
+    /// Shifts the audio under a clip by the given number of beats while the
+    /// clip stays in place.
+    SlipClip(ClipId, f64),
+    /// Renders the selected audio clips with all of their processing into new
+    /// files that replace their audio.
+    BounceSelectedClipsInPlace,
+    /// Renders the selected audio clips into one new clip that replaces them.
+    ConsolidateSelectedClips,
+    /// Renders the audio clips of a channel in a range of the timeline into
+    /// one new clip that replaces them there.
+    ConsolidateRange(ChannelId, Range<MusicalTime>),
+    /// Renders the post-fader output of every channel into a WAV file of its
+    /// own in `dir`. Channels that aren't heard are left out if
+    /// `respect_mute_solo` is set.
+    ExportStems {
+        dir: PathBuf,
+        format: WavSampleFormat,
+        respect_mute_solo: bool,
+    },
+    /// Renders a range of the timeline the way it sounds when played into a
+    /// WAV file at `path`.
+    ExportRange {
+        range: Range<MusicalTime>,
+        format: WavSampleFormat,
+        path: PathBuf,
+    },
+    NudgeSelectedClipsEarlier(MusicalTime),
+    NudgeSelectedClipsLater(MusicalTime),
+    SetSelectedClipsGainDb(f32),
+    SetClipInvertPolarity(ClipId, bool),
+    SetClipChannelGainDb(ClipId, f32, f32),
+    SetClipMultichannelMode(ClipId, MultichannelMode),
+    SetClipChannelMode(ClipId, ClipChannelMode),
+    SetClipInterpolation(ClipId, Option<InterpolationQuality>),
+    SetPlaybackInterpolation(InterpolationQuality),
+    SetExportInterpolation(InterpolationQuality),
+    SetAutoFadeEnabled(bool),
+    SetClipLabel(ClipId, Option<String>),
+    SetClipNotes(ClipId, String),
+    SetSelectedClipsColor(Option<ChannelBaseColor>),
+    DuplicateSelectedClips,
+    BringClipToFront(ClipId),
+    /// Makes the next take of a take folder heard.
+    CycleTake(ClipId),
+    RepeatSelectedClips(usize),
+
     // ----- Browser -----
     SetBrowserWidth(f32),
     BrowserFileClicked(PathBuf),
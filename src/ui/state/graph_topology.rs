@@ -0,0 +1,184 @@
+use super::{ChannelState, OutputAssignment};
+use dropseed::plugin::PluginInstanceID;
+use dropseed::{ModifyGraphRes, PortType};
+use fnv::FnvHashMap;
+use vizia::prelude::*;
+
+/// A node (plugin) in the engine's audio graph.
+#[derive(Debug, Lens, Clone, PartialEq, Data)]
+pub struct GraphNode {
+    /// The reverse domain name of the plugin.
+    pub name: String,
+}
+
+/// A connection between two nodes in the engine's audio graph.
+#[derive(Debug, Lens, Clone, PartialEq, Data)]
+pub struct GraphEdge {
+    /// The index of the source node in `GraphTopology::nodes`.
+    pub src: usize,
+    /// The index of the destination node in `GraphTopology::nodes`.
+    pub dst: usize,
+    /// True if this edge carries audio, false if it carries events.
+    pub is_audio: bool,
+}
+
+/// A read-only view of the engine's audio graph, for the routing view.
+///
+/// This is updated by the program layer whenever the engine modifies the
+/// graph, and may not be mutated directly by the UI.
+#[derive(Debug, Lens, Clone, Default)]
+pub struct GraphTopology {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+
+    /// The indices of the nodes in the order they are processed, where every
+    /// node comes after all the nodes that feed into it.
+    pub processing_order: Vec<usize>,
+
+    #[lens(ignore)]
+    node_ids: Vec<PluginInstanceID>,
+    #[lens(ignore)]
+    node_indices: FnvHashMap<PluginInstanceID, usize>,
+}
+
+impl GraphTopology {
+    /// Applies the changes the engine made to the graph.
+    pub fn apply(&mut self, res: &ModifyGraphRes) {
+        for removed in res.removed_plugins.iter() {
+            if let Some(index) = self.node_indices.remove(removed) {
+                self.remove_node(index);
+            }
+        }
+
+        for new_plugin in res.new_plugins.iter() {
+            self.node_index(&new_plugin.plugin_id);
+        }
+
+        for edge in res.removed_edges.iter() {
+            if let (Some(src), Some(dst)) = (
+                self.node_indices.get(&edge.src_plugin_id).copied(),
+                self.node_indices.get(&edge.dst_plugin_id).copied(),
+            ) {
+                let is_audio = edge.edge_type == PortType::Audio;
+                if let Some(pos) = self
+                    .edges
+                    .iter()
+                    .position(|e| e.src == src && e.dst == dst && e.is_audio == is_audio)
+                {
+                    self.edges.remove(pos);
+                }
+            }
+        }
+
+        for edge in res.new_edges.iter() {
+            let src = self.node_index(&edge.src_plugin_id);
+            let dst = self.node_index(&edge.dst_plugin_id);
+            self.edges.push(GraphEdge { src, dst, is_audio: edge.edge_type == PortType::Audio });
+        }
+
+        self.update_processing_order();
+    }
+
+    /// Removes all nodes and edges (i.e. when the engine is deactivated).
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    fn node_index(&mut self, id: &PluginInstanceID) -> usize {
+        if let Some(index) = self.node_indices.get(id) {
+            return *index;
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(GraphNode { name: id.rdn().as_str().to_string() });
+        self.node_ids.push(id.clone());
+        self.node_indices.insert(id.clone(), index);
+        index
+    }
+
+    fn remove_node(&mut self, index: usize) {
+        self.nodes.remove(index);
+        self.node_ids.remove(index);
+        self.edges.retain(|e| e.src != index && e.dst != index);
+
+        for edge in self.edges.iter_mut() {
+            if edge.src > index {
+                edge.src -= 1;
+            }
+            if edge.dst > index {
+                edge.dst -= 1;
+            }
+        }
+        for node_index in self.node_indices.values_mut() {
+            if *node_index > index {
+                *node_index -= 1;
+            }
+        }
+    }
+
+    fn update_processing_order(&mut self) {
+        let edges: Vec<(usize, usize)> = self.edges.iter().map(|e| (e.src, e.dst)).collect();
+        self.processing_order = topological_order(self.nodes.len(), &edges);
+    }
+}
+
+/// Returns the index of the channel that the channel at `index` sends its
+/// output to, or `None` if it goes to a hardware output (or is the master).
+pub fn channel_destination(channels: &[ChannelState], index: usize) -> Option<usize> {
+    if index == 0 {
+        return None;
+    }
+
+    match channels.get(index)?.routed_to {
+        OutputAssignment::Master => Some(0),
+        OutputAssignment::Group(group) => Some(group),
+        OutputAssignment::Hardware { .. } => None,
+    }
+}
+
+/// Returns the indices of the channels in the order their signal flows, where
+/// every channel comes after all the channels that feed into it (so the master
+/// comes last).
+pub fn channel_processing_order(channels: &[ChannelState]) -> Vec<usize> {
+    let edges: Vec<(usize, usize)> = (0..channels.len())
+        .filter_map(|index| channel_destination(channels, index).map(|dst| (index, dst)))
+        .collect();
+    topological_order(channels.len(), &edges)
+}
+
+/// Sorts `num_nodes` nodes so that every node comes after the nodes with an
+/// edge into it. Nodes that are part of a cycle are appended at the end.
+fn topological_order(num_nodes: usize, edges: &[(usize, usize)]) -> Vec<usize> {
+    let mut num_inputs = vec![0usize; num_nodes];
+    for (_, dst) in edges.iter() {
+        if let Some(n) = num_inputs.get_mut(*dst) {
+            *n += 1;
+        }
+    }
+
+    let mut ready: Vec<usize> = (0..num_nodes).filter(|i| num_inputs[*i] == 0).collect();
+    ready.reverse();
+
+    let mut order = Vec::with_capacity(num_nodes);
+    while let Some(node) = ready.pop() {
+        order.push(node);
+        for (src, dst) in edges.iter() {
+            if *src == node && *dst < num_nodes {
+                num_inputs[*dst] -= 1;
+                if num_inputs[*dst] == 0 {
+                    ready.push(*dst);
+                }
+            }
+        }
+    }
+
+    if order.len() < num_nodes {
+        for node in 0..num_nodes {
+            if !order.contains(&node) {
+                order.push(node);
+            }
+        }
+    }
+
+    order
+}
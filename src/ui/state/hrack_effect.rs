@@ -1,19 +1,44 @@
+use serde::{Deserialize, Serialize};
 use vizia::prelude::*;
 
 /// An effect on the horizontal effect rack.
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum HRackEffectState {
     Internal(InternalEffectState),
     External(ExternalEffectState),
 }
 
-#[derive(Debug, Clone, PartialEq, Data)]
+impl HRackEffectState {
+    /// The amount of delay this effect is creating in samples.
+    pub fn delay(&self) -> u32 {
+        match self {
+            HRackEffectState::Internal(_) => 0,
+            HRackEffectState::External(effect) => effect.delay,
+        }
+    }
+
+    /// Sets the delay the effect reports. Internal effects have no delay, so
+    /// this does nothing for them.
+    ///
+    /// Returns `true` if the delay changed.
+    pub fn set_delay(&mut self, delay: u32) -> bool {
+        match self {
+            HRackEffectState::External(effect) if effect.delay != delay => {
+                effect.delay = delay;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Data, Serialize, Deserialize)]
 pub enum InternalEffectState {
     // TODO
     Todo,
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct ExternalEffectState {
     pub name: String,
 
@@ -78,7 +103,7 @@ pub struct ExternalEffectState {
     pub all_parameters: Vec<ParameterState>,
 }
 
-#[derive(Debug, Clone, Data)]
+#[derive(Debug, Clone, Data, Serialize, Deserialize)]
 pub enum ActivatedStatus {
     /// The plugin is successfully activated an running.
     Activated,
@@ -93,7 +118,7 @@ pub enum ActivatedStatus {
     DeactivatedDueToError { error_msg: String },
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub enum AllParametersState {
     /// The parameters are currently hidden. This should be used by default since
     /// having them enabled creates some overhead in the backend.
@@ -102,7 +127,7 @@ pub enum AllParametersState {
     Shown(Vec<ParameterState>),
 }
 
-#[derive(Debug, Lens, Clone, Data)]
+#[derive(Debug, Lens, Clone, Data, Serialize, Deserialize)]
 pub struct ParameterState {
     pub name: String,
 
@@ -0,0 +1,70 @@
+//! Stable ids for the clips and channels of a project.
+//!
+//! The index of a clip or a channel in `UiState` changes when an earlier one is
+//! removed or when they are reordered. Anything that has to keep pointing at
+//! the same clip or channel across edits (the `UiEvent`s, the clip selection,
+//! work that finishes later, the engine) holds its id instead, and looks up the
+//! current index with `UiState::clip_index()` or `UiState::channel_index()`
+//! when it needs it. The methods of `UiState` that edit a single clip or
+//! channel take its current index.
+//!
+//! Ids are never reused within a project. They are saved with the project, and
+//! the next free id is saved along with them.
+
+use serde::{Deserialize, Serialize};
+use vizia::prelude::*;
+
+/// The id of a clip. The default value means the clip was not given an id yet.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Data, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct ClipId(pub u64);
+
+impl ClipId {
+    pub fn is_assigned(&self) -> bool {
+        self.0 != 0
+    }
+}
+
+/// The id of a mixer channel (and of the track that plays into it). The default
+/// value means the channel was not given an id yet.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Data, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct ChannelId(pub u64);
+
+impl ChannelId {
+    pub fn is_assigned(&self) -> bool {
+        self.0 != 0
+    }
+}
+
+/// Hands out the ids of a project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct IdAllocator {
+    /// The last id that was handed out.
+    last: u64,
+}
+
+impl IdAllocator {
+    pub fn clip_id(&mut self) -> ClipId {
+        ClipId(self.next())
+    }
+
+    pub fn channel_id(&mut self) -> ChannelId {
+        ChannelId(self.next())
+    }
+
+    /// Makes sure that `id` is never handed out, for ids that were assigned
+    /// before this allocator was loaded.
+    pub fn reserve(&mut self, id: u64) {
+        self.last = self.last.max(id);
+    }
+
+    fn next(&mut self) -> u64 {
+        self.last += 1;
+        self.last
+    }
+}
@@ -0,0 +1,198 @@
+use super::{ClipState, ClipType};
+use meadowlark_core_types::time::{SampleRate, Seconds};
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use vizia::prelude::*;
+
+use crate::backend::decode;
+
+/// Information about an audio file as it is stored on disk (before it is
+/// decoded and resampled to the project's sample rate).
+#[derive(Debug, Lens, Clone, PartialEq, Data)]
+pub struct PcmResourceInfo {
+    /// The sample rate of the file, if known.
+    pub sample_rate: Option<u32>,
+    /// The number of bits per sample in the file, if known.
+    pub bit_depth: Option<u16>,
+    /// The number of channels in the file, if known.
+    pub channels: Option<u16>,
+    /// The length of the file in frames, if known.
+    pub frames: Option<u64>,
+    /// The size of the file in bytes.
+    pub file_size: u64,
+    /// The name of the codec (i.e. "PCM" or "FLAC").
+    pub codec: String,
+}
+
+impl PcmResourceInfo {
+    /// Reads the information of the audio file at `path`.
+    ///
+    /// WAV files are read from their header. All other formats are scanned with
+    /// `decode::scan_file()`, which counts the frames of every packet so the
+    /// length of VBR MP3s is exact.
+    pub fn probe(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file_size = std::fs::metadata(path)?.len();
+
+        let extension =
+            path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        if extension == "wav" || extension == "wave" {
+            return probe_wav(path, file_size);
+        }
+
+        let info = decode::scan_file(path)?;
+
+        Ok(Self {
+            sample_rate: Some(info.sample_rate),
+            bit_depth: info.bit_depth.map(|b| b as u16),
+            channels: Some(info.channels as u16),
+            frames: Some(info.frames),
+            file_size,
+            codec: info.codec.to_uppercase(),
+        })
+    }
+
+    /// The length of the file in seconds, if known.
+    pub fn duration(&self) -> Option<Seconds> {
+        match (self.frames, self.sample_rate) {
+            (Some(frames), Some(sample_rate)) if sample_rate > 0 => {
+                Some(Seconds(frames as f64 / f64::from(sample_rate)))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn probe_wav(path: &Path, file_size: u64) -> Result<PcmResourceInfo, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(format!("{} is not a valid WAV file", path.display()).into());
+    }
+
+    let mut info = PcmResourceInfo {
+        sample_rate: None,
+        bit_depth: None,
+        channels: None,
+        frames: None,
+        file_size,
+        codec: "PCM".into(),
+    };
+    let mut block_align = 0u16;
+    let mut data_len = None;
+
+    let mut chunk_header = [0u8; 8];
+    while file.read_exact(&mut chunk_header).is_ok() {
+        let id = [chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]];
+        let len = u32::from_le_bytes([
+            chunk_header[4],
+            chunk_header[5],
+            chunk_header[6],
+            chunk_header[7],
+        ]);
+
+        match &id {
+            b"fmt " => {
+                let mut fmt = vec![0u8; len as usize];
+                file.read_exact(&mut fmt)?;
+                if fmt.len() < 16 {
+                    return Err(format!("{} has an invalid fmt chunk", path.display()).into());
+                }
+
+                let format_tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                info.codec = match format_tag {
+                    1 | 0xFFFE => "PCM",
+                    3 => "IEEE float",
+                    _ => "Unknown",
+                }
+                .into();
+                info.channels = Some(u16::from_le_bytes([fmt[2], fmt[3]]));
+                info.sample_rate = Some(u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]));
+                block_align = u16::from_le_bytes([fmt[12], fmt[13]]);
+                info.bit_depth = Some(u16::from_le_bytes([fmt[14], fmt[15]]));
+            }
+            b"data" => {
+                data_len = Some(u64::from(len));
+                break;
+            }
+            _ => {
+                file.seek(SeekFrom::Current(i64::from(len)))?;
+            }
+        }
+
+        // Chunks are padded to an even length.
+        if len % 2 == 1 {
+            file.seek(SeekFrom::Current(1))?;
+        }
+    }
+
+    if let (Some(data_len), true) = (data_len, block_align > 0) {
+        info.frames = Some(data_len / u64::from(block_align));
+    }
+
+    Ok(info)
+}
+
+/// The properties of the selected clip, shown in the clip inspector.
+///
+/// This is updated by the program layer whenever the selection or the selected
+/// clip changes, and may not be mutated directly by the UI.
+#[derive(Debug, Lens, Clone, Data)]
+pub struct ClipInspector {
+    /// The index of the inspected clip in `UiState::clips`.
+    pub clip_index: usize,
+    /// A copy of the inspected clip.
+    pub clip: ClipState,
+    /// Information about the clip's audio file, or `None` if this is not an
+    /// audio clip or its file could not be read.
+    pub resource_info: Option<PcmResourceInfo>,
+    /// True if the audio file is resampled to the project's sample rate when it
+    /// is loaded.
+    pub resampled: bool,
+    /// True if the audio file is streamed from disk instead of being loaded
+    /// into memory.
+    ///
+    /// TODO: Streaming is not supported yet, so this is always `false`.
+    pub streaming: bool,
+    /// The number of channels the clip actually plays (see
+    /// `ClipChannelMode::output_channels()`), or `None` if this is not an audio
+    /// clip or the number of channels of its file is unknown.
+    pub output_channels: Option<usize>,
+}
+
+impl ClipInspector {
+    /// Creates the inspector data for `clip`, given the information about its
+    /// audio file and the project's sample rate.
+    pub fn new(
+        clip_index: usize,
+        clip: &ClipState,
+        resource_info: Option<PcmResourceInfo>,
+        project_sample_rate: SampleRate,
+    ) -> Self {
+        let resampled = match (&clip.type_, &resource_info) {
+            (ClipType::Audio(_), Some(info)) => {
+                info.sample_rate.map(|sr| sr != project_sample_rate.as_u32()).unwrap_or(false)
+            }
+            _ => false,
+        };
+
+        let output_channels = match (&clip.type_, &resource_info) {
+            (ClipType::Audio(audio_clip), Some(info)) => info
+                .channels
+                .map(|channels| audio_clip.channel_mode.output_channels(usize::from(channels))),
+            _ => None,
+        };
+
+        Self {
+            clip_index,
+            clip: clip.clone(),
+            resource_info,
+            resampled,
+            streaming: false,
+            output_channels,
+        }
+    }
+}
@@ -1,7 +1,16 @@
 use super::{ChannelBaseColor, UiEvent};
+use serde::{Deserialize, Serialize};
 use std::ops::RangeBounds;
 use vizia::prelude::*;
 
+/// The height of a minimized lane (where 1.0 means the "global default lane
+/// height").
+pub const MINIMIZED_LANE_HEIGHT: f64 = 0.25;
+
+/// The height of a new automation sub-lane (where 1.0 means the "global default
+/// lane height").
+pub const DEFAULT_SUB_LANE_HEIGHT: f64 = 0.5;
+
 /// The state of every lane in the timeline.
 #[derive(Debug, Lens, Clone)]
 pub struct LaneStates {
@@ -139,6 +148,76 @@ impl LaneStates {
         self.lanes.clear();
     }
 
+    // ----- Layout -----
+
+    /// Sets the height of the lane at `index`, where `None` means it uses the
+    /// global default lane height.
+    pub fn set_lane_height(&mut self, index: usize, height: Option<f64>) {
+        if let Some(lane) = self.lanes.get_mut(index) {
+            lane.height = height;
+        }
+    }
+
+    /// Minimizes the lane at `index` if it is expanded, or expands it if it is
+    /// minimized.
+    pub fn toggle_minimized(&mut self, index: usize) {
+        if let Some(lane) = self.lanes.get_mut(index) {
+            lane.minimized ^= true;
+        }
+    }
+
+    /// Shows the automation sub-lane of `param` under every lane that belongs to
+    /// the channel at `channel`, adding the sub-lane if it does not exist yet.
+    ///
+    /// Returns `false` if no lane belongs to the channel.
+    pub fn expand_automation_lane(&mut self, channel: usize, param: AutomationParam) -> bool {
+        let mut found = false;
+        for lane in self.lanes.iter_mut().filter(|lane| lane.channel == Some(channel)) {
+            found = true;
+            lane.minimized = false;
+            match lane.sub_lanes.iter_mut().find(|sub_lane| sub_lane.param == param) {
+                Some(sub_lane) => sub_lane.visible = true,
+                None => lane.sub_lanes.push(SubLaneState::new(param.clone())),
+            }
+        }
+        found
+    }
+
+    /// Returns the height of all lanes and their sub-lanes combined (in units of
+    /// "lanes"), where `default_height` is `TimelineGridState::lane_height`.
+    ///
+    /// This can be used to properly set the vertical scroll bar.
+    pub fn total_content_height(&self, default_height: f64) -> f64 {
+        self.lanes.iter().map(|lane| lane.total_height(default_height)).sum()
+    }
+
+    /// Returns the lane or sub-lane at the vertical position `y` (in units of
+    /// "lanes", measured from the top of the first lane), where
+    /// `default_height` is `TimelineGridState::lane_height`.
+    ///
+    /// Returns `None` if `y` is above the first lane or below the last one.
+    pub fn lane_at_y(&self, y: f64, default_height: f64) -> Option<LaneRef> {
+        if y < 0.0 {
+            return None;
+        }
+
+        let mut top = 0.0;
+        for (index, lane) in self.lanes.iter().enumerate() {
+            top += lane.visible_height(default_height);
+            if y < top {
+                return Some(LaneRef::Lane(index));
+            }
+
+            for (sub_lane_index, sub_lane) in lane.visible_sub_lanes() {
+                top += sub_lane.height;
+                if y < top {
+                    return Some(LaneRef::SubLane { lane: index, sub_lane: sub_lane_index });
+                }
+            }
+        }
+        None
+    }
+
     // ----- Utilities -----
 
     /// Returns the selected lane index moved by the given `amount` or `None` if it is out of bounds.
@@ -253,6 +332,9 @@ impl Model for LaneStates {
                     self.select_lane(index);
                 }
             }
+            UiEvent::ToggleLaneMinimized(index) => {
+                self.toggle_minimized(*index);
+            }
             UiEvent::ActivateSelectedLanes => {
                 self.selected_lanes_mut().for_each(|x| x.disabled = false);
             }
@@ -267,7 +349,7 @@ impl Model for LaneStates {
     }
 }
 
-#[derive(Debug, Lens, Clone)]
+#[derive(Debug, Lens, Clone, Serialize, Deserialize)]
 pub struct LaneState {
     /// The name of this lane.
     ///
@@ -292,10 +374,104 @@ pub struct LaneState {
 
     /// Represents if the lane is currently selected.
     pub selected: bool,
+
+    /// The index of the channel this lane belongs to, if any.
+    #[serde(default)]
+    pub channel: Option<usize>,
+
+    /// If true, this lane is drawn with `MINIMIZED_LANE_HEIGHT` and its
+    /// sub-lanes are hidden.
+    #[serde(default)]
+    pub minimized: bool,
+
+    /// The automation sub-lanes shown under this lane.
+    #[serde(default)]
+    pub sub_lanes: Vec<SubLaneState>,
+}
+
+impl LaneState {
+    /// Returns the height of this lane without its sub-lanes, where
+    /// `default_height` is `TimelineGridState::lane_height`.
+    pub fn visible_height(&self, default_height: f64) -> f64 {
+        if self.minimized {
+            MINIMIZED_LANE_HEIGHT
+        } else {
+            self.height.unwrap_or(default_height)
+        }
+    }
+
+    /// Returns the height of this lane including its visible sub-lanes, where
+    /// `default_height` is `TimelineGridState::lane_height`.
+    pub fn total_height(&self, default_height: f64) -> f64 {
+        self.visible_height(default_height)
+            + self.visible_sub_lanes().map(|(_, sub_lane)| sub_lane.height).sum::<f64>()
+    }
+
+    /// Returns the sub-lanes that are currently shown along with their indices.
+    pub fn visible_sub_lanes(&self) -> impl Iterator<Item = (usize, &SubLaneState)> {
+        let minimized = self.minimized;
+        self.sub_lanes
+            .iter()
+            .enumerate()
+            .filter(move |(_, sub_lane)| !minimized && sub_lane.visible)
+    }
 }
 
 impl Default for LaneState {
     fn default() -> Self {
-        Self { name: None, color: None, height: None, disabled: false, selected: false }
+        Self {
+            name: None,
+            color: None,
+            height: None,
+            disabled: false,
+            selected: false,
+            channel: None,
+            minimized: false,
+            sub_lanes: Vec::new(),
+        }
+    }
+}
+
+/// An automation lane shown under a lane in the timeline.
+#[derive(Debug, Lens, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubLaneState {
+    /// The parameter that is automated in this sub-lane.
+    pub param: AutomationParam,
+
+    /// The height of this sub-lane (where 1.0 means the "global default lane
+    /// height").
+    ///
+    /// The UI may mutate this directly without an event.
+    pub height: f64,
+
+    /// If false, this sub-lane is collapsed but keeps its settings.
+    pub visible: bool,
+}
+
+impl SubLaneState {
+    pub fn new(param: AutomationParam) -> Self {
+        Self { param, height: DEFAULT_SUB_LANE_HEIGHT, visible: true }
     }
 }
+
+/// A parameter of a channel that can be automated.
+#[derive(Debug, Clone, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub enum AutomationParam {
+    /// The output gain of the channel.
+    Gain,
+    /// The output pan of the channel.
+    Pan,
+    /// The input trim of the channel, which is applied before its effects and
+    /// its fader.
+    InputTrim,
+    /// A parameter of the effect at `effect_index` in the channel's effect
+    /// rack.
+    Effect { effect_index: usize, param_id: u32 },
+}
+
+/// A reference to a lane or to one of its sub-lanes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LaneRef {
+    Lane(usize),
+    SubLane { lane: usize, sub_lane: usize },
+}
@@ -0,0 +1,51 @@
+//! The loop region and the fades of the transport as the timeline player
+//! sees them.
+
+use super::UiData;
+use crate::backend::timeline::{DeclickTimes, LoopRange, TimelineMsg};
+
+impl UiData {
+    /// The loop region of the transport in frames, or `None` if looping is
+    /// disabled.
+    pub(super) fn timeline_loop_range(&self) -> Option<LoopRange> {
+        let transport = &self.state.transport;
+        if !transport.is_looping {
+            return None;
+        }
+        let sample_rate = self.sample_rate.get();
+        Some(LoopRange {
+            start: self
+                .state
+                .tempo_map
+                .musical_to_frames(transport.loop_start.get(), sample_rate)
+                .0,
+            end: self.state.tempo_map.musical_to_frames(transport.loop_end.get(), sample_rate).0,
+        })
+    }
+
+    /// The lengths of the fades of the transport in frames, with the crossfade
+    /// at the loop point clamped to the material before the loop start.
+    pub(super) fn timeline_declick(&self) -> DeclickTimes {
+        let transport = &self.state.transport;
+        let pre_roll = self.state.tempo_map.musical_to_seconds(transport.loop_start.get());
+        let frames = |secs: f64| (secs * self.sample_rate.get().0).round() as u64;
+        DeclickTimes {
+            start_stop: frames(transport.start_stop_fade_secs.get().0),
+            seek: frames(transport.seek_crossfade_secs.get().0),
+            loop_point: frames(transport.loop_crossfade(pre_roll).0),
+        }
+    }
+
+    /// Sends the loop region of the transport and the fades of the transport
+    /// to the timeline player. If the player is not keeping up, everything is
+    /// sent again on the next poll.
+    pub(super) fn send_loop_to_timeline(&mut self) {
+        let range = self.timeline_loop_range();
+        let declick = self.timeline_declick();
+        if !self.send_to_timeline(TimelineMsg::SetLoop(range))
+            || !self.send_to_timeline(TimelineMsg::SetDeclick(declick))
+        {
+            self.timeline_synced = false;
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! The audio and MIDI files of the project: importing and loading them, and
+//! relinking the ones that went missing.
+
+use dropseed_resource_loader::{PcmKey, ResampleQuality};
+use meadowlark_core_types::time::MusicalTime;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use super::{
+    describe_load_error, read_midi_file, sanitize_bpm, write_midi_file, AudioClipState, ClipId,
+    ClipStart, ClipState, ClipType, NotificationLogType, OnLane, PcmResourceInfo,
+    PianoRollClipState, StateChange, UiData,
+};
+use crate::backend::decode;
+
+impl UiData {
+    /// Marks every audio clip whose file no longer exists as missing, and adds a
+    /// notification listing them so the user can relink them.
+    ///
+    /// This should be called after a project is loaded.
+    pub fn check_missing_audio_clips(&mut self) {
+        let mut missing_paths = Vec::new();
+        for (index, clip) in self.state.clips.iter_mut().enumerate() {
+            if let ClipType::Audio(audio_clip) = &mut clip.type_ {
+                let missing = !audio_clip.pcm_path.is_file();
+                if missing != audio_clip.missing {
+                    audio_clip.missing = missing;
+                    self.state.changes.push(StateChange::ClipChanged { index });
+                }
+                if missing {
+                    missing_paths.push(format!("{}", audio_clip.pcm_path.display()));
+                }
+            }
+        }
+
+        if !missing_paths.is_empty() {
+            self.notification_log.push(NotificationLogType::Error(format!(
+                "Could not find the following audio files. Please relink them:\n{}",
+                missing_paths.join("\n")
+            )));
+        }
+    }
+
+    /// Points the audio clip at index `clip_index` to the file at `new_path` and
+    /// loads it.
+    ///
+    /// If the new file is shorter than the part of the old file the clip plays,
+    /// or has a different number of channels, the file is still relinked but
+    /// the user is told about it.
+    ///
+    /// Returns `true` if the file was loaded successfully.
+    pub fn relink_audio_clip(&mut self, clip_index: usize, new_path: PathBuf) -> bool {
+        let bpm = self.state.timeline_grid.bpm;
+        let (old_info, read_end) = match self.state.clips.get(clip_index) {
+            Some(ClipState { type_: ClipType::Audio(audio_clip), length, .. }) => {
+                // The position in the file where the clip stops reading.
+                let length_secs = length.get().as_beats_f64() * 60.0 / bpm;
+                (
+                    self.resource_info.get(&audio_clip.pcm_path).cloned(),
+                    audio_clip.source_start().0 + length_secs * audio_clip.playback_rate(),
+                )
+            }
+            _ => return false,
+        };
+        self.resource_info.remove(&new_path);
+        let new_info = self.file_resource_info(&new_path);
+
+        let audio_clip = match self.state.clips.get_mut(clip_index).map(|c| &mut c.type_) {
+            Some(ClipType::Audio(audio_clip)) => audio_clip,
+            _ => return false,
+        };
+
+        let (_pcm, res) = self.resource_loader.pcm_loader.load(&PcmKey {
+            path: new_path.clone(),
+            resample_to_project_sr: true,
+            quality: ResampleQuality::Linear,
+        });
+
+        match res {
+            Ok(()) => {
+                if let Some(new_info) = &new_info {
+                    let name = new_path.file_name().unwrap_or_default().to_string_lossy();
+                    if new_info.duration().map(|d| d.0 < read_end).unwrap_or(false) {
+                        self.notification_log.push(NotificationLogType::Info(format!(
+                            "{} is shorter than the clip it was relinked to. The end of the clip will be silent.",
+                            name
+                        )));
+                    }
+                    let old_channels = old_info.as_ref().and_then(|i| i.channels);
+                    if old_channels.is_some() && new_info.channels != old_channels {
+                        self.notification_log.push(NotificationLogType::Info(format!(
+                            "{} has a different number of channels than the file it replaces.",
+                            name
+                        )));
+                    }
+                }
+
+                audio_clip.pcm_path = new_path;
+                audio_clip.missing = false;
+                self.state.changes.push(StateChange::ClipChanged { index: clip_index });
+                true
+            }
+            Err(e) => {
+                self.notification_log.push(NotificationLogType::Error(format!(
+                    "Failed to load {}: {}",
+                    new_path.display(),
+                    describe_load_error(&new_path, e)
+                )));
+                false
+            }
+        }
+    }
+
+    /// Returns the information about the audio file of the audio clip at
+    /// `index` (its channel count, length in frames and native sample rate),
+    /// reading it from the file the first time it is asked for.
+    ///
+    /// Returns `None` if the clip is not an audio clip, if its file is missing,
+    /// or if the file could not be read.
+    pub fn clip_source_info(&mut self, index: usize) -> Option<PcmResourceInfo> {
+        let path = match self.state.clips.get(index).map(|c| &c.type_) {
+            Some(ClipType::Audio(audio_clip)) if !audio_clip.missing => audio_clip.pcm_path.clone(),
+            _ => return None,
+        };
+        self.file_resource_info(&path)
+    }
+
+    /// Returns the information about the audio file at `path`, reading it from
+    /// the file if it isn't known yet.
+    fn file_resource_info(&mut self, path: &Path) -> Option<PcmResourceInfo> {
+        if !self.resource_info.contains_key(path) {
+            match PcmResourceInfo::probe(path) {
+                Ok(info) => {
+                    self.resource_info.insert(path.to_path_buf(), info);
+                }
+                Err(e) => {
+                    log::error!("Failed to read info of {}: {}", path.display(), e);
+                }
+            }
+        }
+        self.resource_info.get(path).cloned()
+    }
+
+    /// Loads the audio of the audio clip at `index` (resampled to the project's
+    /// sample rate), with one buffer per channel. Files with more than two
+    /// channels are reduced to stereo according to the clip's
+    /// `multichannel_mode`.
+    ///
+    /// This is meant for offline analysis, not for playback.
+    pub fn load_clip_audio(&mut self, index: usize) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let (pcm_path, multichannel_mode) = match self.state.clips.get(index).map(|c| &c.type_) {
+            Some(ClipType::Audio(audio_clip)) => {
+                (audio_clip.pcm_path.clone(), audio_clip.multichannel_mode)
+            }
+            _ => return Err("Not an audio clip".into()),
+        };
+
+        Ok(multichannel_mode.apply(self.load_audio_file(pcm_path)?))
+    }
+
+    /// Loads the audio file at `path` (resampled to the project's sample rate),
+    /// with one buffer per channel.
+    ///
+    /// Files that the resource loader can't read are decoded with
+    /// `decode::decode_file()` instead.
+    pub(super) fn load_audio_file(
+        &mut self,
+        path: PathBuf,
+    ) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let (pcm, res) = self.resource_loader.pcm_loader.load(&PcmKey {
+            path: path.clone(),
+            resample_to_project_sr: true,
+            quality: ResampleQuality::Linear,
+        });
+        if res.is_err() {
+            let decoded = decode::decode_file(&path)?;
+            let sample_rate = self.sample_rate.get();
+            return Ok(decoded
+                .buffers
+                .iter()
+                .map(|b| decode::resample_linear(b, f64::from(decoded.sample_rate), sample_rate.0))
+                .collect());
+        }
+
+        let len_frames = pcm.len_frames() as usize;
+        let mut buffers = Vec::with_capacity(pcm.channels());
+        for channel in 0..pcm.channels() {
+            let mut buffer = vec![0.0; len_frames];
+            pcm.fill_channel_f32(channel, 0, &mut buffer)?;
+            buffers.push(buffer);
+        }
+
+        Ok(buffers)
+    }
+
+    /// Adds a clip that plays the audio file at `path` on the lane `lane_index`
+    /// at `start`, routed to the channel at `channel`.
+    ///
+    /// The tempo of the file is analyzed in the background. If `fit_to_tempo` is
+    /// true and the file has a clear tempo, the clip is then stretched so that
+    /// it lasts a whole number of bars at the project tempo.
+    ///
+    /// Returns the index of the new clip.
+    pub fn import_audio_file(
+        &mut self,
+        path: &Path,
+        channel: usize,
+        lane_index: u32,
+        start: MusicalTime,
+        fit_to_tempo: bool,
+    ) -> Result<usize, Box<dyn Error>> {
+        let info = PcmResourceInfo::probe(path)?;
+        let duration = info.duration().ok_or("The length of the audio file is unknown")?;
+        self.resource_info.insert(path.to_path_buf(), info);
+
+        let bpm = self.state.timeline_grid.bpm;
+        let name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+
+        let index = self.state.add_clip(ClipState {
+            id: ClipId::default(),
+            name,
+            label: None,
+            notes: String::new(),
+            z_order: 0,
+            color: None,
+            timeline_start: ClipStart::OnLane(OnLane { lane_index, timeline_start: start.into() }),
+            length: MusicalTime::from_beats_f64(duration.0 * bpm / 60.0).into(),
+            channel,
+            type_: ClipType::Audio(AudioClipState::new(path.to_path_buf())),
+        });
+
+        match self.tempo_analysis.get(path) {
+            Some(estimate) => {
+                if fit_to_tempo {
+                    self.fit_clip_to_tempo(index, *estimate);
+                }
+            }
+            None => {
+                if fit_to_tempo {
+                    let id = self.state.clips[index].id;
+                    self.pending_tempo_fits.push((id, path.to_path_buf()));
+                }
+                if self.tempo_analyses_in_progress.insert(path.to_path_buf()) {
+                    self.tempo_analyzer.analyze(path.to_path_buf());
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Relinks every missing audio clip whose file name exists in `folder`.
+    pub fn relink_missing_audio_clips_in_folder(&mut self, folder: &Path) {
+        let mut relinks = Vec::new();
+        for (index, clip) in self.state.clips.iter().enumerate() {
+            if let ClipType::Audio(audio_clip) = &clip.type_ {
+                if let (true, Some(file_name)) =
+                    (audio_clip.missing, audio_clip.pcm_path.file_name())
+                {
+                    let new_path = folder.join(file_name);
+                    if new_path.is_file() {
+                        relinks.push((index, new_path));
+                    }
+                }
+            }
+        }
+
+        for (index, new_path) in relinks {
+            self.relink_audio_clip(index, new_path);
+        }
+    }
+
+    /// Imports the MIDI file at `path` into the clips panel, with one piano
+    /// roll clip for every track and channel that contains notes. The clips
+    /// are assigned to the mixer channel at `channel`.
+    ///
+    /// If `import_tempo` is true, the project tempo is set to the first tempo
+    /// of the file.
+    ///
+    /// Returns the indices of the new clips.
+    pub fn import_midi_file(
+        &mut self,
+        path: &Path,
+        channel: usize,
+        import_tempo: bool,
+    ) -> Result<Vec<usize>, Box<dyn Error>> {
+        let import = read_midi_file(path)?;
+
+        if import_tempo {
+            if let Some(first) = import.tempo_changes.first() {
+                self.state.set_tempo(sanitize_bpm(first.bpm));
+            }
+            if import.tempo_changes.len() > 1 {
+                // TODO: Import all tempo changes once the project has a tempo map.
+                self.notification_log.push(NotificationLogType::Info(format!(
+                    "{} contains tempo changes. Only the first tempo was imported.",
+                    path.display()
+                )));
+            }
+        }
+
+        let mut new_indices = Vec::with_capacity(import.patterns.len());
+        for pattern in import.patterns {
+            let end = pattern
+                .notes
+                .iter()
+                .map(|note| (note.start.get() + note.length.get()).as_beats_f64())
+                .fold(0.0, f64::max);
+            // Round the length up to a whole bar of 4/4.
+            let length = MusicalTime::from_beats(((end / 4.0).ceil().max(1.0) * 4.0) as u32);
+
+            let index = self.state.add_clip(ClipState {
+                id: ClipId::default(),
+                name: pattern.name,
+                label: None,
+                notes: String::new(),
+                z_order: 0,
+                color: None,
+                timeline_start: ClipStart::NotInTimeline,
+                length: length.into(),
+                channel,
+                type_: ClipType::PianoRoll(PianoRollClipState { notes: pattern.notes }),
+            });
+            new_indices.push(index);
+        }
+
+        Ok(new_indices)
+    }
+
+    /// Exports the notes of the piano roll clip at `index` to a MIDI file at
+    /// `path`, using the project tempo.
+    pub fn export_clip_midi(&self, index: usize, path: &Path) -> Result<(), Box<dyn Error>> {
+        let clip = self.state.clips.get(index).ok_or("The clip does not exist")?;
+        match &clip.type_ {
+            ClipType::PianoRoll(piano_roll) => {
+                write_midi_file(path, &clip.name, &piano_roll.notes, self.state.timeline_grid.bpm)
+            }
+            _ => Err("Only piano roll clips can be exported to MIDI".into()),
+        }
+    }
+}
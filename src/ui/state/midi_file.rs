@@ -0,0 +1,451 @@
+use super::NoteState;
+use meadowlark_core_types::time::MusicalTime;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::Path;
+
+/// The resolution of exported MIDI files in ticks per quarter note.
+pub const MIDI_EXPORT_PPQ: u16 = 960;
+
+/// The notes of one track and channel of an imported MIDI file.
+#[derive(Debug, Clone)]
+pub struct MidiPattern {
+    /// The name of the track (or a generated name if the track has none).
+    pub name: String,
+    /// The MIDI channel of the notes (0-15).
+    pub channel: u8,
+    /// The notes sorted by start time.
+    pub notes: Vec<NoteState>,
+}
+
+/// A change of tempo in an imported MIDI file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MidiTempoChange {
+    pub time: MusicalTime,
+    pub bpm: f64,
+}
+
+/// The contents of a MIDI file.
+#[derive(Debug, Clone)]
+pub struct MidiImport {
+    /// One pattern for every track and channel combination that contains
+    /// notes.
+    pub patterns: Vec<MidiPattern>,
+    /// The tempo changes of the file, sorted by time.
+    pub tempo_changes: Vec<MidiTempoChange>,
+}
+
+/// Reads a standard MIDI file (format 0 or 1) from `path`.
+pub fn read_midi_file(path: &Path) -> Result<MidiImport, Box<dyn Error>> {
+    parse_midi_file(&std::fs::read(path)?)
+}
+
+/// Writes `notes` to a format 0 standard MIDI file at `path` with a single
+/// tempo of `bpm`.
+pub fn write_midi_file(
+    path: &Path,
+    name: &str,
+    notes: &[NoteState],
+    bpm: f64,
+) -> Result<(), Box<dyn Error>> {
+    let to_ticks =
+        |time: MusicalTime| (time.as_beats_f64() * f64::from(MIDI_EXPORT_PPQ)).round() as u64;
+
+    // (tick, is_note_on, status, pitch, velocity)
+    let mut events = Vec::with_capacity(notes.len() * 2);
+    for note in notes.iter() {
+        let start = to_ticks(note.start.get());
+        let end = start + to_ticks(note.length.get()).max(1);
+        let channel = note.channel & 0x0F;
+
+        events.push((start, true, 0x90 | channel, note.pitch & 0x7F, note.velocity.clamp(1, 127)));
+        events.push((end, false, 0x80 | channel, note.pitch & 0x7F, 0));
+    }
+    // Note-offs go before note-ons at the same tick so that repeated notes are
+    // not cut short.
+    events.sort_by_key(|(tick, is_note_on, ..)| (*tick, *is_note_on));
+
+    let mut track = Vec::new();
+
+    if !name.is_empty() {
+        write_vlq(&mut track, 0);
+        track.extend_from_slice(&[0xFF, 0x03]);
+        write_vlq(&mut track, name.len() as u64);
+        track.extend_from_slice(name.as_bytes());
+    }
+
+    let micros_per_beat = (60_000_000.0 / bpm).round().clamp(1.0, 16_777_215.0) as u32;
+    write_vlq(&mut track, 0);
+    track.extend_from_slice(&[0xFF, 0x51, 0x03]);
+    track.extend_from_slice(&micros_per_beat.to_be_bytes()[1..4]);
+
+    let mut last_tick = 0;
+    for (tick, _, status, pitch, velocity) in events {
+        write_vlq(&mut track, tick - last_tick);
+        track.extend_from_slice(&[status, pitch, velocity]);
+        last_tick = tick;
+    }
+
+    write_vlq(&mut track, 0);
+    track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+    let mut bytes = Vec::with_capacity(track.len() + 22);
+    bytes.extend_from_slice(b"MThd");
+    bytes.extend_from_slice(&6u32.to_be_bytes());
+    bytes.extend_from_slice(&0u16.to_be_bytes());
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&MIDI_EXPORT_PPQ.to_be_bytes());
+    bytes.extend_from_slice(b"MTrk");
+    bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&track);
+
+    std::fs::write(path, bytes)?;
+
+    Ok(())
+}
+
+fn parse_midi_file(bytes: &[u8]) -> Result<MidiImport, Box<dyn Error>> {
+    let mut reader = Reader { bytes, pos: 0 };
+
+    if reader.take(4)? != b"MThd" {
+        return Err("Not a MIDI file".into());
+    }
+    let header_len = reader.u32()? as usize;
+    let header = reader.take(header_len)?;
+    if header.len() < 6 {
+        return Err("The MIDI file header is too short".into());
+    }
+    let format = u16::from_be_bytes([header[0], header[1]]);
+    let num_tracks = u16::from_be_bytes([header[2], header[3]]);
+    let division = u16::from_be_bytes([header[4], header[5]]);
+
+    if format > 1 {
+        return Err(format!("MIDI file format {} is not supported", format).into());
+    }
+    if division & 0x8000 != 0 {
+        return Err("MIDI files with SMPTE time division are not supported".into());
+    }
+    if division == 0 {
+        return Err("The MIDI file has an invalid time division".into());
+    }
+    let ppq = f64::from(division);
+    let to_time = |tick: u64| MusicalTime::from_beats_f64(tick as f64 / ppq);
+
+    let mut patterns = Vec::new();
+    let mut tempo_changes = Vec::new();
+
+    for track_index in 0..num_tracks {
+        // Skip any unknown chunks before the track.
+        let track = loop {
+            let id = reader.take(4)?;
+            let len = reader.u32()? as usize;
+            let data = reader.take(len)?;
+            if id == b"MTrk" {
+                break data;
+            }
+        };
+
+        let mut track_reader = Reader { bytes: track, pos: 0 };
+        let mut track_name = None;
+        let mut tick = 0u64;
+        let mut running_status = None;
+
+        // The notes of each channel, and the start tick and velocity of the
+        // notes that are still held (per channel and pitch).
+        let mut notes: [Vec<NoteState>; 16] = Default::default();
+        let mut held: Vec<VecDeque<(u64, u8)>> = vec![VecDeque::new(); 16 * 128];
+
+        while !track_reader.is_empty() {
+            tick += track_reader.vlq()?;
+
+            let mut status = track_reader.u8()?;
+            let first_data = if status < 0x80 {
+                // Running status: this byte is already the first data byte.
+                let data = status;
+                status = running_status.ok_or("MIDI data byte without a status byte")?;
+                Some(data)
+            } else {
+                None
+            };
+
+            match status {
+                // Meta and system exclusive events cancel the running status.
+                0xFF => {
+                    running_status = None;
+                    let meta_type = track_reader.u8()?;
+                    let len = track_reader.vlq()? as usize;
+                    let data = track_reader.take(len)?;
+                    match meta_type {
+                        0x03 if track_name.is_none() => {
+                            track_name = Some(String::from_utf8_lossy(data).into_owned());
+                        }
+                        0x51 if data.len() == 3 => {
+                            let micros = u32::from_be_bytes([0, data[0], data[1], data[2]]);
+                            if micros > 0 {
+                                tempo_changes.push(MidiTempoChange {
+                                    time: to_time(tick),
+                                    bpm: 60_000_000.0 / f64::from(micros),
+                                });
+                            }
+                        }
+                        0x2F => break,
+                        _ => {}
+                    }
+                }
+                0xF0 | 0xF7 => {
+                    running_status = None;
+                    let len = track_reader.vlq()? as usize;
+                    track_reader.take(len)?;
+                }
+                0x80..=0xEF => {
+                    running_status = Some(status);
+                    let channel = status & 0x0F;
+
+                    let data1 = match first_data {
+                        Some(data) => data,
+                        None => track_reader.u8()?,
+                    };
+                    let data2 = match status & 0xF0 {
+                        0xC0 | 0xD0 => 0,
+                        _ => track_reader.u8()?,
+                    };
+
+                    let kind = status & 0xF0;
+                    let held_notes =
+                        &mut held[usize::from(channel) * 128 + usize::from(data1 & 0x7F)];
+                    if kind == 0x90 && data2 > 0 {
+                        held_notes.push_back((tick, data2));
+                    } else if kind == 0x80 || kind == 0x90 {
+                        // A note-on with a velocity of 0 is a note-off. When the
+                        // same pitch overlaps itself, the earliest note ends
+                        // first.
+                        if let Some((start, velocity)) = held_notes.pop_front() {
+                            notes[usize::from(channel)].push(NoteState {
+                                start: to_time(start).into(),
+                                length: to_time(tick - start).into(),
+                                pitch: data1 & 0x7F,
+                                velocity,
+                                channel,
+                            });
+                        }
+                    }
+                }
+                _ => {
+                    // System common and realtime messages don't belong in a
+                    // MIDI file. Skip the status byte.
+                }
+            }
+        }
+
+        // End any notes that are still held at the end of the track.
+        for (index, held_notes) in held.iter_mut().enumerate() {
+            let (channel, pitch) = ((index / 128) as u8, (index % 128) as u8);
+            for (start, velocity) in held_notes.drain(..) {
+                notes[usize::from(channel)].push(NoteState {
+                    start: to_time(start).into(),
+                    length: to_time(tick - start).into(),
+                    pitch,
+                    velocity,
+                    channel,
+                });
+            }
+        }
+
+        let used_channels = notes.iter().filter(|n| !n.is_empty()).count();
+        for (channel, mut channel_notes) in notes.into_iter().enumerate() {
+            if channel_notes.is_empty() {
+                continue;
+            }
+            channel_notes.sort_by(|a, b| {
+                a.start
+                    .get()
+                    .partial_cmp(&b.start.get())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then(a.pitch.cmp(&b.pitch))
+            });
+
+            let base_name =
+                track_name.clone().unwrap_or_else(|| format!("Track {}", track_index + 1));
+            let name = if used_channels > 1 {
+                format!("{} (Ch. {})", base_name, channel + 1)
+            } else {
+                base_name
+            };
+
+            patterns.push(MidiPattern { name, channel: channel as u8, notes: channel_notes });
+        }
+    }
+
+    tempo_changes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+
+    Ok(MidiImport { patterns, tempo_changes })
+}
+
+fn write_vlq(out: &mut Vec<u8>, mut value: u64) {
+    let mut buf = [0u8; 10];
+    let mut i = buf.len() - 1;
+    buf[i] = (value & 0x7F) as u8;
+    value >>= 7;
+    while value > 0 {
+        i -= 1;
+        buf[i] = 0x80 | (value & 0x7F) as u8;
+        value >>= 7;
+    }
+    out.extend_from_slice(&buf[i..]);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len());
+        match end {
+            Some(end) => {
+                let data = &self.bytes[self.pos..end];
+                self.pos = end;
+                Ok(data)
+            }
+            None => Err("Unexpected end of MIDI file".into()),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Reads a variable-length quantity.
+    fn vlq(&mut self) -> Result<u64, Box<dyn Error>> {
+        let mut value = 0u64;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            value = (value << 7) | u64::from(b & 0x7F);
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid variable-length quantity in MIDI file".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(start_beats: f64, length_beats: f64, pitch: u8, velocity: u8) -> NoteState {
+        NoteState {
+            start: MusicalTime::from_beats_f64(start_beats).into(),
+            length: MusicalTime::from_beats_f64(length_beats).into(),
+            pitch,
+            velocity,
+            channel: 0,
+        }
+    }
+
+    /// Wraps the events of each track in `tracks` into a format 1 file with a
+    /// resolution of 480 ticks per quarter note.
+    fn smf(tracks: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"MThd");
+        bytes.extend_from_slice(&6u32.to_be_bytes());
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&480u16.to_be_bytes());
+        for track in tracks {
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(track);
+        }
+        bytes
+    }
+
+    #[test]
+    fn exported_notes_import_unchanged() {
+        let notes = vec![
+            note(0.0, 1.0, 60, 100),
+            // The same pitch right after the first note.
+            note(1.0, 0.5, 60, 80),
+            note(1.5, 0.25, 64, 127),
+            // Overlapping notes of the same pitch.
+            note(2.0, 1.5, 67, 1),
+            note(3.0, 1.5, 67, 64),
+        ];
+        let dir = std::env::temp_dir().join("meadowlark-midi-file-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("round_trip.mid");
+
+        write_midi_file(&path, "Lead", &notes, 132.0).unwrap();
+        let import = read_midi_file(&path).unwrap();
+
+        assert_eq!(import.patterns.len(), 1);
+        assert_eq!(import.patterns[0].name, "Lead");
+        assert_eq!(import.patterns[0].channel, 0);
+        assert_eq!(import.patterns[0].notes, notes);
+
+        assert_eq!(import.tempo_changes.len(), 1);
+        assert_eq!(import.tempo_changes[0].time, MusicalTime::from_beats(0));
+        assert!((import.tempo_changes[0].bpm - 132.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tempo_changes_in_the_middle_of_a_file_are_read() {
+        #[rustfmt::skip]
+        let tempo_track: &[u8] = &[
+            // 120 BPM at the start.
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+            // 90 BPM after two beats (960 ticks).
+            0x87, 0x40, 0xFF, 0x51, 0x03, 0x0A, 0x2C, 0x2B,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        #[rustfmt::skip]
+        let note_track: &[u8] = &[
+            0x00, 0x90, 0x3C, 0x64,
+            // A note-off with running status after four beats.
+            0x8F, 0x00, 0x3C, 0x00,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let import = parse_midi_file(&smf(&[tempo_track, note_track])).unwrap();
+
+        let times: Vec<f64> = import.tempo_changes.iter().map(|t| t.time.as_beats_f64()).collect();
+        assert_eq!(times, vec![0.0, 2.0]);
+        assert!((import.tempo_changes[0].bpm - 120.0).abs() < 1e-3);
+        assert!((import.tempo_changes[1].bpm - 90.0).abs() < 1e-3);
+
+        assert_eq!(import.patterns.len(), 1);
+        assert_eq!(import.patterns[0].notes, vec![note(0.0, 4.0, 60, 100)]);
+    }
+
+    #[test]
+    fn running_status_does_not_carry_over_meta_or_sysex_events() {
+        #[rustfmt::skip]
+        let after_meta: &[u8] = &[
+            0x00, 0x90, 0x3C, 0x64,
+            0x00, 0xFF, 0x01, 0x01, b'x',
+            // A data byte that would continue the note-on before the text.
+            0x10, 0x3C, 0x00,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        assert!(parse_midi_file(&smf(&[after_meta])).is_err());
+
+        #[rustfmt::skip]
+        let after_sysex: &[u8] = &[
+            0x00, 0x90, 0x3C, 0x64,
+            0x00, 0xF0, 0x02, 0x7E, 0xF7,
+            0x10, 0x3C, 0x00,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        assert!(parse_midi_file(&smf(&[after_sysex])).is_err());
+    }
+}
@@ -1,57 +1,119 @@
 use crossbeam::channel::Receiver;
 use dropseed::plugin::PluginSaveState;
-use dropseed::plugin::{HostInfo, ParamID, PluginInstanceID};
+use dropseed::plugin::{ParamID, PluginInstanceID};
 use dropseed::{
-    transport::TransportHandle, ActivateEngineSettings, ActivatePluginError, DSEngineEvent,
+    transport::TransportHandle, ActivatePluginError, AudioGraphSaveState, DSEngineEvent,
     DSEngineHandle, DSEngineRequest, EdgeReq, EdgeReqPortID, EngineActivatedInfo,
     EngineDeactivatedInfo, ModifyGraphRequest, ModifyGraphRes, ParamModifiedInfo,
     PluginActivationStatus, PluginEvent, PluginHandle, PluginIDReq, PluginScannerEvent, PortType,
     RescanPluginDirectoriesRes,
 };
 use dropseed_resource_loader::{PcmKey, ResampleQuality, ResourceLoader};
-use dropseed_sample_browser_plug::{
-    SampleBrowserPlugFactory, SampleBrowserPlugHandle, SAMPLE_BROWSER_PLUG_RDN,
-};
-use fnv::FnvHashMap;
+use dropseed_sample_browser_plug::{SampleBrowserPlugHandle, SAMPLE_BROWSER_PLUG_RDN};
+use fnv::{FnvHashMap, FnvHashSet};
 use meadowlark_core_types::time::{MusicalTime, SampleRate};
 use smallvec::SmallVec;
 use std::error::Error;
-use std::{fmt::Debug, path::PathBuf};
+use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 use vizia::prelude::*;
 
+use crate::backend::count_in::CountIn;
+use crate::backend::decode;
+use crate::backend::engine;
+use crate::backend::rt_log::{RtEvent, RtLogRecord};
 use crate::backend::system_io::{self, SystemIOStreamHandle};
+use crate::backend::tempo_detect::{TempoAnalyzer, TempoEstimate};
+use crate::backend::timeline::TimelineMsg;
+use crate::ui::app_config::{
+    app_config_path, AppConfig, AppConfigSection, AppConfigWatcher, LayoutConfig,
+    LayoutSaveDebounce, RecentProject,
+};
+use crate::ui::diagnostics::RT_TRACE_LEN;
+use crate::ui::keymap::{Action, Chord};
 
+mod automation;
 mod browser;
 mod channel;
 mod clip;
+mod clip_edit;
+mod clip_ops;
+mod clip_selection;
+mod color_serde;
+mod comp;
 mod core_types;
+mod diagnostics;
+mod engine_restart;
 mod event;
+mod graph_topology;
 mod hrack_effect;
+mod ids;
+mod inspector;
 mod lane_states;
+mod loop_range;
+mod media;
+mod midi_file;
 mod panel;
+mod pending_ops;
+mod playback;
+mod project;
+mod recording;
+mod render;
+mod ruler;
+mod snap;
+mod state_change;
+mod tempo;
+mod tempo_map;
+#[cfg(test)]
+mod test_clips;
 mod timeline_grid;
+mod timeline_sync;
+mod track_template;
+mod transport;
+mod undo;
 
+pub use automation::*;
 pub use browser::*;
 pub use channel::*;
 pub use clip::*;
+pub use clip_edit::*;
+pub use clip_selection::*;
+pub use comp::*;
 pub use core_types::*;
+pub use engine_restart::*;
 pub use event::*;
+pub use graph_topology::*;
 pub use hrack_effect::*;
+pub use ids::*;
+pub use inspector::*;
 pub use lane_states::*;
+pub use midi_file::*;
 pub use panel::*;
+pub use pending_ops::*;
+pub use playback::*;
+pub use project::*;
+pub use ruler::*;
+pub use snap::*;
+pub use state_change::*;
+pub use tempo_map::*;
 pub use timeline_grid::*;
-
-// TODO: Have these be configurable.
-const MIN_FRAMES: u32 = 1;
-const MAX_FRAMES: u32 = 512;
-const GRAPH_IN_CHANNELS: u16 = 2;
-const GRAPH_OUT_CHANNELS: u16 = 2;
+pub use track_template::*;
+pub use transport::*;
+pub use undo::*;
 
 pub struct EngineHandles {
     ds_handle: DSEngineHandle,
 
     activated_info: Option<ActivatedEngineInfo>,
     sample_browser_plug_handle: Option<PluginHandle>,
+
+    /// The audio graph requests that have not been confirmed by the engine yet.
+    journal: PendingOpJournal,
 }
 
 pub struct ActivatedEngineInfo {
@@ -110,27 +172,159 @@ pub struct UiData {
     /// The UI may mutate this directly without an event.
     pub notification_log: Vec<NotificationLogType>,
 
+    /// The sample rate of the session. The engine always processes audio as
+    /// 32-bit floating point.
+    pub sample_rate: WSampleRate,
+
+    /// The properties of the selected clip, or `None` if not exactly one clip
+    /// is selected.
+    ///
+    /// This is updated by the program layer and may not be mutated directly
+    /// by the UI.
+    pub inspector: Option<ClipInspector>,
+
     /// True if a backend engine is currently running, false if not.
     ///
     /// Nothing except the settings menu can be accessed when this is false.
     pub engine_running: bool,
 
+    /// The phase correlation of the output of the channel at `correlation_bus`
+    /// in the range [-1.0, 1.0], where values below 0.0 point to problems when
+    /// the mix is summed to mono (see `backend::correlation`). The meter runs
+    /// in the timeline player.
+    ///
+    /// This is updated by the program layer and may not be mutated directly
+    /// by the UI.
+    pub correlation: f32,
+
+    /// The id of the channel whose output the correlation meter measures, or
+    /// `None` for the master channel (the master output), which is the
+    /// default.
+    pub correlation_bus: Option<ChannelId>,
+
     #[lens(ignore)]
     pub resource_loader: ResourceLoader,
 
+    /// Information about every audio file used by a clip, so files are only
+    /// read once.
+    #[lens(ignore)]
+    resource_info: FnvHashMap<PathBuf, PcmResourceInfo>,
+
+    /// The estimated tempo of every audio file that was analyzed, or `None` if
+    /// the file has no clear tempo.
+    #[lens(ignore)]
+    tempo_analysis: FnvHashMap<PathBuf, Option<TempoEstimate>>,
+
+    #[lens(ignore)]
+    tempo_analyzer: TempoAnalyzer,
+
+    #[lens(ignore)]
+    tempo_analyses_in_progress: FnvHashSet<PathBuf>,
+
+    /// The clips that are fitted to the project tempo once the analysis of their
+    /// audio file finishes. These are kept by id, since clips may be added or
+    /// removed before the analysis finishes.
+    #[lens(ignore)]
+    pending_tempo_fits: Vec<(ClipId, PathBuf)>,
+
     #[lens(ignore)]
     last_clicked_browser_file: Option<PathBuf>,
 
     #[lens(ignore)]
     system_io_stream_handle: Option<SystemIOStreamHandle>,
 
+    /// The audio the timeline player plays for each audio file and channel
+    /// setting, so that every file is only loaded once.
+    #[lens(ignore)]
+    playback_audio: FnvHashMap<(PathBuf, MultichannelMode, ClipChannelMode), Arc<Vec<Vec<f32>>>>,
+
+    /// The channels the timeline player has a track for.
+    #[lens(ignore)]
+    timeline_tracks: Vec<ChannelId>,
+
+    /// False if the whole project has to be sent to the timeline player on the
+    /// next poll (i.e. after another project was opened).
+    #[lens(ignore)]
+    timeline_synced: bool,
+
+    /// The tempo the clips in the timeline player are positioned at.
+    #[lens(ignore)]
+    timeline_bpm: f64,
+
     #[lens(ignore)]
     engine_handles: Option<(EngineHandles, Receiver<DSEngineEvent>)>,
+
+    #[lens(ignore)]
+    engine_restart: EngineRestartState,
+
+    #[lens(ignore)]
+    pub app_config: AppConfig,
+
+    #[lens(ignore)]
+    app_config_path: PathBuf,
+
+    /// The file the current project was last opened from or saved to.
+    #[lens(ignore)]
+    project_path: Option<PathBuf>,
+
+    #[lens(ignore)]
+    project_saver: ProjectSaver,
+
+    /// The tempo that is applied once `TEMPO_UPDATE_INTERVAL` has passed since
+    /// `last_tempo_update`.
+    #[lens(ignore)]
+    pending_tempo: Option<f64>,
+
+    /// The automation being recorded while a control is held (see
+    /// `AutomationMode::Touch`), along with the current value of the control.
+    #[lens(ignore)]
+    touch_recording: Option<(TouchRecorder, f64)>,
+    #[lens(ignore)]
+    last_tempo_update: Instant,
+
+    #[lens(ignore)]
+    app_config_watcher: Option<AppConfigWatcher>,
+
+    #[lens(ignore)]
+    layout_save: LayoutSaveDebounce,
+
+    /// The file the selected clips were rendered to while they are dragged out
+    /// of the app (see `begin_drag_export()`).
+    #[lens(ignore)]
+    drag_export: Option<PathBuf>,
+
+    /// The last `RT_TRACE_LEN` records from the audio thread log, for
+    /// diagnostics dumps.
+    #[lens(ignore)]
+    rt_trace: VecDeque<RtLogRecord>,
+
+    /// The application-wide keyboard shortcuts, parsed from `app_config`.
+    #[lens(ignore)]
+    pub key_bindings: Vec<(Chord, Action)>,
 }
 
 impl UiData {
-    // Create some dummy state for now
+    /// Creates the UI state with the default template project.
     pub fn new() -> Result<Self, Box<dyn Error>> {
+        Self::with_project(ProjectState::default_template())
+    }
+
+    /// Creates the UI state with an empty project.
+    pub fn new_empty() -> Result<Self, Box<dyn Error>> {
+        Self::with_project(ProjectState::empty())
+    }
+
+    /// Creates the UI state with the template project at `path` as the
+    /// starting state.
+    pub fn from_template(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut project = ProjectState::load(path)?;
+        // The new project is not a template itself.
+        project.is_template = false;
+
+        Self::with_project(project)
+    }
+
+    fn with_project(project: ProjectState) -> Result<Self, Box<dyn Error>> {
         // This is temporary. Eventually we will have a more sophisticated and
         // configurable system using `rainout`.
         let system_io_stream_handle = system_io::temp_spawn_cpal_default_output_only()?;
@@ -138,108 +332,62 @@ impl UiData {
 
         let resource_loader = ResourceLoader::new(sample_rate.as_u32());
 
-        // Fill with dummy state for now.
+        let mut notification_log = Vec::new();
+
+        let app_config_path = app_config_path();
+        let (app_config, app_config_error) = AppConfig::load_or_default(&app_config_path);
+        if let Some(e) = app_config_error {
+            notification_log.push(NotificationLogType::Error(e));
+        }
+
+        let app_config_watcher = match AppConfigWatcher::new(&app_config_path) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                log::warn!("Failed to watch app config {:?}: {}", &app_config_path, e);
+                None
+            }
+        };
+
+        let (key_bindings, keymap_problems) = app_config.keymap.resolve();
+        for problem in keymap_problems {
+            notification_log.push(NotificationLogType::Error(problem));
+        }
+
+        let timeline_bpm = sanitize_bpm(project.bpm);
         let mut app_data = UiData {
-            state: UiState {
-                channels: vec![
-                    ChannelState {
-                        name: String::from("Master"),
-                        selected: false,
-                        color: Color::from("#D4D5D5").into(),
-                        subchannels: vec![1, 5],
-                        ..Default::default()
-                    },
-                    ChannelState {
-                        name: String::from("Drum Group"),
-                        selected: false,
-                        color: Color::from("#EDE171").into(),
-                        subchannels: vec![2, 3, 4],
-                        ..Default::default()
-                    },
-                    ChannelState {
-                        name: String::from("Kick"),
-                        selected: false,
-                        color: Color::from("#EDE171").into(),
-                        subchannels: vec![],
-                        ..Default::default()
-                    },
-                    ChannelState {
-                        name: String::from("Snare"),
-                        selected: true,
-                        color: Color::from("#EDE171").into(),
-                        subchannels: vec![],
-                        ..Default::default()
-                    },
-                    ChannelState {
-                        name: String::from("Hat"),
-                        selected: false,
-                        color: Color::from("#EDE171").into(),
-                        subchannels: vec![],
-                        ..Default::default()
-                    },
-                    ChannelState {
-                        name: String::from("Spicy Synth"),
-                        selected: false,
-                        color: Color::from("#EA716C").into(),
-                        subchannels: vec![],
-                        ..Default::default()
-                    },
-                ],
-                clips: vec![ClipState {
-                    name: String::from("Drum Group 1"),
-                    channel: 1,
-                    timeline_start: ClipStart::NotInTimeline,
-                    length: MusicalTime::from_beats(4).into(),
-                    type_: ClipType::Automation(AutomationClipState {}),
-                }],
-                timeline_grid: TimelineGridState {
-                    horizontal_zoom_level: 1.0,
-                    vertical_zoom_level: 1.0,
-                    left_start: MusicalTime::from_beats(0).into(),
-                    top_start: 0.0,
-                    lane_height: 1.0,
-                    lane_states: LaneStates::new(vec![
-                        LaneState {
-                            name: Some(String::from("Track 1")),
-                            color: Some(Color::from("#EDE171").into()),
-                            height: Some(2.0),
-                            disabled: false,
-                            selected: false,
-                        },
-                        LaneState {
-                            name: Some(String::from("Track 2")),
-                            color: Some(Color::from("#EDE171").into()),
-                            height: None,
-                            disabled: false,
-                            selected: false,
-                        },
-                        LaneState {
-                            name: Some(String::from("Track 3")),
-                            color: Some(Color::from("#EA716C").into()),
-                            height: None,
-                            disabled: false,
-                            selected: false,
-                        },
-                    ]),
-                    project_length: MusicalTime::from_beats(16).into(),
-                    used_lanes: 0,
-                },
-                browser: BrowserState::default(),
-                panels: PanelState {
-                    channel_rack_orientation: ChannelRackOrientation::Horizontal,
-                    hide_clips: false,
-                    hide_piano_roll: false,
-                    browser_width: 200.0,
-                    hide_browser: false,
-                },
-                dragging_channel: None,
-            },
+            state: UiState::from_project(project, &app_config.layout),
             resource_loader,
-            notification_log: Vec::new(),
+            notification_log,
+            sample_rate: sample_rate.into(),
+            inspector: None,
+            resource_info: FnvHashMap::default(),
+            tempo_analysis: FnvHashMap::default(),
+            tempo_analyzer: TempoAnalyzer::new(),
+            tempo_analyses_in_progress: FnvHashSet::default(),
+            pending_tempo_fits: Vec::new(),
             engine_running: false,
+            correlation: 0.0,
+            correlation_bus: None,
             system_io_stream_handle: Some(system_io_stream_handle),
+            playback_audio: FnvHashMap::default(),
+            timeline_tracks: Vec::new(),
+            timeline_synced: false,
+            timeline_bpm,
             last_clicked_browser_file: None,
             engine_handles: None,
+            engine_restart: EngineRestartState::default(),
+            app_config,
+            app_config_path,
+            project_path: None,
+            project_saver: ProjectSaver::new(),
+            pending_tempo: None,
+            touch_recording: None,
+            last_tempo_update: Instant::now(),
+            app_config_watcher,
+            layout_save: LayoutSaveDebounce::default(),
+            drag_export: None,
+            rt_trace: VecDeque::with_capacity(RT_TRACE_LEN),
+            key_bindings,
         };
 
         app_data.activate_engine();
@@ -249,28 +397,13 @@ impl UiData {
 
     pub fn activate_engine(&mut self) {
         if let Some(system_io_stream_handle) = &mut self.system_io_stream_handle {
-            let (mut engine_handle, engine_rx) = DSEngineHandle::new(
-                HostInfo::new(
-                    String::from("RustyDAW integration test"),
-                    String::from("0.1.0"),
-                    None,
-                    None,
-                ),
-                vec![Box::new(SampleBrowserPlugFactory)],
-            );
-
-            log::debug!("{:?}", &engine_handle.internal_plugins_res);
+            let (mut engine_handle, engine_rx) = engine::spawn_engine();
 
             let sample_rate = system_io_stream_handle.sample_rate();
 
-            engine_handle.send(DSEngineRequest::ActivateEngine(Box::new(ActivateEngineSettings {
-                sample_rate,
-                min_frames: MIN_FRAMES,
-                max_frames: MAX_FRAMES,
-                num_audio_in_channels: GRAPH_IN_CHANNELS,
-                num_audio_out_channels: GRAPH_OUT_CHANNELS,
-                ..ActivateEngineSettings::default()
-            })));
+            engine_handle.send(DSEngineRequest::ActivateEngine(Box::new(
+                engine::activate_settings(sample_rate),
+            )));
 
             engine_handle.send(DSEngineRequest::RescanPluginDirectories);
 
@@ -279,6 +412,7 @@ impl UiData {
                     ds_handle: engine_handle,
                     activated_info: None,
                     sample_browser_plug_handle: None,
+                    journal: PendingOpJournal::default(),
                 },
                 engine_rx,
             ));
@@ -288,7 +422,58 @@ impl UiData {
     }
 
     pub fn poll_engine(&mut self) {
-        let Self { state, system_io_stream_handle, engine_handles, resource_loader, .. } = self;
+        self.correlation = match self.system_io_stream_handle.as_mut() {
+            Some(system_io_stream_handle) => system_io_stream_handle.timeline().correlation(),
+            None => 0.0,
+        };
+        self.poll_timeline();
+
+        let Self {
+            state,
+            system_io_stream_handle,
+            engine_handles,
+            engine_restart,
+            resource_loader,
+            notification_log,
+            rt_trace,
+            ..
+        } = self;
+
+        let mut engine_crashed = false;
+
+        if let Some(system_io_stream_handle) = system_io_stream_handle {
+            system_io_stream_handle.flush_pending();
+
+            let mut sanitized_samples = false;
+            let num_dropped = system_io_stream_handle.drain_rt_log(|record| {
+                if rt_trace.len() >= RT_TRACE_LEN {
+                    rt_trace.pop_front();
+                }
+                rt_trace.push_back(record);
+
+                match record.event {
+                    RtEvent::NanSanitized { num_samples } => {
+                        log::error!(
+                            "Audio thread: replaced {} invalid samples with silence (block {})",
+                            num_samples,
+                            record.block
+                        );
+                        sanitized_samples = true;
+                    }
+                    event => {
+                        log::warn!("Audio thread: {:?} (block {})", event, record.block);
+                    }
+                }
+            });
+            if sanitized_samples {
+                notification_log.push(NotificationLogType::Error(String::from(
+                    "The audio output contained invalid samples, which were silenced. A plugin may be misbehaving.",
+                )));
+            }
+            if num_dropped > 0 {
+                log::warn!("Audio thread: {} log records were dropped", num_dropped);
+            }
+        }
 
         if let Some((engine_handles, engine_rx)) = engine_handles {
             //let EngineHandles { handle, rx, activated_info, sample_browser_plug_handle } = engine_handle;
@@ -318,11 +503,27 @@ impl UiData {
                     }
                     DSEngineEvent::EngineDeactivated(event) => {
                         self.engine_running = false;
-                        state.on_engine_deactivated(event, engine_handles, system_io_stream_handle);
+                        if let EngineDeactivatedInfo::EngineCrashed { .. } = &event {
+                            engine_crashed = true;
+                        }
+                        state.on_engine_deactivated(
+                            event,
+                            engine_handles,
+                            system_io_stream_handle,
+                            engine_restart,
+                            notification_log,
+                        );
                     }
                     DSEngineEvent::EngineActivated(event) => {
                         self.engine_running = true;
-                        state.on_engine_activated(event, engine_handles, system_io_stream_handle);
+                        let restore_from = engine_restart.on_engine_activated();
+                        state.on_engine_activated(
+                            event,
+                            engine_handles,
+                            system_io_stream_handle,
+                            restore_from,
+                            notification_log,
+                        );
                     }
                     DSEngineEvent::AudioGraphCleared => {
                         state.on_audio_graph_cleared();
@@ -341,6 +542,14 @@ impl UiData {
                     }
                 }
             }
+
+            if engine_restart.restart_due() {
+                if let Some(system_io_stream_handle) = system_io_stream_handle {
+                    engine_handles.ds_handle.send(DSEngineRequest::ActivateEngine(Box::new(
+                        engine::activate_settings(system_io_stream_handle.sample_rate()),
+                    )));
+                }
+            }
         }
 
         // Clean up loaded resources that are no longer being used.
@@ -348,6 +557,427 @@ impl UiData {
         // TODO: Only call this periodically (i.e. every 3 seconds or so), because
         // this can get expensive when a lot of resources are loaded in the project.
         resource_loader.collect();
+
+        if engine_crashed {
+            self.write_diagnostics("engine-crash", true);
+        }
+    }
+
+    /// Opens the project at `path`, replacing the current one.
+    ///
+    /// The layout of the UI is kept.
+    pub fn open_project(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let project = ProjectState::load(path)?;
+
+        self.state.replace_project(project);
+        self.project_path = Some(path.to_path_buf());
+        self.timeline_synced = false;
+        self.check_missing_audio_clips();
+        self.remember_recent_project(path);
+
+        Ok(())
+    }
+
+    /// Saves the current project to `path` on a background thread.
+    ///
+    /// A notification is shown if the save fails.
+    pub fn save_project(&mut self, path: &Path) {
+        self.project_saver.save(path.to_path_buf(), self.state.to_project());
+        self.project_path = Some(path.to_path_buf());
+    }
+
+    /// Returns true while the project is being saved.
+    pub fn is_saving_project(&self) -> bool {
+        self.project_saver.is_saving()
+    }
+
+    fn poll_project_save(&mut self) {
+        for ProjectSaveResult { path, result } in self.project_saver.poll() {
+            match result {
+                Ok(()) => {
+                    log::info!("Saved project to {:?}", &path);
+                    self.remember_recent_project(&path);
+                }
+                Err(e) => {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Failed to save the project to {}: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+            }
+        }
+    }
+
+    /// The recently opened projects whose files still exist, most recent first.
+    /// Use this to build the "recent projects" menu.
+    pub fn recent_projects(&self) -> Vec<RecentProject> {
+        self.app_config.existing_recent_projects()
+    }
+
+    fn remember_recent_project(&mut self, path: &Path) {
+        self.app_config.add_recent_project(path);
+        if let Err(e) = self.app_config.save(&self.app_config_path) {
+            log::error!("Failed to save app config {:?}: {}", &self.app_config_path, e);
+        }
+    }
+
+    /// Updates `inspector` to show the selected clip.
+    fn update_inspector(&mut self) {
+        let clip_index = match self.state.selected_clips().as_slice() {
+            [clip_index] => *clip_index,
+            _ => {
+                self.inspector = None;
+                return;
+            }
+        };
+        let clip = match self.state.clips.get(clip_index) {
+            Some(clip) => clip,
+            None => {
+                self.inspector = None;
+                return;
+            }
+        };
+
+        let resource_info = self.clip_source_info(clip_index);
+        let clip = &self.state.clips[clip_index];
+
+        let inspector = ClipInspector::new(clip_index, clip, resource_info, self.sample_rate.get());
+        if !self.inspector.as_ref().map(|i| i.same(&inspector)).unwrap_or(false) {
+            self.inspector = Some(inspector);
+        }
+    }
+
+    /// Saves the channel at `channel_index` and its clips as a track template
+    /// to `path`.
+    ///
+    /// If `bundle_samples` is true, then the audio files used by the clips are
+    /// copied next to the template.
+    pub fn save_track_template(
+        &self,
+        channel_index: usize,
+        path: &Path,
+        bundle_samples: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let template = TrackTemplate::new(&self.state.channels, &self.state.clips, channel_index)
+            .ok_or_else(|| format!("Channel {} does not exist", channel_index))?;
+
+        template.save(path, bundle_samples)
+    }
+
+    /// Loads the track template at `path` as a new channel in the master group
+    /// at position `insert_at`.
+    ///
+    /// Returns the index of the new channel.
+    pub fn load_track_template(
+        &mut self,
+        path: &Path,
+        insert_at: usize,
+    ) -> Result<usize, Box<dyn Error>> {
+        let TrackTemplate { mut channel, clips } = TrackTemplate::load(path)?;
+
+        let channel_index = self.state.channels.len();
+
+        // The channel indices in the template refer to the project it was saved
+        // from, so they are meaningless here.
+        channel.selected = false;
+        channel.parent_channel = Some(0);
+        channel.subchannels.clear();
+        if let OutputAssignment::Group(_) = channel.routed_to {
+            channel.routed_to = OutputAssignment::Master;
+        }
+
+        channel.id = self.state.ids.channel_id();
+        self.state.channels.push(channel);
+        if let Some(master) = self.state.channels.get_mut(0) {
+            let insert_at = insert_at.min(master.subchannels.len());
+            master.subchannels.insert(insert_at, channel_index);
+        }
+        self.state.changes.push(StateChange::ChannelAdded { index: channel_index });
+
+        for mut clip in clips {
+            clip.channel = channel_index;
+            self.state.add_clip(clip);
+        }
+
+        self.check_missing_audio_clips();
+
+        // TODO: Load the audio files of the new clips into the engine.
+
+        Ok(channel_index)
+    }
+
+    /// Imports the channels, clips, and lanes of the project at `path` into the
+    /// current project.
+    ///
+    /// The imported channels are added to the master group, and the imported
+    /// lanes are added below the current lanes. If `append` is true, then the
+    /// imported clips are moved to start after the end of the current content.
+    /// Otherwise they keep their musical positions.
+    pub fn import_project(&mut self, path: &Path, append: bool) -> Result<(), Box<dyn Error>> {
+        let project = ProjectState::load(path)?;
+        let project_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        if (project.bpm - self.state.timeline_grid.bpm).abs() > f64::EPSILON {
+            self.notification_log.push(NotificationLogType::Info(format!(
+                "The imported project uses a tempo of {} BPM. Its clips keep their musical positions at {} BPM.",
+                project.bpm, self.state.timeline_grid.bpm
+            )));
+        }
+
+        // The master channel of the imported project is merged into the current
+        // one, and every other channel is appended.
+        let channel_offset = self.state.channels.len();
+        let remap_channel = |index: usize| if index == 0 { 0 } else { channel_offset + index - 1 };
+
+        let mut new_top_level_channels = Vec::new();
+        for (index, mut channel) in project.channels.into_iter().enumerate().skip(1) {
+            channel.selected = false;
+            channel.parent_channel = channel.parent_channel.map(remap_channel);
+            for subchannel in channel.subchannels.iter_mut() {
+                *subchannel = remap_channel(*subchannel);
+            }
+            if let OutputAssignment::Group(group) = channel.routed_to {
+                channel.routed_to = OutputAssignment::Group(remap_channel(group));
+            }
+
+            if channel.parent_channel == Some(0) {
+                new_top_level_channels.push(remap_channel(index));
+            }
+
+            channel.id = self.state.ids.channel_id();
+            self.state.channels.push(channel);
+            self.state.changes.push(StateChange::ChannelAdded { index: remap_channel(index) });
+        }
+        if let Some(master) = self.state.channels.get_mut(0) {
+            master.subchannels.extend(new_top_level_channels);
+        }
+
+        let lane_offset = self.state.timeline_grid.lane_states.lanes.len() as u32;
+        for mut lane in project.lanes {
+            lane.selected = false;
+            self.state.timeline_grid.lane_states.lanes.push(lane);
+        }
+
+        let time_offset = if append {
+            let end = self
+                .state
+                .clips
+                .iter()
+                .filter_map(|clip| clip.lane_range_beats())
+                .map(|(_, _, end)| end)
+                .fold(0.0, f64::max);
+            MusicalTime::from_beats(end.ceil() as u32)
+        } else {
+            MusicalTime::from_beats(0)
+        };
+
+        for mut clip in project.clips {
+            clip.channel = remap_channel(clip.channel);
+
+            if let ClipStart::OnLane(on_lane) = &mut clip.timeline_start {
+                on_lane.lane_index += lane_offset;
+                on_lane.timeline_start = (on_lane.timeline_start.get() + time_offset).into();
+            }
+
+            if let ClipType::Audio(audio_clip) = &mut clip.type_ {
+                if audio_clip.pcm_path.is_relative() {
+                    audio_clip.pcm_path = project_dir.join(&audio_clip.pcm_path);
+                }
+                audio_clip.missing = !audio_clip.pcm_path.is_file();
+            }
+
+            self.state.add_clip(clip);
+        }
+
+        // Files that were moved along with the imported project are usually
+        // next to it.
+        self.relink_missing_audio_clips_in_folder(project_dir);
+        self.check_missing_audio_clips();
+
+        // TODO: Load the audio files of the new clips into the engine.
+
+        Ok(())
+    }
+
+    /// Saves the layout once it has stopped changing for
+    /// `LAYOUT_SAVE_DEBOUNCE`.
+    fn poll_layout_save(&mut self) {
+        let layout = self.state.layout();
+        if self.layout_save.poll(&layout, &self.app_config.layout, Instant::now()) {
+            self.save_layout();
+        }
+    }
+
+    /// Saves the current layout to the app config file if it has changed.
+    /// Reloads the app config if it was edited outside of the program, and
+    /// applies the sections that changed.
+    ///
+    /// If the edited file is invalid, it is ignored and the current settings
+    /// are kept.
+    fn poll_app_config(&mut self) {
+        let changed = match &mut self.app_config_watcher {
+            Some(watcher) => watcher.poll(),
+            None => false,
+        };
+        if !changed {
+            return;
+        }
+
+        let new_config = match AppConfig::load(&self.app_config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                self.notification_log.push(NotificationLogType::Error(format!(
+                    "Ignored the changes to the app config {:?} because it is invalid: {}",
+                    &self.app_config_path, e
+                )));
+                return;
+            }
+        };
+
+        for section in self.app_config.changed_sections(&new_config) {
+            match section {
+                AppConfigSection::Layout => {
+                    let layout = new_config.layout.clone();
+                    self.state.apply_layout(&layout);
+                    self.app_config.layout = layout;
+                    self.layout_save.reset();
+                }
+                AppConfigSection::Keymap => {
+                    let (key_bindings, problems) = new_config.keymap.resolve();
+                    if !problems.is_empty() {
+                        self.notification_log.push(NotificationLogType::Error(format!(
+                            "Ignored the changes to the keyboard shortcuts because they are invalid:\n{}",
+                            problems.join("\n")
+                        )));
+                        continue;
+                    }
+
+                    self.app_config.keymap = new_config.keymap.clone();
+                    self.key_bindings = key_bindings;
+
+                    // TODO: Reinstall the keymap without a restart.
+                    self.notification_log.push(NotificationLogType::Info(String::from(
+                        "The new keyboard shortcuts will be used after a restart.",
+                    )));
+                }
+                AppConfigSection::RecentProjects => {
+                    self.app_config.recent_projects = new_config.recent_projects.clone();
+                }
+            }
+        }
+    }
+
+    fn save_layout(&mut self) {
+        self.layout_save.reset();
+
+        let layout = self.state.layout();
+        if layout == self.app_config.layout {
+            return;
+        }
+        self.app_config.layout = layout;
+
+        if let Err(e) = self.app_config.save(&self.app_config_path) {
+            log::error!("Failed to save app config {:?}: {}", &self.app_config_path, e);
+        }
+    }
+
+    /// Routes the output of the channel at `index` to `output`.
+    ///
+    /// If `output` is not valid, then a notification is shown and the channel is
+    /// routed to the master channel instead.
+    pub fn set_channel_output(&mut self, index: usize, output: OutputAssignment) {
+        let num_audio_out_channels = self
+            .engine_handles
+            .as_ref()
+            .and_then(|(engine_handles, _)| engine_handles.activated_info.as_ref())
+            .map(|info| info.num_audio_out_channels)
+            .unwrap_or(0);
+
+        let output = match output {
+            OutputAssignment::Master => output,
+            OutputAssignment::Group(group) => {
+                if group != index
+                    && self.state.channels.get(group).map(|c| !c.subchannels.is_empty())
+                        == Some(true)
+                {
+                    output
+                } else {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Cannot route channel {} to channel {}, which is not a group",
+                        index, group
+                    )));
+                    OutputAssignment::Master
+                }
+            }
+            OutputAssignment::Hardware { channel_pair } => {
+                if u32::from(channel_pair) * 2 + 1 < u32::from(num_audio_out_channels) {
+                    output
+                } else {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Hardware output pair {} is not available on the current device",
+                        channel_pair + 1
+                    )));
+                    OutputAssignment::Master
+                }
+            }
+        };
+
+        if let Some(channel_data) = self.state.channels.get_mut(index) {
+            channel_data.routed_to = output;
+            self.state.changes.push(StateChange::ChannelChanged { index });
+        }
+
+        // TODO: Connect the channel's output in the audio graph, and reactivate the
+        // engine with more output channels when a hardware pair beyond the current
+        // ones is selected.
+    }
+
+    /// Sets which hardware inputs feed the channel at `index` when it is
+    /// record-armed.
+    ///
+    /// If the current device doesn't have the selected inputs, a notification
+    /// is shown and the channel falls back to no input.
+    pub fn set_channel_input(&mut self, index: usize, input: InputAssignment) {
+        let num_audio_in_channels = self
+            .engine_handles
+            .as_ref()
+            .and_then(|(engine_handles, _)| engine_handles.activated_info.as_ref())
+            .map(|info| info.num_audio_in_channels)
+            .unwrap_or(0);
+
+        let input = if input.num_required_channels() <= u32::from(num_audio_in_channels) {
+            input
+        } else {
+            let message = match input {
+                InputAssignment::Mono(channel) => {
+                    format!(
+                        "Hardware input {} is not available on the current device",
+                        u32::from(channel) + 1
+                    )
+                }
+                InputAssignment::StereoPair(first_channel) => format!(
+                    "Hardware inputs {} and {} are not available on the current device",
+                    u32::from(first_channel) + 1,
+                    u32::from(first_channel) + 2
+                ),
+                // `None` never requires any channels.
+                InputAssignment::None => String::new(),
+            };
+            self.notification_log.push(NotificationLogType::Error(message));
+            InputAssignment::None
+        };
+
+        if let Some(channel_data) = self.state.channels.get_mut(index) {
+            channel_data.input = input;
+            self.state.changes.push(StateChange::ChannelChanged { index });
+        }
+
+        // TODO: Connect the selected hardware inputs to the channel's track node
+        // (the mono or stereo input port) in the audio graph, and reactivate the
+        // engine with more input channels when inputs beyond the current ones
+        // are selected. If the channel is record-armed, fade its monitoring out
+        // and back in around the switch to avoid a click.
     }
 }
 
@@ -356,16 +986,246 @@ impl Model for UiData {
     fn event(&mut self, cx: &mut Context, event: &mut Event) {
         event.map(|program_event, _| match program_event {
             UiEvent::PollEngine => {
+                self.sync_timeline();
+
+                // Changes are only kept for one frame.
+                self.state.changes.clear();
+
                 self.poll_engine();
+                self.poll_tempo_analysis();
+                self.poll_project_save();
+                self.poll_pending_tempo();
+                self.poll_touch_recording();
+                self.poll_layout_save();
+                self.poll_app_config();
+            }
+            UiEvent::WriteDiagnostics { redact_paths } => {
+                self.write_diagnostics("user", *redact_paths);
             }
             UiEvent::SaveProject => {
-                //let save_state = serde_json::to_string(&self.state).unwrap();
-                //std::fs::write("project.json", save_state).unwrap();
+                // TODO: Ask for a path with a file dialog when the project was
+                // never saved.
+                let path =
+                    self.project_path.clone().unwrap_or_else(|| PathBuf::from("project.json"));
+                self.save_project(&path);
             }
             UiEvent::LoadProject => {
-                //let save_state = std::fs::read_to_string("project.json").unwrap();
-                //let project_state = serde_json::from_str(&save_state).unwrap();
-                //self.state = project_state;
+                // TODO: Ask for a path with a file dialog.
+                let path =
+                    self.project_path.clone().unwrap_or_else(|| PathBuf::from("project.json"));
+                // The current project is only replaced once the new one was
+                // loaded successfully.
+                if let Err(e) = self.open_project(&path) {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Failed to open the project {}: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+            }
+            UiEvent::ResetLayout => {
+                self.state.apply_layout(&LayoutConfig::default());
+            }
+            UiEvent::Undo => {
+                if self.state.undo() {
+                    self.timeline_synced = false;
+                }
+            }
+            UiEvent::Redo => {
+                if self.state.redo() {
+                    self.timeline_synced = false;
+                }
+            }
+            UiEvent::Play => {
+                let transport = &mut self.state.transport;
+                let bar_beats =
+                    self.state.timeline_grid.bar_beats_at(transport.playhead.get().as_beats_f64());
+                let counting_in = transport.is_recording && transport.start_count_in(bar_beats);
+                if !counting_in {
+                    transport.is_playing = true;
+                }
+
+                let sample_rate = self.sample_rate.get();
+                let from =
+                    self.state.tempo_map.musical_to_frames(transport.playhead.get(), sample_rate);
+                if counting_in {
+                    let count_in = CountIn::new(
+                        transport.count_in_bars,
+                        bar_beats,
+                        self.state.tempo_map.bpm(),
+                        sample_rate.0,
+                    );
+                    self.send_to_timeline(TimelineMsg::CountIn { from: from.0, count_in });
+                    self.start_recording();
+                } else {
+                    self.send_to_timeline(TimelineMsg::Play { from: from.0 });
+                    if self.state.transport.is_recording {
+                        self.start_recording();
+                    }
+                }
+            }
+            UiEvent::Stop => {
+                self.stop_recording();
+                self.state.transport.is_playing = false;
+                self.state.transport.is_recording = false;
+                self.state.transport.count_in = None;
+
+                // This also cancels the count-in of the timeline.
+                self.send_to_timeline(TimelineMsg::Stop);
+            }
+            UiEvent::SetCountInBars(bars) => {
+                self.state.transport.set_count_in_bars(*bars);
+            }
+            UiEvent::ToggleLoop => {
+                self.state.transport.is_looping ^= true;
+                self.send_loop_to_timeline();
+            }
+            UiEvent::BeginTouchAutomation(id, param, value) => {
+                if let Some(channel) = self.state.channel_index(*id) {
+                    self.begin_touch_recording(channel, param, *value);
+                }
+            }
+            UiEvent::SetTouchAutomationValue(value) => {
+                if let Some((_, current)) = &mut self.touch_recording {
+                    *current = *value;
+                }
+            }
+            UiEvent::EndTouchAutomation => {
+                self.end_touch_recording();
+            }
+            UiEvent::SetTempo(bpm) => {
+                // The tempo widget sends this continuously while it is dragged,
+                // so apply at most one change per `TEMPO_UPDATE_INTERVAL` and
+                // the last one once the dragging stops.
+                self.pending_tempo = Some(*bpm);
+                self.poll_pending_tempo();
+            }
+            UiEvent::BeginTempoChange => {
+                self.state.begin_tempo_change();
+            }
+            UiEvent::EndTempoChange => {
+                self.apply_pending_tempo();
+                self.state.end_tempo_change();
+            }
+            UiEvent::SetLoopRange(start, end) => {
+                if self.state.transport.set_loop_range(*start, *end) {
+                    self.send_loop_to_timeline();
+                }
+            }
+            UiEvent::DragLoopEdge(edge, position, grid_beats) => {
+                let position = snap_position(position.as_beats_f64(), &[], *grid_beats, 0.0);
+                self.state.transport.move_loop_edge(*edge, position);
+                self.send_loop_to_timeline();
+            }
+            UiEvent::SetStartStopFadeSecs(secs) => {
+                self.state.transport.set_start_stop_fade_secs(*secs);
+                self.send_loop_to_timeline();
+            }
+            UiEvent::SetSeekCrossfadeSecs(secs) => {
+                self.state.transport.set_seek_crossfade_secs(*secs);
+                self.send_loop_to_timeline();
+            }
+            UiEvent::SetLoopCrossfadeSecs(secs) => {
+                self.state.transport.set_loop_crossfade_secs(*secs);
+                self.send_loop_to_timeline();
+            }
+            UiEvent::ToggleRecord => {
+                self.state.transport.is_recording ^= true;
+
+                // Punch in or out by hand while playing.
+                if self.state.transport.is_playing {
+                    if self.state.transport.is_recording {
+                        self.start_recording();
+                    } else {
+                        self.stop_recording();
+                    }
+                }
+            }
+            UiEvent::TogglePunch => {
+                self.state.transport.is_punching ^= true;
+            }
+            UiEvent::SetPunchRange(start, end) => {
+                self.state.transport.set_punch_range(*start, *end);
+            }
+            UiEvent::SetChannelOutput(id, output) => {
+                if let Some(index) = self.state.channel_index(*id) {
+                    self.set_channel_output(index, *output);
+                }
+            }
+            UiEvent::SetChannelInput(id, input) => {
+                if let Some(index) = self.state.channel_index(*id) {
+                    self.set_channel_input(index, *input);
+                }
+            }
+            UiEvent::SetCorrelationBus(id) => {
+                if let Some(index) = self.state.channel_index(*id) {
+                    // The master channel meters the master output.
+                    self.correlation_bus = if index == 0 { None } else { Some(*id) };
+                    self.correlation = 0.0;
+                    let track = self.correlation_track();
+                    if !self.send_to_timeline(TimelineMsg::SetCorrelationBus(track)) {
+                        self.timeline_synced = false;
+                    }
+                }
+            }
+            UiEvent::BounceSelectedClipsInPlace => {
+                let selected = self.state.selected_clips();
+                if let Err(e) = self.bounce_clips_in_place(&selected) {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Failed to bounce the selected clips: {}",
+                        e
+                    )));
+                }
+            }
+            UiEvent::ConsolidateSelectedClips => {
+                let selected = self.state.selected_clips();
+                if let Err(e) = self.consolidate_clips(&selected) {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Failed to consolidate the selected clips: {}",
+                        e
+                    )));
+                }
+            }
+            UiEvent::ConsolidateRange(channel, range) => {
+                if let Err(e) = self.consolidate_range(*channel, range.clone()) {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Failed to consolidate the range: {}",
+                        e
+                    )));
+                }
+            }
+            UiEvent::ExportStems { dir, format, respect_mute_solo } => {
+                match self.export_stems(dir, *format, *respect_mute_solo) {
+                    Ok(paths) => self.notification_log.push(NotificationLogType::Info(format!(
+                        "Exported {} stems to {}",
+                        paths.len(),
+                        dir.display()
+                    ))),
+                    Err(e) => self.notification_log.push(NotificationLogType::Error(format!(
+                        "Failed to export the stems: {}",
+                        e
+                    ))),
+                }
+            }
+            UiEvent::ExportRange { range, format, path } => {
+                if let Err(e) = self.export_range(range.clone(), *format, path) {
+                    self.notification_log.push(NotificationLogType::Error(format!(
+                        "Failed to export the range: {}",
+                        e
+                    )));
+                }
+            }
+            UiEvent::SlipClip(id, delta_beats) => {
+                if let Some(index) = self.state.clip_index(*id) {
+                    let source_duration = self.clip_source_info(index).and_then(|i| i.duration());
+                    self.state.slip_clip(index, *delta_beats, source_duration);
+                }
+            }
+            UiEvent::ResizeClipEnd(id, new_end) => {
+                if let Some(index) = self.state.clip_index(*id) {
+                    let source_duration = self.clip_source_info(index).and_then(|i| i.duration());
+                    self.state.resize_clip_end(index, *new_end, source_duration);
+                }
             }
             UiEvent::BrowserFileClicked(path) => {
                 if let Some((engine_handles, _)) = &mut self.engine_handles {
@@ -402,7 +1262,10 @@ impl Model for UiData {
                                     self.last_clicked_browser_file = Some(path.clone());
                                 }
                                 Err(e) => {
-                                    log::error!("Failed to load pcm resource: {}", e);
+                                    log::error!(
+                                        "Failed to load pcm resource: {}",
+                                        describe_load_error(path, e)
+                                    );
                                     self.last_clicked_browser_file = None;
                                 }
                             }
@@ -430,7 +1293,15 @@ impl Model for UiData {
             _ => {}
         });
 
+        event.map(|window_event, _| {
+            if let WindowEvent::WindowClose = window_event {
+                self.save_layout();
+            }
+        });
+
         self.state.event(cx, event);
+
+        self.update_inspector();
     }
 }
 
@@ -446,6 +1317,9 @@ pub struct UiState {
 
     pub clips: Vec<ClipState>,
 
+    /// The clips that are currently selected in the timeline.
+    pub clip_selection: ClipSelection,
+
     /// The state of the timeline grid.
     ///
     /// (This does not contain the state of the clips.)
@@ -457,9 +1331,296 @@ pub struct UiState {
     ///
     /// This is visual state that is used by the UI and must be serialized.
     pub panels: PanelState,
+
+    /// The state of the transport.
+    ///
+    /// This is updated by the program layer and may not be mutated directly
+    /// by the UI.
+    pub transport: TransportState,
+
+    /// The automatic fade applied at the start and end of every audio clip.
+    pub auto_fade: AutoFade,
+
+    /// The interpolation quality of the clips that don't set their own.
+    pub interpolation: InterpolationSettings,
+
+    /// The nodes and connections of the engine's audio graph.
+    ///
+    /// This is updated by the program layer and may not be mutated directly
+    /// by the UI.
+    pub graph_topology: GraphTopology,
+
+    /// How overlaps are resolved when clips are duplicated or repeated.
+    #[lens(ignore)]
+    pub overlap_policy: OverlapPolicy,
+
+    /// A snapshot of the tempo map for drawing the ruler and converting
+    /// between pixels and musical time. This is updated by `set_tempo()`.
+    #[lens(ignore)]
+    pub tempo_map: TempoMapSnapshot,
+
+    /// The clip edit in progress, if any (see `begin_clip_edit()`).
+    #[lens(ignore)]
+    pub clip_edit: Option<ClipEdit>,
+
+    /// The project from before the tempo change in progress, if any (see
+    /// `begin_tempo_change()`).
+    #[lens(ignore)]
+    tempo_change_before: Option<ProjectState>,
+
+    /// The changes made to this state during the current frame.
+    ///
+    /// Use `UiState::take_changes()` to drain these. Any changes that are not
+    /// taken are dropped at the start of the next frame.
+    #[lens(ignore)]
+    pub changes: Vec<StateChange>,
+
+    /// Hands out the ids of new channels and clips.
+    #[lens(ignore)]
+    pub ids: IdAllocator,
+
+    /// The edits to the project that can be undone.
+    #[lens(ignore)]
+    pub undo_history: UndoHistory,
 }
 
 impl UiState {
+    // The state is split in three parts:
+    //
+    // * The project (`ProjectState`): the musically meaningful state, which is
+    // saved in the project file. See `to_project()` and `replace_project()`.
+    // * The layout (`LayoutConfig`): the panel sizes and the zoom levels, which
+    // are saved in the app config file and are kept when another project is
+    // opened. See `layout()` and `apply_layout()`.
+    // * Everything else (the selection, the scroll position, the transport,
+    // etc.), which is not saved at all.
+
+    /// Creates the state of the given project, using `layout` for the view
+    /// state.
+    pub fn from_project(mut project: ProjectState, layout: &LayoutConfig) -> Self {
+        project.assign_ids();
+
+        let bpm = sanitize_bpm(project.bpm);
+        let tempo_map = TempoMapSnapshot::new(bpm, &project.time_signatures);
+
+        Self {
+            channels: project.channels,
+            dragging_channel: None,
+            clips: project.clips,
+            clip_selection: ClipSelection::new(),
+            timeline_grid: TimelineGridState {
+                horizontal_zoom_level: layout.timeline_horizontal_zoom,
+                vertical_zoom_level: layout.timeline_vertical_zoom,
+                left_start: MusicalTime::from_beats(0).into(),
+                top_start: 0.0,
+                lane_height: 1.0,
+                lane_states: LaneStates::new(project.lanes),
+                project_length: project.project_length,
+                min_project_length: project.min_project_length.unwrap_or(project.project_length),
+                used_lanes: 0,
+                bpm,
+                time_signatures: project.time_signatures,
+            },
+            browser: BrowserState::default(),
+            panels: layout.panels.clone(),
+            transport: TransportState {
+                count_in_bars: project.count_in_bars.min(MAX_COUNT_IN_BARS),
+                ..Default::default()
+            },
+            auto_fade: project.auto_fade,
+            interpolation: project.interpolation,
+            graph_topology: GraphTopology::default(),
+            overlap_policy: OverlapPolicy::default(),
+            tempo_map,
+            clip_edit: None,
+            tempo_change_before: None,
+            changes: Vec::new(),
+            ids: project.ids,
+            undo_history: UndoHistory::new(),
+        }
+    }
+
+    /// Replaces the project with `project`, keeping the layout of the UI, the
+    /// browser and the transport.
+    pub fn replace_project(&mut self, project: ProjectState) {
+        let mut new_state = Self::from_project(project, &self.layout());
+        std::mem::swap(&mut new_state.browser, &mut self.browser);
+        self.transport.count_in_bars = new_state.transport.count_in_bars;
+        std::mem::swap(&mut new_state.transport, &mut self.transport);
+        std::mem::swap(&mut new_state.graph_topology, &mut self.graph_topology);
+        *self = new_state;
+    }
+
+    /// Records the project as it is now as an undo entry named `label`. Call
+    /// this right before an edit.
+    pub fn record_undo(&mut self, label: &str) {
+        let project = self.to_project();
+        self.undo_history.push(label, project);
+    }
+
+    /// Undoes the last edit. Returns `false` if there is nothing to undo.
+    ///
+    /// The whole project is replaced, so everything that mirrors it (i.e. the
+    /// engine) has to be synced again afterwards.
+    pub fn undo(&mut self) -> bool {
+        let current = self.to_project();
+        match self.undo_history.undo(current) {
+            Some(project) => {
+                self.restore_project(project);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Redoes the last undone edit. Returns `false` if there is nothing to
+    /// redo. See `undo()`.
+    pub fn redo(&mut self) -> bool {
+        let current = self.to_project();
+        match self.undo_history.redo(current) {
+            Some(project) => {
+                self.restore_project(project);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces the project with a snapshot from the undo history.
+    ///
+    /// Unlike `replace_project()`, this keeps the undo history, the settings
+    /// that are not saved with the project, the selection (of the clips that
+    /// still exist), and the id allocator (so that ids are never handed out
+    /// twice).
+    fn restore_project(&mut self, project: ProjectState) {
+        let undo_history = std::mem::take(&mut self.undo_history);
+        let mut clip_selection = std::mem::take(&mut self.clip_selection);
+        let overlap_policy = self.overlap_policy;
+        let ids = self.ids;
+
+        self.replace_project(project);
+
+        clip_selection.retain_existing(&self.clips);
+        self.clip_selection = clip_selection;
+        self.overlap_policy = overlap_policy;
+        self.ids = ids;
+        self.undo_history = undo_history;
+    }
+
+    /// Returns the parts of this state that are saved in the app config file.
+    pub fn layout(&self) -> LayoutConfig {
+        LayoutConfig {
+            panels: self.panels.clone(),
+            timeline_horizontal_zoom: self.timeline_grid.horizontal_zoom_level,
+            timeline_vertical_zoom: self.timeline_grid.vertical_zoom_level,
+        }
+    }
+
+    /// Applies a layout loaded from the app config file.
+    pub fn apply_layout(&mut self, layout: &LayoutConfig) {
+        self.panels = layout.panels.clone();
+        self.timeline_grid.horizontal_zoom_level = layout.timeline_horizontal_zoom;
+        self.timeline_grid.vertical_zoom_level = layout.timeline_vertical_zoom;
+    }
+
+    /// Returns the parts of this state that are saved in a project file.
+    pub fn to_project(&self) -> ProjectState {
+        ProjectState {
+            is_template: false,
+            channels: self.channels.clone(),
+            // A clip edit in progress is only saved once it is committed.
+            clips: match &self.clip_edit {
+                Some(clip_edit) => clip_edit.committed_clips(&self.clips),
+                None => self.clips.clone(),
+            },
+            lanes: self.timeline_grid.lane_states.lanes.clone(),
+            project_length: self.timeline_grid.project_length,
+            min_project_length: Some(self.timeline_grid.min_project_length),
+            bpm: self.timeline_grid.bpm,
+            time_signatures: self.timeline_grid.time_signatures.clone(),
+            auto_fade: self.auto_fade,
+            interpolation: self.interpolation,
+            ids: self.ids,
+            count_in_bars: self.transport.count_in_bars,
+        }
+    }
+
+    /// Returns the current index of the clip with the id `id`, or `None` if
+    /// it was removed.
+    pub fn clip_index(&self, id: ClipId) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.id == id)
+    }
+
+    /// Returns the current index of the channel with the id `id`, or `None` if
+    /// it was removed.
+    pub fn channel_index(&self, id: ChannelId) -> Option<usize> {
+        self.channels.iter().position(|channel| channel.id == id)
+    }
+
+    /// Returns the latency in samples of the signal path from the input of the
+    /// channel at `index` to the hardware output, following its routing through
+    /// groups and the master channel.
+    ///
+    /// This is what plugin delay compensation will use to align the channels.
+    pub fn channel_path_latency(&self, index: usize) -> u32 {
+        let mut latency = 0;
+        let mut current = Some(index);
+        // Guard against routing loops.
+        let mut hops = 0;
+
+        while let Some(index) = current {
+            let channel = match self.channels.get(index) {
+                Some(channel) => channel,
+                None => break,
+            };
+            latency += channel.latency();
+
+            hops += 1;
+            if hops > self.channels.len() {
+                break;
+            }
+
+            current = match channel.routed_to {
+                _ if index == 0 => None,
+                OutputAssignment::Master => Some(0),
+                OutputAssignment::Group(group) => Some(group),
+                OutputAssignment::Hardware { .. } => None,
+            };
+        }
+
+        latency
+    }
+
+    /// Returns the highest latency of any signal path in the project in
+    /// samples.
+    pub fn max_latency(&self) -> u32 {
+        (0..self.channels.len()).map(|index| self.channel_path_latency(index)).max().unwrap_or(0)
+    }
+
+    /// Sets the delay that the effect at `effect_index` on the channel at
+    /// `channel_index` reports.
+    ///
+    /// This should be called when the engine reports a new delay for a plugin
+    /// (i.e. after it was reconfigured).
+    pub fn set_effect_latency(&mut self, channel_index: usize, effect_index: usize, delay: u32) {
+        if let Some(effect) =
+            self.channels.get_mut(channel_index).and_then(|c| c.effects.get_mut(effect_index))
+        {
+            if effect.set_delay(delay) {
+                self.changes.push(StateChange::ChannelChanged { index: channel_index });
+            }
+        }
+    }
+
+    /// Drains the changes made to this state during the current frame, in the
+    /// order they were made.
+    ///
+    /// Batch edits (i.e. moving many clips at once) happen within one frame, so
+    /// the engine can apply all of their changes with a single update.
+    pub fn take_changes(&mut self) -> Vec<StateChange> {
+        std::mem::take(&mut self.changes)
+    }
+
     /// Sent whenever the engine is deactivated.
     ///
     /// The DSEngineAudioThread sent in a previous EngineActivated event is now
@@ -475,15 +1636,41 @@ impl UiState {
         event: EngineDeactivatedInfo,
         engine_handles: &mut EngineHandles,
         system_io_stream_handle: &mut Option<SystemIOStreamHandle>,
+        engine_restart: &mut EngineRestartState,
+        notification_log: &mut Vec<NotificationLogType>,
     ) {
         engine_handles.activated_info = None;
         engine_handles.sample_browser_plug_handle = None;
+        self.graph_topology.clear();
+
+        if let EngineDeactivatedInfo::EngineCrashed { .. } = &event {
+            engine_handles.journal.on_engine_deactivated();
+        } else {
+            engine_handles.journal.clear();
+        }
 
         if let Some(system_io_stream_handle) = system_io_stream_handle.as_mut() {
             system_io_stream_handle.engine_deactivated();
         }
 
-        // TODO
+        if let EngineDeactivatedInfo::EngineCrashed { error_msg, recovered_save_state } = event {
+            log::error!("Engine crashed: {}", &error_msg);
+
+            match engine_restart.on_engine_crashed(recovered_save_state) {
+                Some(attempt) => {
+                    notification_log.push(NotificationLogType::Error(format!(
+                        "The audio engine crashed: {}. Restarting it and restoring the session (attempt {} of {})...",
+                        error_msg, attempt, MAX_ENGINE_RESTART_ATTEMPTS
+                    )));
+                }
+                None => {
+                    notification_log.push(NotificationLogType::Error(format!(
+                        "The audio engine crashed: {}. It crashed {} times in a row, so it will not be restarted automatically.",
+                        error_msg, MAX_ENGINE_RESTART_ATTEMPTS
+                    )));
+                }
+            }
+        }
     }
 
     /// This message is sent whenever the engine successfully activates.
@@ -492,6 +1679,8 @@ impl UiState {
         event: EngineActivatedInfo,
         engine_handles: &mut EngineHandles,
         system_io_stream_handle: &mut Option<SystemIOStreamHandle>,
+        restore_from: Option<AudioGraphSaveState>,
+        notification_log: &mut Vec<NotificationLogType>,
     ) {
         engine_handles.activated_info = Some(ActivatedEngineInfo {
             graph_in_node_id: event.graph_in_node_id.clone(),
@@ -517,17 +1706,57 @@ impl UiState {
 
         system_io_stream_handle.as_mut().unwrap().engine_activated(event.audio_thread);
 
-        // Add the sample-browser plugin and connect it directly to the output.
-        engine_handles.ds_handle.send(DSEngineRequest::ModifyGraph(ModifyGraphRequest {
-            add_plugin_instances: vec![PluginSaveState::new_with_default_preset(
-                sample_browser_plug_key,
-            )],
+        // The engine is being restarted after a crash, so restore the graph it
+        // had instead of building a new one.
+        if let Some(save_state) = restore_from {
+            engine_handles.journal.send(
+                &mut engine_handles.ds_handle,
+                "Restore the audio graph",
+                DSEngineRequest::RestoreFromSaveState(save_state),
+                None,
+                None,
+            );
+        } else {
+            Self::add_sample_browser_plug(
+                engine_handles,
+                PluginSaveState::new_with_default_preset(sample_browser_plug_key),
+                &event.graph_out_node_id,
+            );
+        }
+
+        // Re-issue the edits the engine lost when it crashed.
+        if let Some(activated_info) = &engine_handles.activated_info {
+            let report = engine_handles.journal.reconcile(
+                &mut engine_handles.ds_handle,
+                activated_info,
+                self,
+            );
+            for description in report.reissued.iter() {
+                log::info!("Re-issued engine request after restart: {}", description);
+            }
+            if !report.rolled_back.is_empty() {
+                notification_log.push(NotificationLogType::Error(format!(
+                    "These edits were lost when the audio engine crashed and have been undone: {}",
+                    report.rolled_back.join(", ")
+                )));
+            }
+        }
+    }
+
+    /// Adds the sample-browser plugin and connects it directly to the output.
+    fn add_sample_browser_plug(
+        engine_handles: &mut EngineHandles,
+        sample_browser_plug: PluginSaveState,
+        graph_out_node_id: &PluginInstanceID,
+    ) {
+        let request = DSEngineRequest::ModifyGraph(ModifyGraphRequest {
+            add_plugin_instances: vec![sample_browser_plug],
             remove_plugin_instances: vec![],
             connect_new_edges: vec![
                 EdgeReq {
                     edge_type: PortType::Audio,
                     src_plugin_id: PluginIDReq::Added(0),
-                    dst_plugin_id: PluginIDReq::Existing(event.graph_out_node_id.clone()),
+                    dst_plugin_id: PluginIDReq::Existing(graph_out_node_id.clone()),
                     src_port_id: EdgeReqPortID::Main,
                     src_port_channel: 0,
                     dst_port_id: EdgeReqPortID::Main,
@@ -537,7 +1766,7 @@ impl UiState {
                 EdgeReq {
                     edge_type: PortType::Audio,
                     src_plugin_id: PluginIDReq::Added(0),
-                    dst_plugin_id: PluginIDReq::Existing(event.graph_out_node_id.clone()),
+                    dst_plugin_id: PluginIDReq::Existing(graph_out_node_id.clone()),
                     src_port_id: EdgeReqPortID::Main,
                     src_port_channel: 1,
                     dst_port_id: EdgeReqPortID::Main,
@@ -546,7 +1775,14 @@ impl UiState {
                 },
             ],
             disconnect_edges: vec![],
-        }));
+        });
+        engine_handles.journal.send(
+            &mut engine_handles.ds_handle,
+            "Add the sample browser",
+            request,
+            None,
+            None,
+        );
     }
 
     /// When this message is received, it means that the audio graph is starting
@@ -558,6 +1794,8 @@ impl UiState {
     /// If the audio graph is in an invalid state as a result of restoring from
     /// the save state, then the `EngineDeactivated` event will be sent instead.
     fn on_audio_graph_cleared(&mut self) {
+        self.graph_topology.clear();
+
         // TODO
     }
 
@@ -569,6 +1807,9 @@ impl UiState {
         mut event: ModifyGraphRes,
         engine_handles: &mut EngineHandles,
     ) {
+        engine_handles.journal.on_confirmed();
+        self.graph_topology.apply(&event);
+
         for new_plugin in event.new_plugins.drain(..) {
             match new_plugin.status {
                 // This means the plugin successfully activated and returned
@@ -641,6 +1882,168 @@ impl UiState {
         // TODO
     }
 
+    /// Sets how the automation lane of `param` on the channel at `channel`
+    /// behaves during playback.
+    pub fn set_automation_mode(
+        &mut self,
+        channel: usize,
+        param: &AutomationParam,
+        mode: AutomationMode,
+    ) {
+        if let Some(channel_data) = self.channels.get_mut(channel) {
+            channel_data.automation_lane_mut(param).mode = mode;
+            self.changes.push(StateChange::ChannelChanged { index: channel });
+        }
+    }
+
+    /// Adds a point to the automation lane of `param` on the channel at
+    /// `channel` and shows the lane as a sub-lane under the channel's lanes.
+    /// Returns the index of the new point.
+    pub fn add_automation_point(
+        &mut self,
+        channel: usize,
+        param: &AutomationParam,
+        time: MusicalTime,
+        value: f64,
+    ) -> Option<usize> {
+        let channel_data = self.channels.get_mut(channel)?;
+        let index =
+            channel_data.automation_lane_mut(param).add_point(AutomationPoint::new(time, value));
+        self.timeline_grid.lane_states.expand_automation_lane(channel, param.clone());
+        self.changes.push(StateChange::ChannelChanged { index: channel });
+
+        Some(index)
+    }
+
+    /// Moves the point at `index` of the automation lane of `param` on the
+    /// channel at `channel` (see `AutomationLaneState::move_point()`). Returns
+    /// the new index of the point.
+    pub fn move_automation_point(
+        &mut self,
+        channel: usize,
+        param: &AutomationParam,
+        index: usize,
+        time: MusicalTime,
+        value: f64,
+    ) -> Option<usize> {
+        let lane = self
+            .channels
+            .get_mut(channel)?
+            .automation_lanes
+            .iter_mut()
+            .find(|lane| &lane.param == param)?;
+        let new_index = lane.move_point(index, time, value)?;
+        self.changes.push(StateChange::ChannelChanged { index: channel });
+
+        Some(new_index)
+    }
+
+    /// Removes the point at `index` of the automation lane of `param` on the
+    /// channel at `channel`.
+    pub fn remove_automation_point(
+        &mut self,
+        channel: usize,
+        param: &AutomationParam,
+        index: usize,
+    ) {
+        let lane = match self
+            .channels
+            .get_mut(channel)
+            .and_then(|c| c.automation_lanes.iter_mut().find(|lane| &lane.param == param))
+        {
+            Some(lane) => lane,
+            None => return,
+        };
+        if lane.remove_point(index).is_some() {
+            self.changes.push(StateChange::ChannelChanged { index: channel });
+        }
+    }
+
+    /// Sets the shape of the segment that starts at the point at `index` of
+    /// the automation lane of `param` on the channel at `channel`.
+    pub fn set_automation_point_curve(
+        &mut self,
+        channel: usize,
+        param: &AutomationParam,
+        index: usize,
+        curve: AutomationCurve,
+    ) {
+        let lane = match self
+            .channels
+            .get_mut(channel)
+            .and_then(|c| c.automation_lanes.iter_mut().find(|lane| &lane.param == param))
+        {
+            Some(lane) => lane,
+            None => return,
+        };
+        if lane.set_point_curve(index, curve) {
+            self.changes.push(StateChange::ChannelChanged { index: channel });
+        }
+    }
+
+    /// Writes recorded automation segments into the automation lane of `param`
+    /// on the channel at `channel`, replacing the points in the range of each
+    /// segment. Later segments (i.e. after a loop-back) overwrite earlier ones.
+    pub fn write_automation(
+        &mut self,
+        channel: usize,
+        param: &AutomationParam,
+        segments: &[Vec<AutomationPoint>],
+    ) {
+        if segments.is_empty() || channel >= self.channels.len() {
+            return;
+        }
+
+        // All segments are undone together.
+        self.record_undo("Write automation");
+
+        let lane = self.channels[channel].automation_lane_mut(param);
+        for segment in segments.iter() {
+            lane.replace_range(segment);
+        }
+        self.changes.push(StateChange::ChannelChanged { index: channel });
+    }
+
+    /// Returns the end (in beats) of the latest clip on the timeline, padded to
+    /// the start of the next bar.
+    pub fn content_end(&self) -> f64 {
+        let end = self
+            .clips
+            .iter()
+            .filter_map(|clip| clip.lane_range_beats())
+            .map(|(_, _, end)| end)
+            .fold(0.0, f64::max);
+        self.timeline_grid.next_bar_start(end)
+    }
+
+    /// Grows the project length if clips were placed or extended past its end.
+    ///
+    /// This never shrinks the project length (see `trim_project_length()`).
+    pub fn grow_project_length(&mut self) {
+        let content_end = MusicalTime::from_beats_f64(self.content_end());
+        if content_end > self.timeline_grid.project_length.get() {
+            self.timeline_grid.project_length = content_end.into();
+        }
+    }
+
+    /// Sets the length of the project. The project is never shorter than this,
+    /// but it is still longer if clips extend past it.
+    pub fn set_project_length(&mut self, length: MusicalTime) {
+        let max_length = MusicalTime::from_beats(MAX_PROJECT_LENGTH_BEATS);
+        let length = if length > max_length { max_length } else { length };
+        self.timeline_grid.min_project_length = length.into();
+        self.trim_project_length();
+    }
+
+    /// Shrinks the project length to the end of the latest clip, but not below
+    /// the length set with `set_project_length()`.
+    pub fn trim_project_length(&mut self) {
+        let content_end = MusicalTime::from_beats_f64(self.content_end());
+        let min_length = self.timeline_grid.min_project_length.get();
+        let length = if content_end > min_length { content_end } else { min_length };
+        self.timeline_grid.project_length = length.into();
+    }
+
     /// A new CLAP plugin scan path was added.
     fn on_clap_scan_path_added(&mut self, path: PathBuf) {
         // TODO
@@ -662,22 +2065,26 @@ impl Model for UiState {
     fn event(&mut self, cx: &mut Context, event: &mut Event) {
         event.map(|channel_event, _| match channel_event {
             // Select a single channel
-            ChannelEvent::SelectChannel(index) => {
+            ChannelEvent::SelectChannel(id) => {
                 deselect_channels(&mut self.channels);
 
-                if let Some(channel_data) = self.channels.get_mut(*index) {
+                if let Some(channel_data) = self.channels.iter_mut().find(|c| c.id == *id) {
                     channel_data.selected = true;
                 }
             }
 
             // Select a channel and any children in the same group
-            ChannelEvent::SelectChannelGroup(index) => {
+            ChannelEvent::SelectChannelGroup(id) => {
+                let index = match self.channel_index(*id) {
+                    Some(index) => index,
+                    None => return,
+                };
                 println!("Select channel group: {}", index);
                 deselect_channels(&mut self.channels);
 
                 let mut selected = vec![];
 
-                select_channel(&self.channels, *index, &mut selected);
+                select_channel(&self.channels, index, &mut selected);
 
                 for idx in selected.iter() {
                     if let Some(channel_data) = self.channels.get_mut(*idx) {
@@ -694,6 +2101,7 @@ impl Model for UiState {
 
                 // Create a new channel
                 self.channels.push(ChannelState {
+                    id: self.ids.channel_id(),
                     name: String::from("New Channel"),
                     path: PathBuf::from("New Channel"),
                     color: ChannelBaseColor::Color(Color::rgb(200, 50, 50)),
@@ -705,15 +2113,238 @@ impl Model for UiState {
                 if let Some(master) = self.channels.get_mut(0) {
                     master.subchannels.push(channel_id);
                 }
+
+                self.changes.push(StateChange::ChannelAdded { index: channel_id });
             }
 
             // Remove the specified channel from the channels panel
             ChannelEvent::RemoveChannel => {}
+
+            // Set the input gain trim of a channel
+            ChannelEvent::SetInputTrim(id, trim_db) => {
+                if let Some(index) = self.channel_index(*id) {
+                    self.channels[index].input_trim_db =
+                        trim_db.clamp(MIN_INPUT_TRIM_DB, MAX_INPUT_TRIM_DB);
+                    self.changes.push(StateChange::ChannelChanged { index });
+                }
+            }
+
+            // Set how the output pan of a channel turns down each side. The
+            // timeline track of the channel ramps to the new pan gains.
+            ChannelEvent::SetPanLaw(id, pan_law) => {
+                if let Some(index) = self.channel_index(*id) {
+                    self.channels[index].pan_law = *pan_law;
+                    self.changes.push(StateChange::ChannelChanged { index });
+                }
+            }
+
+            // Flip the polarity of a channel's input
+            ChannelEvent::TogglePhaseInvert(id) => {
+                if let Some(index) = self.channel_index(*id) {
+                    self.channels[index].phase_invert ^= true;
+                    self.changes.push(StateChange::ChannelChanged { index });
+                }
+            }
+
+            // Arm or disarm a channel for recording
+            ChannelEvent::ToggleRecordArm(id) => {
+                if let Some(index) = self.channel_index(*id) {
+                    self.channels[index].record_armed ^= true;
+                    self.changes.push(StateChange::ChannelChanged { index });
+                }
+            }
+
+            // Remove an effect from a channel's effect rack
+            ChannelEvent::RemoveEffect { channel, index } => {
+                if let Some(channel) = self.channel_index(*channel) {
+                    let channel_data = &mut self.channels[channel];
+                    if *index < channel_data.effects.len() {
+                        channel_data.effects.remove(*index);
+                        self.changes.push(StateChange::ChannelChanged { index: channel });
+                    }
+                }
+
+                // TODO: Remove the plugin from the audio graph.
+            }
+
+            // Move an effect to a new position in a channel's effect rack
+            ChannelEvent::MoveEffect { channel, from, to } => {
+                if let Some(channel) = self.channel_index(*channel) {
+                    let channel_data = &mut self.channels[channel];
+                    if *from < channel_data.effects.len() && *to < channel_data.effects.len() {
+                        let effect = channel_data.effects.remove(*from);
+                        channel_data.effects.insert(*to, effect);
+                        self.changes.push(StateChange::ChannelChanged { index: channel });
+                    }
+                }
+
+                // TODO: Reconnect the plugin chain in the audio graph.
+            }
+        });
+
+        event.map(|ui_event, _| match ui_event {
+            UiEvent::SelectClip(id) => {
+                if cx.modifiers().contains(Modifiers::SHIFT) {
+                    self.clip_selection.select_range(*id, &self.clips);
+                } else {
+                    if !cx.modifiers().contains(Modifiers::CTRL) {
+                        self.clip_selection.clear();
+                    }
+
+                    self.clip_selection.select(*id);
+                }
+            }
+            UiEvent::ToggleClipSelection(id) => {
+                self.clip_selection.toggle(*id);
+            }
+            UiEvent::ClearClipSelection => {
+                self.clip_selection.clear();
+            }
+            UiEvent::DeleteSelectedClips => {
+                self.delete_selected_clips();
+            }
+            UiEvent::NudgeSelectedClipsEarlier(delta) => {
+                self.nudge_selected_clips_earlier(*delta);
+            }
+            UiEvent::NudgeSelectedClipsLater(delta) => {
+                self.nudge_selected_clips_later(*delta);
+            }
+            UiEvent::SetSelectedClipsGainDb(gain_db) => {
+                self.set_selected_clips_gain_db(*gain_db);
+            }
+            UiEvent::SetClipInvertPolarity(id, invert) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.set_clip_invert_polarity(index, *invert);
+                }
+            }
+            UiEvent::SetClipMultichannelMode(id, mode) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.set_clip_multichannel_mode(index, *mode);
+                }
+            }
+            UiEvent::SetClipChannelMode(id, mode) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.set_clip_channel_mode(index, *mode);
+                }
+            }
+            UiEvent::SetClipChannelGainDb(id, gain_l_db, gain_r_db) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.set_clip_channel_gain_db(index, *gain_l_db, *gain_r_db);
+                }
+            }
+            UiEvent::SetClipInterpolation(id, quality) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.set_clip_interpolation(index, *quality);
+                }
+            }
+            UiEvent::SetPlaybackInterpolation(quality) => {
+                if self.interpolation.playback != *quality {
+                    self.interpolation.playback = *quality;
+
+                    // TODO: Send the new setting to the engine's timeline tracks.
+                }
+            }
+            UiEvent::SetExportInterpolation(quality) => {
+                self.interpolation.export = *quality;
+            }
+            UiEvent::SetAutoFadeEnabled(enabled) => {
+                self.auto_fade.enabled = *enabled;
+
+                // TODO: Send the new setting to the engine.
+            }
+            UiEvent::SetClipLabel(id, label) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.set_clip_label(index, label.clone());
+                }
+            }
+            UiEvent::SetProjectLength(length) => {
+                self.set_project_length(*length);
+            }
+            UiEvent::TrimProjectLength => {
+                self.trim_project_length();
+            }
+            UiEvent::BeginClipEdit(ids) => {
+                let indices: Vec<usize> =
+                    ids.iter().filter_map(|id| self.clip_index(*id)).collect();
+                self.begin_clip_edit(&indices);
+            }
+            UiEvent::CommitClipEdit => {
+                self.commit_clip_edit();
+            }
+            UiEvent::CancelClipEdit => {
+                self.cancel_clip_edit();
+            }
+            UiEvent::ResizeClipStart(id, new_start) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.resize_clip_start(index, *new_start);
+                }
+            }
+            UiEvent::SetAutomationMode(id, param, mode) => {
+                if let Some(channel) = self.channel_index(*id) {
+                    self.set_automation_mode(channel, param, *mode);
+                }
+            }
+            UiEvent::AddAutomationPoint(id, param, time, value) => {
+                if let Some(channel) = self.channel_index(*id) {
+                    self.add_automation_point(channel, param, *time, *value);
+                }
+            }
+            UiEvent::MoveAutomationPoint(id, param, index, time, value) => {
+                if let Some(channel) = self.channel_index(*id) {
+                    self.move_automation_point(channel, param, *index, *time, *value);
+                }
+            }
+            UiEvent::RemoveAutomationPoint(id, param, index) => {
+                if let Some(channel) = self.channel_index(*id) {
+                    self.remove_automation_point(channel, param, *index);
+                }
+            }
+            UiEvent::SetAutomationPointCurve(id, param, index, curve) => {
+                if let Some(channel) = self.channel_index(*id) {
+                    self.set_automation_point_curve(channel, param, *index, *curve);
+                }
+            }
+            UiEvent::SetClipNotes(id, notes) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.set_clip_notes(index, notes.clone());
+                }
+            }
+            UiEvent::SetSelectedClipsColor(color) => {
+                self.set_selected_clips_color(color.clone());
+            }
+            UiEvent::BringClipToFront(id) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.bring_clip_to_front(index);
+                }
+            }
+            UiEvent::CycleTake(id) => {
+                if let Some(index) = self.clip_index(*id) {
+                    self.cycle_take(index);
+                }
+            }
+            UiEvent::DuplicateSelectedClips => {
+                self.duplicate_selected_clips_in_place();
+            }
+            UiEvent::RepeatSelectedClips(times) => {
+                self.repeat_selected_clips(*times);
+            }
+            _ => {}
         });
 
         self.panels.event(cx, event);
         self.timeline_grid.event(cx, event);
         self.browser.event(cx, event);
+
+        if self.changes.iter().any(|change| {
+            matches!(
+                change,
+                StateChange::ClipAdded { .. }
+                    | StateChange::ClipMoved { .. }
+                    | StateChange::ClipChanged { .. }
+            )
+        }) {
+            self.grow_project_length();
+        }
     }
 }
 
@@ -733,3 +2364,71 @@ fn deselect_channels(channel_data: &mut Vec<ChannelState>) {
         channel.selected = false;
     }
 }
+
+/// Returns why the resource loader could not load the file at `path`.
+///
+/// The errors of the resource loader don't say much, so the file is probed
+/// again to find out what exactly is wrong with it.
+fn describe_load_error(path: &Path, error: impl std::fmt::Display) -> String {
+    match decode::scan_file(path) {
+        Err(e) => e.to_string(),
+        Ok(_) => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_clips::clip;
+    use super::*;
+
+    #[test]
+    fn project_tempos_out_of_range_are_clamped_on_load() {
+        let dir = std::env::temp_dir().join("meadowlark-project-tempo-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (saved, loaded) in [(1.0, MIN_BPM), (19.9, MIN_BPM), (90.0, 90.0), (5000.0, MAX_BPM)] {
+            let path = dir.join(format!("{}.json", saved));
+            let mut project = ProjectState::empty();
+            project.bpm = saved;
+            project.save(&path).unwrap();
+
+            assert_eq!(ProjectState::load(&path).unwrap().bpm, loaded);
+        }
+    }
+
+    #[test]
+    fn project_length_follows_clips_on_every_track() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let length = |state: &UiState| state.timeline_grid.project_length.get().as_beats_f64();
+        assert_eq!(length(&state), 16.0);
+
+        let mut bass = clip(1, 17, 2);
+        bass.channel = 1;
+        state.add_clip(clip(0, 10, 4));
+        state.add_clip(bass);
+        state.grow_project_length();
+        // The bass ends at beat 19, which is padded to the next bar.
+        assert_eq!(length(&state), 20.0);
+
+        state.clips[0].length = MusicalTime::from_beats(13).into();
+        state.grow_project_length();
+        assert_eq!(length(&state), 24.0);
+
+        // Deleting the longest clip only shrinks the project once it is
+        // trimmed.
+        state.delete_clips(&[0]);
+        state.grow_project_length();
+        assert_eq!(length(&state), 24.0);
+        state.trim_project_length();
+        assert_eq!(length(&state), 20.0);
+
+        // Trimming never goes below the length that was set.
+        state.delete_clips(&[0]);
+        state.trim_project_length();
+        assert_eq!(length(&state), 16.0);
+        state.set_project_length(MusicalTime::from_beats(32));
+        assert_eq!(length(&state), 32.0);
+        state.set_project_length(MusicalTime::from_beats(8));
+        assert_eq!(length(&state), 8.0);
+        assert_eq!(state.default_bounce_range().1, MusicalTime::from_beats(8));
+    }
+}
@@ -1,7 +1,8 @@
+use serde::{Deserialize, Serialize};
 use vizia::prelude::*;
 
 // TODO - Move this to its own file with other local UI state
-#[derive(Debug, Lens, Clone)]
+#[derive(Debug, Lens, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PanelState {
     pub channel_rack_orientation: ChannelRackOrientation,
     pub hide_clips: bool,
@@ -10,6 +11,18 @@ pub struct PanelState {
     pub hide_browser: bool,
 }
 
+impl Default for PanelState {
+    fn default() -> Self {
+        Self {
+            channel_rack_orientation: ChannelRackOrientation::Horizontal,
+            hide_clips: false,
+            hide_piano_roll: false,
+            browser_width: 200.0,
+            hide_browser: false,
+        }
+    }
+}
+
 pub enum PanelEvent {
     ToggleChannelRackOrientation,
     ToggleClips,
@@ -59,7 +72,7 @@ impl Model for PanelState {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Data)]
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
 pub enum ChannelRackOrientation {
     Horizontal,
     Vertical,
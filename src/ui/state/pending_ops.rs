@@ -0,0 +1,341 @@
+use dropseed::{DSEngineHandle, DSEngineRequest};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{ActivatedEngineInfo, UiState};
+
+/// Builds the engine request of an operation for the currently activated engine.
+///
+/// This is called again when the operation is re-issued after the engine
+/// restarted, since the IDs of the nodes in the audio graph change when it does.
+/// Returns `None` if the operation can no longer be applied.
+pub type RebuildRequestFn = Box<dyn FnMut(&ActivatedEngineInfo) -> Option<DSEngineRequest>>;
+
+/// Undoes the state-side change of an operation that could not be re-issued.
+pub type RollbackFn = Box<dyn FnOnce(&mut UiState)>;
+
+/// A request that was sent to the engine and has not been confirmed yet.
+pub struct PendingOp {
+    /// A short description of the operation to show in the debug view.
+    pub description: String,
+
+    /// When the request was (last) sent.
+    pub sent_at: Instant,
+
+    /// The number of times this operation was re-issued after an engine
+    /// restart.
+    pub num_reissues: u32,
+
+    /// This is `None` if the request has no state-side counterpart, in which
+    /// case it is simply dropped if the engine crashes before confirming it.
+    rebuild: Option<RebuildRequestFn>,
+    rollback: Option<RollbackFn>,
+}
+
+impl PendingOp {
+    /// How long this operation has been waiting for its confirmation.
+    pub fn age(&self) -> Duration {
+        self.sent_at.elapsed()
+    }
+}
+
+impl fmt::Debug for PendingOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingOp")
+            .field("description", &self.description)
+            .field("sent_at", &self.sent_at)
+            .field("num_reissues", &self.num_reissues)
+            .field("reissuable", &self.rebuild.is_some())
+            .finish()
+    }
+}
+
+/// The result of reconciling the journal after the engine restarted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// The operations that were sent to the engine again.
+    pub reissued: Vec<String>,
+    /// The operations whose state-side changes were undone.
+    pub rolled_back: Vec<String>,
+}
+
+/// Keeps track of the audio graph requests that were sent to the engine but not
+/// confirmed by an `AudioGraphModified` event yet.
+///
+/// The engine handles graph requests in the order they were sent, so every
+/// confirmation retires the oldest entry. If the engine crashes, the unconfirmed
+/// entries were lost. Once it is activated again, they are re-issued, or rolled
+/// back in `UiState` if they can't be, so that the state and the engine don't
+/// drift apart.
+///
+/// Every graph request has to go through the journal (even ones without a
+/// state-side counterpart), or the confirmations would be matched to the wrong
+/// entries.
+#[derive(Debug, Default)]
+pub struct PendingOpJournal {
+    in_flight: VecDeque<PendingOp>,
+    /// The operations that were in flight when the engine was deactivated.
+    lost: Vec<PendingOp>,
+}
+
+impl PendingOpJournal {
+    /// Sends a graph request to the engine and journals it.
+    ///
+    /// * `rebuild` - Builds the request. If this is `None`, then `request` is sent
+    /// once and never re-issued.
+    /// * `rollback` - Undoes the state-side change if the request can't be
+    /// re-issued.
+    pub fn send(
+        &mut self,
+        ds_handle: &mut DSEngineHandle,
+        description: impl Into<String>,
+        request: DSEngineRequest,
+        rebuild: Option<RebuildRequestFn>,
+        rollback: Option<RollbackFn>,
+    ) {
+        ds_handle.send(request);
+        self.in_flight.push_back(PendingOp {
+            description: description.into(),
+            sent_at: Instant::now(),
+            num_reissues: 0,
+            rebuild,
+            rollback,
+        });
+    }
+
+    /// Retires the oldest operation. Call this for every `AudioGraphModified`
+    /// event.
+    pub fn on_confirmed(&mut self) -> Option<PendingOp> {
+        self.in_flight.pop_front()
+    }
+
+    /// Call this when the engine is deactivated. All operations in flight are
+    /// considered lost.
+    pub fn on_engine_deactivated(&mut self) {
+        self.lost.extend(self.in_flight.drain(..));
+    }
+
+    /// Call this once the engine is activated again (after the audio graph was
+    /// restored, if it was).
+    ///
+    /// Lost operations are re-issued if they can be rebuilt for the new engine,
+    /// and rolled back in `state` otherwise. Lost operations without a
+    /// state-side counterpart are dropped.
+    pub fn reconcile(
+        &mut self,
+        ds_handle: &mut DSEngineHandle,
+        activated_info: &ActivatedEngineInfo,
+        state: &mut UiState,
+    ) -> ReconcileReport {
+        let mut report = ReconcileReport::default();
+
+        for mut op in self.lost.drain(..) {
+            let request = match op.rebuild.as_mut() {
+                Some(rebuild) => rebuild(activated_info),
+                None => continue,
+            };
+
+            match request {
+                Some(request) => {
+                    ds_handle.send(request);
+                    report.reissued.push(op.description.clone());
+                    op.sent_at = Instant::now();
+                    op.num_reissues += 1;
+                    self.in_flight.push_back(op);
+                }
+                None => {
+                    if let Some(rollback) = op.rollback.take() {
+                        rollback(state);
+                    }
+                    report.rolled_back.push(op.description);
+                }
+            }
+        }
+
+        report
+    }
+
+    /// Returns the operations that are waiting for a confirmation, oldest first.
+    ///
+    /// This is meant for a debug view.
+    pub fn in_flight(&self) -> impl Iterator<Item = &PendingOp> {
+        self.in_flight.iter()
+    }
+
+    /// Returns the operations that were lost in an engine crash and have not
+    /// been reconciled yet.
+    pub fn lost(&self) -> impl Iterator<Item = &PendingOp> {
+        self.lost.iter()
+    }
+
+    /// Forgets all operations, i.e. when the engine is deactivated on purpose
+    /// and the audio graph will be rebuilt from scratch.
+    pub fn clear(&mut self) {
+        self.in_flight.clear();
+        self.lost.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use dropseed::{DSEngineEvent, ModifyGraphRequest};
+
+    use super::*;
+    use crate::backend::engine;
+    use crate::ui::app_config::LayoutConfig;
+    use crate::ui::state::ProjectState;
+    use meadowlark_core_types::time::SampleRate;
+
+    /// Spawns and activates an engine to send the journaled requests to.
+    fn activated_engine() -> (DSEngineHandle, ActivatedEngineInfo) {
+        let (mut ds_handle, engine_rx) = engine::spawn_engine();
+        ds_handle.send(DSEngineRequest::ActivateEngine(Box::new(engine::activate_settings(
+            SampleRate(48_000.0),
+        ))));
+
+        loop {
+            if let DSEngineEvent::EngineActivated(event) =
+                engine_rx.recv_timeout(Duration::from_secs(5)).unwrap()
+            {
+                let info = ActivatedEngineInfo {
+                    graph_in_node_id: event.graph_in_node_id.clone(),
+                    graph_out_node_id: event.graph_out_node_id.clone(),
+                    transport_handle: event.transport_handle,
+                    sample_rate: event.sample_rate,
+                    min_frames: event.min_frames,
+                    max_frames: event.max_frames,
+                    num_audio_in_channels: event.num_audio_in_channels,
+                    num_audio_out_channels: event.num_audio_out_channels,
+                };
+                return (ds_handle, info);
+            }
+        }
+    }
+
+    fn empty_request() -> DSEngineRequest {
+        DSEngineRequest::ModifyGraph(ModifyGraphRequest {
+            add_plugin_instances: vec![],
+            remove_plugin_instances: vec![],
+            connect_new_edges: vec![],
+            disconnect_edges: vec![],
+        })
+    }
+
+    /// A rebuild function that counts its calls, and gives up once it was
+    /// called `max_calls` times.
+    fn counting_rebuild(calls: &Rc<Cell<u32>>, max_calls: u32) -> Option<RebuildRequestFn> {
+        let calls = Rc::clone(calls);
+        Some(Box::new(move |_| {
+            calls.set(calls.get() + 1);
+            if calls.get() <= max_calls {
+                Some(empty_request())
+            } else {
+                None
+            }
+        }))
+    }
+
+    fn flag_rollback(rolled_back: &Rc<Cell<bool>>) -> Option<RollbackFn> {
+        let rolled_back = Rc::clone(rolled_back);
+        Some(Box::new(move |_| rolled_back.set(true)))
+    }
+
+    fn descriptions<'a>(ops: impl Iterator<Item = &'a PendingOp>) -> Vec<&'a str> {
+        ops.map(|op| op.description.as_str()).collect()
+    }
+
+    #[test]
+    fn confirmations_retire_the_oldest_operation() {
+        let (mut ds_handle, _) = activated_engine();
+        let mut journal = PendingOpJournal::default();
+        for description in ["first", "second", "third"] {
+            journal.send(&mut ds_handle, description, empty_request(), None, None);
+        }
+
+        assert_eq!(journal.on_confirmed().unwrap().description, "first");
+        assert_eq!(descriptions(journal.in_flight()), vec!["second", "third"]);
+    }
+
+    #[test]
+    fn lost_operations_are_reissued_or_rolled_back_after_a_crash() {
+        let (mut ds_handle, info) = activated_engine();
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let mut journal = PendingOpJournal::default();
+
+        let reissue_calls = Rc::new(Cell::new(0));
+        let stale_calls = Rc::new(Cell::new(0));
+        let reissued_rolled_back = Rc::new(Cell::new(false));
+        let stale_rolled_back = Rc::new(Cell::new(false));
+
+        journal.send(&mut ds_handle, "confirmed", empty_request(), None, None);
+        journal.send(
+            &mut ds_handle,
+            "reissued",
+            empty_request(),
+            counting_rebuild(&reissue_calls, u32::MAX),
+            flag_rollback(&reissued_rolled_back),
+        );
+        journal.send(
+            &mut ds_handle,
+            "stale",
+            empty_request(),
+            counting_rebuild(&stale_calls, 0),
+            flag_rollback(&stale_rolled_back),
+        );
+        journal.send(&mut ds_handle, "fire and forget", empty_request(), None, None);
+        journal.on_confirmed();
+
+        // The engine crashes with three operations in flight.
+        journal.on_engine_deactivated();
+        assert_eq!(journal.in_flight().count(), 0);
+        assert_eq!(descriptions(journal.lost()), vec!["reissued", "stale", "fire and forget"]);
+
+        let report = journal.reconcile(&mut ds_handle, &info, &mut state);
+        assert_eq!(
+            report,
+            ReconcileReport {
+                reissued: vec![String::from("reissued")],
+                rolled_back: vec![String::from("stale")]
+            }
+        );
+        assert!(!reissued_rolled_back.get());
+        assert!(stale_rolled_back.get());
+        assert_eq!(journal.lost().count(), 0);
+        assert_eq!(descriptions(journal.in_flight()), vec!["reissued"]);
+        assert_eq!(journal.in_flight().next().unwrap().num_reissues, 1);
+
+        // It crashes again before confirming the re-issued operation.
+        journal.on_engine_deactivated();
+        let report = journal.reconcile(&mut ds_handle, &info, &mut state);
+        assert_eq!(report.reissued, vec![String::from("reissued")]);
+        assert_eq!(reissue_calls.get(), 2);
+        assert_eq!(stale_calls.get(), 1);
+        assert_eq!(journal.in_flight().next().unwrap().num_reissues, 2);
+    }
+
+    #[test]
+    fn a_deliberate_restart_forgets_all_operations() {
+        let (mut ds_handle, info) = activated_engine();
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let mut journal = PendingOpJournal::default();
+        let rolled_back = Rc::new(Cell::new(false));
+
+        journal.send(
+            &mut ds_handle,
+            "edit",
+            empty_request(),
+            counting_rebuild(&Rc::new(Cell::new(0)), 0),
+            flag_rollback(&rolled_back),
+        );
+        journal.clear();
+
+        let report = journal.reconcile(&mut ds_handle, &info, &mut state);
+        assert_eq!(report, ReconcileReport::default());
+        assert!(!rolled_back.get());
+        assert_eq!(journal.in_flight().count(), 0);
+    }
+}
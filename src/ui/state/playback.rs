@@ -0,0 +1,55 @@
+//! The audio clips of the project as the timeline player plays them (see
+//! `backend::timeline`).
+
+use meadowlark_core_types::time::MusicalTime;
+
+use super::clip::{
+    AudioClipState, AutoFade, ClipJoins, InterpolationQuality, SUPER_FRAMES_PER_SECOND,
+};
+use crate::backend::timeline::ClipSource;
+
+/// Plays an audio clip with its warp markers, pitch, fades and gain envelope.
+///
+/// Live playback and the offline renders (export, bounce and consolidate) all
+/// play clips through this, so that a render sounds exactly like playback.
+///
+/// TODO: Use the tempo map instead of a single `bpm`.
+pub struct AudioClipPlayback {
+    clip: AudioClipState,
+    length: MusicalTime,
+    bpm: f64,
+    auto_fade: AutoFade,
+    joins: ClipJoins,
+    sample_rate: f64,
+    quality: InterpolationQuality,
+}
+
+impl AudioClipPlayback {
+    pub fn new(
+        clip: AudioClipState,
+        length: MusicalTime,
+        bpm: f64,
+        auto_fade: AutoFade,
+        joins: ClipJoins,
+        sample_rate: f64,
+        quality: InterpolationQuality,
+    ) -> Self {
+        Self { clip, length, bpm, auto_fade, joins, sample_rate, quality }
+    }
+}
+
+impl ClipSource for AudioClipPlayback {
+    fn position_and_gain(&self, frame: u64) -> (f64, f32) {
+        let frames_per_beat = 60.0 / self.bpm * self.sample_rate;
+        let time = MusicalTime::from_beats_f64(frame as f64 / frames_per_beat);
+
+        let pos = self.clip.source_position_at(time, self.bpm).0 as f64 / SUPER_FRAMES_PER_SECOND
+            * self.sample_rate;
+        let gain = self.clip.gain_at(time, self.length, self.bpm, &self.auto_fade, self.joins);
+        (pos, gain)
+    }
+
+    fn read(&self, source: &[f32], pos: f64, rate: f64) -> f32 {
+        self.quality.read(source, pos, rate)
+    }
+}
@@ -0,0 +1,387 @@
+use super::core_types::WMusicalTime;
+use super::timeline_grid::sanitize_bpm;
+use super::{
+    AutoFade, AutomationClipState, ChannelState, ClipId, ClipStart, ClipState, ClipType,
+    IdAllocator, InterpolationSettings, LaneState, TimeSignature, TimeSignatureChange, DEFAULT_BPM,
+};
+use crossbeam::channel::{self, Receiver, Sender};
+use meadowlark_core_types::time::MusicalTime;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use vizia::prelude::*;
+
+/// The musically meaningful state of a project, as it is saved to a project
+/// file.
+///
+/// A template is a saved project with `is_template` set. New projects can be
+/// started from one with `UiData::from_template()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectState {
+    #[serde(default)]
+    pub is_template: bool,
+
+    pub channels: Vec<ChannelState>,
+    pub clips: Vec<ClipState>,
+    pub lanes: Vec<LaneState>,
+    pub project_length: WMusicalTime,
+
+    /// The length of the project set by the user. The project is never shorter
+    /// than this, even if it has no clips. This is `project_length` in older
+    /// project files.
+    #[serde(default)]
+    pub min_project_length: Option<WMusicalTime>,
+
+    /// The tempo of the project in beats per minute.
+    #[serde(default = "default_bpm")]
+    pub bpm: f64,
+
+    pub time_signatures: Vec<TimeSignatureChange>,
+
+    /// The automatic fade applied at the start and end of every audio clip.
+    #[serde(default)]
+    pub auto_fade: AutoFade,
+
+    /// The interpolation quality of the clips that don't set their own.
+    #[serde(default)]
+    pub interpolation: InterpolationSettings,
+
+    /// Hands out the ids of the channels and clips. Older project files don't
+    /// have ids, see `assign_ids()`.
+    #[serde(default)]
+    pub ids: IdAllocator,
+
+    /// The number of bars the metronome counts in before recording starts.
+    #[serde(default)]
+    pub count_in_bars: u8,
+}
+
+impl ProjectState {
+    /// Loads the project file at `path`.
+    ///
+    /// The project is checked for references to channels and lanes that don't
+    /// exist, so a hand-edited file can't crash the app later on. A tempo
+    /// outside of [`MIN_BPM`, `MAX_BPM`] (i.e. of a project saved when the
+    /// minimum was 1 BPM) is clamped to the range.
+    pub fn load(path: &Path) -> Result<Self, ProjectLoadError> {
+        let contents = std::fs::read_to_string(path).map_err(ProjectLoadError::Io)?;
+        let mut project: Self = serde_json::from_str(&contents).map_err(ProjectLoadError::Parse)?;
+        project.validate().map_err(ProjectLoadError::Invalid)?;
+
+        let bpm = sanitize_bpm(project.bpm);
+        if bpm != project.bpm {
+            log::warn!(
+                "The project's tempo of {} BPM is out of range, using {} BPM",
+                project.bpm,
+                bpm
+            );
+            project.bpm = bpm;
+        }
+
+        Ok(project)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let num_channels = self.channels.len();
+        if num_channels == 0 {
+            return Err("the project has no master channel".into());
+        }
+
+        for (index, channel) in self.channels.iter().enumerate() {
+            let parent_ok = channel.parent_channel.map_or(true, |parent| parent < num_channels);
+            if !parent_ok || channel.subchannels.iter().any(|sub| *sub >= num_channels) {
+                return Err(format!("channel {} refers to a channel that doesn't exist", index));
+            }
+        }
+
+        for (index, lane) in self.lanes.iter().enumerate() {
+            if lane.channel.map_or(false, |channel| channel >= num_channels) {
+                return Err(format!("lane {} refers to a channel that doesn't exist", index));
+            }
+        }
+
+        for (index, clip) in self.clips.iter().enumerate() {
+            if clip.channel >= num_channels {
+                return Err(format!("clip {} refers to a channel that doesn't exist", index));
+            }
+            if let ClipStart::OnLane(on_lane) = &clip.timeline_start {
+                if on_lane.lane_index as usize >= self.lanes.len() {
+                    return Err(format!("clip {} refers to a lane that doesn't exist", index));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gives an id to every channel and clip that doesn't have one yet.
+    ///
+    /// Projects saved before there were ids (and the templates built in code)
+    /// have none. They get ids in the order the channels and then the clips are
+    /// stored in, so loading the same file always gives the same ids.
+    pub fn assign_ids(&mut self) {
+        for channel in self.channels.iter() {
+            self.ids.reserve(channel.id.0);
+        }
+        for clip in self.clips.iter() {
+            self.ids.reserve(clip.id.0);
+        }
+
+        for channel in self.channels.iter_mut().filter(|c| !c.id.is_assigned()) {
+            channel.id = self.ids.channel_id();
+        }
+        for clip in self.clips.iter_mut().filter(|c| !c.id.is_assigned()) {
+            clip.id = self.ids.clip_id();
+        }
+    }
+
+    /// Saves the project to `path`.
+    ///
+    /// The project is written to a temporary file first, which then replaces
+    /// the old file, so a failed save never destroys the previous version.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// A project with only a master channel and a single empty lane.
+    pub fn empty() -> Self {
+        Self {
+            is_template: false,
+            channels: vec![ChannelState {
+                name: String::from("Master"),
+                color: Color::from("#D4D5D5").into(),
+                ..Default::default()
+            }],
+            clips: Vec::new(),
+            lanes: vec![LaneState::default()],
+            project_length: MusicalTime::from_beats(16).into(),
+            min_project_length: Some(MusicalTime::from_beats(16).into()),
+            bpm: DEFAULT_BPM,
+            time_signatures: vec![TimeSignatureChange {
+                bar: 0,
+                time_signature: TimeSignature::new(4, 4),
+            }],
+            auto_fade: AutoFade::default(),
+            interpolation: InterpolationSettings::default(),
+            ids: IdAllocator::default(),
+            count_in_bars: 0,
+        }
+    }
+
+    /// The project that is opened when no other project or template is given.
+    pub fn default_template() -> Self {
+        // Fill with dummy state for now.
+        Self {
+            is_template: true,
+            channels: vec![
+                ChannelState {
+                    name: String::from("Master"),
+                    selected: false,
+                    color: Color::from("#D4D5D5").into(),
+                    subchannels: vec![1, 5],
+                    ..Default::default()
+                },
+                ChannelState {
+                    name: String::from("Drum Group"),
+                    selected: false,
+                    color: Color::from("#EDE171").into(),
+                    subchannels: vec![2, 3, 4],
+                    ..Default::default()
+                },
+                ChannelState {
+                    name: String::from("Kick"),
+                    selected: false,
+                    color: Color::from("#EDE171").into(),
+                    subchannels: vec![],
+                    ..Default::default()
+                },
+                ChannelState {
+                    name: String::from("Snare"),
+                    selected: true,
+                    color: Color::from("#EDE171").into(),
+                    subchannels: vec![],
+                    ..Default::default()
+                },
+                ChannelState {
+                    name: String::from("Hat"),
+                    selected: false,
+                    color: Color::from("#EDE171").into(),
+                    subchannels: vec![],
+                    ..Default::default()
+                },
+                ChannelState {
+                    name: String::from("Spicy Synth"),
+                    selected: false,
+                    color: Color::from("#EA716C").into(),
+                    subchannels: vec![],
+                    ..Default::default()
+                },
+            ],
+            clips: vec![ClipState {
+                id: ClipId::default(),
+                name: String::from("Drum Group 1"),
+                label: None,
+                notes: String::new(),
+                z_order: 0,
+                color: None,
+                channel: 1,
+                timeline_start: ClipStart::NotInTimeline,
+                length: MusicalTime::from_beats(4).into(),
+                type_: ClipType::Automation(AutomationClipState {}),
+            }],
+            lanes: vec![
+                LaneState {
+                    name: Some(String::from("Track 1")),
+                    color: Some(Color::from("#EDE171").into()),
+                    height: Some(2.0),
+                    disabled: false,
+                    selected: false,
+                    ..Default::default()
+                },
+                LaneState {
+                    name: Some(String::from("Track 2")),
+                    color: Some(Color::from("#EDE171").into()),
+                    height: None,
+                    disabled: false,
+                    selected: false,
+                    ..Default::default()
+                },
+                LaneState {
+                    name: Some(String::from("Track 3")),
+                    color: Some(Color::from("#EA716C").into()),
+                    height: None,
+                    disabled: false,
+                    selected: false,
+                    ..Default::default()
+                },
+            ],
+            project_length: MusicalTime::from_beats(16).into(),
+            min_project_length: Some(MusicalTime::from_beats(16).into()),
+            bpm: DEFAULT_BPM,
+            time_signatures: vec![TimeSignatureChange {
+                bar: 0,
+                time_signature: TimeSignature::new(4, 4),
+            }],
+            auto_fade: AutoFade::default(),
+            interpolation: InterpolationSettings::default(),
+            ids: IdAllocator::default(),
+            count_in_bars: 0,
+        }
+    }
+}
+
+fn default_bpm() -> f64 {
+    DEFAULT_BPM
+}
+
+/// The result of a project save that ran in the background.
+#[derive(Debug, Clone)]
+pub struct ProjectSaveResult {
+    pub path: PathBuf,
+    pub result: Result<(), String>,
+}
+
+/// The reason a project file could not be loaded.
+#[derive(Debug)]
+pub enum ProjectLoadError {
+    Io(io::Error),
+    /// The file is not valid JSON or doesn't match the project format (i.e. it
+    /// was truncated or edited by hand).
+    Parse(serde_json::Error),
+    /// The file was parsed but its contents are inconsistent.
+    Invalid(String),
+}
+
+impl fmt::Display for ProjectLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectLoadError::Io(e) => write!(f, "{}", e),
+            ProjectLoadError::Parse(e) => write!(
+                f,
+                "Failed to parse project: {} (line {}, column {})",
+                e,
+                e.line(),
+                e.column()
+            ),
+            ProjectLoadError::Invalid(reason) => write!(f, "The project is invalid: {}", reason),
+        }
+    }
+}
+
+impl Error for ProjectLoadError {}
+
+/// Serializes and writes projects on a background thread, so that saving a
+/// large project doesn't stall the UI (and with it the polling of the engine).
+///
+/// Only one save runs at a time. If another save is requested while one is
+/// running, the newest one is started as soon as it finishes.
+pub struct ProjectSaver {
+    tx: Sender<ProjectSaveResult>,
+    rx: Receiver<ProjectSaveResult>,
+    saving: bool,
+    queued: Option<(PathBuf, ProjectState)>,
+}
+
+impl ProjectSaver {
+    pub fn new() -> Self {
+        let (tx, rx) = channel::unbounded();
+        Self { tx, rx, saving: false, queued: None }
+    }
+
+    /// Saves `project` to `path` in the background. `project` is a snapshot, so
+    /// edits made while saving don't end up half-written in the file.
+    pub fn save(&mut self, path: PathBuf, project: ProjectState) {
+        if self.saving {
+            self.queued = Some((path, project));
+            return;
+        }
+
+        let tx = self.tx.clone();
+        let res = std::thread::Builder::new().name("project save".into()).spawn({
+            let path = path.clone();
+            move || {
+                let result = project.save(&path).map_err(|e| e.to_string());
+                let _ = tx.send(ProjectSaveResult { path, result });
+            }
+        });
+
+        match res {
+            Ok(_) => self.saving = true,
+            Err(e) => {
+                let _ = self.tx.send(ProjectSaveResult {
+                    path,
+                    result: Err(format!("Failed to start saving: {}", e)),
+                });
+            }
+        }
+    }
+
+    /// Returns true if a save is running or queued.
+    pub fn is_saving(&self) -> bool {
+        self.saving || self.queued.is_some()
+    }
+
+    /// Returns the saves that finished since the last call, and starts the
+    /// queued save if there is one.
+    pub fn poll(&mut self) -> Vec<ProjectSaveResult> {
+        let results: Vec<ProjectSaveResult> = self.rx.try_iter().collect();
+        if !results.is_empty() {
+            self.saving = false;
+            if let Some((path, project)) = self.queued.take() {
+                self.save(path, project);
+            }
+        }
+        results
+    }
+}
+
+impl Default for ProjectSaver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,282 @@
+//! Recording the inputs of the record-armed channels into takes, and recording
+//! the movements of automation controls in touch mode.
+
+use meadowlark_core_types::time::{Frames, MusicalTime, SuperFrames};
+use std::error::Error;
+use std::path::PathBuf;
+
+use super::clip::SUPER_FRAMES_PER_SECOND;
+use super::{
+    AudioClipState, AutomationMode, AutomationParam, ClipId, ClipStart, ClipState, ClipType,
+    CompClipState, InputAssignment, NotificationLogType, OnLane, PcmResourceInfo, TouchRecorder,
+    UiData,
+};
+use crate::backend::recorder::{RecordedTake, TakeSettings};
+
+impl UiData {
+    /// Starts recording the movements of the control of `param` on the channel
+    /// at `channel`, if its automation lane is in touch mode.
+    pub(super) fn begin_touch_recording(
+        &mut self,
+        channel: usize,
+        param: &AutomationParam,
+        value: f64,
+    ) {
+        let is_touch = self
+            .state
+            .channels
+            .get(channel)
+            .and_then(|c| c.automation_lanes.iter().find(|lane| &lane.param == param))
+            .map(|lane| lane.mode == AutomationMode::Touch)
+            .unwrap_or(false);
+
+        self.touch_recording =
+            if is_touch { Some((TouchRecorder::new(channel, param.clone()), value)) } else { None };
+    }
+
+    /// Samples the held control while the transport is playing.
+    ///
+    /// TODO: Sample once per block on the audio thread, timestamped with the
+    /// block's musical position, instead of once per frame of the UI.
+    pub(super) fn poll_touch_recording(&mut self) {
+        if !self.state.transport.is_playing {
+            return;
+        }
+        if let Some((recorder, value)) = &mut self.touch_recording {
+            recorder.record(self.state.transport.playhead.get().as_beats_f64(), *value);
+        }
+    }
+
+    /// Writes the recorded automation into the lane once the control is
+    /// released.
+    pub(super) fn end_touch_recording(&mut self) {
+        if let Some((recorder, _)) = self.touch_recording.take() {
+            let channel = recorder.channel;
+            let param = recorder.param.clone();
+            self.state.write_automation(channel, &param, &recorder.finish());
+        }
+    }
+
+    /// Starts recording the inputs of the record-armed channels into new files,
+    /// from the last start of the timeline player's transport on. The recorder
+    /// lines the input up with the frames the player plays (see
+    /// `backend::recorder`), so the first recorded frame is where the
+    /// transport started or, if it started with a count-in, where the count-in
+    /// ended. If the transport is punching, only the punch range is kept.
+    ///
+    /// TODO: Compensate for the latency of the input and the output device, so
+    /// that the takes line up with what was heard while recording.
+    pub(super) fn start_recording(&mut self) {
+        let dir = match self.project_path.as_ref().and_then(|path| path.parent()) {
+            Some(project_dir) => project_dir.join("Recordings"),
+            None => recording_cache_dir(),
+        };
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+
+        let takes: Vec<TakeSettings> = self
+            .state
+            .channels
+            .iter()
+            .filter(|channel| channel.record_armed)
+            .filter_map(|channel| {
+                let (first_input, num_channels) = match channel.input {
+                    InputAssignment::None => return None,
+                    InputAssignment::Mono(input) => (usize::from(input), 1),
+                    InputAssignment::StereoPair(first_input) => (usize::from(first_input), 2),
+                };
+                // The name ends up in a file name, so anything that might not
+                // be valid in one is replaced.
+                let name: String = channel
+                    .name
+                    .chars()
+                    .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                    .collect();
+                Some(TakeSettings {
+                    track: channel.id.0,
+                    first_input,
+                    num_channels,
+                    path: dir.join(format!("{}_take_{}_{}.wav", name, timestamp, channel.id.0)),
+                })
+            })
+            .collect();
+        if takes.is_empty() {
+            self.notification_log.push(NotificationLogType::Info(String::from(
+                "Nothing is recorded because no record-armed channel has an input",
+            )));
+            return;
+        }
+
+        let transport = &self.state.transport;
+        let sample_rate = self.sample_rate.get();
+        let punch = if transport.is_punching {
+            Some((
+                self.state.tempo_map.musical_to_frames(transport.punch_in.get(), sample_rate).0,
+                self.state.tempo_map.musical_to_frames(transport.punch_out.get(), sample_rate).0,
+            ))
+        } else {
+            None
+        };
+
+        let result =
+            std::fs::create_dir_all(&dir).map_err(Box::<dyn Error>::from).and_then(|_| match self
+                .system_io_stream_handle
+                .as_mut()
+            {
+                Some(system_io_stream_handle) => {
+                    let transport_start = system_io_stream_handle.timeline().num_starts();
+                    system_io_stream_handle.recorder()?.start(takes, transport_start, punch)
+                }
+                None => Err("There is no audio device".into()),
+            });
+        if let Err(e) = result {
+            self.state.transport.is_recording = false;
+            self.notification_log
+                .push(NotificationLogType::Error(format!("Failed to start recording: {}", e)));
+        }
+    }
+
+    /// Stops recording, and adds every recorded take as a clip at the position
+    /// it was recorded at, on the first lane of its channel. If a channel
+    /// recorded several passes over the loop, they are added as one take
+    /// folder (see `add_take_folder()`).
+    pub(super) fn stop_recording(&mut self) {
+        let takes = match self.system_io_stream_handle.as_mut() {
+            Some(system_io_stream_handle) => system_io_stream_handle.stop_recording(),
+            None => return,
+        };
+        let takes = match takes {
+            Ok(takes) => takes,
+            Err(e) => {
+                self.notification_log
+                    .push(NotificationLogType::Error(format!("Failed to record: {}", e)));
+                return;
+            }
+        };
+
+        // The passes of a channel come one after the other.
+        let mut takes = takes.into_iter().peekable();
+        while let Some(first) = takes.next() {
+            let mut passes = vec![first];
+            while let Some(take) = takes.next_if(|take| take.track == passes[0].track) {
+                passes.push(take);
+            }
+            // Nothing was recorded (i.e. when stopping before the punch range).
+            passes.retain(|take| {
+                if take.num_frames == 0 {
+                    let _ = std::fs::remove_file(&take.path);
+                }
+                take.num_frames > 0
+            });
+            let track = match passes.first() {
+                Some(take) => take.track,
+                None => continue,
+            };
+
+            // If the channel was removed while recording, the files are kept
+            // so that the takes are not lost.
+            let channel = match self.state.channels.iter().position(|c| c.id.0 == track) {
+                Some(channel) => channel,
+                None => continue,
+            };
+            let lane_index = self
+                .state
+                .timeline_grid
+                .lane_states
+                .lanes
+                .iter()
+                .position(|lane| lane.channel == Some(channel))
+                .unwrap_or(0) as u32;
+
+            let result = match passes.as_slice() {
+                [take] => {
+                    let start = self
+                        .state
+                        .tempo_map
+                        .frames_to_musical(Frames(take.start), self.sample_rate.get());
+                    self.import_audio_file(&take.path, channel, lane_index, start, false)
+                }
+                _ => self.add_take_folder(&passes, channel, lane_index),
+            };
+            if let Err(e) = result {
+                self.notification_log.push(NotificationLogType::Error(format!(
+                    "Failed to add the recorded take {}: {}",
+                    passes[0].path.display(),
+                    e
+                )));
+            }
+        }
+    }
+
+    /// Adds the `passes` over the loop that were recorded on `channel` as one
+    /// take folder (a comp clip with a take per pass) on the lane
+    /// `lane_index`, with the last pass heard. Returns the index of the clip.
+    ///
+    /// The folder spans from the latest start of a pass to the latest end,
+    /// which is the part of the loop that every pass recorded (unless the last
+    /// one was stopped early). The takes of passes that started earlier (i.e.
+    /// the first one if recording started before the loop start) start that
+    /// much later into their audio, so that every take stays where it was
+    /// recorded.
+    fn add_take_folder(
+        &mut self,
+        passes: &[RecordedTake],
+        channel: usize,
+        lane_index: u32,
+    ) -> Result<usize, Box<dyn Error>> {
+        let sample_rate = self.sample_rate.get();
+        let start = passes.iter().map(|take| take.start).max().unwrap_or(0);
+        let end = passes.iter().map(|take| take.start + take.num_frames).max().unwrap_or(start);
+
+        let mut takes = Vec::with_capacity(passes.len());
+        for pass in passes {
+            let info = PcmResourceInfo::probe(&pass.path)?;
+            self.resource_info.insert(pass.path.clone(), info);
+
+            let mut take = AudioClipState::new(pass.path.clone());
+            let offset_secs = (start - pass.start) as f64 / sample_rate.0;
+            take.clip_start_offset =
+                SuperFrames((offset_secs * SUPER_FRAMES_PER_SECOND).round() as u64).into();
+            takes.push(take);
+        }
+
+        let tempo_map = &self.state.tempo_map;
+        let musical_start = tempo_map.frames_to_musical(Frames(start), sample_rate);
+        let musical_end = tempo_map.frames_to_musical(Frames(end), sample_rate);
+        let length =
+            MusicalTime::from_beats_f64(musical_end.as_beats_f64() - musical_start.as_beats_f64());
+
+        let mut takes = takes.into_iter();
+        let mut comp = match takes.next() {
+            Some(first) => CompClipState::new(first, length),
+            None => return Err("Nothing was recorded".into()),
+        };
+        for take in takes {
+            comp.add_take(take);
+        }
+        comp.set_region(MusicalTime::from_beats(0)..length, passes.len() - 1);
+
+        let name =
+            passes[0].path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        Ok(self.state.add_clip(ClipState {
+            id: ClipId::default(),
+            name,
+            label: None,
+            notes: String::new(),
+            z_order: 0,
+            color: None,
+            timeline_start: ClipStart::OnLane(OnLane {
+                lane_index,
+                timeline_start: musical_start.into(),
+            }),
+            length: length.into(),
+            channel,
+            type_: ClipType::Comp(comp),
+        }))
+    }
+}
+
+/// Returns the directory that takes are recorded to while the project has not
+/// been saved yet.
+fn recording_cache_dir() -> PathBuf {
+    dirs::cache_dir().unwrap_or_else(std::env::temp_dir).join("meadowlark").join("recordings")
+}
@@ -0,0 +1,852 @@
+//! Offline renders: exports of clips and stems, bouncing clips in place and
+//! consolidating clips into one.
+
+use meadowlark_core_types::time::{MusicalTime, Seconds};
+use std::error::Error;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use super::{
+    AudioClipState, AutoFade, ChannelId, ClipId, ClipJoins, ClipStart, ClipType,
+    NotificationLogType, StateChange, UiData, UiState,
+};
+use crate::backend::timeline::{self, TimelineClip};
+use crate::backend::wav_export::{write_wav, WavExportOptions, WavSampleFormat};
+
+impl UiData {
+    /// Renders the audio clips at `indices` to a WAV file at `path`, with their
+    /// gains, fades and envelopes but without any processing of their channels.
+    ///
+    /// The file covers the range from the start of the earliest clip to the end
+    /// of the latest one, with silence where no clip plays. The clips are
+    /// resampled with the project's export interpolation quality (unless they
+    /// set their own), and the integer formats are dithered.
+    ///
+    /// TODO: Use the tempo map instead of a single bpm.
+    pub fn export_clips_to_file(
+        &mut self,
+        indices: &[usize],
+        format: WavSampleFormat,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let auto_fade = self.state.auto_fade;
+        let rendered = self.render_clips(indices, &auto_fade)?;
+        let options = WavExportOptions::for_channels(format, rendered.len());
+        write_wav(path, &rendered, self.sample_rate.get(), &options)?;
+
+        Ok(())
+    }
+
+    /// Renders the audio clips at `indices` (see `export_clips_to_file()`),
+    /// with one buffer per channel. The result is mono if all of the clips are
+    /// mono, and stereo otherwise.
+    ///
+    /// The clips are played exactly like the timeline player plays them (and in
+    /// the same order), so the render matches what is heard. `auto_fade` is the
+    /// automatic fade applied to the clips.
+    fn render_clips(
+        &mut self,
+        indices: &[usize],
+        auto_fade: &AutoFade,
+    ) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let indices = self.state.in_render_order(indices);
+        let joins = self.state.clip_joins(&indices);
+        self.render_timeline_clips(&indices, &joins, auto_fade, true)
+    }
+
+    /// Renders the audio clips at `indices` (in render order) from the start of
+    /// the first one to the end of the last one, through the same
+    /// `TimelineTrack` that plays them. `joins` are the edges of the clips that
+    /// are left without an automatic fade, in the same order as `indices`.
+    ///
+    /// `for_export` selects the interpolation quality of exports instead of the
+    /// one of playback (see `timeline_clip()`).
+    fn render_timeline_clips(
+        &mut self,
+        indices: &[usize],
+        joins: &[ClipJoins],
+        auto_fade: &AutoFade,
+        for_export: bool,
+    ) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+        let clips = self.timeline_clips(indices, joins, auto_fade, for_export)?;
+        let start = clips.iter().map(|clip| clip.start).min().unwrap_or(0);
+        let end = clips.iter().map(|clip| clip.end).max().unwrap_or(start);
+        Ok(self.render_frames(&clips, start..end))
+    }
+
+    /// Returns the audio clips at `indices` the way the timeline player plays
+    /// them (see `render_timeline_clips()`). Fails if none of them is an audio
+    /// clip on the timeline.
+    fn timeline_clips(
+        &mut self,
+        indices: &[usize],
+        joins: &[ClipJoins],
+        auto_fade: &AutoFade,
+        for_export: bool,
+    ) -> Result<Vec<TimelineClip>, Box<dyn Error>> {
+        let mut clips = Vec::with_capacity(indices.len());
+        for (index, joins) in indices.iter().zip(joins) {
+            if let Some(clip) = self.timeline_clip(*index, auto_fade, *joins, for_export)? {
+                clips.push(clip);
+            }
+        }
+        if clips.is_empty() {
+            return Err("No audio clips to export".into());
+        }
+        Ok(clips)
+    }
+
+    /// Renders `clips` over the timeline frames `frames`, with one buffer per
+    /// channel. The result is mono if all of the clips are mono, and stereo
+    /// otherwise.
+    fn render_frames(&self, clips: &[TimelineClip], frames: Range<u64>) -> Vec<Vec<f32>> {
+        let num_channels = clips.iter().map(|clip| clip.audio.len()).max().unwrap_or(1);
+        timeline::render_clips(
+            clips,
+            self.sample_rate.get().0,
+            frames.start,
+            frames.end.saturating_sub(frames.start) as usize,
+            num_channels,
+        )
+    }
+
+    /// Renders the post-fader output of the timeline track of every channel to
+    /// a WAV file of its own in `dir`, named after the id of the channel (i.e.
+    /// `3.wav`), so that the channels can be mixed elsewhere.
+    ///
+    /// Each channel is played exactly like the timeline player plays it: its
+    /// audio clips through its input stage, fader, pan and automation, but
+    /// without any processing of the channel. All of the stems cover the
+    /// project from its start to its end (see
+    /// `UiState::default_bounce_range()`), so they line up sample for sample
+    /// and add up to the mix.
+    ///
+    /// If `respect_mute_solo` is set, only the channels that are heard with
+    /// the current mute and solo states get a stem (see
+    /// `UiState::audible_channels()`). Otherwise every channel does.
+    ///
+    /// The render runs right away, like the other renders. Returns the paths
+    /// of the written files.
+    pub fn export_stems(
+        &mut self,
+        dir: &Path,
+        format: WavSampleFormat,
+        respect_mute_solo: bool,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let channels = if respect_mute_solo {
+            self.state.audible_channels()
+        } else {
+            (0..self.state.channels.len()).collect()
+        };
+        if channels.is_empty() {
+            return Err("There are no channels to export".into());
+        }
+        std::fs::create_dir_all(dir)?;
+
+        let sample_rate = self.sample_rate.get();
+        let (start, end) = self.state.default_bounce_range();
+        let tempo_map = &self.state.tempo_map;
+        let frames = tempo_map.musical_to_frames(start, sample_rate).0
+            ..tempo_map.musical_to_frames(end, sample_rate).0;
+
+        let mut paths = Vec::with_capacity(channels.len());
+        for channel in channels {
+            let track = self.offline_track(channel, true);
+            let path = dir.join(format!("{}.wav", track.id));
+            // The stems start and end at the edges of the project, which are
+            // not faded.
+            let rendered = timeline::render_timeline(vec![track], sample_rate.0, frames.clone(), 0);
+            let options = WavExportOptions::for_channels(format, rendered.len());
+            write_wav(&path, &rendered, sample_rate, &options)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Renders `range` of the timeline to a WAV file at `path` the way it
+    /// sounds when the transport plays it, i.e. to bounce a single section.
+    ///
+    /// The timeline tracks of the channels that are heard (see
+    /// `UiState::audible_channels()`) are played from the start of the range
+    /// and mixed like in `export_stems()`. Clips that cross an edge of the
+    /// range are rendered for the part inside of it. The render fades in and
+    /// out with the start/stop fade of the transport, so that it doesn't click
+    /// where the range cuts through the audio.
+    ///
+    /// The render runs right away, like the other renders.
+    pub fn export_range(
+        &mut self,
+        range: Range<MusicalTime>,
+        format: WavSampleFormat,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        if range.end <= range.start {
+            return Err("The range to export is empty".into());
+        }
+
+        let sample_rate = self.sample_rate.get();
+        let tempo_map = &self.state.tempo_map;
+        let frames = tempo_map.musical_to_frames(range.start, sample_rate).0
+            ..tempo_map.musical_to_frames(range.end, sample_rate).0;
+        let fade = self.timeline_declick().start_stop;
+
+        let tracks = self
+            .state
+            .audible_channels()
+            .into_iter()
+            .map(|channel| self.offline_track(channel, true))
+            .collect();
+        let rendered = timeline::render_timeline(tracks, sample_rate.0, frames, fade);
+        let options = WavExportOptions::for_channels(format, rendered.len());
+        write_wav(path, &rendered, sample_rate, &options)?;
+
+        Ok(())
+    }
+
+    /// Renders the selected clips to a WAV file in a temporary directory, so
+    /// that the timeline can hand the file to the OS when the clips are dragged
+    /// out of the app. Returns the path of the file, or `None` if the clips
+    /// could not be rendered (in which case a notification is shown).
+    ///
+    /// Call `finish_drag_export()` when the drag ends.
+    pub fn begin_drag_export(&mut self, format: WavSampleFormat) -> Option<PathBuf> {
+        self.finish_drag_export(false);
+
+        let indices = self.state.selected_clips();
+        let name = match indices.as_slice() {
+            [index] => self
+                .state
+                .clips
+                .get(*index)
+                .map(|clip| clip.display_label().to_string())
+                .unwrap_or_default(),
+            _ => String::from("Selection"),
+        };
+        // The name ends up in a file name, so anything that might not be valid
+        // in one is replaced.
+        let name: String = name
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let name = if name.trim().is_empty() { String::from("Clip") } else { name };
+
+        let dir = drag_export_dir();
+        let path = dir.join(format!("{}.wav", name.trim()));
+        let result = std::fs::create_dir_all(&dir)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|_| self.export_clips_to_file(&indices, format, &path));
+        match result {
+            Ok(()) => {
+                self.drag_export = Some(path.clone());
+                Some(path)
+            }
+            Err(e) => {
+                self.notification_log.push(NotificationLogType::Error(format!(
+                    "Failed to render the selected clips: {}",
+                    e
+                )));
+                None
+            }
+        }
+    }
+
+    /// Ends the drag started by `begin_drag_export()`. If the drag was
+    /// cancelled (`dropped` is false), the rendered file is deleted. Otherwise
+    /// it is left for the app it was dropped on to copy.
+    pub fn finish_drag_export(&mut self, dropped: bool) {
+        if let Some(path) = self.drag_export.take() {
+            if !dropped {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    log::warn!("Failed to remove drag export {:?}: {}", &path, e);
+                }
+            }
+        }
+    }
+
+    /// Renders each of the audio clips at `indices` with its pitch, warp
+    /// markers, fades, gain envelope and channel settings into a new audio
+    /// file, and replaces the clip's audio with the file and default settings.
+    ///
+    /// The clips stay where they are on the timeline and keep their name,
+    /// label, notes, color and stacking order. The files are written next to
+    /// the project (or to the cache directory if the project was never saved).
+    ///
+    /// All of the clips are rendered before any of them is replaced, so if one
+    /// fails to render, none of them are bounced (and the files written so far
+    /// are removed). The clips are replaced as one undo entry.
+    ///
+    /// Returns the indices of the bounced clips. Indices that don't point to an
+    /// audio clip are skipped.
+    pub fn bounce_clips_in_place(
+        &mut self,
+        indices: &[usize],
+    ) -> Result<Vec<usize>, Box<dyn Error>> {
+        let dir = match self.project_path.as_ref().and_then(|path| path.parent()) {
+            Some(project_dir) => project_dir.join("Bounces"),
+            None => bounce_cache_dir(),
+        };
+        std::fs::create_dir_all(&dir)?;
+
+        // The bounced clip gets the automatic fade anyway, so it is left out of
+        // the render to avoid applying it twice.
+        let auto_fade = AutoFade { enabled: false, ..self.state.auto_fade };
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+
+        let mut bounced: Vec<(usize, PathBuf)> = Vec::new();
+        for index in indices.iter() {
+            let stem = match self.state.clips.get(*index).map(|c| &c.type_) {
+                Some(ClipType::Audio(audio_clip)) => audio_clip
+                    .pcm_path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                _ => continue,
+            };
+            if bounced.iter().any(|(i, _)| i == index) {
+                continue;
+            }
+
+            let path = dir.join(format!("{}_bounce_{}_{}.wav", stem, timestamp, index));
+            let result = self.render_clips(&[*index], &auto_fade).and_then(|rendered| {
+                let options =
+                    WavExportOptions::for_channels(WavSampleFormat::Float32, rendered.len());
+                write_wav(&path, &rendered, self.sample_rate.get(), &options)
+            });
+            if let Err(e) = result {
+                for (_, path) in bounced.iter() {
+                    if let Err(e) = std::fs::remove_file(path) {
+                        log::warn!("Failed to remove bounce {:?}: {}", path, e);
+                    }
+                }
+                return Err(e);
+            }
+            bounced.push((*index, path));
+        }
+
+        if !bounced.is_empty() {
+            self.state.record_undo("Bounce clips in place");
+        }
+        for (index, path) in bounced.iter() {
+            if let ClipType::Audio(audio_clip) = &mut self.state.clips[*index].type_ {
+                *audio_clip = AudioClipState::new(path.clone());
+            }
+            self.state.changes.push(StateChange::ClipChanged { index: *index });
+        }
+
+        Ok(bounced.into_iter().map(|(index, _)| index).collect())
+    }
+
+    /// Renders the audio clips at `indices` into one new audio clip that
+    /// replaces them (i.e. after heavy editing).
+    ///
+    /// The clips must all play into the same channel. They are mixed exactly
+    /// like the timeline player plays them (with their fades and crossfades)
+    /// from the start of the earliest clip to the end of the latest one, and
+    /// the new clip is put in their place on the lane of the earliest clip.
+    /// The automatic fade is left out of the render at the outer edges (see
+    /// `consolidation_joins()`), since the new clip gets it there itself.
+    ///
+    /// The originals are replaced as one undo entry, so undoing brings them
+    /// back. Returns the index of the new clip.
+    pub fn consolidate_clips(&mut self, indices: &[usize]) -> Result<usize, Box<dyn Error>> {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        if indices.len() < 2 {
+            return Err("Select at least two clips to consolidate".into());
+        }
+
+        let mut first: Option<(usize, u32, MusicalTime)> = None;
+        for index in indices.iter() {
+            let clip = self.state.clips.get(*index).ok_or("A selected clip does not exist")?;
+            let on_lane = match (&clip.type_, &clip.timeline_start) {
+                (ClipType::Audio(_), ClipStart::OnLane(on_lane)) => on_lane,
+                _ => return Err("Only audio clips on the timeline can be consolidated".into()),
+            };
+            match first {
+                Some((channel, _, _)) if channel != clip.channel => {
+                    return Err("Only clips on the same channel can be consolidated".into());
+                }
+                Some((_, _, start)) if start <= on_lane.timeline_start.get() => {}
+                _ => {
+                    first = Some((clip.channel, on_lane.lane_index, on_lane.timeline_start.get()));
+                }
+            }
+        }
+        let (channel, lane_index, start) = first.ok_or("No clips to consolidate")?;
+
+        let dir = match self.project_path.as_ref().and_then(|path| path.parent()) {
+            Some(project_dir) => project_dir.join("Bounces"),
+            None => bounce_cache_dir(),
+        };
+        std::fs::create_dir_all(&dir)?;
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let path = dir.join(format!("consolidated_{}_{}.wav", timestamp, indices[0]));
+
+        // The clips are rendered the way they play, so that the new clip sounds
+        // the same. It gets the automatic fade at its own edges, so that is
+        // left out of the render.
+        let auto_fade = self.state.auto_fade;
+        let render_order = self.state.in_render_order(&indices);
+        let joins = self.state.consolidation_joins(&render_order);
+        let rendered = self.render_timeline_clips(&render_order, &joins, &auto_fade, false)?;
+        let options = WavExportOptions::for_channels(WavSampleFormat::Float32, rendered.len());
+        write_wav(&path, &rendered, self.sample_rate.get(), &options)?;
+
+        let before = self.state.to_project();
+        let index = self.import_audio_file(&path, channel, lane_index, start, false)?;
+        // The new clip covers the same range as the originals, so the crossfades
+        // of the neighbors with them are kept.
+        self.state.remove_clips(&indices, false);
+        self.state.undo_history.push("Consolidate clips", before);
+
+        // All of the originals came before the new clip.
+        let index = index - indices.len();
+        self.state.clip_selection.clear();
+        self.state.clip_selection.select(self.state.clips[index].id);
+        Ok(index)
+    }
+
+    /// Renders everything the audio clips of the channel `channel` play in
+    /// `range` into one new audio clip that replaces them there (i.e. to
+    /// flatten a section after lots of splits and crossfades).
+    ///
+    /// The clips are mixed exactly like the timeline player plays them (with
+    /// their gains, fades, envelopes and overlaps) but without any processing
+    /// of the channel. Clips that cross an edge of the range only have their
+    /// part inside of it rendered, and are cut there (see `cut_range()`). The
+    /// new clip starts at the start of the range, on the lane of the earliest
+    /// clip, and gets the automatic fade at its edges like any other clip.
+    ///
+    /// The render runs right away, like the other renders. The clips are
+    /// replaced as one undo entry. Returns the id of the new clip.
+    ///
+    /// TODO: Render long ranges on a worker thread, with progress.
+    pub fn consolidate_range(
+        &mut self,
+        channel: ChannelId,
+        range: Range<MusicalTime>,
+    ) -> Result<ClipId, Box<dyn Error>> {
+        let channel = self.state.channel_index(channel).ok_or("The channel does not exist")?;
+        if range.end <= range.start {
+            return Err("The range to consolidate is empty".into());
+        }
+
+        let indices = self.state.clips_in_range(channel, &range);
+        let mut audio_indices = Vec::with_capacity(indices.len());
+        for index in indices {
+            match &self.state.clips[index].type_ {
+                ClipType::Audio(_) => audio_indices.push(index),
+                ClipType::Comp(_) => {
+                    return Err("Flatten the take folders in the range to consolidate it".into());
+                }
+                // Only the audio of the channel is consolidated.
+                _ => {}
+            }
+        }
+        let order = self.state.in_render_order(&audio_indices);
+        let lane_index = match order.first().and_then(|i| self.state.clips[*i].lane_range_beats()) {
+            Some((lane_index, _, _)) => lane_index,
+            None => return Err("There are no audio clips in the range".into()),
+        };
+
+        let dir = match self.project_path.as_ref().and_then(|path| path.parent()) {
+            Some(project_dir) => project_dir.join("Bounces"),
+            None => bounce_cache_dir(),
+        };
+        std::fs::create_dir_all(&dir)?;
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let path = dir
+            .join(format!("consolidated_{}_{}.wav", timestamp, self.state.channels[channel].id.0));
+
+        let auto_fade = self.state.auto_fade;
+        let joins = self.state.range_consolidation_joins(&order, &range);
+        let clips = self.timeline_clips(&order, &joins, &auto_fade, false)?;
+        let sample_rate = self.sample_rate.get();
+        let tempo_map = &self.state.tempo_map;
+        let frames = tempo_map.musical_to_frames(range.start, sample_rate).0
+            ..tempo_map.musical_to_frames(range.end, sample_rate).0;
+        let rendered = self.render_frames(&clips, frames);
+        let options = WavExportOptions::for_channels(WavSampleFormat::Float32, rendered.len());
+        write_wav(&path, &rendered, sample_rate, &options)?;
+
+        let before = self.state.to_project();
+        let index = self.import_audio_file(&path, channel, lane_index, range.start, false)?;
+        let id = self.state.clips[index].id;
+        self.state.cut_range(&order, &range);
+        self.state.undo_history.push("Consolidate range", before);
+
+        self.state.clip_selection.clear();
+        self.state.clip_selection.select(id);
+        Ok(id)
+    }
+}
+
+impl UiState {
+    /// Returns the edges of the clips at `indices` that are left without an
+    /// automatic fade when they are rendered into one clip that replaces them
+    /// (see `clip_joins()`).
+    ///
+    /// Besides the edges where the clips join, these are the edges at the start
+    /// and at the end of the rendered range, since the new clip gets the
+    /// automatic fade there itself.
+    pub fn consolidation_joins(&self, indices: &[usize]) -> Vec<ClipJoins> {
+        let ranges = self.clip_ranges(indices);
+        let start = ranges.iter().flatten().map(|(start, _)| *start).fold(f64::INFINITY, f64::min);
+        let end = ranges.iter().flatten().map(|(_, end)| *end).fold(f64::NEG_INFINITY, f64::max);
+        self.joins_within(indices, &ranges, start, end)
+    }
+
+    /// Like `consolidation_joins()`, for rendering the clips at `indices` from
+    /// the start to the end of `range` (see `UiData::consolidate_range()`).
+    /// Edges at or outside of the range are left without an automatic fade.
+    pub fn range_consolidation_joins(
+        &self,
+        indices: &[usize],
+        range: &Range<MusicalTime>,
+    ) -> Vec<ClipJoins> {
+        let ranges = self.clip_ranges(indices);
+        self.joins_within(indices, &ranges, range.start.as_beats_f64(), range.end.as_beats_f64())
+    }
+
+    /// The starts and the ends in beats of the clips at `indices`, or `None`
+    /// for the ones that are not on the timeline.
+    fn clip_ranges(&self, indices: &[usize]) -> Vec<Option<(f64, f64)>> {
+        indices
+            .iter()
+            .map(|index| {
+                let (_, start, end) = self.clips.get(*index)?.lane_range_beats()?;
+                Some((start, end))
+            })
+            .collect()
+    }
+
+    /// Returns the `clip_joins()` of the clips at `indices`, with `ranges` from
+    /// `clip_ranges()`, plus the edges at or outside of `start` and `end`.
+    fn joins_within(
+        &self,
+        indices: &[usize],
+        ranges: &[Option<(f64, f64)>],
+        start: f64,
+        end: f64,
+    ) -> Vec<ClipJoins> {
+        let mut joins = self.clip_joins(indices);
+        for (joins, range) in joins.iter_mut().zip(ranges) {
+            if let Some((clip_start, clip_end)) = range {
+                joins.start |= *clip_start <= start;
+                joins.end |= *clip_end >= end;
+            }
+        }
+        joins
+    }
+
+    /// Returns the clips on the channel at `channel` that overlap `range` on
+    /// the timeline.
+    pub fn clips_in_range(&self, channel: usize, range: &Range<MusicalTime>) -> Vec<usize> {
+        let (start, end) = (range.start.as_beats_f64(), range.end.as_beats_f64());
+        self.clips
+            .iter()
+            .enumerate()
+            .filter(|(_, clip)| clip.channel == channel)
+            .filter_map(|(index, clip)| {
+                let (_, clip_start, clip_end) = clip.lane_range_beats()?;
+                (clip_start < end && clip_end > start).then_some(index)
+            })
+            .collect()
+    }
+
+    /// Cuts `range` out of the clips at `indices`. Clips inside of the range
+    /// are removed, clips that cross one of its edges are trimmed to it, and a
+    /// clip that spans all of it is split into the pieces before and after it.
+    ///
+    /// The fades of the pieces are cleared where they were cut, since the audio
+    /// there ends up in the clip that replaces the range. The clips keep their
+    /// ids, except for the piece after the range of a split clip.
+    pub fn cut_range(&mut self, indices: &[usize], range: &Range<MusicalTime>) {
+        let bpm = self.timeline_grid.bpm;
+        let (start, end) = (range.start.as_beats_f64(), range.end.as_beats_f64());
+
+        let mut removed = Vec::new();
+        for index in indices.iter().copied() {
+            let (clip_start, clip_end) =
+                match self.clips.get(index).and_then(|clip| clip.lane_range_beats()) {
+                    Some((_, clip_start, clip_end)) => (clip_start, clip_end),
+                    None => continue,
+                };
+            if clip_start >= start && clip_end <= end {
+                removed.push(index);
+                continue;
+            }
+
+            if clip_end > end {
+                let mut after = self.clips[index].clone();
+                after.resize_start(range.end, bpm);
+                if let ClipType::Audio(audio_clip) = &mut after.type_ {
+                    audio_clip.fade_in_secs = Seconds(0.0).into();
+                }
+                if clip_start >= start {
+                    self.clips[index] = after;
+                    self.changes.push(StateChange::ClipMoved { index });
+                    continue;
+                }
+                // The piece stays in the same place in the stacking order.
+                let z_order = after.z_order;
+                let after = self.add_clip(after);
+                self.clips[after].z_order = z_order;
+            }
+
+            let clip = &mut self.clips[index];
+            clip.resize_end(range.start, bpm, None);
+            if let ClipType::Audio(audio_clip) = &mut clip.type_ {
+                audio_clip.fade_out_secs = Seconds(0.0).into();
+            }
+            self.changes.push(StateChange::ClipMoved { index });
+        }
+        self.remove_clips(&removed, false);
+    }
+
+    /// Returns the indices of the channels that are heard with the current mute
+    /// and solo states: the soloed channels if any channel is soloed, or else
+    /// every channel. Muted channels are never heard, even when soloed.
+    pub fn audible_channels(&self) -> Vec<usize> {
+        let any_soloed = self.channels.iter().any(|channel| channel.soloed);
+        self.channels
+            .iter()
+            .enumerate()
+            .filter(|(_, channel)| !channel.muted && (channel.soloed || !any_soloed))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// The range of the timeline that is bounced by default, from the start of
+    /// the project to its end.
+    pub fn default_bounce_range(&self) -> (MusicalTime, MusicalTime) {
+        (MusicalTime::from_beats(0), self.timeline_grid.project_length.get())
+    }
+}
+
+/// Returns the directory that bounced clips are written to while the project
+/// has not been saved yet.
+fn bounce_cache_dir() -> PathBuf {
+    dirs::cache_dir().unwrap_or_else(std::env::temp_dir).join("meadowlark").join("bounce")
+}
+
+/// Returns the directory that clips dragged out of the app are rendered to.
+fn drag_export_dir() -> PathBuf {
+    std::env::temp_dir().join("meadowlark").join("drag-export")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::timeline::{TimelineClip, TimelineMsg, TimelineTrack, TrackClips};
+    use crate::ui::app_config::LayoutConfig;
+    use crate::ui::state::clip::beats_to_super_frames;
+    use crate::ui::state::test_clips::clip;
+    use crate::ui::state::{AudioClipPlayback, ChannelState, InterpolationQuality, ProjectState};
+    use std::sync::Arc;
+
+    /// The audio clip at `index` the way `UiData::timeline_clip()` makes it for
+    /// the timeline player, with `source` as its audio.
+    fn player_clip(
+        state: &UiState,
+        index: usize,
+        joins: ClipJoins,
+        source: &Arc<Vec<Vec<f32>>>,
+    ) -> TimelineClip {
+        let clip = &state.clips[index];
+        let audio_clip = match &clip.type_ {
+            ClipType::Audio(audio_clip) => audio_clip.clone(),
+            _ => panic!("not an audio clip"),
+        };
+        let bpm = state.timeline_grid.bpm;
+        let frames = |beats: f64| (beats * 60.0 / bpm * 48_000.0).round() as u64;
+        let (_, start, end) = clip.lane_range_beats().unwrap();
+        TimelineClip {
+            id: clip.id.0,
+            start: frames(start),
+            end: frames(end),
+            audio: Arc::clone(source),
+            rate: audio_clip.playback_rate(),
+            polarity: audio_clip.polarity(),
+            trims: [1.0, 1.0],
+            source: Arc::new(AudioClipPlayback::new(
+                audio_clip,
+                clip.length.get(),
+                bpm,
+                state.auto_fade,
+                joins,
+                48_000.0,
+                InterpolationQuality::Linear,
+            )),
+        }
+    }
+
+    /// Plays `clips` on a track of the timeline player from frame 0, and
+    /// returns the left channel.
+    fn play_clips(clips: Vec<TimelineClip>, num_frames: usize) -> Vec<f32> {
+        let (mut handle, mut player) = timeline::timeline(48_000.0);
+        handle.send(TimelineMsg::AddTrack(TimelineTrack::new(1, 48_000.0)));
+        handle.send(TimelineMsg::SetClips { track: 1, clips: TrackClips::new(clips, 48_000.0) });
+        handle.send(TimelineMsg::Play { from: 0 });
+
+        let mut out = vec![0.0; num_frames * 2];
+        for block in out.chunks_mut(256 * 2) {
+            player.process_interleaved(block, 2);
+        }
+        out.iter().step_by(2).copied().collect()
+    }
+
+    #[test]
+    fn a_consolidated_clip_plays_like_the_clips_it_replaces() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        assert!(state.auto_fade.length().is_some());
+        let source: Vec<f32> = (0..10 * 48_000).map(|i| (i as f32 * 0.001).sin()).collect();
+        let source = Arc::new(vec![source]);
+        // Two clips with a gap between them, which fade there.
+        let a = state.add_clip(clip(0, 0, 2));
+        let b = state.add_clip(clip(0, 3, 2));
+        let num_frames = 5 * 24_000;
+
+        let order = state.in_render_order(&[a, b]);
+        let clips = |joins: Vec<ClipJoins>| -> Vec<TimelineClip> {
+            order
+                .iter()
+                .zip(joins)
+                .map(|(i, joins)| player_clip(&state, *i, joins, &source))
+                .collect()
+        };
+        let played = play_clips(clips(state.clip_joins(&order)), num_frames);
+
+        // Consolidate the clips like `UiData::consolidate_clips()`.
+        let rendered = timeline::render_clips(
+            &clips(state.consolidation_joins(&order)),
+            48_000.0,
+            0,
+            num_frames,
+            1,
+        );
+        assert_eq!(rendered.len(), 1);
+        state.remove_clips(&[a, b], false);
+        let consolidated = state.add_clip(clip(0, 0, 5));
+        let consolidated =
+            player_clip(&state, consolidated, ClipJoins::default(), &Arc::new(rendered));
+        let consolidated = play_clips(vec![consolidated], num_frames);
+
+        // The automatic fades are heard once, at the same places.
+        assert!(played.iter().any(|s| s.abs() > 0.5));
+        assert_eq!(played[0], 0.0);
+        assert_eq!(played[48_000..72_000], [0.0; 24_000]);
+        let max_diff =
+            played.iter().zip(consolidated.iter()).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+        assert!(max_diff < 1e-5, "max diff {}", max_diff);
+    }
+
+    #[test]
+    fn a_consolidated_range_plays_like_the_butt_joined_clips_in_it() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let source: Vec<f32> = (0..10 * 48_000).map(|i| (i as f32 * 0.001).sin()).collect();
+        let source = Arc::new(vec![source]);
+        // Two clips that meet at beat 2 but play different audio, so they fade
+        // there.
+        let a = state.add_clip(clip(0, 0, 2));
+        let b = state.add_clip(clip(0, 2, 2));
+        let range = MusicalTime::from_beats(0)..MusicalTime::from_beats(4);
+        let num_frames = 96_000;
+
+        let order = state.in_render_order(&state.clips_in_range(0, &range));
+        assert_eq!(order, vec![a, b]);
+        let clips = |joins: Vec<ClipJoins>| -> Vec<TimelineClip> {
+            order
+                .iter()
+                .zip(joins)
+                .map(|(i, joins)| player_clip(&state, *i, joins, &source))
+                .collect()
+        };
+        let played = play_clips(clips(state.clip_joins(&order)), num_frames);
+
+        // Consolidate the range like `UiData::consolidate_range()`.
+        let joins = state.range_consolidation_joins(&order, &range);
+        let rendered = timeline::render_clips(&clips(joins), 48_000.0, 0, num_frames, 1);
+        state.cut_range(&order, &range);
+        assert!(state.clips.is_empty());
+        let consolidated = state.add_clip(clip(0, 0, 4));
+        let consolidated =
+            player_clip(&state, consolidated, ClipJoins::default(), &Arc::new(rendered));
+        let consolidated = play_clips(vec![consolidated], num_frames);
+
+        assert_eq!(played[48_000], 0.0);
+        let max_diff =
+            played.iter().zip(consolidated.iter()).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+        assert!(max_diff < 1e-5, "max diff {}", max_diff);
+    }
+
+    #[test]
+    fn muted_and_unsoloed_channels_are_not_heard() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        for _ in 0..3 {
+            state.channels.push(ChannelState::default());
+        }
+        assert_eq!(state.audible_channels(), vec![0, 1, 2, 3]);
+
+        state.channels[1].muted = true;
+        assert_eq!(state.audible_channels(), vec![0, 2, 3]);
+
+        // A muted channel stays silent when it is soloed.
+        state.channels[1].soloed = true;
+        state.channels[2].soloed = true;
+        assert_eq!(state.audible_channels(), vec![2]);
+    }
+
+    #[test]
+    fn clips_across_the_edges_of_a_consolidated_range_are_cut_there() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+        let spanning = state.add_clip(clip(0, 0, 8));
+        let crossing = state.add_clip(clip(1, 4, 4));
+        let mut other_channel = clip(0, 2, 4);
+        other_channel.channel = 1;
+        state.add_clip(other_channel);
+        if let ClipType::Audio(audio_clip) = &mut state.clips[spanning].type_ {
+            audio_clip.fade_in_secs = Seconds(0.1).into();
+            audio_clip.fade_out_secs = Seconds(0.1).into();
+        }
+        let spanning_id = state.clips[spanning].id;
+
+        let range = MusicalTime::from_beats(2)..MusicalTime::from_beats(6);
+        let indices = state.clips_in_range(0, &range);
+        assert_eq!(indices, vec![spanning, crossing]);
+        state.cut_range(&indices, &range);
+
+        // The spanning clip is split around the range, and keeps its fades
+        // only at its outer edges.
+        let ranges: Vec<_> = state.clips.iter().map(|clip| clip.lane_range_beats()).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                Some((0, 0.0, 2.0)),
+                Some((1, 6.0, 8.0)),
+                Some((0, 2.0, 6.0)),
+                Some((0, 6.0, 8.0))
+            ]
+        );
+        assert_eq!(state.clips[0].id, spanning_id);
+        let fades = |index: usize| match &state.clips[index].type_ {
+            ClipType::Audio(audio_clip) => {
+                (audio_clip.fade_in_secs.get().0, audio_clip.fade_out_secs.get().0)
+            }
+            _ => panic!("not an audio clip"),
+        };
+        assert_eq!(fades(0), (0.1, 0.0));
+        assert_eq!(fades(3), (0.0, 0.1));
+
+        // The piece after the range plays on from where the range ends.
+        let bpm = state.timeline_grid.bpm;
+        match &state.clips[3].type_ {
+            ClipType::Audio(audio_clip) => {
+                assert_eq!(audio_clip.clip_start_offset.get(), beats_to_super_frames(6.0, bpm))
+            }
+            _ => panic!("not an audio clip"),
+        }
+        assert_eq!(state.clips[3].z_order, state.clips[0].z_order);
+    }
+}
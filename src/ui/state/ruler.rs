@@ -0,0 +1,254 @@
+use serde::{Deserialize, Serialize};
+use vizia::prelude::*;
+
+/// The possible spacings (in bars) between bar labels, from densest to sparsest.
+const BAR_LABEL_STRIDES: [u32; 8] = [1, 2, 4, 8, 16, 32, 64, 128];
+
+/// The possible number of subdivisions per beat, from densest to sparsest.
+const BEAT_SUBDIVISIONS: [u32; 4] = [16, 8, 4, 2];
+
+/// A time signature (i.e. 4/4 or 7/8).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+pub struct TimeSignature {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl TimeSignature {
+    pub const fn new(numerator: u32, denominator: u32) -> Self {
+        Self { numerator, denominator }
+    }
+
+    /// The length of one step of this time signature (i.e. an eighth note in
+    /// 7/8) in beats, where one beat is a quarter note.
+    pub fn step_beats(&self) -> f64 {
+        4.0 / f64::from(self.denominator)
+    }
+
+    /// The length of one bar of this time signature in beats.
+    pub fn bar_beats(&self) -> f64 {
+        f64::from(self.numerator) * self.step_beats()
+    }
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+/// A change in time signature that takes effect at the start of `bar`.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize, Deserialize)]
+pub struct TimeSignatureChange {
+    /// The index of the bar where this time signature starts (starting from 0).
+    pub bar: u32,
+    pub time_signature: TimeSignature,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RulerEntryKind {
+    Bar,
+    Beat,
+    Subdivision,
+}
+
+/// A single tick on the timeline ruler.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RulerEntry {
+    /// The position of this tick in beats.
+    pub position: f64,
+    pub kind: RulerEntryKind,
+    /// The text to display next to this tick (only used for bars).
+    pub label: Option<String>,
+}
+
+/// Returns the ticks of the timeline ruler in the range `[start, end)` (in
+/// beats).
+///
+/// * `pixels_per_beat` - The current horizontal zoom.
+/// * `time_signatures` - The time signature changes sorted by bar. Bars before
+/// the first change use 4/4.
+/// * `min_label_spacing_px` - The minimum spacing between two bar labels.
+/// * `min_tick_spacing_px` - The minimum spacing between two unlabeled ticks.
+///
+/// The densest subdivision that respects the spacing is chosen. When zoomed far
+/// out, only every 2nd/4th/8th/etc. bar is labeled. Since these choices only
+/// depend on the zoom level and the time signature, they stay stable while
+/// scrolling.
+pub fn ruler_entries(
+    start: f64,
+    end: f64,
+    pixels_per_beat: f64,
+    time_signatures: &[TimeSignatureChange],
+    min_label_spacing_px: f64,
+    min_tick_spacing_px: f64,
+) -> Vec<RulerEntry> {
+    let mut entries = Vec::new();
+    if end <= start || pixels_per_beat <= 0.0 {
+        return entries;
+    }
+
+    let mut time_signature = TimeSignature::default();
+    let mut next_change = 0;
+    let mut bar = 0;
+    let mut bar_start = 0.0;
+
+    while bar_start < end {
+        while let Some(change) = time_signatures.get(next_change) {
+            if change.bar > bar {
+                break;
+            }
+            time_signature = change.time_signature;
+            next_change += 1;
+        }
+
+        let bar_beats = time_signature.bar_beats();
+        if bar_beats <= 0.0 {
+            break;
+        }
+
+        if bar_start + bar_beats > start {
+            push_bar_entries(
+                &mut entries,
+                bar,
+                bar_start,
+                time_signature,
+                start..end,
+                pixels_per_beat,
+                min_label_spacing_px,
+                min_tick_spacing_px,
+            );
+        }
+
+        bar += 1;
+        bar_start += bar_beats;
+    }
+
+    entries
+}
+
+#[allow(clippy::too_many_arguments)]
+fn push_bar_entries(
+    entries: &mut Vec<RulerEntry>,
+    bar: u32,
+    bar_start: f64,
+    time_signature: TimeSignature,
+    range: std::ops::Range<f64>,
+    pixels_per_beat: f64,
+    min_label_spacing_px: f64,
+    min_tick_spacing_px: f64,
+) {
+    let bar_px = time_signature.bar_beats() * pixels_per_beat;
+    let label_stride = BAR_LABEL_STRIDES
+        .iter()
+        .copied()
+        .find(|stride| f64::from(*stride) * bar_px >= min_label_spacing_px)
+        .unwrap_or(BAR_LABEL_STRIDES[BAR_LABEL_STRIDES.len() - 1]);
+
+    if range.contains(&bar_start) {
+        if bar % label_stride == 0 {
+            entries.push(RulerEntry {
+                position: bar_start,
+                kind: RulerEntryKind::Bar,
+                label: Some(format!("{}", bar + 1)),
+            });
+        } else if bar_px >= min_tick_spacing_px {
+            entries.push(RulerEntry {
+                position: bar_start,
+                kind: RulerEntryKind::Bar,
+                label: None,
+            });
+        }
+    }
+
+    let step_beats = time_signature.step_beats();
+    let step_px = step_beats * pixels_per_beat;
+    if step_px < min_tick_spacing_px {
+        return;
+    }
+
+    let subdivisions = BEAT_SUBDIVISIONS
+        .iter()
+        .copied()
+        .find(|subdivisions| step_px / f64::from(*subdivisions) >= min_tick_spacing_px)
+        .unwrap_or(1);
+
+    for step in 0..time_signature.numerator {
+        let step_start = bar_start + f64::from(step) * step_beats;
+
+        if step != 0 && range.contains(&step_start) {
+            entries.push(RulerEntry {
+                position: step_start,
+                kind: RulerEntryKind::Beat,
+                label: None,
+            });
+        }
+
+        for subdivision in 1..subdivisions {
+            let position =
+                step_start + f64::from(subdivision) * step_beats / f64::from(subdivisions);
+            if range.contains(&position) {
+                entries.push(RulerEntry {
+                    position,
+                    kind: RulerEntryKind::Subdivision,
+                    label: None,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(entries: &[RulerEntry], kind: RulerEntryKind) -> Vec<f64> {
+        entries.iter().filter(|e| e.kind == kind).map(|e| e.position).collect()
+    }
+
+    #[test]
+    fn subdivisions_get_denser_when_zooming_in() {
+        // 10 px per beat: only bars and beats have room.
+        let entries = ruler_entries(0.0, 4.0, 10.0, &[], 40.0, 6.0);
+        assert_eq!(positions(&entries, RulerEntryKind::Bar), vec![0.0]);
+        assert_eq!(positions(&entries, RulerEntryKind::Beat), vec![1.0, 2.0, 3.0]);
+        assert!(positions(&entries, RulerEntryKind::Subdivision).is_empty());
+
+        // 40 px per beat: eighth and sixteenth notes have room too.
+        let entries = ruler_entries(0.0, 1.0, 40.0, &[], 40.0, 10.0);
+        assert_eq!(positions(&entries, RulerEntryKind::Subdivision), vec![0.25, 0.5, 0.75]);
+    }
+
+    #[test]
+    fn only_every_nth_bar_is_labeled_when_zoomed_out() {
+        // A bar is 8 px wide, so a label needs 8 bars.
+        let entries = ruler_entries(0.0, 64.0, 2.0, &[], 60.0, 4.0);
+        let labels: Vec<&str> = entries.iter().filter_map(|e| e.label.as_deref()).collect();
+        assert_eq!(labels, vec!["1", "9"]);
+        // The other bars still get an unlabeled tick.
+        assert_eq!(positions(&entries, RulerEntryKind::Bar).len(), 16);
+    }
+
+    #[test]
+    fn time_signature_changes_move_the_bar_lines() {
+        let changes = [TimeSignatureChange { bar: 1, time_signature: TimeSignature::new(7, 8) }];
+        let entries = ruler_entries(0.0, 12.0, 20.0, &changes, 10.0, 5.0);
+
+        assert_eq!(positions(&entries, RulerEntryKind::Bar), vec![0.0, 4.0, 7.5, 11.0]);
+        let beats_in_7_8: Vec<f64> = positions(&entries, RulerEntryKind::Beat)
+            .into_iter()
+            .filter(|p| *p > 4.0 && *p < 7.5)
+            .collect();
+        assert_eq!(beats_in_7_8, vec![4.5, 5.0, 5.5, 6.0, 6.5, 7.0]);
+    }
+
+    #[test]
+    fn ticks_stay_the_same_while_scrolling() {
+        let all = ruler_entries(0.0, 32.0, 13.0, &[], 50.0, 6.0);
+        let scrolled = ruler_entries(5.5, 21.0, 13.0, &[], 50.0, 6.0);
+
+        let expected: Vec<RulerEntry> =
+            all.into_iter().filter(|e| e.position >= 5.5 && e.position < 21.0).collect();
+        assert_eq!(scrolled, expected);
+    }
+}
@@ -0,0 +1,57 @@
+use super::{ClipStart, ClipState};
+
+/// Returns the start and end positions (in beats) of every clip on the timeline,
+/// skipping the clips whose indices are in `exclude` (i.e. the clips being
+/// dragged).
+pub fn clip_edges(clips: &[ClipState], exclude: &[usize]) -> Vec<f64> {
+    let mut edges = Vec::new();
+    for (index, clip) in clips.iter().enumerate() {
+        if exclude.contains(&index) {
+            continue;
+        }
+
+        if let ClipStart::OnLane(on_lane) = &clip.timeline_start {
+            let start = on_lane.timeline_start.get().as_beats_f64();
+            edges.push(start);
+            edges.push(start + clip.length.get().as_beats_f64());
+        }
+    }
+    edges
+}
+
+/// Snaps `position` (in beats) to the nearest clip edge in `edges` or to the
+/// nearest line of the grid (if `grid_beats` is `Some`), whichever is closer.
+///
+/// Clip edges are only snapped to when they are within `tolerance_beats` of
+/// `position`. The grid is always snapped to.
+///
+/// The UI should skip calling this while the snapping modifier key is held.
+pub fn snap_position(
+    position: f64,
+    edges: &[f64],
+    grid_beats: Option<f64>,
+    tolerance_beats: f64,
+) -> f64 {
+    let nearest_edge = edges
+        .iter()
+        .copied()
+        .filter(|edge| (edge - position).abs() <= tolerance_beats)
+        .min_by(|a, b| (a - position).abs().total_cmp(&(b - position).abs()));
+
+    let nearest_grid_line = grid_beats
+        .filter(|grid_beats| *grid_beats > 0.0)
+        .map(|grid_beats| (position / grid_beats).round() * grid_beats);
+
+    match (nearest_edge, nearest_grid_line) {
+        (Some(edge), Some(grid_line)) => {
+            if (edge - position).abs() <= (grid_line - position).abs() {
+                edge
+            } else {
+                grid_line
+            }
+        }
+        (Some(edge), None) => edge,
+        (None, Some(grid_line)) => grid_line,
+        (None, None) => position,
+    }
+}
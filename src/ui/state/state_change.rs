@@ -0,0 +1,37 @@
+/// A change made to the `UiState`.
+///
+/// These are recorded by every method that mutates the state, so that views
+/// can update only the parts of the UI that changed instead of re-reading
+/// whole lists.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateChange {
+    ChannelAdded {
+        index: usize,
+    },
+    /// A setting of the channel at `index` changed (i.e. its trim or routing).
+    ChannelChanged {
+        index: usize,
+    },
+    ClipAdded {
+        index: usize,
+    },
+    ClipMoved {
+        index: usize,
+    },
+    /// The clip at `index` was removed. The indices of all clips after it are
+    /// shifted down by one.
+    ClipRemoved {
+        index: usize,
+    },
+    /// A setting of the clip at `index` changed (i.e. its gain or fades).
+    ClipChanged {
+        index: usize,
+    },
+    /// The label or notes of the clip at `index` changed. Nothing has to be
+    /// sent to the engine for this.
+    ClipRenamed {
+        index: usize,
+    },
+    /// The project tempo changed.
+    TempoChanged,
+}
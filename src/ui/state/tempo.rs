@@ -0,0 +1,350 @@
+//! Changing the project tempo, and fitting audio clips to it.
+
+use meadowlark_core_types::time::{MusicalTime, SuperFrames};
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::clip::SUPER_FRAMES_PER_SECOND;
+use super::{
+    sanitize_bpm, ClipStart, ClipType, NotificationLogType, StateChange, TempoMapSnapshot, UiData,
+    UiState, MAX_BPM, MIN_BPM,
+};
+use crate::backend::tempo_detect::{self, TempoEstimate, MIN_TEMPO_FIT_CONFIDENCE};
+use crate::backend::time_stretch;
+use crate::backend::wav_export::{write_wav, WavExportOptions, WavSampleFormat};
+
+/// The minimum time between two tempo changes while the tempo widget is being
+/// dragged.
+const TEMPO_UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+
+impl UiData {
+    /// Applies the tempo sent by the last `UiEvent::SetTempo` if the previous
+    /// change was at least `TEMPO_UPDATE_INTERVAL` ago.
+    pub(super) fn poll_pending_tempo(&mut self) {
+        if self.last_tempo_update.elapsed() >= TEMPO_UPDATE_INTERVAL {
+            self.apply_pending_tempo();
+        }
+    }
+
+    /// Applies the tempo sent by the last `UiEvent::SetTempo` right away.
+    pub(super) fn apply_pending_tempo(&mut self) {
+        if let Some(bpm) = self.pending_tempo.take() {
+            self.last_tempo_update = Instant::now();
+            if !self.state.set_tempo(bpm) {
+                log::warn!("Ignored invalid tempo {}", bpm);
+            }
+        }
+    }
+
+    /// Returns the estimated tempo of the audio file at `path` if it was
+    /// analyzed, where the inner `None` means it has no clear tempo.
+    pub fn analyzed_tempo(&self, path: &Path) -> Option<Option<TempoEstimate>> {
+        self.tempo_analysis.get(path).copied()
+    }
+
+    /// Collects the finished tempo analyses and fits the clips that were
+    /// waiting for them.
+    pub(super) fn poll_tempo_analysis(&mut self) {
+        let analyses: Vec<_> = self.tempo_analyzer.poll().collect();
+        for analysis in analyses {
+            let estimate = match analysis.result {
+                Ok(estimate) => estimate,
+                Err(e) => {
+                    log::warn!("Failed to analyze the tempo of {:?}: {}", &analysis.path, e);
+                    None
+                }
+            };
+            self.tempo_analyses_in_progress.remove(&analysis.path);
+            self.tempo_analysis.insert(analysis.path.clone(), estimate);
+
+            let (fits, pending): (Vec<_>, Vec<_>) =
+                self.pending_tempo_fits.drain(..).partition(|(_, path)| *path == analysis.path);
+            self.pending_tempo_fits = pending;
+            for (id, _) in fits {
+                if let Some(index) = self.state.clip_index(id) {
+                    self.fit_clip_to_tempo(index, estimate);
+                }
+            }
+        }
+    }
+
+    /// Stretches the audio clip at `index` so that it lasts the whole number of
+    /// bars closest to its length at the `estimate`d tempo.
+    ///
+    /// Nothing is changed if the estimate is missing or not confident enough.
+    pub(super) fn fit_clip_to_tempo(&mut self, index: usize, estimate: Option<TempoEstimate>) {
+        let clip = match self.state.clips.get(index) {
+            Some(clip) => clip,
+            None => return,
+        };
+        let name = clip.display_label().to_string();
+        let estimate = match estimate {
+            Some(estimate) if estimate.confidence >= MIN_TEMPO_FIT_CONFIDENCE => estimate,
+            _ => {
+                self.notification_log.push(NotificationLogType::Info(format!(
+                    "{} was not fitted to the project tempo because its tempo is unclear.",
+                    name
+                )));
+                return;
+            }
+        };
+
+        let clip_start = clip.lane_range_beats().map(|(_, start, _)| start).unwrap_or(0.0);
+        let bar_beats = self.state.timeline_grid.bar_beats_at(clip_start);
+        let source_beats = match &clip.type_ {
+            ClipType::Audio(audio_clip) => {
+                self.resource_info.get(&audio_clip.pcm_path).and_then(|info| info.duration()).map(
+                    |duration| {
+                        audio_clip.effective_source_duration(duration).0 * estimate.bpm / 60.0
+                    },
+                )
+            }
+            _ => None,
+        };
+        let source_beats = match source_beats {
+            Some(source_beats) if bar_beats > 0.0 => source_beats,
+            _ => return,
+        };
+
+        let bars = (source_beats / bar_beats).round().max(1.0);
+        if let Err(e) = self.stretch_clip_to_bars(index, bars) {
+            self.notification_log.push(NotificationLogType::Error(format!(
+                "Failed to fit {} to the project tempo: {}",
+                name, e
+            )));
+        }
+    }
+
+    /// Estimates the tempo of the audio clip at `index`.
+    ///
+    /// If `set_project_tempo` is true and a tempo was detected, the project
+    /// tempo is set to it.
+    pub fn detect_clip_tempo(
+        &mut self,
+        index: usize,
+        set_project_tempo: bool,
+    ) -> Result<Option<TempoEstimate>, Box<dyn Error>> {
+        let buffers = self.load_clip_audio(index)?;
+        let estimate = tempo_detect::detect_tempo(&buffers, self.sample_rate.get());
+
+        if let (true, Some(estimate)) = (set_project_tempo, &estimate) {
+            self.state.set_tempo(sanitize_bpm(estimate.bpm));
+        }
+
+        Ok(estimate)
+    }
+
+    /// Time-stretches the audio clip at `index` (keeping its pitch) so that it
+    /// is exactly `bars` bars long at the current tempo, and resizes the clip to
+    /// match. `bars` does not need to be a whole number.
+    ///
+    /// The stretched audio is written to the cache directory and the clip is
+    /// pointed at it. Stretching a clip again always starts from its original
+    /// audio file, so the quality does not degrade.
+    ///
+    /// TODO: Use the tempo map instead of a single bpm.
+    pub fn stretch_clip_to_bars(&mut self, index: usize, bars: f64) -> Result<(), Box<dyn Error>> {
+        if !(bars.is_finite() && bars > 0.0) {
+            return Err(format!("Invalid number of bars: {}", bars).into());
+        }
+
+        let clip = self.state.clips.get(index).ok_or("Clip does not exist")?;
+        let audio_clip = match &clip.type_ {
+            ClipType::Audio(audio_clip) => audio_clip,
+            _ => return Err("Not an audio clip".into()),
+        };
+        let clip_start = match &clip.timeline_start {
+            ClipStart::OnLane(on_lane) => on_lane.timeline_start.get().as_beats_f64(),
+            ClipStart::NotInTimeline => 0.0,
+        };
+        let source_path =
+            audio_clip.stretched_from.clone().unwrap_or_else(|| audio_clip.pcm_path.clone());
+        let playback_rate = audio_clip.playback_rate();
+        let multichannel_mode = audio_clip.multichannel_mode;
+        let clip_start_offset = if audio_clip.stretched_from.is_some() {
+            // The offset was already applied when the stretched copy was made.
+            SuperFrames(0)
+        } else {
+            audio_clip.clip_start_offset.get()
+        };
+
+        let bpm = self.state.timeline_grid.bpm;
+        let target_beats = bars * self.state.timeline_grid.bar_beats_at(clip_start);
+        let target_secs = target_beats * 60.0 / bpm;
+
+        let sample_rate = self.sample_rate.get();
+        let mut buffers = multichannel_mode.apply(self.load_audio_file(source_path.clone())?);
+        let offset_frames = clip_start_offset.0 as f64 / SUPER_FRAMES_PER_SECOND * sample_rate.0;
+        for buffer in buffers.iter_mut() {
+            buffer.drain(0..(offset_frames.round() as usize).min(buffer.len()));
+        }
+        let source_frames = buffers.iter().map(|b| b.len()).min().unwrap_or(0);
+        if source_frames == 0 {
+            return Err("The clip has no audio to stretch".into());
+        }
+
+        // The clip is played back at `playback_rate`, so the stretched audio has
+        // to be that much longer to fill the target length.
+        let source_secs = source_frames as f64 / sample_rate.0 / playback_rate;
+        let ratio = target_secs / source_secs;
+        let stretched = time_stretch::time_stretch(&buffers, ratio, sample_rate);
+
+        // Different source files can share a name, so the path is part of the
+        // cache key too.
+        let mut hasher = DefaultHasher::new();
+        (&source_path, clip_start_offset.0).hash(&mut hasher);
+        let stem = source_path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+        let cache_dir = stretch_cache_dir();
+        let cache_path =
+            cache_dir.join(format!("{}_{:016x}_{:.6}.wav", stem, hasher.finish(), ratio));
+        std::fs::create_dir_all(&cache_dir)?;
+        let options = WavExportOptions::for_channels(WavSampleFormat::Float32, stretched.len());
+        write_wav(&cache_path, &stretched, sample_rate, &options)?;
+
+        let clip = &mut self.state.clips[index];
+        if let ClipType::Audio(audio_clip) = &mut clip.type_ {
+            audio_clip.stretched_from = Some(source_path);
+            audio_clip.pcm_path = cache_path;
+            audio_clip.clip_start_offset = SuperFrames(0).into();
+            // The markers point into the original audio, so they no longer fit.
+            audio_clip.warp_markers.clear();
+        }
+        clip.length = MusicalTime::from_beats_f64(target_beats).into();
+
+        self.state.changes.push(StateChange::ClipChanged { index });
+
+        Ok(())
+    }
+}
+
+impl UiState {
+    /// Sets the project tempo in beats per minute.
+    ///
+    /// Returns `false` if `bpm` is not in the range [`MIN_BPM`, `MAX_BPM`].
+    ///
+    /// Everything on the timeline (clips, the loop region, and the playhead) is
+    /// positioned in musical time, so it stays on the same bar and beat. While
+    /// playing, the timeline player moves its playhead and crossfades the clips
+    /// to their new positions (see `UiData::sync_timeline()`).
+    ///
+    /// Every change is an undo entry of its own, unless it is made between
+    /// `begin_tempo_change()` and `end_tempo_change()`.
+    pub fn set_tempo(&mut self, bpm: f64) -> bool {
+        if !(MIN_BPM..=MAX_BPM).contains(&bpm) {
+            return false;
+        }
+        if self.timeline_grid.bpm == bpm {
+            return true;
+        }
+
+        if self.tempo_change_before.is_none() {
+            self.record_undo("Change tempo");
+        }
+        self.timeline_grid.bpm = bpm;
+        self.tempo_map = TempoMapSnapshot::new(bpm, &self.timeline_grid.time_signatures);
+        self.changes.push(StateChange::TempoChanged);
+
+        true
+    }
+
+    /// Starts a tempo change that is made in several steps (i.e. while the tempo
+    /// widget is dragged). The steps until `end_tempo_change()` are undone as
+    /// one.
+    pub fn begin_tempo_change(&mut self) {
+        self.end_tempo_change();
+        self.tempo_change_before = Some(self.to_project());
+    }
+
+    /// Ends the tempo change started by `begin_tempo_change()`, and records it
+    /// as an undo entry if the tempo changed.
+    pub fn end_tempo_change(&mut self) {
+        if let Some(before) = self.tempo_change_before.take() {
+            if before.bpm != self.timeline_grid.bpm {
+                self.undo_history.push("Change tempo", before);
+            }
+        }
+    }
+}
+
+/// Returns the directory that the audio made by "stretch to fit" is cached in.
+fn stretch_cache_dir() -> PathBuf {
+    dirs::cache_dir().unwrap_or_else(std::env::temp_dir).join("meadowlark").join("stretch")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::app_config::LayoutConfig;
+    use crate::ui::state::clip::{beats_to_super_frames, super_frames_to_beats};
+    use crate::ui::state::test_clips::{EXTREME_BEATS, EXTREME_BPMS};
+    use crate::ui::state::{ProjectState, DEFAULT_BPM};
+
+    #[test]
+    fn tempo_changes_can_be_undone() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+
+        assert!(state.set_tempo(90.0));
+        assert!(state.set_tempo(140.0));
+        assert!(!state.set_tempo(MAX_BPM + 1.0));
+        assert_eq!(state.undo_history.undo_label(), Some("Change tempo"));
+
+        assert!(state.undo());
+        assert_eq!(state.tempo_map.bpm(), 90.0);
+        assert!(state.undo());
+        assert_eq!(state.tempo_map.bpm(), DEFAULT_BPM);
+        assert!(!state.undo());
+        assert!(state.redo());
+        assert_eq!(state.timeline_grid.bpm, 90.0);
+    }
+
+    #[test]
+    fn dragging_the_tempo_is_undone_in_one_step() {
+        let mut state = UiState::from_project(ProjectState::empty(), &LayoutConfig::default());
+
+        state.begin_tempo_change();
+        for bpm in [121.0, 125.0, 133.0, 140.0] {
+            assert!(state.set_tempo(bpm));
+        }
+        state.end_tempo_change();
+        assert_eq!(state.timeline_grid.bpm, 140.0);
+
+        assert!(state.undo());
+        assert_eq!(state.timeline_grid.bpm, DEFAULT_BPM);
+        assert!(!state.undo());
+        assert!(state.redo());
+        assert_eq!(state.timeline_grid.bpm, 140.0);
+
+        // A drag that ends where it started leaves no undo entry.
+        state.begin_tempo_change();
+        assert!(state.set_tempo(150.0));
+        assert!(state.set_tempo(140.0));
+        state.end_tempo_change();
+        assert!(state.undo());
+        assert_eq!(state.timeline_grid.bpm, DEFAULT_BPM);
+    }
+
+    #[test]
+    fn tempo_conversions_hold_at_extreme_tempos_and_positions() {
+        for bpm in EXTREME_BPMS {
+            let sanitized = sanitize_bpm(bpm);
+            assert!((MIN_BPM..=MAX_BPM).contains(&sanitized), "{} -> {}", bpm, sanitized);
+
+            for beats in EXTREME_BEATS {
+                let beats = f64::from(beats);
+                let super_frames = beats_to_super_frames(beats, bpm);
+                let back = super_frames_to_beats(super_frames, bpm);
+                assert!(
+                    (back - beats).abs() <= beats * 1e-9 + 1e-6,
+                    "{} beats at {} bpm -> {:?} -> {} beats",
+                    beats,
+                    bpm,
+                    super_frames,
+                    back
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use super::clip::SUPER_FRAMES_PER_SECOND;
+use super::TimeSignatureChange;
+use meadowlark_core_types::time::{Frames, MusicalTime, SampleRate, Seconds, SuperFrames};
+use std::sync::Arc;
+
+/// The width of one beat on the timeline in pixels at a horizontal zoom level of
+/// 1.0.
+pub const DEFAULT_BEAT_WIDTH_PX: f64 = 100.0;
+
+/// A read-only snapshot of the project's tempo map, used by the ruler and the
+/// snapping helpers to convert between pixels, frames, and musical time the
+/// same way the engine does.
+///
+/// This is cheap to clone, so views can keep a copy around while drawing. It
+/// is replaced (not mutated) whenever the tempo or the time signatures change.
+///
+/// The project only has a single tempo for now, so this is a constant-tempo
+/// map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMapSnapshot {
+    bpm: f64,
+    time_signatures: Arc<[TimeSignatureChange]>,
+}
+
+impl TempoMapSnapshot {
+    pub fn new(bpm: f64, time_signatures: &[TimeSignatureChange]) -> Self {
+        Self { bpm, time_signatures: time_signatures.into() }
+    }
+
+    /// The tempo in beats per minute.
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    /// The time signature changes, sorted by bar.
+    pub fn time_signatures(&self) -> &[TimeSignatureChange] {
+        &self.time_signatures
+    }
+
+    pub fn musical_to_seconds(&self, time: MusicalTime) -> Seconds {
+        Seconds(time.as_beats_f64() * 60.0 / self.bpm)
+    }
+
+    pub fn seconds_to_musical(&self, seconds: Seconds) -> MusicalTime {
+        MusicalTime::from_beats_f64(seconds.0.max(0.0) * self.bpm / 60.0)
+    }
+
+    pub fn musical_to_frames(&self, time: MusicalTime, sample_rate: SampleRate) -> Frames {
+        Frames((self.musical_to_seconds(time).0 * sample_rate.0).round() as u64)
+    }
+
+    pub fn frames_to_musical(&self, frames: Frames, sample_rate: SampleRate) -> MusicalTime {
+        self.seconds_to_musical(Seconds(frames.0 as f64 / sample_rate.0))
+    }
+
+    pub fn musical_to_super_frames(&self, time: MusicalTime) -> SuperFrames {
+        SuperFrames((self.musical_to_seconds(time).0 * SUPER_FRAMES_PER_SECOND).round() as u64)
+    }
+
+    pub fn super_frames_to_musical(&self, super_frames: SuperFrames) -> MusicalTime {
+        self.seconds_to_musical(Seconds(super_frames.0 as f64 / SUPER_FRAMES_PER_SECOND))
+    }
+
+    /// The width of one beat in pixels at the given horizontal zoom level.
+    pub fn pixels_per_beat(&self, horizontal_zoom_level: f64) -> f64 {
+        DEFAULT_BEAT_WIDTH_PX * horizontal_zoom_level
+    }
+
+    /// Returns the x position in pixels of `time`, relative to the left side of
+    /// a timeline view that starts at `left_start`.
+    pub fn musical_to_pixel(
+        &self,
+        time: MusicalTime,
+        left_start: MusicalTime,
+        horizontal_zoom_level: f64,
+    ) -> f64 {
+        (time.as_beats_f64() - left_start.as_beats_f64())
+            * self.pixels_per_beat(horizontal_zoom_level)
+    }
+
+    /// Returns the musical time at the x position `x` in pixels, relative to
+    /// the left side of a timeline view that starts at `left_start`. Positions
+    /// before the start of the project are clamped to it.
+    pub fn pixel_to_musical(
+        &self,
+        x: f64,
+        left_start: MusicalTime,
+        horizontal_zoom_level: f64,
+    ) -> MusicalTime {
+        let pixels_per_beat = self.pixels_per_beat(horizontal_zoom_level);
+        if pixels_per_beat <= 0.0 {
+            return left_start;
+        }
+        MusicalTime::from_beats_f64((left_start.as_beats_f64() + x / pixels_per_beat).max(0.0))
+    }
+}
@@ -0,0 +1,53 @@
+//! Clips and values that tests of the UI state share.
+
+use meadowlark_core_types::time::MusicalTime;
+use std::path::PathBuf;
+
+use super::{
+    AudioClipState, ClipId, ClipStart, ClipState, ClipType, OnLane, MAX_BPM,
+    MAX_PROJECT_LENGTH_BEATS, MIN_BPM,
+};
+
+/// Tempos and positions at and beyond the limits of the project.
+pub const EXTREME_BPMS: [f64; 12] = [
+    0.0,
+    -120.0,
+    1e-300,
+    1.0,
+    MIN_BPM,
+    120.0,
+    MAX_BPM,
+    MAX_BPM + 1.0,
+    1e300,
+    f64::NAN,
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+];
+pub const EXTREME_BEATS: [u32; 6] = [
+    0,
+    1,
+    1000,
+    MAX_PROJECT_LENGTH_BEATS / 2,
+    MAX_PROJECT_LENGTH_BEATS - 1,
+    MAX_PROJECT_LENGTH_BEATS,
+];
+
+/// An audio clip on the channel at index 0, on the lane `lane_index`, from
+/// `start_beats` for `length_beats`.
+pub fn clip(lane_index: u32, start_beats: u32, length_beats: u32) -> ClipState {
+    ClipState {
+        id: ClipId::default(),
+        name: String::new(),
+        label: None,
+        notes: String::new(),
+        z_order: 0,
+        color: None,
+        timeline_start: ClipStart::OnLane(OnLane {
+            lane_index,
+            timeline_start: MusicalTime::from_beats(start_beats).into(),
+        }),
+        length: MusicalTime::from_beats(length_beats).into(),
+        channel: 0,
+        type_: ClipType::Audio(AudioClipState::new(PathBuf::from("clip.wav"))),
+    }
+}
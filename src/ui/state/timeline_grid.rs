@@ -1,5 +1,6 @@
 use super::core_types::WMusicalTime;
-use super::{LaneStates, UiEvent};
+use super::{LaneStates, TimeSignature, TimeSignatureChange, UiEvent};
+use meadowlark_core_types::time::MusicalTime;
 use vizia::prelude::*;
 
 #[derive(Debug, Lens, Clone)]
@@ -41,14 +42,27 @@ pub struct TimelineGridState {
     /// The list of all current lanes. (Maybe start with like 100 for a new project?)
     pub lane_states: LaneStates,
 
-    /// The time of the end of the latest clip on the timeline. This can be used to
+    /// The time of the end of the latest clip on the timeline (padded to the
+    /// next bar), but at least `min_project_length`. This can be used to
     /// properly set the horizontal scroll bar.
+    ///
+    /// This grows automatically when clips are placed or extended past it, but
+    /// it only shrinks with `UiState::trim_project_length()`, so deleting a clip
+    /// doesn't make the scroll bar jump.
     pub project_length: WMusicalTime,
 
+    /// The length set by the user with `UiState::set_project_length()`.
+    pub min_project_length: WMusicalTime,
+
     /// The index of the highest-indexed lane that currently has a clip on it. This
     /// can be used to properly set the vertical scroll bar.
     pub used_lanes: u32,
-    // TODO: Time signature
+
+    /// The tempo of the project in beats per minute.
+    pub bpm: f64,
+
+    /// The time signature changes in the project, sorted by bar.
+    pub time_signatures: Vec<TimeSignatureChange>,
 }
 
 pub const VERTICAL_ZOOM_STEP: f64 = 0.25;
@@ -60,6 +74,91 @@ pub const MINIMUM_LANE_HEIGHT: f64 = 0.25;
 pub const MAXIMUM_LANE_HEIGHT: f64 = 4.0;
 pub const LANE_HEIGHT_STEP: f64 = 0.25;
 
+/// Clips may not be placed or extended past this point on the timeline.
+pub const MAX_PROJECT_LENGTH_BEATS: u32 = 1_000_000;
+
+pub const MIN_BPM: f64 = 20.0;
+pub const MAX_BPM: f64 = 999.0;
+pub const DEFAULT_BPM: f64 = 120.0;
+
+/// Clamps `bpm` to the range [`MIN_BPM`, `MAX_BPM`], falling back to
+/// `DEFAULT_BPM` if it is not a finite number.
+pub fn sanitize_bpm(bpm: f64) -> f64 {
+    if bpm.is_finite() {
+        bpm.clamp(MIN_BPM, MAX_BPM)
+    } else {
+        DEFAULT_BPM
+    }
+}
+
+impl TimelineGridState {
+    /// Returns the length in beats of the bar that contains `position` (in
+    /// beats).
+    pub fn bar_beats_at(&self, position: f64) -> f64 {
+        let mut time_signature = TimeSignature::default();
+        let mut next_change = 0;
+        let mut bar = 0;
+        let mut bar_start = 0.0;
+
+        loop {
+            while let Some(change) = self.time_signatures.get(next_change) {
+                if change.bar > bar {
+                    break;
+                }
+                time_signature = change.time_signature;
+                next_change += 1;
+            }
+
+            let bar_beats = time_signature.bar_beats();
+            if bar_beats <= 0.0 || bar_start + bar_beats > position {
+                return bar_beats;
+            }
+
+            bar += 1;
+            bar_start += bar_beats;
+        }
+    }
+
+    /// Returns the start (in beats) of the first bar that starts at or after
+    /// `position` (in beats).
+    pub fn next_bar_start(&self, position: f64) -> f64 {
+        let mut time_signature = TimeSignature::default();
+        let mut next_change = 0;
+        let mut bar = 0;
+        let mut bar_start = 0.0;
+
+        while bar_start < position {
+            while let Some(change) = self.time_signatures.get(next_change) {
+                if change.bar > bar {
+                    break;
+                }
+                time_signature = change.time_signature;
+                next_change += 1;
+            }
+
+            let bar_beats = time_signature.bar_beats();
+            if bar_beats <= 0.0 {
+                return position;
+            }
+
+            bar += 1;
+            bar_start += bar_beats;
+        }
+
+        bar_start
+    }
+
+    /// Zooms and scrolls horizontally so that the whole project fits into a view
+    /// that is `view_width_beats` wide at the default zoom level.
+    pub fn zoom_to_fit_project(&mut self, view_width_beats: f64) {
+        let project_beats = self.project_length.get().as_beats_f64();
+        if project_beats > 0.0 && view_width_beats > 0.0 {
+            self.horizontal_zoom_level = view_width_beats / project_beats;
+        }
+        self.left_start = MusicalTime::from_beats(0).into();
+    }
+}
+
 impl Model for TimelineGridState {
     fn event(&mut self, cx: &mut Context, event: &mut Event) {
         event.map(|event, _| match event {
@@ -91,6 +190,10 @@ impl Model for TimelineGridState {
                     }
                 }
             }
+            UiEvent::ZoomToFitProject(view_width_beats) => {
+                self.zoom_to_fit_project(*view_width_beats);
+                cx.need_redraw();
+            }
             _ => {}
         });
         self.lane_states.event(cx, event);
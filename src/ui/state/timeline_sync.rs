@@ -0,0 +1,374 @@
+//! Keeping the timeline player in sync with the project: the clips and the
+//! automation of every track, and the position of the transport.
+
+use meadowlark_core_types::time::{Frames, MusicalTime};
+use std::error::Error;
+use std::sync::Arc;
+
+use super::{
+    AudioClipPlayback, AudioClipState, AutoFade, AutomationParam, ChannelId, ChannelState,
+    ClipJoins, InterpolationQuality, StateChange, UiData,
+};
+use crate::backend::automation::{fader_gain, AutomationLane, LanePoint, TrackAutomation};
+use crate::backend::timeline::{
+    OfflineTrack, TimelineClip, TimelineMsg, TimelineTrack, TrackClips,
+};
+
+impl UiData {
+    /// Returns the audio clip at `index` the way the timeline player plays it,
+    /// or `None` if it is not an audio clip on the timeline.
+    ///
+    /// `joins` are the edges of the clip that join onto another clip that is
+    /// played along with it (see `UiState::clip_joins()`). `for_export` selects the interpolation quality of exports instead of the
+    /// one of playback.
+    ///
+    /// TODO: Use the tempo map instead of a single `bpm`.
+    pub(super) fn timeline_clip(
+        &mut self,
+        index: usize,
+        auto_fade: &AutoFade,
+        joins: ClipJoins,
+        for_export: bool,
+    ) -> Result<Option<TimelineClip>, Box<dyn Error>> {
+        let (id, audio_clip, length, start, end) = match self.state.clips.get(index) {
+            Some(clip) => match (clip.played_audio(), clip.lane_range_beats()) {
+                (Some(audio_clip), Some((_, start, end))) => {
+                    (clip.id, audio_clip.clone(), clip.length.get(), start, end)
+                }
+                _ => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        let quality = self.state.clip_interpolation(index, for_export).unwrap_or(if for_export {
+            InterpolationQuality::Sinc
+        } else {
+            InterpolationQuality::Linear
+        });
+        let audio = self.playback_audio(&audio_clip)?;
+
+        let sample_rate = self.sample_rate.get();
+        let tempo_map = &self.state.tempo_map;
+        let start = tempo_map.musical_to_frames(MusicalTime::from_beats_f64(start), sample_rate);
+        let end = tempo_map.musical_to_frames(MusicalTime::from_beats_f64(end), sample_rate);
+
+        Ok(Some(TimelineClip {
+            id: id.0,
+            start: start.0,
+            end: end.0,
+            rate: audio_clip.playback_rate(),
+            polarity: audio_clip.polarity(),
+            trims: [
+                audio_clip.channel_trim(0, audio.len()),
+                audio_clip.channel_trim(1, audio.len()),
+            ],
+            audio,
+            source: Arc::new(AudioClipPlayback::new(
+                audio_clip,
+                length,
+                tempo_map.bpm(),
+                *auto_fade,
+                joins,
+                sample_rate.0,
+                quality,
+            )),
+        }))
+    }
+
+    /// Returns the audio of `audio_clip` as the timeline player plays it (with
+    /// its multichannel and channel modes applied), loading it if no other clip
+    /// uses it yet.
+    fn playback_audio(
+        &mut self,
+        audio_clip: &AudioClipState,
+    ) -> Result<Arc<Vec<Vec<f32>>>, Box<dyn Error>> {
+        let key =
+            (audio_clip.pcm_path.clone(), audio_clip.multichannel_mode, audio_clip.channel_mode);
+        if let Some(audio) = self.playback_audio.get(&key) {
+            return Ok(Arc::clone(audio));
+        }
+
+        let buffers = self.load_audio_file(audio_clip.pcm_path.clone())?;
+        let audio =
+            Arc::new(audio_clip.channel_mode.apply(audio_clip.multichannel_mode.apply(buffers)));
+        self.playback_audio.insert(key, Arc::clone(&audio));
+        Ok(audio)
+    }
+
+    /// Returns the audio clips of the channel at `channel` for its timeline
+    /// track, in render order (see `UiState::clips_in_render_order()`). Clips
+    /// whose audio file is missing or can't be loaded are left out.
+    ///
+    /// `for_export` selects the interpolation quality of exports instead of
+    /// the one of playback.
+    fn channel_timeline_clips(&mut self, channel: usize, for_export: bool) -> Vec<TimelineClip> {
+        let auto_fade = self.state.auto_fade;
+        let clips = &self.state.clips;
+        let indices: Vec<usize> = self
+            .state
+            .clips_in_render_order()
+            .into_iter()
+            .filter(|index| {
+                let clip = &clips[*index];
+                clip.channel == channel
+                    && matches!(clip.played_audio(), Some(audio_clip) if !audio_clip.missing)
+            })
+            .collect();
+
+        let joins = self.state.clip_joins(&indices);
+        let mut clips = Vec::with_capacity(indices.len());
+        for (index, joins) in indices.into_iter().zip(joins) {
+            match self.timeline_clip(index, &auto_fade, joins, for_export) {
+                Ok(Some(clip)) => clips.push(clip),
+                Ok(None) => {}
+                Err(e) => log::error!("Failed to load the audio of clip {}: {}", index, e),
+            }
+        }
+        clips
+    }
+
+    /// Sends the tracks that changed during this frame to the timeline player,
+    /// or all of them if the player doesn't have the current project yet.
+    ///
+    /// Every channel has a track in the player, which plays the channel's audio
+    /// clips through the channel's input stage, volume and pan, and follows the
+    /// channel's gain and pan automation.
+    ///
+    /// When the tempo changed (also by an undo), the clips of all tracks are
+    /// sent in one `TimelineMsg::Retime`, so that the player moves the playhead
+    /// and crossfades every clip to its new position in the same block.
+    pub(super) fn sync_timeline(&mut self) {
+        if self.system_io_stream_handle.is_none() {
+            return;
+        }
+        let sample_rate = self.sample_rate.get().0;
+
+        let bpm = self.state.tempo_map.bpm();
+        let retime = self.timeline_bpm != bpm;
+        let mut retime_tracks = Vec::new();
+
+        let full = !self.timeline_synced;
+        let mut all_clips = full || retime;
+        let mut clip_channels: Vec<usize> = Vec::new();
+        let mut setting_channels: Vec<usize> = Vec::new();
+        for change in self.state.changes.iter() {
+            match change {
+                StateChange::ChannelAdded { index } | StateChange::ChannelChanged { index } => {
+                    setting_channels.push(*index);
+                }
+                StateChange::ClipAdded { index } | StateChange::ClipChanged { index } => {
+                    if let Some(clip) = self.state.clips.get(*index) {
+                        clip_channels.push(clip.channel);
+                    }
+                }
+                // The clip may have left another channel, and the positions of
+                // all clips change with the tempo.
+                StateChange::ClipMoved { .. }
+                | StateChange::ClipRemoved { .. }
+                | StateChange::TempoChanged => all_clips = true,
+                StateChange::ClipRenamed { .. } => {}
+            }
+        }
+
+        let mut msgs = Vec::new();
+        let channel_ids: Vec<ChannelId> = self.state.channels.iter().map(|c| c.id).collect();
+        self.timeline_tracks.retain(|id| {
+            let keep = channel_ids.contains(id);
+            if !keep {
+                msgs.push(TimelineMsg::RemoveTrack(id.0));
+            }
+            keep
+        });
+
+        for (index, id) in channel_ids.iter().enumerate() {
+            let is_new = !self.timeline_tracks.contains(id);
+            if is_new {
+                msgs.push(TimelineMsg::AddTrack(TimelineTrack::new(id.0, sample_rate)));
+                self.timeline_tracks.push(*id);
+            }
+            // The automation is kept in frames, which move with the tempo.
+            if is_new || full || retime || setting_channels.contains(&index) {
+                let channel = &self.state.channels[index];
+                msgs.push(TimelineMsg::SetInput {
+                    track: id.0,
+                    trim_db: channel.input_trim_db,
+                    phase_invert: channel.phase_invert,
+                });
+                let (left_gain, right_gain) = channel.pan_law.gains(channel.out_pan_normalized);
+                msgs.push(TimelineMsg::SetPan {
+                    track: id.0,
+                    left_gain: left_gain as f32,
+                    right_gain: right_gain as f32,
+                });
+                msgs.push(TimelineMsg::SetVolume {
+                    track: id.0,
+                    gain: fader_gain(channel.out_gain_normalized),
+                });
+                msgs.push(TimelineMsg::SetAutomation {
+                    track: id.0,
+                    automation: self.track_automation(channel),
+                });
+            }
+            if is_new || all_clips || clip_channels.contains(&index) {
+                let clips = TrackClips::new(self.channel_timeline_clips(index, false), sample_rate);
+                if retime {
+                    retime_tracks.push((id.0, clips));
+                } else {
+                    msgs.push(TimelineMsg::SetClips { track: id.0, clips });
+                }
+            }
+        }
+        if full {
+            msgs.push(TimelineMsg::SetCorrelationBus(self.correlation_track()));
+        }
+        // The loop region is kept in frames, which move with the tempo.
+        if full || retime {
+            msgs.push(TimelineMsg::SetLoop(self.timeline_loop_range()));
+            msgs.push(TimelineMsg::SetDeclick(self.timeline_declick()));
+        }
+
+        let mut synced = true;
+        for msg in msgs {
+            synced &= self.send_to_timeline(msg);
+        }
+        if retime {
+            let playhead_scale = self.timeline_bpm / bpm;
+            if self.send_to_timeline(TimelineMsg::Retime { playhead_scale, tracks: retime_tracks })
+            {
+                self.timeline_bpm = bpm;
+            } else {
+                synced = false;
+            }
+        }
+        if !synced {
+            // Send everything again on the next poll.
+            self.timeline_tracks.clear();
+        }
+        self.timeline_synced = synced;
+
+        // Unload the audio that no clip plays anymore.
+        self.playback_audio.retain(|_, audio| Arc::strong_count(audio) > 1);
+    }
+
+    /// Returns the timeline track of the channel at `channel` with the clips
+    /// and the settings that `sync_timeline()` sends to the player, to render
+    /// it offline. `for_export` selects the interpolation quality of exports.
+    pub(super) fn offline_track(&mut self, channel: usize, for_export: bool) -> OfflineTrack {
+        let clips = self.channel_timeline_clips(channel, for_export);
+        let channel = &self.state.channels[channel];
+        let (left_gain, right_gain) = channel.pan_law.gains(channel.out_pan_normalized);
+        OfflineTrack {
+            id: channel.id.0,
+            clips,
+            trim_db: channel.input_trim_db,
+            phase_invert: channel.phase_invert,
+            pan_gains: [left_gain as f32, right_gain as f32],
+            volume: fader_gain(channel.out_gain_normalized),
+            automation: self.track_automation(channel),
+        }
+    }
+
+    /// Converts the gain and the pan lane of `channel` into the automation of
+    /// its timeline track, with the points in frames.
+    fn track_automation(&self, channel: &ChannelState) -> TrackAutomation {
+        let sample_rate = self.sample_rate.get().0;
+        let tempo_map = &self.state.tempo_map;
+        let lane = |param: AutomationParam| {
+            let points = channel
+                .automation_lanes
+                .iter()
+                .find(|lane| lane.param == param)
+                .map(|lane| {
+                    lane.points
+                        .iter()
+                        .map(|point| LanePoint {
+                            frame: tempo_map.musical_to_seconds(point.time.get()).0 * sample_rate,
+                            value: point.value,
+                            curve: point.curve.into(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            AutomationLane::new(points)
+        };
+
+        TrackAutomation {
+            gain: lane(AutomationParam::Gain),
+            pan: lane(AutomationParam::Pan),
+            pan_law: channel.pan_law.law(),
+            corner_smoothing: None,
+        }
+    }
+
+    /// The id of the timeline track that the correlation meter measures, or
+    /// `None` for the master output.
+    pub(super) fn correlation_track(&self) -> Option<u64> {
+        // A channel that was removed leaves the meter on the master output.
+        self.correlation_bus.filter(|id| self.state.channel_index(*id).is_some()).map(|id| id.0)
+    }
+
+    /// Sends `msg` to the timeline player. Returns `false` if the player is
+    /// not keeping up and the message was dropped.
+    pub(super) fn send_to_timeline(&mut self, msg: TimelineMsg) -> bool {
+        match self.system_io_stream_handle.as_mut() {
+            Some(system_io_stream_handle) => system_io_stream_handle.timeline().send(msg),
+            None => false,
+        }
+    }
+
+    /// Mirrors the loop region of the timeline player in the transport, and
+    /// the playhead and the count-in while it is playing.
+    pub(super) fn poll_timeline(&mut self) {
+        let (playing, playhead, count_in_elapsed, loop_range) =
+            match self.system_io_stream_handle.as_mut() {
+                Some(system_io_stream_handle) => {
+                    let timeline = system_io_stream_handle.timeline();
+                    timeline.collect_garbage();
+                    (
+                        timeline.is_playing(),
+                        timeline.playhead(),
+                        timeline.count_in_elapsed(),
+                        timeline.loop_range(),
+                    )
+                }
+                None => return,
+            };
+
+        // Only take the player's loop region once it is synced, or it would
+        // undo the changes that weren't sent yet.
+        if self.timeline_synced && loop_range != self.timeline_loop_range() {
+            let sample_rate = self.sample_rate.get();
+            let transport = &mut self.state.transport;
+            transport.is_looping = loop_range.is_some();
+            if let Some(range) = loop_range {
+                let tempo_map = &self.state.tempo_map;
+                transport.loop_start =
+                    tempo_map.frames_to_musical(Frames(range.start), sample_rate).into();
+                transport.loop_end =
+                    tempo_map.frames_to_musical(Frames(range.end), sample_rate).into();
+            }
+        }
+
+        if !playing {
+            return;
+        }
+
+        let transport = &mut self.state.transport;
+        if let Some(count_in) = &mut transport.count_in {
+            match count_in_elapsed {
+                Some(elapsed) => {
+                    let secs = elapsed as f64 / self.sample_rate.get().0;
+                    count_in.elapsed_beats = secs * self.state.tempo_map.bpm() / 60.0;
+                    return;
+                }
+                // The transport started moving.
+                None => {
+                    transport.count_in = None;
+                    transport.is_playing = true;
+                }
+            }
+        }
+
+        self.state.transport.playhead =
+            self.state.tempo_map.frames_to_musical(Frames(playhead), self.sample_rate.get()).into();
+    }
+}
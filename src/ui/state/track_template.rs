@@ -0,0 +1,92 @@
+use super::{ChannelState, ClipState, ClipType};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// The file extension used for track templates.
+pub const TRACK_TEMPLATE_EXTENSION: &str = "mltrack";
+
+/// A self-contained copy of a channel and its clips that can be saved to a file
+/// and loaded into any project.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackTemplate {
+    pub channel: ChannelState,
+
+    /// The clips assigned to the channel.
+    pub clips: Vec<ClipState>,
+}
+
+impl TrackTemplate {
+    /// Creates a template from the channel at `channel_index` and all of the
+    /// clips assigned to it, or returns `None` if the channel doesn't exist.
+    pub fn new(
+        channels: &[ChannelState],
+        clips: &[ClipState],
+        channel_index: usize,
+    ) -> Option<Self> {
+        let channel = channels.get(channel_index)?.clone();
+        let clips = clips.iter().filter(|clip| clip.channel == channel_index).cloned().collect();
+
+        Some(Self { channel, clips })
+    }
+
+    /// Saves this template to `path`.
+    ///
+    /// If `bundle_samples` is true, then the audio files of all audio clips are
+    /// copied into a folder next to the template file, and the clips refer to
+    /// them with paths relative to the template file.
+    pub fn save(&self, path: &Path, bundle_samples: bool) -> Result<(), Box<dyn Error>> {
+        let mut template = self.clone();
+
+        if bundle_samples {
+            let samples_dir_name = samples_dir_name(path);
+            let samples_dir = path.with_file_name(&samples_dir_name);
+            std::fs::create_dir_all(&samples_dir)?;
+
+            for clip in template.clips.iter_mut() {
+                if let ClipType::Audio(audio_clip) = &mut clip.type_ {
+                    let file_name = audio_clip
+                        .pcm_path
+                        .file_name()
+                        .ok_or_else(|| {
+                            format!("Invalid audio clip path {:?}", audio_clip.pcm_path)
+                        })?
+                        .to_owned();
+
+                    std::fs::copy(&audio_clip.pcm_path, samples_dir.join(&file_name))?;
+                    audio_clip.pcm_path = PathBuf::from(&samples_dir_name).join(file_name);
+                }
+            }
+        }
+
+        std::fs::write(path, serde_json::to_string_pretty(&template)?)?;
+
+        Ok(())
+    }
+
+    /// Loads a template from `path`.
+    ///
+    /// Relative audio clip paths are resolved against the folder that contains
+    /// the template file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut template: TrackTemplate = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+
+        let template_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for clip in template.clips.iter_mut() {
+            if let ClipType::Audio(audio_clip) = &mut clip.type_ {
+                if audio_clip.pcm_path.is_relative() {
+                    audio_clip.pcm_path = template_dir.join(&audio_clip.pcm_path);
+                }
+            }
+        }
+
+        Ok(template)
+    }
+}
+
+/// The name of the folder that bundled samples are copied into (i.e.
+/// "drums_samples" for "drums.mltrack").
+fn samples_dir_name(template_path: &Path) -> String {
+    let stem = template_path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    format!("{}_samples", stem)
+}
@@ -0,0 +1,252 @@
+use super::core_types::{WMusicalTime, WSeconds};
+use super::MAX_PROJECT_LENGTH_BEATS;
+use meadowlark_core_types::time::{MusicalTime, Seconds};
+use vizia::prelude::*;
+
+/// The default length of the crossfade at the loop point in seconds.
+pub const DEFAULT_LOOP_CROSSFADE_SECS: f64 = 0.005;
+
+/// The longest crossfade that can be set at the loop point in seconds.
+pub const MAX_LOOP_CROSSFADE_SECS: f64 = 2.0;
+
+/// The default length of the fades when the transport starts and stops, and of
+/// the crossfade when it seeks, in seconds.
+pub const DEFAULT_DECLICK_SECS: f64 = 0.005;
+
+/// The longest fade that can be set when the transport starts, stops or seeks
+/// in seconds.
+pub const MAX_DECLICK_SECS: f64 = 0.5;
+
+/// The shortest loop region that can be set in beats (a sixteenth note).
+pub const MIN_LOOP_LENGTH_BEATS: f64 = 0.25;
+
+/// The loop region of a new project in beats (four bars of 4/4).
+const DEFAULT_LOOP_END_BEATS: u32 = 16;
+
+/// The longest count-in before recording that can be set in bars.
+pub const MAX_COUNT_IN_BARS: u8 = 4;
+
+/// An edge of the loop region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopEdge {
+    Start,
+    End,
+}
+
+/// The count-in before recording, while it is running.
+///
+/// The transport stays at the record start position during the count-in, and
+/// only the metronome plays (see `backend::count_in`).
+#[derive(Debug, Clone, Copy, PartialEq, Data)]
+pub struct CountInPhase {
+    /// The length of the count-in in bars.
+    pub bars: u8,
+    /// The length of a bar in beats.
+    pub bar_beats: f64,
+    /// The beats of the count-in that have played.
+    pub elapsed_beats: f64,
+}
+
+impl CountInPhase {
+    /// The length of the count-in in beats.
+    pub fn total_beats(&self) -> f64 {
+        f64::from(self.bars) * self.bar_beats
+    }
+
+    /// Returns the position to show on the playhead display, as bars before the
+    /// record start position, beat and sixteenth (i.e. "-2.1.1" at the start of
+    /// a two bar count-in and "-1.4.4" just before recording starts).
+    pub fn label(&self) -> String {
+        if self.bar_beats <= 0.0 {
+            return format!("-{}.1.1", self.bars);
+        }
+
+        let elapsed = self.elapsed_beats.clamp(0.0, (self.total_beats() - 1e-9).max(0.0));
+        let bar = (elapsed / self.bar_beats).floor();
+        let beat = (elapsed - bar * self.bar_beats).floor();
+        let sixteenth = ((elapsed - elapsed.floor()) * 4.0).floor();
+        format!("-{}.{}.{}", f64::from(self.bars) - bar, beat + 1.0, sixteenth + 1.0)
+    }
+}
+
+/// The state of the transport.
+#[derive(Debug, Lens, Clone)]
+pub struct TransportState {
+    /// True if the transport is currently playing.
+    pub is_playing: bool,
+
+    /// True if looping is enabled.
+    pub is_looping: bool,
+
+    /// True if the transport is currently recording.
+    pub is_recording: bool,
+
+    /// True if recording only keeps what is recorded inside the punch range.
+    pub is_punching: bool,
+
+    /// The start of the punch range.
+    pub punch_in: WMusicalTime,
+
+    /// The end of the punch range.
+    pub punch_out: WMusicalTime,
+
+    /// The length of the fade in when the transport starts and the fade out
+    /// when it stops.
+    pub start_stop_fade_secs: WSeconds,
+
+    /// The length of the crossfade when playback starts from another position
+    /// while playing.
+    pub seek_crossfade_secs: WSeconds,
+
+    /// The length of the crossfade at the loop point.
+    ///
+    /// This is independent of the fades when starting, stopping and seeking,
+    /// since loops often want longer crossfades than seeks. The crossfade is centered on
+    /// the loop point: before the jump, the material just before the loop
+    /// start fades in, so it is clamped with `loop_crossfade()` to never reach
+    /// before the start of the project.
+    pub loop_crossfade_secs: WSeconds,
+
+    /// The start of the loop region.
+    ///
+    /// This mirrors the loop region of the timeline player, so it also
+    /// changes when the player changes the loop region.
+    pub loop_start: WMusicalTime,
+
+    /// The end of the loop region.
+    pub loop_end: WMusicalTime,
+
+    /// The position of the playhead.
+    ///
+    /// This mirrors the playhead of the engine's transport.
+    pub playhead: WMusicalTime,
+
+    /// The number of bars the metronome counts in before recording starts, or
+    /// 0 for no count-in. This is saved with the project.
+    pub count_in_bars: u8,
+
+    /// The count-in that is running, or `None` if there is none.
+    ///
+    /// This mirrors the count-in of the timeline player, and is cleared once the
+    /// playhead starts moving.
+    pub count_in: Option<CountInPhase>,
+}
+
+impl TransportState {
+    /// Sets the length of the fades when the transport starts and stops,
+    /// clamped to the range [0.0, `MAX_DECLICK_SECS`].
+    pub fn set_start_stop_fade_secs(&mut self, secs: f64) {
+        self.start_stop_fade_secs = Seconds(secs.clamp(0.0, MAX_DECLICK_SECS)).into();
+    }
+
+    /// Sets the length of the crossfade when the transport seeks, clamped to
+    /// the range [0.0, `MAX_DECLICK_SECS`].
+    pub fn set_seek_crossfade_secs(&mut self, secs: f64) {
+        self.seek_crossfade_secs = Seconds(secs.clamp(0.0, MAX_DECLICK_SECS)).into();
+    }
+
+    /// Sets the length of the crossfade at the loop point, clamped to the range
+    /// [0.0, `MAX_LOOP_CROSSFADE_SECS`].
+    pub fn set_loop_crossfade_secs(&mut self, secs: f64) {
+        self.loop_crossfade_secs = Seconds(secs.clamp(0.0, MAX_LOOP_CROSSFADE_SECS)).into();
+    }
+
+    /// Sets the loop region. Returns `false` (and leaves the loop region as it
+    /// is) if the region is shorter than `MIN_LOOP_LENGTH_BEATS`.
+    pub fn set_loop_range(&mut self, start: MusicalTime, end: MusicalTime) -> bool {
+        let max_end = MusicalTime::from_beats(MAX_PROJECT_LENGTH_BEATS);
+        let end = if end > max_end { max_end } else { end };
+        if end.as_beats_f64() - start.as_beats_f64() < MIN_LOOP_LENGTH_BEATS {
+            return false;
+        }
+
+        self.loop_start = start.into();
+        self.loop_end = end.into();
+        true
+    }
+
+    /// Moves one edge of the loop region to `position` (in beats), i.e. while it
+    /// is being dragged. The edge is clamped so the region is never shorter than
+    /// `MIN_LOOP_LENGTH_BEATS` and never extends past the start or the maximum
+    /// length of the project.
+    pub fn move_loop_edge(&mut self, edge: LoopEdge, position: f64) {
+        let start = self.loop_start.get().as_beats_f64();
+        let end = self.loop_end.get().as_beats_f64();
+        let max_end = f64::from(MAX_PROJECT_LENGTH_BEATS);
+
+        match edge {
+            LoopEdge::Start => {
+                let start = position.clamp(0.0, (end - MIN_LOOP_LENGTH_BEATS).max(0.0));
+                self.loop_start = MusicalTime::from_beats_f64(start).into();
+            }
+            LoopEdge::End => {
+                let end = position.clamp(start + MIN_LOOP_LENGTH_BEATS, max_end);
+                self.loop_end = MusicalTime::from_beats_f64(end).into();
+            }
+        }
+    }
+
+    /// Sets the punch range. Returns `false` (and leaves the punch range as it
+    /// is) if `end` is not after `start`.
+    pub fn set_punch_range(&mut self, start: MusicalTime, end: MusicalTime) -> bool {
+        let max_end = MusicalTime::from_beats(MAX_PROJECT_LENGTH_BEATS);
+        let end = if end > max_end { max_end } else { end };
+        if end <= start {
+            return false;
+        }
+
+        self.punch_in = start.into();
+        self.punch_out = end.into();
+        true
+    }
+
+    /// Sets the length of the count-in before recording, clamped to
+    /// `MAX_COUNT_IN_BARS`.
+    pub fn set_count_in_bars(&mut self, bars: u8) {
+        self.count_in_bars = bars.min(MAX_COUNT_IN_BARS);
+    }
+
+    /// Starts playing with a count-in of `count_in_bars` bars of `bar_beats`
+    /// beats. Returns `false` (and leaves the transport as it is) if there is
+    /// no count-in.
+    pub fn start_count_in(&mut self, bar_beats: f64) -> bool {
+        if self.count_in_bars == 0 {
+            return false;
+        }
+
+        self.count_in =
+            Some(CountInPhase { bars: self.count_in_bars, bar_beats, elapsed_beats: 0.0 });
+        self.is_playing = true;
+        true
+    }
+
+    /// Returns the length of the crossfade at the loop point, given how much
+    /// material is available before the loop start (`pre_roll`).
+    ///
+    /// Half of the crossfade happens before the loop start, so it is clamped to
+    /// twice the pre-roll.
+    pub fn loop_crossfade(&self, pre_roll: Seconds) -> Seconds {
+        Seconds(self.loop_crossfade_secs.get().0.min(pre_roll.0.max(0.0) * 2.0))
+    }
+}
+
+impl Default for TransportState {
+    fn default() -> Self {
+        Self {
+            is_playing: false,
+            is_looping: false,
+            is_recording: false,
+            is_punching: false,
+            punch_in: MusicalTime::from_beats(0).into(),
+            punch_out: MusicalTime::from_beats(DEFAULT_LOOP_END_BEATS).into(),
+            start_stop_fade_secs: Seconds(DEFAULT_DECLICK_SECS).into(),
+            seek_crossfade_secs: Seconds(DEFAULT_DECLICK_SECS).into(),
+            loop_crossfade_secs: Seconds(DEFAULT_LOOP_CROSSFADE_SECS).into(),
+            loop_start: MusicalTime::from_beats(0).into(),
+            loop_end: MusicalTime::from_beats(DEFAULT_LOOP_END_BEATS).into(),
+            playhead: MusicalTime::from_beats(0).into(),
+            count_in_bars: 0,
+            count_in: None,
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use super::ProjectState;
+
+/// The most edits that can be undone. The oldest entries are dropped first.
+pub const MAX_UNDO_ENTRIES: usize = 100;
+
+/// An edit that can be undone or redone.
+#[derive(Debug, Clone)]
+struct UndoEntry {
+    /// The name of the edit (i.e. "Delete clips"), for the "Undo ..." menu item.
+    label: String,
+    /// The project from the other side of the edit: from before the edit for an
+    /// undo entry, and from after it for a redo entry.
+    project: ProjectState,
+}
+
+/// The history of the edits to the project.
+///
+/// Every entry keeps a snapshot of the project (see `UiState::to_project()`),
+/// so an edit that changes many clips at once (i.e. deleting a selection) is
+/// still undone in one step.
+#[derive(Debug, Clone, Default)]
+pub struct UndoHistory {
+    undo: Vec<UndoEntry>,
+    redo: Vec<UndoEntry>,
+}
+
+impl UndoHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an edit named `label`, where `before` is the project from before
+    /// the edit. This clears the redo history.
+    pub fn push(&mut self, label: &str, before: ProjectState) {
+        if self.undo.len() >= MAX_UNDO_ENTRIES {
+            self.undo.remove(0);
+        }
+        self.undo.push(UndoEntry { label: label.to_string(), project: before });
+        self.redo.clear();
+    }
+
+    /// Undoes the last edit, where `current` is the project as it is now.
+    /// Returns the project to restore, or `None` if there is nothing to undo.
+    pub fn undo(&mut self, current: ProjectState) -> Option<ProjectState> {
+        let entry = self.undo.pop()?;
+        self.redo.push(UndoEntry { label: entry.label, project: current });
+        Some(entry.project)
+    }
+
+    /// Redoes the last undone edit, where `current` is the project as it is
+    /// now. Returns the project to restore, or `None` if there is nothing to
+    /// redo.
+    pub fn redo(&mut self, current: ProjectState) -> Option<ProjectState> {
+        let entry = self.redo.pop()?;
+        self.undo.push(UndoEntry { label: entry.label, project: current });
+        Some(entry.project)
+    }
+
+    /// The name of the edit that `undo()` undoes, if any.
+    pub fn undo_label(&self) -> Option<&str> {
+        self.undo.last().map(|entry| entry.label.as_str())
+    }
+
+    /// The name of the edit that `redo()` redoes, if any.
+    pub fn redo_label(&self) -> Option<&str> {
+        self.redo.last().map(|entry| entry.label.as_str())
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}